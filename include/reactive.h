@@ -0,0 +1,122 @@
+/*
+ * C interface for embedding the Reactive VM, matching src/capi.rs.
+ *
+ * Build the library with the `capi` feature, for example:
+ *
+ *     cargo rustc --release --lib --features capi --crate-type cdylib
+ *     cargo rustc --release --lib --features capi --crate-type staticlib
+ *
+ * Regenerate this header after changing src/capi.rs with:
+ *
+ *     cbindgen --config cbindgen.toml --output include/reactive.h
+ */
+
+#ifndef REACTIVE_H
+#define REACTIVE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define REACTIVE_OK 0
+
+#define REACTIVE_ERROR 1
+
+#define REACTIVE_NULL 0
+
+#define REACTIVE_INT 1
+
+#define REACTIVE_CHAR 2
+
+#define REACTIVE_STRING 3
+
+#define REACTIVE_OTHER 4
+
+/*
+ * A VM and the last result and error read through it.
+ */
+typedef struct ReactiveVm ReactiveVm;
+
+/*
+ * A value passed to or returned from Reactive code. `integer` holds an
+ * int, or a char's code point; `string` a NUL-terminated UTF-8 string for
+ * `REACTIVE_STRING` and `REACTIVE_OTHER`, and is null otherwise.
+ */
+typedef struct ReactiveValue {
+  uint32_t kind;
+  int64_t integer;
+  const char *string;
+} ReactiveValue;
+
+/*
+ * Called when a program calls the function it was registered as, with
+ * the `user_data` given at registration. It returns `REACTIVE_OK` after
+ * storing the function's result in `*result` (null if left untouched), or
+ * `REACTIVE_ERROR` to raise a runtime error, with the message in
+ * `result->string` if it sets one. Strings in `args` are only valid during
+ * the call; a string in `*result` must stay valid until the callback is
+ * called again or the VM is freed.
+ */
+typedef int (*ReactiveCallback)(void *user_data,
+                                const ReactiveValue *args,
+                                size_t argc,
+                                ReactiveValue *result);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ * Creates a VM with the default options. Free it with `reactive_vm_free`.
+ */
+ReactiveVm *reactive_vm_new(void);
+
+/*
+ * Frees a VM from `reactive_vm_new`, with its heaps and open handles.
+ * Null is ignored.
+ */
+void reactive_vm_free(ReactiveVm *vm);
+
+/*
+ * Loads `len` bytes of bytecode (the text of an uncompressed `.rxb`) and
+ * runs its top level.
+ */
+int reactive_vm_load_bytecode(ReactiveVm *vm, const char *bytecode, size_t len);
+
+/*
+ * Loads a bytecode file (`.rxb`, or compressed if the build supports it)
+ * and runs its top level.
+ */
+int reactive_vm_load_file(ReactiveVm *vm, const char *path);
+
+/*
+ * Defines global function `name`, which calls `callback` with `user_data`.
+ * Register callbacks before loading code that calls them at top level.
+ */
+int reactive_vm_register(ReactiveVm *vm,
+                         const char *name,
+                         ReactiveCallback callback,
+                         void *user_data);
+
+/*
+ * Calls global function `name` with `argc` arguments; on success its
+ * result is available from `reactive_vm_result`.
+ */
+int reactive_vm_call(ReactiveVm *vm, const char *name, const ReactiveValue *args, size_t argc);
+
+/*
+ * The result of the last successful `reactive_vm_call` (null before the
+ * first). It and its string stay valid until the next `reactive_vm_call`.
+ */
+const ReactiveValue *reactive_vm_result(const ReactiveVm *vm);
+
+/*
+ * The message of the error the last call on `vm` returned
+ * `REACTIVE_ERROR` for, or null if it succeeded. Valid until the next call.
+ */
+const char *reactive_vm_error(const ReactiveVm *vm);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* REACTIVE_H */
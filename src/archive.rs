@@ -0,0 +1,152 @@
+//! `.rxa` archives: several named bytecode modules and the one to run, in a
+//! single file, built by `reactive pack`.
+//!
+//! ```text
+//! RXA1
+//! @entry app
+//! Module "app" 12
+//! RXB1
+//! ...                 (12 lines of RXB text, checksum trailer included)
+//! Module "std.vec" 40
+//! ...
+//! ```
+
+use crate::bytecode::{Metadata, deserialize_bytecode, quote, serialize_bytecode, tokenize_line};
+use crate::grammar::Instruction;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+
+const MAGIC: &str = "RXA1";
+
+pub struct ArchiveModule {
+    /// Dotted import path (`std.vec`), or the entry program's file stem.
+    pub name: String,
+    pub code: Vec<Instruction>,
+    pub metadata: Option<Metadata>,
+}
+
+pub struct Archive {
+    /// Name of the module run by `reactive run`.
+    pub entry: String,
+    /// Entry first, then imports in the order they were first reached.
+    pub modules: Vec<ArchiveModule>,
+}
+
+impl Archive {
+    pub fn module(&self, name: &str) -> Option<&ArchiveModule> {
+        self.modules.iter().find(|m| m.name == name)
+    }
+
+    /// The entry module and every other module by name, ready for
+    /// `VM::set_archive_modules`.
+    pub fn split_entry(self) -> Result<(ArchiveModule, HashMap<String, Vec<Instruction>>), String> {
+        let mut entry = None;
+        let mut modules = HashMap::new();
+        for module in self.modules {
+            if module.name == self.entry {
+                entry = Some(module);
+            } else {
+                modules.insert(module.name, module.code);
+            }
+        }
+        let entry =
+            entry.ok_or_else(|| format!("archive entry module `{}` is missing", self.entry))?;
+        Ok((entry, modules))
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut out = format!("{MAGIC}\n@entry {}", self.entry);
+        for module in &self.modules {
+            let text = serialize_bytecode(&module.code, module.metadata.as_ref());
+            out.push_str(&format!(
+                "\nModule {} {}\n{text}",
+                quote(&module.name),
+                text.lines().count()
+            ));
+        }
+        out
+    }
+
+    pub fn deserialize(input: &str) -> Result<Self, String> {
+        let lines: Vec<&str> = input.lines().collect();
+        if lines.first().map(|l| l.trim()) != Some(MAGIC) {
+            return Err(format!("invalid archive header: expected {MAGIC}"));
+        }
+        let entry = lines
+            .get(1)
+            .and_then(|l| l.strip_prefix("@entry "))
+            .ok_or("archive line 2: expected `@entry <module>`")?
+            .to_string();
+
+        let mut modules: Vec<ArchiveModule> = Vec::new();
+        let mut index = 2;
+        while index < lines.len() {
+            let line_no = index + 1;
+            let tokens =
+                tokenize_line(lines[index]).map_err(|e| format!("archive line {line_no}: {e}"))?;
+            let [keyword, name, count] = tokens.as_slice() else {
+                return Err(format!(
+                    "archive line {line_no}: expected `Module <name> <line count>`"
+                ));
+            };
+            if keyword != "Module" {
+                return Err(format!(
+                    "archive line {line_no}: expected `Module <name> <line count>`"
+                ));
+            }
+            let count: usize = count
+                .parse()
+                .ok()
+                .filter(|&n| n < lines.len() - index)
+                .ok_or_else(|| {
+                    format!("archive line {line_no}: module `{name}` has an invalid line count")
+                })?;
+            if modules.iter().any(|m| m.name == *name) {
+                return Err(format!("archive line {line_no}: duplicate module `{name}`"));
+            }
+            let text = lines[index + 1..index + 1 + count].join("\n");
+            let (code, metadata) = deserialize_bytecode(&text)
+                .map_err(|e| format!("archive module `{name}` (from line {}): {e}", line_no + 1))?;
+            modules.push(ArchiveModule {
+                name: name.clone(),
+                code,
+                metadata,
+            });
+            index += 1 + count;
+        }
+
+        let archive = Self { entry, modules };
+        if archive.module(&archive.entry).is_none() {
+            return Err(format!(
+                "archive entry module `{}` is missing",
+                archive.entry
+            ));
+        }
+        Ok(archive)
+    }
+
+    pub fn read_file(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read archive `{path}`: {e}"))?;
+        Self::deserialize(&text).map_err(|e| format!("`{path}`: {e}"))
+    }
+
+    pub fn write_file(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.serialize())
+            .map_err(|e| format!("failed to write archive `{path}`: {e}"))
+    }
+}
+
+/// Whether the file at `path` starts with the archive header.
+pub fn is_archive(path: &str) -> bool {
+    let mut head = [0; MAGIC.len()];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut head))
+        .is_ok_and(|_| has_archive_header(&head))
+}
+
+/// Whether `data` starts with the archive header.
+pub fn has_archive_header(data: &[u8]) -> bool {
+    data.starts_with(MAGIC.as_bytes())
+}
@@ -0,0 +1,263 @@
+//! Structural bytecode diff for `reactive bcdiff`.
+//!
+//! Two programs are compared declaration by declaration rather than line by
+//! line: top-level functions, structs and enums are paired by name, so moving
+//! a declaration is not a difference, and the remaining top-level code is
+//! compared as one unit. Within each code body, labels are renamed `L0`,
+//! `L1`, ... in order of first use, so two compilers that number their labels
+//! differently produce the same listing. Metadata and checksums are ignored.
+
+use crate::bytecode::{instruction_line, quote, visibility_suffix};
+use crate::grammar::{CompiledStructFieldInit, Instruction, ReactiveExpr};
+use std::collections::HashMap;
+
+/// Lines of unchanged listing shown around each change.
+const CONTEXT: usize = 3;
+
+/// A named, normalized listing: a top-level declaration or the remaining
+/// top-level code.
+struct Unit {
+    name: String,
+    lines: Vec<String>,
+}
+
+/// Differences between two programs, one unit at a time.
+pub struct BytecodeDiff {
+    /// Rendered hunks, empty when the programs are equivalent.
+    pub text: String,
+    /// Units that were added, removed or changed.
+    pub changed: usize,
+}
+
+impl BytecodeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changed == 0
+    }
+}
+
+/// Compares `left` and `right`; `labels` name the two sides in the output.
+pub fn diff_bytecode(
+    left: &[Instruction],
+    right: &[Instruction],
+    labels: [&str; 2],
+) -> BytecodeDiff {
+    let left_units = units(left);
+    let right_units = units(right);
+    let right_order: Vec<String> = right_units.iter().map(|u| u.name.clone()).collect();
+    let mut right_units: HashMap<String, Unit> = right_units
+        .into_iter()
+        .map(|u| (u.name.clone(), u))
+        .collect();
+
+    let mut text = String::new();
+    let mut changed = 0;
+    for unit in left_units {
+        match right_units.remove(&unit.name) {
+            Some(other) if other.lines == unit.lines => {}
+            Some(other) => {
+                changed += 1;
+                text.push_str(&format!("@@ {} @@\n", unit.name));
+                write_hunks(&mut text, &unit.lines, &other.lines);
+            }
+            None => {
+                changed += 1;
+                text.push_str(&format!("@@ {} (only in {}) @@\n", unit.name, labels[0]));
+                for line in &unit.lines {
+                    text.push_str(&format!("-{line}\n"));
+                }
+            }
+        }
+    }
+    for name in right_order {
+        if let Some(unit) = right_units.remove(&name) {
+            changed += 1;
+            text.push_str(&format!("@@ {} (only in {}) @@\n", unit.name, labels[1]));
+            for line in &unit.lines {
+                text.push_str(&format!("+{line}\n"));
+            }
+        }
+    }
+
+    if changed > 0 {
+        text = format!("--- {}\n+++ {}\n{text}", labels[0], labels[1]);
+    }
+    BytecodeDiff { text, changed }
+}
+
+/// Splits a program into its top-level declarations and the rest of its
+/// top-level code. Redefinitions get a `#2`, `#3`, ... suffix.
+fn units(code: &[Instruction]) -> Vec<Unit> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut units = Vec::new();
+    let mut rest = Vec::new();
+    for instr in code {
+        let name = match instr {
+            Instruction::StoreFunction(name, ..) => format!("function {name}"),
+            Instruction::StoreStruct(name, ..) => format!("struct {name}"),
+            Instruction::StoreEnum(name, _) => format!("enum {name}"),
+            other => {
+                rest.push(other.clone());
+                continue;
+            }
+        };
+        let count = seen.entry(name.clone()).or_default();
+        *count += 1;
+        let name = if *count > 1 {
+            format!("{name} #{count}")
+        } else {
+            name
+        };
+        let mut lines = Vec::new();
+        render_body(&mut lines, std::slice::from_ref(instr), 0);
+        units.push(Unit { name, lines });
+    }
+
+    let mut lines = Vec::new();
+    render_body(&mut lines, &rest, 0);
+    units.push(Unit {
+        name: "<top-level>".to_string(),
+        lines,
+    });
+    units
+}
+
+/// Appends `code` with its labels normalized and nested bodies indented
+/// below their declaring line. Body lengths are left out, since a change
+/// inside a body already shows up in the body itself.
+fn render_body(lines: &mut Vec<String>, code: &[Instruction], depth: usize) {
+    let indent = "  ".repeat(depth);
+    let mut renames: HashMap<String, String> = HashMap::new();
+    for instr in code {
+        let op = instr.opcode();
+        match instr {
+            Instruction::StoreFunction(name, params, body, visibility) => {
+                let params: Vec<String> = params.iter().map(|p| quote(p)).collect();
+                lines.push(format!(
+                    "{indent}{op} {} ({}){}",
+                    quote(name),
+                    params.join(", "),
+                    visibility_suffix(*visibility)
+                ));
+                render_body(lines, body, depth + 1);
+            }
+            Instruction::StoreReactive(name, expr)
+            | Instruction::StoreIndexReactive(name, expr)
+            | Instruction::FieldSetReactive(name, expr) => {
+                render_reactive(lines, &format!("{indent}{op} {}", quote(name)), expr, depth);
+            }
+            Instruction::StoreThroughReactive(expr) => {
+                render_reactive(lines, &format!("{indent}{op}"), expr, depth);
+            }
+            Instruction::StoreStruct(name, fields, visibility) => {
+                lines.push(format!(
+                    "{indent}{op} {}{}",
+                    quote(name),
+                    visibility_suffix(*visibility)
+                ));
+                for (field, init) in fields {
+                    let head = format!("{indent}  Field {}", quote(field));
+                    match init {
+                        None => lines.push(format!("{head} None")),
+                        Some(CompiledStructFieldInit::Mutable(code)) => {
+                            lines.push(format!("{head} Mutable"));
+                            render_body(lines, code, depth + 2);
+                        }
+                        Some(CompiledStructFieldInit::Immutable(code)) => {
+                            lines.push(format!("{head} Immutable"));
+                            render_body(lines, code, depth + 2);
+                        }
+                        Some(CompiledStructFieldInit::Reactive(expr)) => {
+                            render_reactive(lines, &format!("{head} Reactive"), expr, depth + 1);
+                        }
+                    }
+                }
+            }
+            other => {
+                let mut instr = other.clone();
+                for label in instr.labels_mut() {
+                    let next = renames.len();
+                    *label = renames
+                        .entry(label.clone())
+                        .or_insert_with(|| format!("L{next}"))
+                        .clone();
+                }
+                lines.push(format!("{indent}{}", instruction_line(&instr)));
+            }
+        }
+    }
+}
+
+fn render_reactive(lines: &mut Vec<String>, head: &str, expr: &ReactiveExpr, depth: usize) {
+    let captures: Vec<String> = expr.captures.iter().map(|c| quote(c)).collect();
+    lines.push(format!("{head} [{}]", captures.join(", ")));
+    render_body(lines, &expr.code, depth + 1);
+}
+
+/// Unified-style hunks between two listings, from a longest common
+/// subsequence after trimming the shared prefix and suffix.
+fn write_hunks(out: &mut String, left: &[String], right: &[String]) {
+    let prefix = left.iter().zip(right).take_while(|(a, b)| a == b).count();
+    let suffix = left[prefix..]
+        .iter()
+        .rev()
+        .zip(right[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &left[prefix..left.len() - suffix];
+    let b = &right[prefix..right.len() - suffix];
+
+    // lcs[i][j]: common subsequence length of a[i..] and b[j..]
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops: Vec<(char, &str)> = left[..prefix].iter().map(|l| (' ', l.as_str())).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push((' ', &a[i]));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(('-', &a[i]));
+            i += 1;
+        } else {
+            ops.push(('+', &b[j]));
+            j += 1;
+        }
+    }
+    ops.extend(
+        left[left.len() - suffix..]
+            .iter()
+            .map(|l| (' ', l.as_str())),
+    );
+
+    // keep changes and the context around them, eliding the rest
+    let near_change = |index: usize| {
+        let start = index.saturating_sub(CONTEXT);
+        let end = (index + CONTEXT + 1).min(ops.len());
+        ops[start..end].iter().any(|(kind, _)| *kind != ' ')
+    };
+    let mut elided = false;
+    for (index, (kind, line)) in ops.iter().enumerate() {
+        if near_change(index) {
+            if elided {
+                out.push_str(" ...\n");
+                elided = false;
+            }
+            out.push_str(&format!("{kind}{line}\n"));
+        } else {
+            elided = true;
+        }
+    }
+    if elided {
+        out.push_str(" ...\n");
+    }
+}
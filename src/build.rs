@@ -0,0 +1,160 @@
+use crate::bytecode::{
+    COMPILER_VERSION, Metadata, read_bytecode_file, source_hash, write_bytecode_file,
+};
+use crate::compiler::Compiler;
+use crate::grammar::Instruction;
+use crate::manifest::Manifest;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How one module of a build was obtained.
+pub struct ModuleReport {
+    /// Dotted import path, or the entry's file stem.
+    pub name: String,
+    /// Reused from the cache rather than compiled.
+    pub cached: bool,
+    /// Time to compile it, or to load it from the cache.
+    pub time: Duration,
+}
+
+pub struct BuildOutput {
+    /// The linked program: every module inlined after its first `import`.
+    pub code: Vec<Instruction>,
+    /// The entry first, then modules in the order they were reached.
+    pub modules: Vec<ModuleReport>,
+    pub link_time: Duration,
+}
+
+/// Compiles the project's entry point and each module it imports, directly
+/// or not, on its own with the native compiler, and links them into one
+/// program equivalent to compiling the entry with imports inlined.
+///
+/// Compiled modules are kept in `<project>/target/cache/<module>.rxb`, with
+/// the hash of their source in the metadata; a module whose source hash and
+/// compiler version still match is loaded from there instead of compiled.
+/// The entry is always compiled. `root` is the compiler's fallback import
+/// root (`project`).
+pub fn build(manifest: &Manifest, root: &Path) -> Result<BuildOutput, String> {
+    let cache_dir = manifest.dir.join("target").join("cache");
+    fs::create_dir_all(&cache_dir)
+        .map_err(|e| format!("failed to create `{}`: {e}", cache_dir.display()))?;
+    let mut compiler = Compiler::new(root)
+        .with_search_paths(manifest.search_paths())
+        .with_separate_imports();
+
+    let start = Instant::now();
+    let entry = compiler.compile_file(&manifest.entry)?;
+    let mut modules = vec![ModuleReport {
+        name: manifest
+            .entry
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        cached: false,
+        time: start.elapsed(),
+    }];
+
+    let mut compiled: HashMap<String, Vec<Instruction>> = HashMap::new();
+    let mut pending: VecDeque<Vec<String>> = imports_of(&entry).into();
+    while let Some(import) = pending.pop_front() {
+        let name = import.join(".");
+        if compiled.contains_key(&name) {
+            continue;
+        }
+        let start = Instant::now();
+        let path = compiler.module_path(&import);
+        let source =
+            fs::read(&path).map_err(|e| format!("failed to read `{}`: {e}", path.display()))?;
+        let cache = cache_dir.join(format!("{name}.rxb"));
+        let (code, cached) = match cached_module(&cache, &source) {
+            Some(code) => {
+                crate::verbose!("{name}: up to date in {}", cache.display());
+                (code, true)
+            }
+            None => {
+                crate::verbose!("{name}: compiling {}", path.display());
+                let code = compiler.compile_module_file(&path)?;
+                let metadata = Metadata::new("native", &source, 0);
+                write_bytecode_file(&cache.to_string_lossy(), &code, Some(&metadata))?;
+                (code, false)
+            }
+        };
+        pending.extend(imports_of(&code));
+        compiled.insert(name.clone(), code);
+        modules.push(ModuleReport {
+            name,
+            cached,
+            time: start.elapsed(),
+        });
+    }
+
+    let start = Instant::now();
+    let mut linked = HashSet::new();
+    let code = link(&entry, &compiled, &mut linked);
+    Ok(BuildOutput {
+        code,
+        modules,
+        link_time: start.elapsed(),
+    })
+}
+
+/// Where `reactive build` writes the linked program.
+pub fn artifact_path(manifest: &Manifest) -> PathBuf {
+    manifest
+        .dir
+        .join("target")
+        .join(format!("{}.rxb", manifest.name))
+}
+
+/// The cached code for a module with `source`, if it is up to date.
+fn cached_module(cache: &Path, source: &[u8]) -> Option<Vec<Instruction>> {
+    let (code, metadata) = read_bytecode_file(&cache.to_string_lossy()).ok()?;
+    let metadata = metadata?;
+    let current = metadata.compiler == "native"
+        && metadata.version == COMPILER_VERSION
+        && metadata.source_hash == Some(source_hash(source));
+    current.then_some(code)
+}
+
+/// Modules imported by the top level of `code`, in order.
+pub(crate) fn imports_of(code: &[Instruction]) -> Vec<Vec<String>> {
+    code.iter()
+        .filter_map(|instr| match instr {
+            Instruction::Import(path) => Some(path.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `code` with each module's linked code after the first `Import` of it.
+/// A module's top-level labels are prefixed with its name, since they now
+/// share the importer's top-level body.
+fn link(
+    code: &[Instruction],
+    modules: &HashMap<String, Vec<Instruction>>,
+    linked: &mut HashSet<String>,
+) -> Vec<Instruction> {
+    let mut out = Vec::with_capacity(code.len());
+    for instr in code {
+        out.push(instr.clone());
+        let Instruction::Import(path) = instr else {
+            continue;
+        };
+        let name = path.join(".");
+        let Some(module) = modules.get(&name) else {
+            continue;
+        };
+        if !linked.insert(name.clone()) {
+            continue;
+        }
+        for mut instr in link(module, modules, linked) {
+            for label in instr.labels_mut() {
+                *label = format!("{name}::{label}");
+            }
+            out.push(instr);
+        }
+    }
+    out
+}
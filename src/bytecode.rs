@@ -1,79 +1,938 @@
-use crate::grammar::{CastType, CompiledStructFieldInit, Instruction, ReactiveExpr};
+use crate::debug_info::NamedCode;
+use crate::grammar::{CastType, CompiledStructFieldInit, Instruction, ReactiveExpr, Visibility};
+use crate::hash::{Crc32, crc32, sha256, to_hex};
+use crate::rxb2;
 use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
 
 const MAGIC: &str = "RXB1";
 
-pub fn deserialize_instructions(input: &str) -> Result<Vec<Instruction>, String> {
-    let mut lines: Vec<&str> = input.lines().collect();
-    if lines.is_empty() {
-        return Err("bytecode is empty".to_string());
+/// Last line of bytecode written by `serialize_bytecode`: a CRC-32 of all
+/// text before it. Files without it (older files, the bootstrap compilers'
+/// own output) are read unchecked.
+const CHECKSUM_PREFIX: &str = "@checksum crc32:";
+
+/// Deepest nesting of code bodies (functions, field initializers, reactive
+/// expressions) accepted on load. The parser itself has no limit, but
+/// cloning and dropping instructions recurse per level; real programs stay
+/// in single digits.
+pub(crate) const MAX_NESTING: usize = 4096;
+
+/// Start of every load error that points at a line of the file.
+const CORRUPTED: &str = "bytecode corrupted";
+
+/// Version of this build, recorded in the metadata of bytecode it writes.
+pub const COMPILER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Optional block after the `RXB1` header describing how the file was
+/// produced, one `@key value` line per field. Readers ignore keys they do not
+/// know, and files without the block load as before.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    /// `stable`, `experimental` or `native`.
+    pub compiler: String,
+    pub version: String,
+    /// `sha256:<hex>` of the source file the program was compiled from.
+    pub source_hash: Option<String>,
+    /// 0: as compiled; 1: unreachable code stripped (`--strip-dead`).
+    pub opt_level: u32,
+}
+
+impl Metadata {
+    /// Metadata for `source` compiled by `compiler` with this build.
+    pub fn new(compiler: &str, source: &[u8], opt_level: u32) -> Self {
+        Self {
+            compiler: compiler.to_string(),
+            version: COMPILER_VERSION.to_string(),
+            source_hash: Some(source_hash(source)),
+            opt_level,
+        }
+    }
+
+    fn append_lines(&self, lines: &mut Vec<String>) {
+        lines.push(format!("@compiler {}", self.compiler));
+        lines.push(format!("@version {}", self.version));
+        if let Some(hash) = &self.source_hash {
+            lines.push(format!("@source {hash}"));
+        }
+        lines.push(format!("@opt {}", self.opt_level));
     }
-    let header = lines.remove(0);
-    if header.trim() != MAGIC {
-        return Err(format!("invalid bytecode header: expected {MAGIC}"));
+
+    /// `@` lines following the header; `None` when there are none.
+    fn parse(lines: &[&str]) -> Result<Option<Self>, String> {
+        if lines.is_empty() {
+            return Ok(None);
+        }
+        let mut meta = Self {
+            compiler: String::new(),
+            version: String::new(),
+            source_hash: None,
+            opt_level: 0,
+        };
+        for (i, line) in lines.iter().enumerate() {
+            let line_no = i + 2;
+            let (key, value) = line[1..].split_once(' ').unwrap_or((&line[1..], ""));
+            match key {
+                "compiler" => meta.compiler = value.to_string(),
+                "version" => meta.version = value.to_string(),
+                "source" => meta.source_hash = Some(value.to_string()),
+                "opt" => {
+                    meta.opt_level = value.parse().map_err(|_| {
+                        format!(
+                            "{CORRUPTED} at line {line_no}: invalid optimization level `{value}`"
+                        )
+                    })?
+                }
+                _ => {}
+            }
+        }
+        Ok(Some(meta))
     }
+}
 
-    let mut parser = Parser::new(lines);
-    let mut instructions = Vec::new();
-    while !parser.is_done() {
-        instructions.push(parser.parse_instruction()?);
+impl std::fmt::Display for Metadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "compiled by {} {}", self.compiler, self.version)?;
+        if let Some(hash) = &self.source_hash {
+            write!(f, ", source {hash}")?;
+        }
+        write!(f, ", opt level {}", self.opt_level)
     }
-    Ok(instructions)
+}
+
+/// Content hash recorded as `Metadata::source_hash`.
+pub fn source_hash(source: &[u8]) -> String {
+    format!("sha256:{}", to_hex(&sha256(source)))
+}
+
+pub fn deserialize_instructions(input: &str) -> Result<Vec<Instruction>, String> {
+    deserialize_bytecode(input).map(|(code, _)| code)
+}
+
+/// Instructions and metadata block (if any) of RXB text.
+pub fn deserialize_bytecode(input: &str) -> Result<(Vec<Instruction>, Option<Metadata>), String> {
+    read_bytecode(input.as_bytes())
+}
+
+/// `deserialize_bytecode` over a stream, holding one line at a time.
+pub fn read_bytecode(reader: impl BufRead) -> Result<(Vec<Instruction>, Option<Metadata>), String> {
+    Parser::new(reader).parse()
 }
 
 pub fn read_instructions_from_file(path: &str) -> Result<Vec<Instruction>, String> {
-    let input = fs::read_to_string(path)
+    read_bytecode_file(path).map(|(code, _)| code)
+}
+
+/// Reads bytecode from `path`: RXB text or RXB2, plain or zstd-compressed
+/// (see `ZSTD_MAGIC`).
+pub fn read_bytecode_file(path: &str) -> Result<(Vec<Instruction>, Option<Metadata>), String> {
+    let mut reader = open_bytecode(path)?;
+    let binary = reader
+        .fill_buf()
+        .map_err(|e| format!("failed to read bytecode `{}`: {}", path, e))?
+        .starts_with(rxb2::MAGIC);
+    if binary {
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .map_err(|e| format!("failed to read bytecode `{}`: {}", path, e))?;
+        return rxb2::decode(&data);
+    }
+    read_bytecode(reader)
+}
+
+/// First bytes of a zstd frame. Bytecode starting with them is RXB text
+/// compressed with zstd (`.rxz`); it is detected by content, not by name.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Whether `data` is bytecode, text or RXB2, plain or compressed, rather
+/// than source.
+pub fn is_bytecode(data: &[u8]) -> bool {
+    data.starts_with(MAGIC.as_bytes())
+        || data.starts_with(rxb2::MAGIC)
+        || data.starts_with(&ZSTD_MAGIC)
+}
+
+/// Whether bytecode written to `path` is compressed.
+pub fn is_compressed_path(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|ext| ext == "rxz")
+}
+
+/// Whether bytecode written to `path` is RXB2 rather than text.
+pub fn is_binary_path(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|ext| ext == "rxb2")
+}
+
+/// The RXB text of the file at `path`, decompressing it if needed.
+fn open_bytecode(path: &str) -> Result<Box<dyn BufRead>, String> {
+    let read_error = |e: std::io::Error| format!("failed to read bytecode `{}`: {}", path, e);
+    let mut reader = BufReader::new(fs::File::open(path).map_err(read_error)?);
+    if reader
+        .fill_buf()
+        .map_err(read_error)?
+        .starts_with(&ZSTD_MAGIC)
+    {
+        return decompressor(reader, path);
+    }
+    Ok(Box::new(reader))
+}
+
+#[cfg(feature = "zstd")]
+fn decompressor(reader: BufReader<fs::File>, path: &str) -> Result<Box<dyn BufRead>, String> {
+    let decoder = ruzstd::decoding::StreamingDecoder::new(reader)
+        .map_err(|e| format!("failed to read compressed bytecode `{path}`: {e}"))?;
+    Ok(Box::new(BufReader::new(decoder)))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompressor(_reader: BufReader<fs::File>, path: &str) -> Result<Box<dyn BufRead>, String> {
+    Err(format!(
+        "`{path}` is compressed bytecode: reactive was built without the `zstd` feature"
+    ))
+}
+
+#[cfg(feature = "zstd")]
+fn compress(text: String, _path: &str) -> Result<Vec<u8>, String> {
+    use ruzstd::encoding::{CompressionLevel, compress_to_vec};
+    Ok(compress_to_vec(text.as_bytes(), CompressionLevel::Fastest))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn compress(_text: String, path: &str) -> Result<Vec<u8>, String> {
+    Err(format!(
+        "cannot write compressed bytecode `{path}`: reactive was built without the `zstd` feature"
+    ))
+}
+
+pub fn serialize_instructions(code: &[Instruction]) -> String {
+    serialize_bytecode(code, None)
+}
+
+/// RXB text for `code`, ending with a checksum trailer.
+pub fn serialize_bytecode(code: &[Instruction], metadata: Option<&Metadata>) -> String {
+    let body = serialize_unsealed(code, metadata);
+    let checksum = checksum(&body);
+    format!("{body}\n{CHECKSUM_PREFIX}{checksum}")
+}
+
+fn serialize_unsealed(code: &[Instruction], metadata: Option<&Metadata>) -> String {
+    let mut lines = vec![MAGIC.to_string()];
+    if let Some(meta) = metadata {
+        meta.append_lines(&mut lines);
+    }
+    append_instructions(&mut lines, code);
+    lines.join("\n")
+}
+
+fn checksum(body: &str) -> String {
+    format!("{:08x}", crc32(body.as_bytes()))
+}
+
+pub fn write_instructions_to_file(path: &str, code: &[Instruction]) -> Result<(), String> {
+    write_bytecode_file(path, code, None)
+}
+
+pub fn write_bytecode_file(
+    path: &str,
+    code: &[Instruction],
+    metadata: Option<&Metadata>,
+) -> Result<(), String> {
+    let bytes = if is_binary_path(path) {
+        rxb2::encode(code, metadata)
+            .map_err(|e| format!("cannot write RXB2 bytecode `{path}`: {e}"))?
+    } else if is_compressed_path(path) {
+        compress(serialize_bytecode(code, metadata), path)?
+    } else {
+        serialize_bytecode(code, metadata).into_bytes()
+    };
+    fs::write(path, bytes).map_err(|e| format!("failed to write bytecode `{}`: {}", path, e))
+}
+
+/// Checks that `code` survives serialize → deserialize → serialize: the
+/// decoded instructions equal the originals and re-encoding them is
+/// byte-identical to the first encoding.
+pub fn verify_round_trip(code: &[Instruction]) -> Result<(), String> {
+    let text = serialize_instructions(code);
+    let decoded = deserialize_instructions(&text)?;
+
+    if let Some(i) = (0..code.len().min(decoded.len())).find(|&i| code[i] != decoded[i]) {
+        return Err(format!(
+            "instruction {i} changed in round trip: `{}` decoded as `{}`",
+            instruction_line(&code[i]),
+            instruction_line(&decoded[i])
+        ));
+    }
+    if code.len() != decoded.len() {
+        return Err(format!(
+            "round trip decoded {} instruction(s), expected {}",
+            decoded.len(),
+            code.len()
+        ));
+    }
+
+    let again = serialize_instructions(&decoded);
+    if again != text {
+        let line = text
+            .lines()
+            .zip(again.lines())
+            .position(|(a, b)| a != b)
+            .unwrap_or_else(|| text.lines().count().min(again.lines().count()));
+        return Err(format!(
+            "re-serialized bytecode differs at line {}",
+            line + 1
+        ));
+    }
+    Ok(())
+}
+
+/// `verify_round_trip` for bytecode on disk, which must also be exactly what
+/// `serialize_instructions` (or, for RXB2, `rxb2::encode`) writes for it.
+/// Returns the instruction count.
+pub fn verify_round_trip_file(path: &str) -> Result<usize, String> {
+    let mut data = Vec::new();
+    open_bytecode(path)?
+        .read_to_end(&mut data)
         .map_err(|e| format!("failed to read bytecode `{}`: {}", path, e))?;
-    deserialize_instructions(&input)
+    if data.starts_with(rxb2::MAGIC) {
+        let (code, metadata) = rxb2::decode(&data)?;
+        verify_round_trip(&code)?;
+        if rxb2::encode(&code, metadata.as_ref())? != data {
+            return Err(format!("`{path}` is not in canonical form"));
+        }
+        return Ok(code.len());
+    }
+    let text = String::from_utf8(data)
+        .map_err(|_| format!("failed to read bytecode `{path}`: not UTF-8 text"))?;
+    let (code, metadata) = deserialize_bytecode(&text)?;
+    verify_round_trip(&code)?;
+
+    let sealed = text
+        .lines()
+        .last()
+        .is_some_and(|line| line.starts_with(CHECKSUM_PREFIX));
+    let canonical = if sealed {
+        serialize_bytecode(&code, metadata.as_ref())
+    } else {
+        serialize_unsealed(&code, metadata.as_ref())
+    };
+    if let Some(line) = text
+        .lines()
+        .zip(canonical.lines())
+        .position(|(a, b)| a != b)
+    {
+        return Err(format!(
+            "`{path}` line {} is not in canonical form: `{}`",
+            line + 1,
+            text.lines().nth(line).unwrap_or_default()
+        ));
+    }
+    if text.lines().count() != canonical.lines().count() {
+        return Err(format!(
+            "`{path}` has trailing content after the last instruction"
+        ));
+    }
+    Ok(code.len())
+}
+
+/// The RXB header line of a single instruction (nested bodies omitted).
+pub fn instruction_line(instr: &Instruction) -> String {
+    let mut lines = Vec::new();
+    append_instruction(&mut lines, instr);
+    lines.swap_remove(0)
+}
+
+/// One instruction sequence of a program: the top level, a function body,
+/// a struct field initializer or a reactive expression.
+pub struct CodeBody {
+    pub name: String,
+    /// Line of the instruction that declares the body (0 for the top level).
+    pub decl_line: usize,
+    pub code: Vec<Instruction>,
+    /// Line in the serialized RXB text of each instruction of `code`.
+    pub lines: Vec<usize>,
 }
 
+impl NamedCode for CodeBody {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn code(&self) -> &[Instruction] {
+        &self.code
+    }
+}
+
+/// Every instruction sequence in `code` with the line each instruction is
+/// written on by `serialize_bytecode` with `metadata` (the header is line 1).
+/// Mirrors the layout of `append_instruction`.
+pub fn code_bodies(code: &[Instruction], metadata: Option<&Metadata>) -> Vec<CodeBody> {
+    let mut bodies = Vec::new();
+    let mut line = 2;
+    if let Some(meta) = metadata {
+        let mut meta_lines = Vec::new();
+        meta.append_lines(&mut meta_lines);
+        line += meta_lines.len();
+    }
+    collect_body(&mut bodies, "<top-level>", 0, code, &mut line);
+    bodies
+}
+
+fn collect_body(
+    bodies: &mut Vec<CodeBody>,
+    name: &str,
+    decl_line: usize,
+    code: &[Instruction],
+    line: &mut usize,
+) {
+    let index = bodies.len();
+    bodies.push(CodeBody {
+        name: name.to_string(),
+        decl_line,
+        code: code.to_vec(),
+        lines: Vec::with_capacity(code.len()),
+    });
+
+    for instr in code {
+        let here = *line;
+        bodies[index].lines.push(here);
+        *line += 1;
+        match instr {
+            Instruction::StoreFunction(name, _, body, _) => {
+                collect_body(bodies, name, here, body, line);
+            }
+            Instruction::StoreReactive(name, expr)
+            | Instruction::StoreIndexReactive(name, expr)
+            | Instruction::FieldSetReactive(name, expr) => {
+                collect_body(
+                    bodies,
+                    &format!("<reactive {name}>"),
+                    here,
+                    &expr.code,
+                    line,
+                );
+            }
+            Instruction::StoreThroughReactive(expr) => {
+                collect_body(bodies, "<reactive>", here, &expr.code, line);
+            }
+            Instruction::StoreStruct(name, fields, _) => {
+                for (field, init) in fields {
+                    let field_line = *line;
+                    *line += 1;
+                    let body = match init {
+                        None => continue,
+                        Some(CompiledStructFieldInit::Mutable(code))
+                        | Some(CompiledStructFieldInit::Immutable(code)) => code,
+                        Some(CompiledStructFieldInit::Reactive(expr)) => &expr.code,
+                    };
+                    collect_body(bodies, &format!("{name}.{field}"), field_line, body, line);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn append_instructions(lines: &mut Vec<String>, code: &[Instruction]) {
+    for instr in code {
+        append_instruction(lines, instr);
+    }
+}
+
+fn append_instruction(lines: &mut Vec<String>, instr: &Instruction) {
+    if let Instruction::JumpTable(table) = instr {
+        return append_instruction(lines, &table.source);
+    }
+    let op = instr.opcode();
+    match instr {
+        Instruction::Push(n)
+        | Instruction::JumpRel(n)
+        | Instruction::JumpIfZeroRel(n)
+        | Instruction::IterNextRel(n) => lines.push(format!("{op} {n}")),
+        Instruction::PushChar(c) => lines.push(format!("{op} {c}")),
+        Instruction::MakeTuple(n) | Instruction::TupleGet(n) | Instruction::Interp(n) => {
+            lines.push(format!("{op} {n}"))
+        }
+        Instruction::LoadPush(name, n) => lines.push(format!("{op} {} {n}", quote(name))),
+
+        Instruction::Load(name)
+        | Instruction::Store(name)
+        | Instruction::StoreImmutable(name)
+        | Instruction::Label(name)
+        | Instruction::Jump(name)
+        | Instruction::JumpIfZero(name)
+        | Instruction::IterNext(name)
+        | Instruction::StoreIndex(name)
+        | Instruction::NewStruct(name)
+        | Instruction::FieldGet(name)
+        | Instruction::FieldSet(name)
+        | Instruction::FieldLValue(name)
+        | Instruction::Error(name) => lines.push(format!("{op} {}", quote(name))),
+
+        Instruction::LoadLoad(a, b)
+        | Instruction::LoadField(a, b)
+        | Instruction::NewVariant(a, b)
+        | Instruction::ExtendStruct(a, b) => lines.push(format!("{op} {} {}", quote(a), quote(b))),
+
+        Instruction::StoreReactive(name, expr)
+        | Instruction::StoreIndexReactive(name, expr)
+        | Instruction::FieldSetReactive(name, expr) => {
+            append_reactive(lines, &format!("{op} {}", quote(name)), expr)
+        }
+        Instruction::StoreThroughReactive(expr) => append_reactive(lines, op, expr),
+
+        Instruction::StoreEnum(name, variants) => {
+            let mut line = format!("{op} {} {}", quote(name), variants.len());
+            for (variant, arity) in variants {
+                line.push_str(&format!(" {} {arity}", quote(variant)));
+            }
+            lines.push(line);
+        }
+        Instruction::Switch(cases, default) => {
+            let mut line = format!("{op} {}", cases.len());
+            for (value, label) in cases {
+                line.push_str(&format!(" {value} {}", quote(label)));
+            }
+            line.push_str(&format!(" {}", quote(default)));
+            lines.push(line);
+        }
+        Instruction::SwitchRel(cases, default) => {
+            let mut line = format!("{op} {}", cases.len());
+            for (value, offset) in cases {
+                line.push_str(&format!(" {value} {offset}"));
+            }
+            line.push_str(&format!(" {default}"));
+            lines.push(line);
+        }
+        Instruction::MatchVariantRel(arms, default) => {
+            let mut line = format!("{op} {}", arms.len());
+            for (variant, offset) in arms {
+                line.push_str(&format!(" {} {offset}", quote(variant)));
+            }
+            line.push_str(&format!(" {default}"));
+            lines.push(line);
+        }
+        Instruction::MatchVariant(arms, default) => {
+            let mut line = format!("{op} {}", arms.len());
+            for (variant, label) in arms {
+                line.push_str(&format!(" {} {}", quote(variant), quote(label)));
+            }
+            line.push_str(&format!(" {}", quote(default)));
+            lines.push(line);
+        }
+
+        Instruction::StoreStruct(name, fields, visibility) => {
+            lines.push(format!(
+                "{op} {} {}{}",
+                quote(name),
+                fields.len(),
+                visibility_suffix(*visibility)
+            ));
+            for (field, init) in fields {
+                let head = format!("Field {}", quote(field));
+                match init {
+                    None => lines.push(format!("{head} None")),
+                    Some(CompiledStructFieldInit::Mutable(code)) => {
+                        lines.push(format!("{head} Mutable {}", code.len()));
+                        append_instructions(lines, code);
+                    }
+                    Some(CompiledStructFieldInit::Immutable(code)) => {
+                        lines.push(format!("{head} Immutable {}", code.len()));
+                        append_instructions(lines, code);
+                    }
+                    Some(CompiledStructFieldInit::Reactive(expr)) => {
+                        append_reactive(lines, &format!("{head} Reactive"), expr)
+                    }
+                }
+            }
+        }
+
+        Instruction::StoreFunction(name, params, body, visibility) => {
+            let mut line = format!("{op} {} {}", quote(name), params.len());
+            for p in params {
+                line.push(' ');
+                line.push_str(&quote(p));
+            }
+            line.push_str(&format!(" {}", body.len()));
+            line.push_str(visibility_suffix(*visibility));
+            lines.push(line);
+            append_instructions(lines, body);
+        }
+        Instruction::Call(name, argc) | Instruction::Spawn(name, argc) => {
+            lines.push(format!("{op} {} {argc}", quote(name)))
+        }
+
+        Instruction::Import(path) => {
+            let mut line = format!("{op} {}", path.len());
+            for seg in path {
+                line.push(' ');
+                line.push_str(&quote(seg));
+            }
+            lines.push(line);
+        }
+
+        Instruction::Cast(target) => {
+            let target = match target {
+                CastType::Int => "Int",
+                CastType::Char => "Char",
+            };
+            lines.push(format!("{op} {target}"));
+        }
+
+        _ => lines.push(op.to_string()),
+    }
+}
+
+fn append_reactive(lines: &mut Vec<String>, head: &str, expr: &ReactiveExpr) {
+    let mut line = format!("{head} {}", expr.captures.len());
+    for cap in &expr.captures {
+        line.push(' ');
+        line.push_str(&quote(cap));
+    }
+    line.push_str(&format!(" {}", expr.code.len()));
+    lines.push(line);
+    append_instructions(lines, &expr.code);
+}
+
+/// The marker ending a `StoreFunction`/`StoreStruct` line: nothing for
+/// public definitions, so bytecode without visibility reads as before.
+pub(crate) fn visibility_suffix(visibility: Visibility) -> &'static str {
+    match visibility {
+        Visibility::Public => "",
+        Visibility::Private => " private",
+    }
+}
+
+pub(crate) fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 32 || (c as u32) > 126 => {
+                out.push_str(&format!("\\u{{{:x}}}", c as u32));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Streams RXB text line by line. Nested code bodies are tracked on an
+/// explicit stack of `Open` entries, so neither file size nor nesting depth
+/// is limited by the native stack.
 struct Parser<'a> {
-    lines: Vec<&'a str>,
-    index: usize,
-    last_line: usize,
+    reader: Box<dyn BufRead + 'a>,
+    buf: String,
+    // number of the last line read (1-based)
+    line: usize,
+    crc: Crc32,
+    // line terminator of the last line read, hashed once another line
+    // follows (the trailer's own preceding newline is not covered)
+    pending_eol: &'static str,
+}
+
+/// Result of parsing one instruction line.
+enum Parsed {
+    Instr(Instruction),
+    /// The line starts a nested body; its contents follow.
+    Open(Open),
+}
+
+/// Result of parsing one `Field` line of a `StoreStruct`.
+enum FieldStart {
+    Done(StructField),
+    Open(Open),
+}
+
+type StructField = (String, Option<CompiledStructFieldInit>);
+
+/// An instruction whose nested lines are still being read.
+enum Open {
+    Body {
+        code: Vec<Instruction>,
+        remaining: usize,
+        owner: Owner,
+    },
+    Struct {
+        name: String,
+        fields: Vec<StructField>,
+        remaining: usize,
+        visibility: Visibility,
+    },
+}
+
+/// What a completed `Open::Body` becomes.
+enum Owner {
+    Function(String, Vec<String>, Visibility),
+    Reactive(fn(String, ReactiveExpr) -> Instruction, String, Vec<String>),
+    ThroughReactive(Vec<String>),
+    Field(String, FieldKind),
+}
+
+enum FieldKind {
+    Mutable,
+    Immutable,
+    Reactive(Vec<String>),
+}
+
+/// Largest allocation made up front for a declared length; longer bodies
+/// grow as their lines arrive, so a corrupted length costs nothing.
+const MAX_PREALLOC: usize = 1024;
+
+impl Open {
+    fn body(remaining: usize, owner: Owner) -> Self {
+        Open::Body {
+            code: Vec::with_capacity(remaining.min(MAX_PREALLOC)),
+            remaining,
+            owner,
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        match self {
+            Open::Body { remaining, .. } | Open::Struct { remaining, .. } => *remaining,
+        }
+    }
+
+    fn close(self) -> Result<Instruction, StructField> {
+        match self {
+            Open::Struct {
+                name,
+                fields,
+                visibility,
+                ..
+            } => Ok(Instruction::StoreStruct(name, fields, visibility)),
+            Open::Body { code, owner, .. } => match owner {
+                Owner::Function(name, params, visibility) => {
+                    Ok(Instruction::StoreFunction(name, params, code, visibility))
+                }
+                Owner::Reactive(ctor, name, captures) => {
+                    Ok(ctor(name, ReactiveExpr { code, captures }))
+                }
+                Owner::ThroughReactive(captures) => {
+                    Ok(Instruction::StoreThroughReactive(ReactiveExpr {
+                        code,
+                        captures,
+                    }))
+                }
+                Owner::Field(name, kind) => {
+                    let init = match kind {
+                        FieldKind::Mutable => CompiledStructFieldInit::Mutable(code),
+                        FieldKind::Immutable => CompiledStructFieldInit::Immutable(code),
+                        FieldKind::Reactive(captures) => {
+                            CompiledStructFieldInit::Reactive(ReactiveExpr { code, captures })
+                        }
+                    };
+                    Err((name, Some(init)))
+                }
+            },
+        }
+    }
 }
 
 impl<'a> Parser<'a> {
-    fn new(lines: Vec<&'a str>) -> Self {
+    fn new(reader: impl BufRead + 'a) -> Self {
         Self {
-            lines,
-            index: 0,
-            last_line: 0,
+            reader: Box::new(reader),
+            buf: String::new(),
+            line: 0,
+            crc: Crc32::default(),
+            pending_eol: "",
+        }
+    }
+
+    fn parse(mut self) -> Result<(Vec<Instruction>, Option<Metadata>), String> {
+        match self.next_line()? {
+            None => return Err("bytecode is empty".to_string()),
+            Some(header) if header.trim() != MAGIC => {
+                return Err(format!("invalid bytecode header: expected {MAGIC}"));
+            }
+            Some(_) => {}
+        }
+
+        let mut meta_lines = Vec::new();
+        let mut top = Vec::new();
+        let mut open: Vec<Open> = Vec::new();
+        loop {
+            match open.last() {
+                Some(entry) if entry.remaining() == 0 => {
+                    let entry = open.pop().unwrap();
+                    match entry.close() {
+                        Ok(instr) => deliver(&mut open, &mut top, instr),
+                        Err(field) => deliver_field(&mut open, field),
+                    }
+                }
+                Some(Open::Struct { .. }) => {
+                    let line = self.expect_line()?;
+                    match self.parse_field(&line)? {
+                        FieldStart::Done(field) => deliver_field(&mut open, field),
+                        FieldStart::Open(body) => self.push_open(&mut open, body)?,
+                    }
+                }
+                _ => {
+                    let Some(line) = self.next_line()? else {
+                        if open.is_empty() {
+                            break;
+                        }
+                        return Err(self.error("unexpected end of bytecode"));
+                    };
+                    if top.is_empty() && open.is_empty() && line.starts_with('@') {
+                        meta_lines.push(line);
+                        continue;
+                    }
+                    match self.parse_instruction(&line)? {
+                        Parsed::Instr(instr) => deliver(&mut open, &mut top, instr),
+                        Parsed::Open(entry) => self.push_open(&mut open, entry)?,
+                    }
+                }
+            }
+        }
+
+        let meta_lines: Vec<&str> = meta_lines.iter().map(String::as_str).collect();
+        Ok((top, Metadata::parse(&meta_lines)?))
+    }
+
+    fn push_open(&self, open: &mut Vec<Open>, entry: Open) -> Result<(), String> {
+        if open.len() >= MAX_NESTING {
+            return Err(self.error(&format!("code bodies nested more than {MAX_NESTING} deep")));
+        }
+        open.push(entry);
+        Ok(())
+    }
+
+    /// The next line without its terminator, or `None` at the end of the
+    /// text. A checksum trailer ends the text; it is verified here.
+    fn next_line(&mut self) -> Result<Option<String>, String> {
+        self.buf.clear();
+        let read = self
+            .reader
+            .read_line(&mut self.buf)
+            .map_err(|e| self.error_at(self.line + 1, &format!("unreadable line: {e}")))?;
+        if read == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+
+        let mut content = self.buf.as_str();
+        let mut eol = "";
+        if let Some(rest) = content.strip_suffix('\n') {
+            content = rest;
+            eol = "\n";
+            if let Some(rest) = content.strip_suffix('\r') {
+                content = rest;
+                eol = "\r\n";
+            }
+        }
+
+        if let Some(recorded) = content.strip_prefix(CHECKSUM_PREFIX) {
+            let recorded = recorded.trim_end().to_string();
+            let computed = format!("{:08x}", self.crc.finish());
+            if recorded != computed {
+                return Err(self.error(&format!(
+                    "checksum mismatch (recorded {recorded}, computed {computed}); \
+                     the file was truncated or modified"
+                )));
+            }
+            self.expect_end()?;
+            return Ok(None);
+        }
+
+        self.crc.update(self.pending_eol.as_bytes());
+        self.crc.update(content.as_bytes());
+        self.pending_eol = eol;
+        Ok(Some(content.to_string()))
+    }
+
+    /// Only blank lines may follow the checksum trailer.
+    fn expect_end(&mut self) -> Result<(), String> {
+        loop {
+            self.buf.clear();
+            let read = self
+                .reader
+                .read_line(&mut self.buf)
+                .map_err(|e| self.error_at(self.line + 1, &format!("unreadable line: {e}")))?;
+            if read == 0 {
+                return Ok(());
+            }
+            self.line += 1;
+            if !self.buf.trim_end_matches(['\n', '\r']).is_empty() {
+                return Err(self.error("content after the checksum trailer"));
+            }
         }
     }
 
-    fn is_done(&self) -> bool {
-        self.index >= self.lines.len()
+    fn expect_line(&mut self) -> Result<String, String> {
+        match self.next_line()? {
+            Some(line) => Ok(line),
+            None => Err(self.error("unexpected end of bytecode")),
+        }
     }
 
-    fn parse_instruction(&mut self) -> Result<Instruction, String> {
-        let line = self.next_line()?;
+    fn parse_instruction(&self, line: &str) -> Result<Parsed, String> {
         let tokens = tokenize_line(line).map_err(|e| self.error(&e))?;
         if tokens.is_empty() {
             return Err(self.error("empty instruction line"));
         }
+        match tokens[0].as_str() {
+            "StoreFunction" => return self.parse_function(tokens).map(Parsed::Open),
+            "StoreStruct" => return self.parse_struct(tokens).map(Parsed::Open),
+            "StoreReactive" => {
+                return self
+                    .parse_reactive_named(tokens, Instruction::StoreReactive)
+                    .map(Parsed::Open);
+            }
+            "StoreIndexReactive" => {
+                return self
+                    .parse_reactive_named(tokens, Instruction::StoreIndexReactive)
+                    .map(Parsed::Open);
+            }
+            "FieldSetReactive" => {
+                return self
+                    .parse_reactive_named(tokens, Instruction::FieldSetReactive)
+                    .map(Parsed::Open);
+            }
+            "StoreThroughReactive" => return self.parse_reactive_unnamed(tokens).map(Parsed::Open),
+            _ => {}
+        }
         let op = tokens[0].as_str();
-        match op {
+        let parsed = match op {
             "Push" => parse_arity(&tokens, 2, op, self)
-                .and_then(|_| parse_i32(&tokens[1]).map(Instruction::Push)),
+                .and_then(|_| parse_i64(&tokens[1]).map(Instruction::Push)),
             "PushChar" => parse_arity(&tokens, 2, op, self)
                 .and_then(|_| parse_u32(&tokens[1]).map(Instruction::PushChar)),
+            "PushNull" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::PushNull),
             "Load" => {
                 parse_arity(&tokens, 2, op, self).map(|_| Instruction::Load(tokens[1].clone()))
             }
+            "LoadLoad" => parse_arity(&tokens, 3, op, self)
+                .map(|_| Instruction::LoadLoad(tokens[1].clone(), tokens[2].clone())),
+            "LoadPush" => parse_arity(&tokens, 3, op, self).and_then(|_| {
+                parse_i64(&tokens[2]).map(|n| Instruction::LoadPush(tokens[1].clone(), n))
+            }),
+            "LoadField" => parse_arity(&tokens, 3, op, self)
+                .map(|_| Instruction::LoadField(tokens[1].clone(), tokens[2].clone())),
+            "Dup" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Dup),
+            "Swap" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Swap),
+            "Drop" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Drop),
 
             "Store" => {
                 parse_arity(&tokens, 2, op, self).map(|_| Instruction::Store(tokens[1].clone()))
             }
             "StoreImmutable" => parse_arity(&tokens, 2, op, self)
                 .map(|_| Instruction::StoreImmutable(tokens[1].clone())),
-            "StoreReactive" => self.parse_reactive_named(tokens, Instruction::StoreReactive),
 
             "Add" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Add),
             "Sub" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Sub),
             "Mul" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Mul),
             "Div" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Div),
             "Modulo" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Modulo),
+            "Neg" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Neg),
 
             "Greater" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Greater),
             "Less" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Less),
@@ -81,8 +940,10 @@ impl<'a> Parser<'a> {
             "LessEqual" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::LessEqual),
             "Equal" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Equal),
             "NotEqual" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::NotEqual),
+            "DeepEqual" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::DeepEqual),
             "And" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::And),
             "Or" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Or),
+            "Not" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Not),
 
             "Label" => {
                 parse_arity(&tokens, 2, op, self).map(|_| Instruction::Label(tokens[1].clone()))
@@ -92,18 +953,41 @@ impl<'a> Parser<'a> {
             }
             "JumpIfZero" => parse_arity(&tokens, 2, op, self)
                 .map(|_| Instruction::JumpIfZero(tokens[1].clone())),
+            "IterRange" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::IterRange),
+            "IterNew" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::IterNew),
+            "IterNext" => {
+                parse_arity(&tokens, 2, op, self).map(|_| Instruction::IterNext(tokens[1].clone()))
+            }
             "Return" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Return),
+            "JumpRel" => parse_arity(&tokens, 2, op, self)
+                .and_then(|_| parse_i64(&tokens[1]).map(Instruction::JumpRel)),
+            "JumpIfZeroRel" => parse_arity(&tokens, 2, op, self)
+                .and_then(|_| parse_i64(&tokens[1]).map(Instruction::JumpIfZeroRel)),
+            "IterNextRel" => parse_arity(&tokens, 2, op, self)
+                .and_then(|_| parse_i64(&tokens[1]).map(Instruction::IterNextRel)),
 
             "ArrayNew" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::ArrayNew),
             "ArrayGet" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::ArrayGet),
             "ArrayLValue" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::ArrayLValue),
+            "ArraySlice" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::ArraySlice),
+            "ArrayConcat" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::ArrayConcat),
+            "DeepCopy" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::DeepCopy),
+            "MakeTuple" => parse_arity(&tokens, 2, op, self)
+                .and_then(|_| parse_usize(&tokens[1]).map(Instruction::MakeTuple)),
+            "TupleGet" => parse_arity(&tokens, 2, op, self)
+                .and_then(|_| parse_usize(&tokens[1]).map(Instruction::TupleGet)),
             "StoreIndex" => parse_arity(&tokens, 2, op, self)
                 .map(|_| Instruction::StoreIndex(tokens[1].clone())),
-            "StoreIndexReactive" => {
-                self.parse_reactive_named(tokens, Instruction::StoreIndexReactive)
-            }
 
-            "StoreStruct" => self.parse_struct(tokens),
+            "StoreEnum" => self.parse_enum(tokens),
+            "NewVariant" => parse_arity(&tokens, 3, op, self)
+                .map(|_| Instruction::NewVariant(tokens[1].clone(), tokens[2].clone())),
+            "MatchVariant" => self.parse_match_variant(tokens),
+            "MatchVariantRel" => self.parse_match_variant_rel(tokens),
+            "Switch" => self.parse_switch(tokens),
+            "SwitchRel" => self.parse_switch_rel(tokens),
+            "ExtendStruct" => parse_arity(&tokens, 3, op, self)
+                .map(|_| Instruction::ExtendStruct(tokens[1].clone(), tokens[2].clone())),
             "NewStruct" => {
                 parse_arity(&tokens, 2, op, self).map(|_| Instruction::NewStruct(tokens[1].clone()))
             }
@@ -113,20 +997,21 @@ impl<'a> Parser<'a> {
             "FieldSet" => {
                 parse_arity(&tokens, 2, op, self).map(|_| Instruction::FieldSet(tokens[1].clone()))
             }
-            "FieldSetReactive" => self.parse_reactive_named(tokens, Instruction::FieldSetReactive),
             "FieldLValue" => parse_arity(&tokens, 2, op, self)
                 .map(|_| Instruction::FieldLValue(tokens[1].clone())),
 
             "StoreThrough" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::StoreThrough),
-            "StoreThroughReactive" => self.parse_reactive_unnamed(tokens),
             "StoreThroughImmutable" => {
                 parse_arity(&tokens, 1, op, self).map(|_| Instruction::StoreThroughImmutable)
             }
 
-            "StoreFunction" => self.parse_function(tokens),
             "Call" => parse_arity(&tokens, 3, op, self).and_then(|_| {
                 parse_usize(&tokens[2]).map(|argc| Instruction::Call(tokens[1].clone(), argc))
             }),
+            "Spawn" => parse_arity(&tokens, 3, op, self).and_then(|_| {
+                parse_usize(&tokens[2]).map(|argc| Instruction::Spawn(tokens[1].clone(), argc))
+            }),
+            "Yield" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Yield),
 
             "PushImmutableContext" => {
                 parse_arity(&tokens, 1, op, self).map(|_| Instruction::PushImmutableContext)
@@ -140,7 +1025,11 @@ impl<'a> Parser<'a> {
 
             "Print" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Print),
             "Println" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Println),
+            "EPrint" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::EPrint),
+            "EPrintln" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::EPrintln),
             "Assert" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::Assert),
+            "AssertEq" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::AssertEq),
+            "AssertNe" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::AssertNe),
             "Error" => {
                 parse_arity(&tokens, 2, op, self).map(|_| Instruction::Error(tokens[1].clone()))
             }
@@ -155,15 +1044,27 @@ impl<'a> Parser<'a> {
                 };
                 Ok(Instruction::Cast(target))
             }),
+            "TypeOf" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::TypeOf),
+            "Interp" => parse_arity(&tokens, 2, op, self)
+                .and_then(|_| parse_usize(&tokens[1]).map(Instruction::Interp)),
+            "IsNull" => parse_arity(&tokens, 1, op, self).map(|_| Instruction::IsNull),
             other => Err(self.error(&format!("unknown instruction `{}`", other))),
-        }
+        };
+        // operand errors (`invalid i64`, ...) do not carry a line yet
+        parsed.map(Parsed::Instr).map_err(|e| {
+            if e.starts_with(CORRUPTED) {
+                e
+            } else {
+                self.error(&e)
+            }
+        })
     }
 
-    fn parse_import(&mut self, tokens: Vec<String>) -> Result<Instruction, String> {
+    fn parse_import(&self, tokens: Vec<String>) -> Result<Instruction, String> {
         if tokens.len() < 2 {
             return Err(self.error("Import expects a count"));
         }
-        let count = parse_usize(&tokens[1]).map_err(|e| self.error(&e))?;
+        let count = self.parse_count(&tokens[1], tokens.len())?;
         let expected = 2 + count;
         if tokens.len() != expected {
             return Err(self.error(&format!("Import expects {} segment(s)", count)));
@@ -175,43 +1076,137 @@ impl<'a> Parser<'a> {
         Ok(Instruction::Import(segments))
     }
 
-    fn parse_function(&mut self, tokens: Vec<String>) -> Result<Instruction, String> {
+    fn parse_enum(&self, tokens: Vec<String>) -> Result<Instruction, String> {
+        if tokens.len() < 3 {
+            return Err(self.error("StoreEnum expects name and variant count"));
+        }
+        let count = self.parse_count(&tokens[2], tokens.len())?;
+        if tokens.len() != 3 + 2 * count {
+            return Err(self.error(&format!(
+                "StoreEnum expects {} variant(s) as name/arity pairs",
+                count
+            )));
+        }
+        let mut variants = Vec::with_capacity(count);
+        for pair in tokens[3..].chunks(2) {
+            let arity = parse_usize(&pair[1]).map_err(|e| self.error(&e))?;
+            variants.push((pair[0].clone(), arity));
+        }
+        Ok(Instruction::StoreEnum(tokens[1].clone(), variants))
+    }
+
+    fn parse_switch(&self, tokens: Vec<String>) -> Result<Instruction, String> {
+        let (cases, default) = self.parse_jump_table(&tokens, "value", "label")?;
+        let cases = cases
+            .into_iter()
+            .map(|(value, label)| Ok((parse_i64(&value)?, label)))
+            .collect::<Result<_, String>>()
+            .map_err(|e| self.error(&e))?;
+        Ok(Instruction::Switch(cases, default))
+    }
+
+    fn parse_switch_rel(&self, tokens: Vec<String>) -> Result<Instruction, String> {
+        let (cases, default) = self.parse_jump_table(&tokens, "value", "offset")?;
+        let cases = cases
+            .into_iter()
+            .map(|(value, offset)| Ok((parse_i64(&value)?, parse_i64(&offset)?)))
+            .collect::<Result<_, String>>()
+            .map_err(|e| self.error(&e))?;
+        let default = parse_i64(&default).map_err(|e| self.error(&e))?;
+        Ok(Instruction::SwitchRel(cases, default))
+    }
+
+    fn parse_match_variant(&self, tokens: Vec<String>) -> Result<Instruction, String> {
+        let (arms, default) = self.parse_jump_table(&tokens, "variant", "label")?;
+        Ok(Instruction::MatchVariant(arms, default))
+    }
+
+    fn parse_match_variant_rel(&self, tokens: Vec<String>) -> Result<Instruction, String> {
+        let (arms, default) = self.parse_jump_table(&tokens, "variant", "offset")?;
+        let arms = arms
+            .into_iter()
+            .map(|(variant, offset)| Ok((variant, parse_i64(&offset)?)))
+            .collect::<Result<_, String>>()
+            .map_err(|e| self.error(&e))?;
+        let default = parse_i64(&default).map_err(|e| self.error(&e))?;
+        Ok(Instruction::MatchVariantRel(arms, default))
+    }
+
+    /// The operands of a jump table: a count, that many key/target pairs and
+    /// a default target, all still as tokens.
+    fn parse_jump_table(
+        &self,
+        tokens: &[String],
+        key: &str,
+        target: &str,
+    ) -> Result<(Vec<(String, String)>, String), String> {
+        let op = &tokens[0];
+        if tokens.len() < 2 {
+            let noun = if key == "variant" { "an arm" } else { "a case" };
+            return Err(self.error(&format!("{op} expects {noun} count")));
+        }
+        let count = self.parse_count(&tokens[1], tokens.len())?;
+        if tokens.len() != 3 + 2 * count {
+            return Err(self.error(&format!(
+                "{op} expects {count} {key}/{target} pair(s) and a default {target}"
+            )));
+        }
+        let pairs = tokens[2..2 + 2 * count]
+            .chunks(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect();
+        Ok((pairs, tokens[2 + 2 * count].clone()))
+    }
+
+    fn parse_function(&self, tokens: Vec<String>) -> Result<Open, String> {
         if tokens.len() < 4 {
             return Err(self.error("StoreFunction expects name, param count, params, code length"));
         }
         let name = tokens[1].clone();
-        let param_count = parse_usize(&tokens[2]).map_err(|e| self.error(&e))?;
+        let param_count = self.parse_count(&tokens[2], tokens.len())?;
         let expected = 4 + param_count;
-        if tokens.len() != expected {
+        if tokens.len() != expected && tokens.len() != expected + 1 {
             return Err(self.error(&format!(
                 "StoreFunction expects {} parameter(s)",
                 param_count
             )));
         }
-        let mut params = Vec::with_capacity(param_count);
-        for p in tokens.iter().skip(3).take(param_count) {
-            params.push(p.clone());
-        }
+        let params = tokens[3..3 + param_count].to_vec();
         let code_len = parse_usize(&tokens[3 + param_count]).map_err(|e| self.error(&e))?;
-        let code = self.parse_instructions(code_len)?;
-        Ok(Instruction::StoreFunction(name, params, code))
+        let visibility = self.parse_visibility("StoreFunction", tokens.get(expected))?;
+        Ok(Open::body(
+            code_len,
+            Owner::Function(name, params, visibility),
+        ))
     }
 
-    fn parse_struct(&mut self, tokens: Vec<String>) -> Result<Instruction, String> {
-        if tokens.len() != 3 {
+    fn parse_struct(&self, tokens: Vec<String>) -> Result<Open, String> {
+        if tokens.len() != 3 && tokens.len() != 4 {
             return Err(self.error("StoreStruct expects name and field count"));
         }
         let name = tokens[1].clone();
         let field_count = parse_usize(&tokens[2]).map_err(|e| self.error(&e))?;
-        let mut fields = Vec::with_capacity(field_count);
-        for _ in 0..field_count {
-            fields.push(self.parse_field()?);
+        let visibility = self.parse_visibility("StoreStruct", tokens.get(3))?;
+        Ok(Open::Struct {
+            name,
+            fields: Vec::with_capacity(field_count.min(MAX_PREALLOC)),
+            remaining: field_count,
+            visibility,
+        })
+    }
+
+    /// The optional trailing marker of a definition; absent means public.
+    fn parse_visibility(&self, op: &str, token: Option<&String>) -> Result<Visibility, String> {
+        match token.map(String::as_str) {
+            None => Ok(Visibility::Public),
+            Some("private") => Ok(Visibility::Private),
+            Some(other) => Err(self.error(&format!(
+                "{op} expects `private` or nothing after its definition, got `{other}`"
+            ))),
         }
-        Ok(Instruction::StoreStruct(name, fields))
     }
 
-    fn parse_field(&mut self) -> Result<(String, Option<CompiledStructFieldInit>), String> {
-        let line = self.next_line()?;
+    fn parse_field(&self, line: &str) -> Result<FieldStart, String> {
         let tokens = tokenize_line(line).map_err(|e| self.error(&e))?;
         if tokens.len() < 3 || tokens[0] != "Field" {
             return Err(self.error("expected Field entry"));
@@ -223,29 +1218,33 @@ impl<'a> Parser<'a> {
                 if tokens.len() != 3 {
                     return Err(self.error("Field None expects no extra tokens"));
                 }
-                Ok((name, None))
+                Ok(FieldStart::Done((name, None)))
             }
             "Mutable" => {
                 if tokens.len() != 4 {
                     return Err(self.error("Field Mutable expects code length"));
                 }
                 let code_len = parse_usize(&tokens[3]).map_err(|e| self.error(&e))?;
-                let code = self.parse_instructions(code_len)?;
-                Ok((name, Some(CompiledStructFieldInit::Mutable(code))))
+                Ok(FieldStart::Open(Open::body(
+                    code_len,
+                    Owner::Field(name, FieldKind::Mutable),
+                )))
             }
             "Immutable" => {
                 if tokens.len() != 4 {
                     return Err(self.error("Field Immutable expects code length"));
                 }
                 let code_len = parse_usize(&tokens[3]).map_err(|e| self.error(&e))?;
-                let code = self.parse_instructions(code_len)?;
-                Ok((name, Some(CompiledStructFieldInit::Immutable(code))))
+                Ok(FieldStart::Open(Open::body(
+                    code_len,
+                    Owner::Field(name, FieldKind::Immutable),
+                )))
             }
             "Reactive" => {
                 if tokens.len() < 5 {
                     return Err(self.error("Field Reactive expects captures and code length"));
                 }
-                let cap_count = parse_usize(&tokens[3]).map_err(|e| self.error(&e))?;
+                let cap_count = self.parse_count(&tokens[3], tokens.len())?;
                 let expected = 5 + cap_count;
                 if tokens.len() != expected {
                     return Err(
@@ -254,86 +1253,98 @@ impl<'a> Parser<'a> {
                 }
                 let captures = tokens[4..4 + cap_count].to_vec();
                 let code_len = parse_usize(&tokens[4 + cap_count]).map_err(|e| self.error(&e))?;
-                let code = self.parse_instructions(code_len)?;
-                Ok((
-                    name,
-                    Some(CompiledStructFieldInit::Reactive(ReactiveExpr {
-                        code,
-                        captures,
-                    })),
-                ))
+                Ok(FieldStart::Open(Open::body(
+                    code_len,
+                    Owner::Field(name, FieldKind::Reactive(captures)),
+                )))
             }
             other => Err(self.error(&format!("unknown field init `{}`", other))),
         }
     }
 
     fn parse_reactive_named(
-        &mut self,
+        &self,
         tokens: Vec<String>,
         ctor: fn(String, ReactiveExpr) -> Instruction,
-    ) -> Result<Instruction, String> {
+    ) -> Result<Open, String> {
         if tokens.len() < 4 {
             return Err(self.error("expected name, capture count, captures, code length"));
         }
         let name = tokens[1].clone();
-        let cap_count = parse_usize(&tokens[2]).map_err(|e| self.error(&e))?;
+        let cap_count = self.parse_count(&tokens[2], tokens.len())?;
         let expected = 4 + cap_count;
         if tokens.len() != expected {
             return Err(self.error(&format!("expected {} capture(s)", cap_count)));
         }
         let captures = tokens[3..3 + cap_count].to_vec();
         let code_len = parse_usize(&tokens[3 + cap_count]).map_err(|e| self.error(&e))?;
-        let code = self.parse_instructions(code_len)?;
-        Ok(ctor(name, ReactiveExpr { code, captures }))
+        Ok(Open::body(code_len, Owner::Reactive(ctor, name, captures)))
     }
 
-    fn parse_reactive_unnamed(&mut self, tokens: Vec<String>) -> Result<Instruction, String> {
+    fn parse_reactive_unnamed(&self, tokens: Vec<String>) -> Result<Open, String> {
         if tokens.len() < 3 {
             return Err(self.error("expected capture count, captures, code length"));
         }
-        let cap_count = parse_usize(&tokens[1]).map_err(|e| self.error(&e))?;
+        let cap_count = self.parse_count(&tokens[1], tokens.len())?;
         let expected = 3 + cap_count;
         if tokens.len() != expected {
             return Err(self.error(&format!("expected {} capture(s)", cap_count)));
         }
         let captures = tokens[2..2 + cap_count].to_vec();
         let code_len = parse_usize(&tokens[2 + cap_count]).map_err(|e| self.error(&e))?;
-        let code = self.parse_instructions(code_len)?;
-        Ok(Instruction::StoreThroughReactive(ReactiveExpr {
-            code,
-            captures,
-        }))
+        Ok(Open::body(code_len, Owner::ThroughReactive(captures)))
     }
 
-    fn parse_instructions(&mut self, count: usize) -> Result<Vec<Instruction>, String> {
-        let mut code = Vec::with_capacity(count);
-        for _ in 0..count {
-            code.push(self.parse_instruction()?);
+    /// A declared count of tokens on the same line, rejected when it is
+    /// larger than `available`, so a corrupted count fails before anything is
+    /// allocated for it.
+    fn parse_count(&self, token: &str, available: usize) -> Result<usize, String> {
+        let count = parse_usize(token).map_err(|e| self.error(&e))?;
+        if count > available {
+            return Err(self.error(&format!(
+                "declared count {count} exceeds the {available} available"
+            )));
         }
-        Ok(code)
+        Ok(count)
+    }
+
+    fn error(&self, message: &str) -> String {
+        self.error_at(self.line, message)
     }
 
-    fn next_line(&mut self) -> Result<&'a str, String> {
-        if self.index >= self.lines.len() {
-            return Err(self.error("unexpected end of bytecode"));
+    fn error_at(&self, line: usize, message: &str) -> String {
+        format!("{CORRUPTED} at line {}: {}", line, message)
+    }
+}
+
+/// Adds a completed instruction to the innermost open body, or to the top
+/// level.
+fn deliver(open: &mut [Open], top: &mut Vec<Instruction>, instr: Instruction) {
+    match open.last_mut() {
+        Some(Open::Body {
+            code, remaining, ..
+        }) => {
+            code.push(instr);
+            *remaining -= 1;
         }
-        let line = self.lines[self.index];
-        self.last_line = self.index + 1;
-        self.index += 1;
-        Ok(line)
+        Some(Open::Struct { .. }) => unreachable!("struct entries hold fields"),
+        None => top.push(instr),
     }
+}
 
-    fn error(&self, message: &str) -> String {
-        let line = if self.last_line == 0 {
-            self.index + 1
-        } else {
-            self.last_line
-        };
-        format!("line {}: {}", line, message)
+fn deliver_field(open: &mut [Open], field: StructField) {
+    match open.last_mut() {
+        Some(Open::Struct {
+            fields, remaining, ..
+        }) => {
+            fields.push(field);
+            *remaining -= 1;
+        }
+        _ => unreachable!("fields are only parsed inside a struct"),
     }
 }
 
-fn tokenize_line(line: &str) -> Result<Vec<String>, String> {
+pub(crate) fn tokenize_line(line: &str) -> Result<Vec<String>, String> {
     let mut tokens = Vec::new();
     let mut chars = line.chars().peekable();
     while let Some(&ch) = chars.peek() {
@@ -425,8 +1436,8 @@ fn parse_arity(
     Ok(())
 }
 
-fn parse_i32(s: &str) -> Result<i32, String> {
-    s.parse::<i32>().map_err(|_| format!("invalid i32 `{}`", s))
+fn parse_i64(s: &str) -> Result<i64, String> {
+    s.parse::<i64>().map_err(|_| format!("invalid i64 `{}`", s))
 }
 
 fn parse_u32(s: &str) -> Result<u32, String> {
@@ -437,3 +1448,496 @@ fn parse_usize(s: &str) -> Result<usize, String> {
     s.parse::<usize>()
         .map_err(|_| format!("invalid usize `{}`", s))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    fn expr(code: Vec<Instruction>, captures: &[&str]) -> ReactiveExpr {
+        ReactiveExpr {
+            code,
+            captures: captures.iter().map(|c| s(c)).collect(),
+        }
+    }
+
+    /// Asserts `parse(emit(code)) == code` and that emitting again gives the
+    /// same text.
+    fn assert_round_trip(code: &[Instruction]) {
+        let text = serialize_instructions(code);
+        let decoded = deserialize_instructions(&text)
+            .unwrap_or_else(|e| panic!("{e}\n--- bytecode ---\n{text}"));
+        assert_eq!(decoded, code, "\n--- bytecode ---\n{text}");
+        assert_eq!(serialize_instructions(&decoded), text);
+    }
+
+    /// One of each instruction, with nested bodies of every kind.
+    fn every_instruction() -> Vec<Instruction> {
+        let body = vec![Load(s("x")), Push(1), Add, Return];
+        let reactive = expr(vec![Load(s("a")), Load(s("b")), Mul], &["a", "b"]);
+        vec![
+            Push(i64::MIN),
+            Push(-1),
+            Push(i64::MAX),
+            PushChar(0x1F600),
+            PushNull,
+            Load(s("x")),
+            LoadLoad(s("a"), s("b")),
+            LoadPush(s("n"), -7),
+            LoadField(s("p"), s("x")),
+            Dup,
+            Swap,
+            Drop,
+            Store(s("x")),
+            StoreImmutable(s("k")),
+            StoreReactive(s("r"), reactive.clone()),
+            Add,
+            Sub,
+            Mul,
+            Div,
+            Modulo,
+            Neg,
+            Greater,
+            Less,
+            GreaterEqual,
+            LessEqual,
+            Equal,
+            NotEqual,
+            DeepEqual,
+            And,
+            Or,
+            Not,
+            Label(s("top")),
+            Jump(s("top")),
+            JumpIfZero(s("end")),
+            Switch(vec![(-1, s("neg")), (0, s("zero"))], s("end")),
+            Switch(Vec::new(), s("end")),
+            IterRange,
+            IterNew,
+            IterNext(s("end")),
+            Return,
+            JumpRel(-3),
+            JumpIfZeroRel(2),
+            SwitchRel(vec![(-1, 2), (0, 0)], 1),
+            SwitchRel(Vec::new(), -1),
+            IterNextRel(4),
+            ArrayNew,
+            ArrayGet,
+            ArrayLValue,
+            ArraySlice,
+            ArrayConcat,
+            DeepCopy,
+            MakeTuple(3),
+            TupleGet(2),
+            StoreEnum(s("Shape"), vec![(s("Circle"), 1), (s("Empty"), 0)]),
+            NewVariant(s("Shape"), s("Circle")),
+            MatchVariant(vec![(s("Circle"), s("circle"))], s("other")),
+            MatchVariantRel(vec![(s("Circle"), 1)], -2),
+            StoreIndex(s("arr")),
+            StoreIndexReactive(s("arr"), reactive.clone()),
+            StoreStruct(
+                s("lib.Point"),
+                vec![
+                    (s("x"), None),
+                    (s("y"), Some(CompiledStructFieldInit::Mutable(body.clone()))),
+                    (
+                        s("id"),
+                        Some(CompiledStructFieldInit::Immutable(vec![Push(7)])),
+                    ),
+                    (
+                        s("sum"),
+                        Some(CompiledStructFieldInit::Reactive(reactive.clone())),
+                    ),
+                    (
+                        s("empty"),
+                        Some(CompiledStructFieldInit::Mutable(Vec::new())),
+                    ),
+                ],
+                Visibility::Private,
+            ),
+            StoreStruct(s("Unit"), Vec::new(), Visibility::Public),
+            ExtendStruct(s("Point3"), s("lib.Point")),
+            NewStruct(s("Point3")),
+            FieldGet(s("x")),
+            FieldSet(s("x")),
+            FieldSetReactive(s("sum"), reactive.clone()),
+            FieldLValue(s("x")),
+            StoreThrough,
+            StoreThroughReactive(expr(Vec::new(), &[])),
+            StoreThroughImmutable,
+            StoreFunction(s("lib.f"), vec![s("x")], body.clone(), Visibility::Private),
+            StoreFunction(s("g"), Vec::new(), Vec::new(), Visibility::Public),
+            Call(s("lib.f"), 1),
+            Spawn(s("worker"), 0),
+            Yield,
+            PushImmutableContext,
+            PopImmutableContext,
+            ClearImmutableContext,
+            Print,
+            Println,
+            EPrint,
+            EPrintln,
+            Assert,
+            AssertEq,
+            AssertNe,
+            Error(s("boom")),
+            Import(vec![s("std"), s("vec")]),
+            Cast(CastType::Int),
+            Cast(CastType::Char),
+            TypeOf,
+            Interp(2),
+            IsNull,
+        ]
+    }
+
+    /// Position of `instr`'s variant in `Instruction`. No wildcard arm, so a
+    /// new variant does not compile until it is listed here, and then fails
+    /// `every_variant_is_covered` until `every_instruction` builds it.
+    fn ordinal(instr: &Instruction) -> usize {
+        match instr {
+            Push(_) => 0,
+            PushChar(_) => 1,
+            PushNull => 2,
+            Load(_) => 3,
+            LoadLoad(..) => 4,
+            LoadPush(..) => 5,
+            LoadField(..) => 6,
+            Dup => 7,
+            Swap => 8,
+            Drop => 9,
+            Store(_) => 10,
+            StoreImmutable(_) => 11,
+            StoreReactive(..) => 12,
+            Add => 13,
+            Sub => 14,
+            Mul => 15,
+            Div => 16,
+            Modulo => 17,
+            Neg => 18,
+            Greater => 19,
+            Less => 20,
+            GreaterEqual => 21,
+            LessEqual => 22,
+            Equal => 23,
+            NotEqual => 24,
+            DeepEqual => 25,
+            And => 26,
+            Or => 27,
+            Not => 28,
+            Label(_) => 29,
+            Jump(_) => 30,
+            JumpIfZero(_) => 31,
+            Switch(..) => 32,
+            IterRange => 33,
+            IterNew => 34,
+            IterNext(_) => 35,
+            Return => 36,
+            JumpRel(_) => 37,
+            JumpIfZeroRel(_) => 38,
+            SwitchRel(..) => 39,
+            IterNextRel(_) => 40,
+            JumpTable(_) => 41,
+            ArrayNew => 42,
+            ArrayGet => 43,
+            ArrayLValue => 44,
+            ArraySlice => 45,
+            ArrayConcat => 46,
+            DeepCopy => 47,
+            MakeTuple(_) => 48,
+            TupleGet(_) => 49,
+            StoreEnum(..) => 50,
+            NewVariant(..) => 51,
+            MatchVariant(..) => 52,
+            MatchVariantRel(..) => 53,
+            StoreIndex(_) => 54,
+            StoreIndexReactive(..) => 55,
+            StoreStruct(..) => 56,
+            ExtendStruct(..) => 57,
+            NewStruct(_) => 58,
+            FieldGet(_) => 59,
+            FieldSet(_) => 60,
+            FieldSetReactive(..) => 61,
+            FieldLValue(_) => 62,
+            StoreThrough => 63,
+            StoreThroughReactive(_) => 64,
+            StoreThroughImmutable => 65,
+            StoreFunction(..) => 66,
+            Call(..) => 67,
+            Spawn(..) => 68,
+            Yield => 69,
+            PushImmutableContext => 70,
+            PopImmutableContext => 71,
+            ClearImmutableContext => 72,
+            Print => 73,
+            Println => 74,
+            EPrint => 75,
+            EPrintln => 76,
+            Assert => 77,
+            AssertEq => 78,
+            AssertNe => 79,
+            Error(_) => 80,
+            Import(_) => 81,
+            Cast(_) => 82,
+            TypeOf => 83,
+            Interp(_) => 84,
+            IsNull => 85,
+        }
+    }
+    const VARIANTS: usize = 86;
+
+    /// `every_instruction` as the VM loads it, with its switches resolved
+    /// into jump tables.
+    fn every_loaded_instruction() -> Vec<Instruction> {
+        crate::vm::jumptable::resolve_switches(every_instruction())
+    }
+
+    #[test]
+    fn every_variant_is_covered() {
+        let mut seen = [false; VARIANTS];
+        for instr in every_instruction()
+            .into_iter()
+            .chain(every_loaded_instruction())
+        {
+            seen[ordinal(&instr)] = true;
+        }
+        let missing: Vec<usize> = (0..VARIANTS).filter(|&i| !seen[i]).collect();
+        assert!(missing.is_empty(), "variants not built: {missing:?}");
+    }
+
+    #[test]
+    fn opcode_list_names_every_variant() {
+        assert_eq!(Instruction::OPCODES.len(), VARIANTS);
+        for instr in every_instruction()
+            .into_iter()
+            .chain(every_loaded_instruction())
+        {
+            assert_eq!(Instruction::OPCODES[ordinal(&instr)], instr.opcode());
+        }
+    }
+
+    #[test]
+    fn every_instruction_round_trips() {
+        let code = every_instruction();
+        for instr in &code {
+            assert_round_trip(std::slice::from_ref(instr));
+        }
+        assert_round_trip(&code);
+    }
+
+    #[test]
+    fn jump_tables_are_written_as_their_switch() {
+        let loaded = every_loaded_instruction();
+        assert!(loaded.iter().any(|i| matches!(i, JumpTable(_))));
+        assert_eq!(
+            serialize_instructions(&loaded),
+            serialize_instructions(&every_instruction())
+        );
+        let label_free = |code: Vec<Instruction>| -> Vec<Instruction> {
+            code.into_iter()
+                .filter(|instr| instr.clone().labels_mut().is_empty())
+                .collect()
+        };
+        assert_eq!(
+            rxb2::encode(&label_free(loaded), None).unwrap(),
+            rxb2::encode(&label_free(every_instruction()), None).unwrap()
+        );
+    }
+
+    #[test]
+    fn every_label_free_instruction_round_trips_as_rxb2() {
+        let code: Vec<Instruction> = every_instruction()
+            .into_iter()
+            .filter(|instr| instr.clone().labels_mut().is_empty())
+            .collect();
+        let meta = Metadata::new("stable", b"fn main() {}", 1);
+        let bytes = rxb2::encode(&code, Some(&meta)).unwrap();
+        let (decoded, decoded_meta) = rxb2::decode(&bytes).unwrap();
+        assert_eq!(decoded, code);
+        assert_eq!(decoded_meta, Some(meta));
+        assert_eq!(
+            rxb2::encode(&decoded, decoded_meta.as_ref()).unwrap(),
+            bytes
+        );
+    }
+
+    #[test]
+    fn nested_bodies_round_trip() {
+        // every instruction inside a function, inside a struct initializer
+        // and inside a reactive expression, two levels deep
+        let inner = every_instruction();
+        let function = StoreFunction(s("outer"), vec![s("a")], inner.clone(), Visibility::Public);
+        let reactive = expr(vec![function.clone()], &["a"]);
+        let code = vec![
+            StoreStruct(
+                s("S"),
+                vec![
+                    (
+                        s("m"),
+                        Some(CompiledStructFieldInit::Mutable(vec![function.clone()])),
+                    ),
+                    (
+                        s("i"),
+                        Some(CompiledStructFieldInit::Immutable(inner.clone())),
+                    ),
+                    (
+                        s("r"),
+                        Some(CompiledStructFieldInit::Reactive(reactive.clone())),
+                    ),
+                ],
+                Visibility::Public,
+            ),
+            StoreFunction(s("wrap"), Vec::new(), vec![function], Visibility::Private),
+            StoreReactive(s("r"), reactive.clone()),
+            StoreIndexReactive(s("arr"), reactive.clone()),
+            FieldSetReactive(s("f"), reactive.clone()),
+            StoreThroughReactive(reactive),
+        ];
+        assert_round_trip(&code);
+    }
+
+    #[test]
+    fn names_with_escapes_round_trip() {
+        for name in [
+            "",
+            " ",
+            "a b",
+            "\"",
+            "\\",
+            "\\\"",
+            "\n\r\t",
+            "\u{0}\u{7f}",
+            "é",
+            "😀",
+            "\\u{41}",
+        ] {
+            assert_round_trip(&[
+                Load(s(name)),
+                Error(s(name)),
+                Import(vec![s(name), s(name)]),
+                StoreFunction(
+                    s(name),
+                    vec![s(name)],
+                    vec![Label(s(name))],
+                    Visibility::Public,
+                ),
+                StoreStruct(s(name), vec![(s(name), None)], Visibility::Private),
+                StoreReactive(s(name), expr(vec![Load(s(name))], &[name])),
+            ]);
+        }
+    }
+
+    /// xorshift64*, so the generated programs are the same on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn int(&mut self) -> i64 {
+            match self.below(4) {
+                0 => [i64::MIN, -1, 0, 1, i64::MAX][self.below(5)],
+                _ => self.next() as i64 >> self.below(64),
+            }
+        }
+
+        fn name(&mut self) -> String {
+            const CHARS: &[char] = &['a', 'Z', '_', '.', ' ', '"', '\\', '\n', '\t', 'é', '😀'];
+            (0..self.below(6))
+                .map(|_| CHARS[self.below(CHARS.len())])
+                .collect()
+        }
+
+        fn names(&mut self) -> Vec<String> {
+            (0..self.below(3)).map(|_| self.name()).collect()
+        }
+
+        fn visibility(&mut self) -> Visibility {
+            [Visibility::Public, Visibility::Private][self.below(2)]
+        }
+
+        fn code(&mut self, depth: usize) -> Vec<Instruction> {
+            (0..self.below(6))
+                .map(|_| self.instruction(depth))
+                .collect()
+        }
+
+        fn reactive(&mut self, depth: usize) -> ReactiveExpr {
+            ReactiveExpr {
+                code: self.code(depth + 1),
+                captures: self.names(),
+            }
+        }
+
+        fn field_init(&mut self, depth: usize) -> Option<CompiledStructFieldInit> {
+            match self.below(4) {
+                0 => None,
+                1 => Some(CompiledStructFieldInit::Mutable(self.code(depth + 1))),
+                2 => Some(CompiledStructFieldInit::Immutable(self.code(depth + 1))),
+                _ => Some(CompiledStructFieldInit::Reactive(self.reactive(depth))),
+            }
+        }
+
+        /// An instruction with random operands; nested bodies stop at depth 3.
+        fn instruction(&mut self, depth: usize) -> Instruction {
+            let simple = every_instruction();
+            let nested = depth < 3;
+            match self.below(if nested { 12 } else { 8 }) {
+                0 => Push(self.int()),
+                1 => PushChar(self.next() as u32),
+                2 => LoadPush(self.name(), self.int()),
+                3 => LoadField(self.name(), self.name()),
+                4 => Switch(
+                    (0..self.below(3))
+                        .map(|_| (self.int(), self.name()))
+                        .collect(),
+                    self.name(),
+                ),
+                5 => StoreEnum(
+                    self.name(),
+                    (0..self.below(3))
+                        .map(|_| (self.name(), self.below(4)))
+                        .collect(),
+                ),
+                6 => Import(self.names()),
+                7 => simple[self.below(simple.len())].clone(),
+                8 => StoreFunction(
+                    self.name(),
+                    self.names(),
+                    self.code(depth + 1),
+                    self.visibility(),
+                ),
+                9 => StoreStruct(
+                    self.name(),
+                    (0..self.below(3))
+                        .map(|_| (self.name(), self.field_init(depth)))
+                        .collect(),
+                    self.visibility(),
+                ),
+                10 => StoreReactive(self.name(), self.reactive(depth)),
+                _ => StoreThroughReactive(self.reactive(depth)),
+            }
+        }
+    }
+
+    #[test]
+    fn random_programs_round_trip() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..300 {
+            let code = rng.code(0);
+            assert_round_trip(&code);
+            assert!(verify_round_trip(&code).is_ok());
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! C interface for embedding the VM in other applications (`--features
+//! capi`). `include/reactive.h` declares these functions for C and C++.
+//!
+//! A host creates a VM, loads a program's bytecode (which runs its top
+//! level, `main` included), registers callbacks the program can call like
+//! natives, then calls the program's global functions and reads their
+//! results. Runtime errors do not exit the process: the failing function
+//! returns `REACTIVE_ERROR`, the report is printed as usual, and the message
+//! is kept for `reactive_vm_error`. The VM can be used again afterwards.
+//!
+//! Values cross the boundary as `ReactiveValue`s: null, ints, chars and
+//! strings. Any other result (an array of ints, a struct, ...) is returned
+//! as `REACTIVE_OTHER` with its display form in `string`.
+
+use crate::bytecode;
+use crate::grammar::{Instruction, Type};
+use crate::vm::{VM, VmOptions};
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::rc::Rc;
+
+pub const REACTIVE_OK: c_int = 0;
+pub const REACTIVE_ERROR: c_int = 1;
+
+pub const REACTIVE_NULL: u32 = 0;
+pub const REACTIVE_INT: u32 = 1;
+pub const REACTIVE_CHAR: u32 = 2;
+pub const REACTIVE_STRING: u32 = 3;
+pub const REACTIVE_OTHER: u32 = 4;
+
+/// A value passed to or returned from Reactive code. `integer` holds an
+/// int, or a char's code point; `string` a NUL-terminated UTF-8 string for
+/// `REACTIVE_STRING` and `REACTIVE_OTHER`, and is null otherwise.
+#[repr(C)]
+pub struct ReactiveValue {
+    pub kind: u32,
+    pub integer: i64,
+    pub string: *const c_char,
+}
+
+impl ReactiveValue {
+    fn null() -> Self {
+        ReactiveValue {
+            kind: REACTIVE_NULL,
+            integer: 0,
+            string: std::ptr::null(),
+        }
+    }
+}
+
+/// Called when a program calls the function it was registered as, with
+/// the `user_data` given at registration. It returns `REACTIVE_OK` after
+/// storing the function's result in `*result` (null if left untouched), or
+/// `REACTIVE_ERROR` to raise a runtime error, with the message in
+/// `result->string` if it sets one. Strings in `args` are only valid during
+/// the call; a string in `*result` must stay valid until the callback is
+/// called again or the VM is freed.
+pub type ReactiveCallback = extern "C" fn(
+    user_data: *mut c_void,
+    args: *const ReactiveValue,
+    argc: usize,
+    result: *mut ReactiveValue,
+) -> c_int;
+
+/// A VM and the last result and error read through it.
+pub struct ReactiveVm {
+    vm: VM,
+    result: ReactiveValue,
+    // owns `result.string`
+    result_text: Option<CString>,
+    error: Option<CString>,
+}
+
+impl ReactiveVm {
+    /// Runs `f` against the VM, turning runtime errors and any other panic
+    /// into `None` with the message kept for `reactive_vm_error`.
+    fn guard<T>(&mut self, f: impl FnOnce(&mut VM) -> Result<T, String>) -> Option<T> {
+        self.error = None;
+        let vm = &mut self.vm;
+        let outcome = catch_unwind(AssertUnwindSafe(|| vm.catch_runtime_error(f)));
+        let message = match outcome {
+            Ok(Ok(Ok(value))) => return Some(value),
+            Ok(Ok(Err(message))) => message,
+            Ok(Err(error)) => error.message,
+            Err(_) => "internal error: the VM panicked".to_string(),
+        };
+        self.error = Some(c_string(message));
+        None
+    }
+
+    /// Runs `code` as the program's top level.
+    fn load(&mut self, name: String, code: Result<Vec<Instruction>, String>) -> c_int {
+        let loaded = self.guard(|vm| {
+            vm.run_module(name, code?);
+            Ok(())
+        });
+        status(loaded)
+    }
+}
+
+/// Creates a VM with the default options. Free it with `reactive_vm_free`.
+#[unsafe(no_mangle)]
+pub extern "C" fn reactive_vm_new() -> *mut ReactiveVm {
+    let options = VmOptions {
+        unwind_on_error: true,
+        ..VmOptions::default()
+    };
+    Box::into_raw(Box::new(ReactiveVm {
+        vm: VM::with_options(Vec::new(), options),
+        result: ReactiveValue::null(),
+        result_text: None,
+        error: None,
+    }))
+}
+
+/// Frees a VM from `reactive_vm_new`, with its heaps and open handles.
+/// Null is ignored.
+///
+/// # Safety
+///
+/// `vm` must be null or a VM from `reactive_vm_new` not yet freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn reactive_vm_free(vm: *mut ReactiveVm) {
+    if !vm.is_null() {
+        drop(unsafe { Box::from_raw(vm) });
+    }
+}
+
+/// Loads `len` bytes of bytecode (the text of an uncompressed `.rxb`) and
+/// runs its top level.
+///
+/// # Safety
+///
+/// `vm` must be a live VM and `bytecode` point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn reactive_vm_load_bytecode(
+    vm: *mut ReactiveVm,
+    bytecode: *const c_char,
+    len: usize,
+) -> c_int {
+    let vm = unsafe { &mut *vm };
+    let data = unsafe { std::slice::from_raw_parts(bytecode.cast::<u8>(), len) };
+    let code = std::str::from_utf8(data)
+        .map_err(|_| "bytecode is not valid UTF-8".to_string())
+        .and_then(bytecode::deserialize_instructions);
+    vm.load("<bytecode>".to_string(), code)
+}
+
+/// Loads a bytecode file (`.rxb`, or compressed if the build supports it)
+/// and runs its top level.
+///
+/// # Safety
+///
+/// `vm` must be a live VM and `path` a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn reactive_vm_load_file(vm: *mut ReactiveVm, path: *const c_char) -> c_int {
+    let vm = unsafe { &mut *vm };
+    let path = unsafe { CStr::from_ptr(path) }
+        .to_string_lossy()
+        .into_owned();
+    let code = bytecode::read_instructions_from_file(&path);
+    vm.load(path, code)
+}
+
+/// Defines global function `name`, which calls `callback` with `user_data`.
+/// Register callbacks before loading code that calls them at top level.
+///
+/// # Safety
+///
+/// `vm` must be a live VM, `name` a NUL-terminated string, and `user_data`
+/// valid for `callback` for as long as the VM lives.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn reactive_vm_register(
+    vm: *mut ReactiveVm,
+    name: *const c_char,
+    callback: ReactiveCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    let vm = unsafe { &mut *vm };
+    let name = match unsafe { CStr::from_ptr(name) }.to_str() {
+        Ok(name) => name.to_string(),
+        Err(_) => {
+            vm.error = Some(c_string("function name is not valid UTF-8".to_string()));
+            return REACTIVE_ERROR;
+        }
+    };
+    let function = name.clone();
+    vm.vm.register_host_function(
+        &name,
+        Rc::new(move |vm: &mut VM, args: Vec<Type>| {
+            call_callback(vm, &function, callback, user_data, args)
+        }),
+    );
+    vm.error = None;
+    REACTIVE_OK
+}
+
+/// Calls global function `name` with `argc` arguments; on success its
+/// result is available from `reactive_vm_result`.
+///
+/// # Safety
+///
+/// `vm` must be a live VM, `name` a NUL-terminated string and `args` point
+/// to `argc` values (it may be null when `argc` is 0).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn reactive_vm_call(
+    vm: *mut ReactiveVm,
+    name: *const c_char,
+    args: *const ReactiveValue,
+    argc: usize,
+) -> c_int {
+    let vm = unsafe { &mut *vm };
+    let name = unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .into_owned();
+    let args = unsafe { c_values(args, argc) };
+    let result = vm.guard(|vm| {
+        let args = args
+            .iter()
+            .map(|arg| unsafe { from_c_value(vm, arg) })
+            .collect::<Result<Vec<_>, _>>()?;
+        let value = vm.call_global(&name, args);
+        Ok(to_c_value(vm, value))
+    });
+    let called = result.map(|(result, text)| {
+        vm.result = result;
+        vm.result_text = text;
+    });
+    status(called)
+}
+
+/// The result of the last successful `reactive_vm_call` (null before the
+/// first). It and its string stay valid until the next `reactive_vm_call`.
+///
+/// # Safety
+///
+/// `vm` must be a live VM.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn reactive_vm_result(vm: *const ReactiveVm) -> *const ReactiveValue {
+    unsafe { &(*vm).result }
+}
+
+/// The message of the error the last call on `vm` returned
+/// `REACTIVE_ERROR` for, or null if it succeeded. Valid until the next call.
+///
+/// # Safety
+///
+/// `vm` must be a live VM.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn reactive_vm_error(vm: *const ReactiveVm) -> *const c_char {
+    match unsafe { &(*vm).error } {
+        Some(message) => message.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+fn status(outcome: Option<()>) -> c_int {
+    match outcome {
+        Some(()) => REACTIVE_OK,
+        None => REACTIVE_ERROR,
+    }
+}
+
+fn call_callback(
+    vm: &mut VM,
+    name: &str,
+    callback: ReactiveCallback,
+    user_data: *mut c_void,
+    args: Vec<Type>,
+) -> Type {
+    // the CStrings own the argument strings until the callback returns
+    let (args, _texts): (Vec<ReactiveValue>, Vec<Option<CString>>) =
+        args.into_iter().map(|arg| to_c_value(vm, arg)).unzip();
+    let mut result = ReactiveValue::null();
+    let status = callback(user_data, args.as_ptr(), args.len(), &mut result);
+    if status != REACTIVE_OK {
+        let message = if result.string.is_null() {
+            "callback failed".to_string()
+        } else {
+            unsafe { CStr::from_ptr(result.string) }
+                .to_string_lossy()
+                .into_owned()
+        };
+        vm.runtime_error(&format!("{name}: {message}"));
+    }
+    unsafe { from_c_value(vm, &result) }
+        .unwrap_or_else(|message| vm.runtime_error(&format!("{name}: {message}")))
+}
+
+/// # Safety
+///
+/// `values` must point to `len` values, or `len` be 0.
+unsafe fn c_values<'a>(values: *const ReactiveValue, len: usize) -> &'a [ReactiveValue] {
+    match len {
+        0 => &[],
+        _ => unsafe { std::slice::from_raw_parts(values, len) },
+    }
+}
+
+/// `value` as a `ReactiveValue`, with the string it points to, if any.
+fn to_c_value(vm: &mut VM, value: Type) -> (ReactiveValue, Option<CString>) {
+    let value = vm.force(value);
+    let (kind, integer, text) = match value {
+        Type::Null => (REACTIVE_NULL, 0, None),
+        Type::Integer(n) => (REACTIVE_INT, n, None),
+        Type::Char(c) => (REACTIVE_CHAR, c as i64, None),
+        other => match vm.string_value(other.clone()) {
+            Some(text) => (REACTIVE_STRING, 0, Some(text)),
+            None => (REACTIVE_OTHER, 0, Some(vm.format_value(&other))),
+        },
+    };
+    let text = text.map(c_string);
+    let string = text.as_ref().map_or(std::ptr::null(), |text| text.as_ptr());
+    let value = ReactiveValue {
+        kind,
+        integer,
+        string,
+    };
+    (value, text)
+}
+
+/// # Safety
+///
+/// `value.string` must be null or a NUL-terminated string.
+unsafe fn from_c_value(vm: &mut VM, value: &ReactiveValue) -> Result<Type, String> {
+    match value.kind {
+        REACTIVE_NULL => Ok(Type::Null),
+        REACTIVE_INT => Ok(Type::Integer(value.integer)),
+        REACTIVE_CHAR => u32::try_from(value.integer)
+            .ok()
+            .filter(|&c| char::from_u32(c).is_some())
+            .map(Type::Char)
+            .ok_or_else(|| format!("invalid char code {}", value.integer)),
+        REACTIVE_STRING if !value.string.is_null() => {
+            match unsafe { CStr::from_ptr(value.string) }.to_str() {
+                Ok(text) => Ok(vm.string_to_array(text)),
+                Err(_) => Err("string is not valid UTF-8".to_string()),
+            }
+        }
+        REACTIVE_STRING => Err("string value with a null pointer".to_string()),
+        kind => Err(format!("cannot pass a value of kind {kind} to Reactive")),
+    }
+}
+
+/// `text` up to its first NUL, which C cannot see past.
+fn c_string(mut text: String) -> CString {
+    if let Some(end) = text.find('\0') {
+        text.truncate(end);
+    }
+    CString::new(text).unwrap_or_default()
+}
@@ -0,0 +1,341 @@
+use crate::debug_info::{BodyMatcher, DebugInfo, SourceLoc};
+use crate::grammar::{Instruction, ReactiveExpr};
+use crate::vm::VM;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A problem found by `check` without running the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Enclosing function, or `None` for top-level code.
+    pub function: Option<String>,
+    /// Kind of problem, such as `unknown-variable` (see `check`).
+    pub code: &'static str,
+    pub message: String,
+    /// Source line of the offending instruction (`check_located` only).
+    pub loc: Option<SourceLoc>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some(name) => write!(f, "in {name}(): {}", self.message),
+            None => write!(f, "at top level: {}", self.message),
+        }
+    }
+}
+
+/// Validates compiled program bytecode, reporting the mistakes the VM would
+/// otherwise only hit at runtime (or not at all):
+///
+/// - loads and calls of names that are never bound (`unknown-variable`,
+///   `undefined-function`)
+/// - calls whose argument count differs from the function's parameters
+///   (`arity-mismatch`)
+/// - re-binding an immutable (`:=`) name in the same scope, or assigning to
+///   a top-level immutable (`immutable-rebound`, `immutable-assigned`)
+/// - `NewStruct` / `NewVariant` of undeclared structs, enums or variants
+///   (`unknown-struct`, `unknown-enum`, `unknown-variant`)
+///
+/// Struct field initializers are skipped: they run against the fields and
+/// whatever scope creates the struct, which is not known statically.
+pub fn check(code: &[Instruction]) -> Vec<Diagnostic> {
+    check_with(code, false, None)
+}
+
+/// `check` for a module compiled without `main`. A std module's natives are
+/// installed by whoever imports it, so any `internal_*` name is accepted.
+pub fn check_module(code: &[Instruction]) -> Vec<Diagnostic> {
+    check_with(code, true, None)
+}
+
+/// `check` (or `check_module`) of code compiled with
+/// `Compiler::with_debug_info`, giving each problem its source line. A
+/// problem repeated on several lines is reported once per line.
+pub fn check_located(code: &[Instruction], info: &DebugInfo, module: bool) -> Vec<Diagnostic> {
+    check_with(code, module, Some(info))
+}
+
+fn check_with(
+    code: &[Instruction],
+    allow_internal: bool,
+    info: Option<&DebugInfo>,
+) -> Vec<Diagnostic> {
+    let mut globals = Globals {
+        allow_internal,
+        ..Globals::default()
+    };
+    globals.collect(code, true);
+    globals.names.extend(VM::native_names(&globals.imports));
+
+    let mut checker = Checker {
+        globals: &globals,
+        debug: info.map(|info| (info, BodyMatcher::new(&info.bodies))),
+        diagnostics: Vec::new(),
+    };
+    checker.check_top_level(code);
+    checker.diagnostics
+}
+
+#[derive(Default)]
+struct Globals {
+    names: HashSet<String>,
+    functions: HashMap<String, usize>,
+    structs: HashSet<String>,
+    enums: HashMap<String, Vec<String>>,
+    imports: Vec<Vec<String>>,
+    allow_internal: bool,
+}
+
+impl Globals {
+    fn is_bound(&self, name: &str) -> bool {
+        self.names.contains(name) || (self.allow_internal && name.starts_with("internal_"))
+    }
+
+    fn collect(&mut self, code: &[Instruction], top_level: bool) {
+        for instr in code {
+            match instr {
+                Instruction::Store(name)
+                | Instruction::StoreImmutable(name)
+                | Instruction::StoreReactive(name, _)
+                    if top_level =>
+                {
+                    self.names.insert(name.clone());
+                }
+                Instruction::StoreFunction(name, params, body, _) => {
+                    self.names.insert(name.clone());
+                    self.functions.insert(name.clone(), params.len());
+                    self.collect(body, false);
+                }
+                Instruction::StoreStruct(name, ..) => {
+                    self.structs.insert(name.clone());
+                }
+                Instruction::StoreEnum(name, variants) => {
+                    let names = variants.iter().map(|(v, _)| v.clone()).collect();
+                    self.enums.insert(name.clone(), names);
+                }
+                Instruction::Import(path) => self.imports.push(path.clone()),
+                _ => {}
+            }
+        }
+    }
+}
+
+struct Checker<'a> {
+    globals: &'a Globals,
+    debug: Option<(&'a DebugInfo, BodyMatcher)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+struct Scope<'a> {
+    function: Option<&'a str>,
+    // debug info body being checked and the instruction reached in it
+    body: Option<usize>,
+    pc: usize,
+    // every name stored anywhere in the body (order-insensitive, loops)
+    locals: HashSet<&'a str>,
+    // immutable contexts as pushed / popped by the linear instruction stream
+    immutables: Vec<HashSet<&'a str>>,
+}
+
+impl<'a> Checker<'a> {
+    fn check_top_level(&mut self, code: &'a [Instruction]) {
+        let mut scope = Scope {
+            function: None,
+            body: None,
+            pc: 0,
+            locals: HashSet::new(),
+            immutables: vec![HashSet::new()],
+        };
+        self.check_body(code, &mut scope);
+    }
+
+    fn check_function(&mut self, name: &'a str, params: &'a [String], body: &'a [Instruction]) {
+        let mut locals: HashSet<&str> = params.iter().map(String::as_str).collect();
+        collect_locals(body, &mut locals);
+        let mut scope = Scope {
+            function: Some(name),
+            body: None,
+            pc: 0,
+            locals,
+            immutables: vec![params.iter().map(String::as_str).collect()],
+        };
+        self.check_body(body, &mut scope);
+    }
+
+    fn check_body(&mut self, code: &'a [Instruction], scope: &mut Scope<'a>) {
+        if let Some((info, matcher)) = &self.debug {
+            let function = scope.function.unwrap_or("<top-level>");
+            scope.body = matcher.find(&info.bodies, code, function);
+        }
+        for (pc, instr) in code.iter().enumerate() {
+            scope.pc = pc;
+            match instr {
+                Instruction::Load(name)
+                | Instruction::LoadPush(name, _)
+                | Instruction::LoadField(name, _)
+                | Instruction::StoreIndex(name) => self.check_name(name, scope),
+                Instruction::LoadLoad(a, b) => {
+                    self.check_name(a, scope);
+                    self.check_name(b, scope);
+                }
+                Instruction::StoreIndexReactive(name, expr) => {
+                    self.check_name(name, scope);
+                    self.check_reactive(expr, scope);
+                }
+                Instruction::FieldSetReactive(_, expr)
+                | Instruction::StoreThroughReactive(expr) => {
+                    self.check_reactive(expr, scope);
+                }
+                Instruction::Call(name, argc) | Instruction::Spawn(name, argc) => {
+                    self.check_call(name, *argc, scope)
+                }
+                Instruction::Store(name) => self.check_mutable_store(name, scope),
+                Instruction::StoreReactive(name, expr) => {
+                    self.check_mutable_store(name, scope);
+                    self.check_reactive(expr, scope);
+                }
+                Instruction::StoreImmutable(name) => {
+                    let current = scope.immutables.last_mut().expect("immutable scope");
+                    if !current.insert(name) {
+                        self.report(
+                            scope,
+                            "immutable-rebound",
+                            format!("`{name}` is already bound with `:=` in this scope"),
+                        );
+                    }
+                }
+                Instruction::PushImmutableContext => scope.immutables.push(HashSet::new()),
+                Instruction::PopImmutableContext if scope.immutables.len() > 1 => {
+                    scope.immutables.pop();
+                }
+                Instruction::ClearImmutableContext => {
+                    if let Some(current) = scope.immutables.last_mut() {
+                        current.clear();
+                    }
+                }
+                Instruction::NewStruct(name) if !self.globals.structs.contains(name) => {
+                    self.report(scope, "unknown-struct", format!("unknown struct `{name}`"));
+                }
+                Instruction::ExtendStruct(name, parent)
+                    if !self.globals.structs.contains(parent) =>
+                {
+                    self.report(
+                        scope,
+                        "unknown-struct",
+                        format!("struct `{name}` extends unknown struct `{parent}`"),
+                    );
+                }
+                Instruction::NewVariant(enum_name, variant) => {
+                    match self.globals.enums.get(enum_name) {
+                        None => self.report(
+                            scope,
+                            "unknown-enum",
+                            format!("unknown enum `{enum_name}`"),
+                        ),
+                        Some(variants) if !variants.contains(variant) => self.report(
+                            scope,
+                            "unknown-variant",
+                            format!("enum `{enum_name}` has no variant `{variant}`"),
+                        ),
+                        Some(_) => {}
+                    }
+                }
+                Instruction::StoreFunction(name, params, body, _) => {
+                    self.check_function(name, params, body);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn check_reactive(&mut self, expr: &'a ReactiveExpr, scope: &mut Scope<'a>) {
+        for name in &expr.captures {
+            self.check_name(name, scope);
+        }
+        // Reactive code runs later against the same scope, plus any
+        // temporaries it stores itself (string literals).
+        let mut inner = Scope {
+            function: scope.function,
+            body: None,
+            pc: 0,
+            locals: scope.locals.clone(),
+            immutables: vec![HashSet::new()],
+        };
+        collect_locals(&expr.code, &mut inner.locals);
+        self.check_body(&expr.code, &mut inner);
+    }
+
+    fn check_name(&mut self, name: &str, scope: &Scope<'a>) {
+        if !scope.locals.contains(name) && !self.globals.is_bound(name) {
+            self.report(
+                scope,
+                "unknown-variable",
+                format!("unknown variable `{name}`"),
+            );
+        }
+    }
+
+    fn check_call(&mut self, name: &str, argc: usize, scope: &Scope<'a>) {
+        // Calls resolve through the global environment only.
+        if !self.globals.is_bound(name) {
+            self.report(
+                scope,
+                "undefined-function",
+                format!("call to undefined function `{name}`"),
+            );
+            return;
+        }
+        if let Some(&arity) = self.globals.functions.get(name)
+            && arity != argc
+        {
+            self.report(
+                scope,
+                "arity-mismatch",
+                format!("`{name}` takes {arity} argument(s) but is called with {argc}"),
+            );
+        }
+    }
+
+    fn check_mutable_store(&mut self, name: &str, scope: &Scope<'a>) {
+        // Inside functions assignments create locals, so only top-level
+        // immutables are protected (mirrors `ensure_mutable_binding`).
+        if scope.function.is_none() && scope.immutables.iter().any(|s| s.contains(name)) {
+            self.report(
+                scope,
+                "immutable-assigned",
+                format!("cannot assign to immutable variable `{name}`"),
+            );
+        }
+    }
+
+    fn report(&mut self, scope: &Scope<'a>, code: &'static str, message: String) {
+        let loc = self
+            .debug
+            .as_ref()
+            .zip(scope.body)
+            .and_then(|((info, _), body)| info.loc(body, scope.pc));
+        let diagnostic = Diagnostic {
+            function: scope.function.map(str::to_string),
+            code,
+            message,
+            loc,
+        };
+        if !self.diagnostics.contains(&diagnostic) {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+}
+
+fn collect_locals<'a>(code: &'a [Instruction], out: &mut HashSet<&'a str>) {
+    for instr in code {
+        match instr {
+            Instruction::Store(name)
+            | Instruction::StoreImmutable(name)
+            | Instruction::StoreReactive(name, _) => {
+                out.insert(name);
+            }
+            _ => {}
+        }
+    }
+}
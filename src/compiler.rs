@@ -0,0 +1,728 @@
+use crate::debug_info::{DebugBody, DebugInfo, SourceLoc};
+use crate::grammar::{
+    AST, CompiledStructFieldInit, Instruction, Operator, ReactiveExpr, StructFieldInit, Visibility,
+};
+use crate::parser::{parse, parse_spanned};
+use crate::tokenizer::{tokenize, tokenize_spanned};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Native reference implementation of the self-hosted compiler
+/// (`project/bootstrap/stable/compiler.rx`). For the same source it emits the
+/// same instructions, labels and temporaries, so the two can be diffed. The
+/// exceptions are unary minus and `!` (`Neg`, `Not`) and else-if chains on
+/// one variable (`Switch`).
+///
+/// `import a.b` reads `<root>/a/b.rx`; the CLI uses `project` like the
+/// bootstrapped compilers. Directories added with `with_search_paths` are
+/// tried first.
+pub struct Compiler {
+    root: PathBuf,
+    search_paths: Vec<PathBuf>,
+    imported_modules: HashSet<String>,
+    // source locations, when requested with `with_debug_info`
+    debug: Option<DebugInfo>,
+    // index in `debug.files` of the file being compiled
+    file: usize,
+    // contents used instead of reading these paths, set with `with_source`
+    sources: HashMap<PathBuf, String>,
+    // `with_separate_imports`: imports reached, in order, left uncompiled
+    separate_imports: Option<Vec<Vec<String>>>,
+}
+
+impl Compiler {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            search_paths: Vec::new(),
+            imported_modules: HashSet::new(),
+            debug: None,
+            file: 0,
+            sources: HashMap::new(),
+            separate_imports: None,
+        }
+    }
+
+    /// Directories to look for imported modules in, in order, before the
+    /// root (a project manifest's `build.paths`).
+    pub fn with_search_paths(mut self, paths: Vec<PathBuf>) -> Self {
+        self.search_paths = paths;
+        self
+    }
+
+    /// Emits `Import` for each import without compiling the module into the
+    /// output; collect the paths with `take_imports` and compile each module
+    /// on its own (as `reactive pack` does for archives).
+    pub fn with_separate_imports(mut self) -> Self {
+        self.separate_imports = Some(Vec::new());
+        self
+    }
+
+    /// Modules imported since the last call, in the order first reached,
+    /// under `with_separate_imports`.
+    pub fn take_imports(&mut self) -> Vec<Vec<String>> {
+        self.separate_imports
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Compiles `text` wherever `path` is read (as the input or an import),
+    /// for sources that are not saved, such as editor buffers.
+    pub fn with_source(mut self, path: impl Into<PathBuf>, text: String) -> Self {
+        self.sources.insert(path.into(), text);
+        self
+    }
+
+    /// Also records the source line of every emitted instruction; collect
+    /// it with `take_debug_info` after compiling. Errors then carry
+    /// `file:line:column` positions. The bytecode is the same either way.
+    pub fn with_debug_info(mut self) -> Self {
+        self.debug = Some(DebugInfo::default());
+        self
+    }
+
+    pub fn take_debug_info(&mut self) -> Option<DebugInfo> {
+        self.debug.take()
+    }
+
+    /// Compiles a program; it must define `main`, which is called last.
+    pub fn compile_file(&mut self, path: &Path) -> Result<Vec<Instruction>, String> {
+        let ast = self.parse_file(path)?;
+        let mut body = Body::default();
+        self.compile(ast, &mut body)?;
+        self.record_body("<top-level>", &body);
+        Ok(body.code)
+    }
+
+    /// Compiles a module: top-level items only, no `main` required.
+    pub fn compile_module_file(&mut self, path: &Path) -> Result<Vec<Instruction>, String> {
+        let ast = self.parse_file(path)?;
+        let mut body = Body::default();
+        self.compile_module(ast, &mut body)?;
+        self.record_body("<top-level>", &body);
+        Ok(body.code)
+    }
+
+    /// Reads and parses `path`, making it the current file for debug info.
+    fn parse_file(&mut self, path: &Path) -> Result<AST, String> {
+        let source = match self.sources.get(path) {
+            Some(text) => text.clone(),
+            None => std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read `{}`: {e}", path.display()))?,
+        };
+        let Some(debug) = &mut self.debug else {
+            return parse(tokenize(&source)?);
+        };
+
+        debug.files.push(path.to_path_buf());
+        self.file = debug.files.len() - 1;
+        tokenize_spanned(&source)
+            .and_then(parse_spanned)
+            .map_err(|e| format!("{}:{e}", path.display()))
+    }
+
+    fn record_body(&mut self, name: &str, body: &Body) {
+        if let Some(debug) = &mut self.debug {
+            debug.bodies.push(DebugBody {
+                name: name.to_string(),
+                code: body.code.clone(),
+                locs: body.locs.clone(),
+                names: HashMap::new(),
+            });
+        }
+    }
+
+    fn compile_module(&mut self, ast: AST, body: &mut Body) -> Result<(), String> {
+        match ast {
+            AST::Program(items) => {
+                for item in items {
+                    self.compile(item, body)?;
+                }
+                Ok(())
+            }
+            other => self.compile(other, body),
+        }
+    }
+
+    fn compile(&mut self, ast: AST, body: &mut Body) -> Result<(), String> {
+        match ast {
+            // ---------- literals ----------
+            AST::Number(n) => body.emit(Instruction::Push(n)),
+            AST::Char(c) => body.emit(Instruction::PushChar(c)),
+            AST::StringLiteral(s) => body.string_literal(&s),
+            AST::Var(name) => body.emit(Instruction::Load(name)),
+
+            // ---------- expressions ----------
+            AST::ArrayNew(size) => {
+                self.compile(*size, body)?;
+                body.emit(Instruction::ArrayNew);
+            }
+            AST::Index(base, index) => {
+                self.compile(*base, body)?;
+                self.compile(*index, body)?;
+                body.emit(Instruction::ArrayGet);
+            }
+            AST::FieldAccess(base, field) => {
+                self.compile(*base, body)?;
+                body.emit(Instruction::FieldGet(field));
+            }
+            AST::Operation(l, op, r) => {
+                self.compile(*l, body)?;
+                self.compile(*r, body)?;
+                body.emit(operator_instruction(op));
+            }
+            AST::Neg(expr) => {
+                self.compile(*expr, body)?;
+                body.emit(Instruction::Neg);
+            }
+            AST::Not(expr) => {
+                self.compile(*expr, body)?;
+                body.emit(Instruction::Not);
+            }
+            AST::Ternary {
+                cond,
+                then_expr,
+                else_expr,
+            } => {
+                self.compile(*cond, body)?;
+
+                let else_lbl = body.fresh("ternary_else");
+                let end_lbl = body.fresh("ternary_end");
+
+                body.emit(Instruction::JumpIfZero(else_lbl.clone()));
+                self.compile(*then_expr, body)?;
+                body.emit(Instruction::Jump(end_lbl.clone()));
+
+                body.emit(Instruction::Label(else_lbl));
+                self.compile(*else_expr, body)?;
+
+                body.emit(Instruction::Label(end_lbl));
+            }
+            AST::Call { name, args } => {
+                let argc = args.len();
+                for arg in args {
+                    self.compile(arg, body)?;
+                }
+                body.emit(Instruction::Call(name, argc));
+            }
+            AST::Cast { target, expr } => {
+                self.compile(*expr, body)?;
+                body.emit(Instruction::Cast(target));
+            }
+
+            // ---------- assignments ----------
+            AST::Assign(name, expr) => {
+                self.compile(*expr, body)?;
+                body.emit(Instruction::Store(name));
+            }
+            AST::ImmutableAssign(name, expr) => {
+                self.compile(*expr, body)?;
+                body.emit(Instruction::StoreImmutable(name));
+            }
+            AST::ReactiveAssign(name, expr) => {
+                let reactive = self.compile_reactive_expr(*expr, body.loc)?;
+                body.emit(Instruction::StoreReactive(name, reactive));
+            }
+            AST::AssignTarget(target, value) => {
+                self.compile_lvalue(*target, body)?;
+                self.compile(*value, body)?;
+                body.emit(Instruction::StoreThrough);
+            }
+            AST::ReactiveAssignTarget(target, value) => {
+                self.compile_lvalue(*target, body)?;
+                let reactive = self.compile_reactive_expr(*value, body.loc)?;
+                body.emit(Instruction::StoreThroughReactive(reactive));
+            }
+            AST::ImmutableAssignTarget(target, value) => {
+                self.compile_lvalue(*target, body)?;
+                self.compile(*value, body)?;
+                body.emit(Instruction::StoreThroughImmutable);
+            }
+
+            // ---------- control ----------
+            AST::IfElse(cond, then_block, else_block)
+                if switch_chain_len(&cond, &else_block) >= SWITCH_MIN_CASES =>
+            {
+                self.compile_switch(*cond, then_block, else_block, body)?;
+            }
+            AST::IfElse(cond, then_block, else_block) => {
+                self.compile(*cond, body)?;
+
+                let else_lbl = body.fresh("else");
+                let end_lbl = body.fresh("ifend");
+
+                body.emit(Instruction::JumpIfZero(else_lbl.clone()));
+
+                body.emit(Instruction::PushImmutableContext);
+                self.compile_block(then_block, body)?;
+                body.emit(Instruction::PopImmutableContext);
+
+                body.emit(Instruction::Jump(end_lbl.clone()));
+                body.emit(Instruction::Label(else_lbl));
+
+                body.emit(Instruction::PushImmutableContext);
+                self.compile_block(else_block, body)?;
+                body.emit(Instruction::PopImmutableContext);
+
+                body.emit(Instruction::Label(end_lbl));
+            }
+            AST::Loop(stmts) => {
+                let start = body.fresh("loop_start");
+                let end = body.fresh("loop_end");
+                body.break_stack.push(end.clone());
+                body.continue_stack.push(start.clone());
+
+                body.emit(Instruction::PushImmutableContext);
+                body.emit(Instruction::Label(start.clone()));
+                body.emit(Instruction::ClearImmutableContext);
+
+                self.compile_block(stmts, body)?;
+
+                body.emit(Instruction::Jump(start));
+                body.emit(Instruction::Label(end));
+                body.emit(Instruction::PopImmutableContext);
+
+                body.break_stack.pop();
+                body.continue_stack.pop();
+            }
+            AST::Break => {
+                let target = body
+                    .break_stack
+                    .last()
+                    .cloned()
+                    .ok_or("compiler: break used outside of loop")?;
+                body.emit(Instruction::Jump(target));
+            }
+            AST::Continue => {
+                let target = body
+                    .continue_stack
+                    .last()
+                    .cloned()
+                    .ok_or("compiler: continue used outside of loop")?;
+                body.emit(Instruction::Jump(target));
+            }
+            AST::Return(expr) => {
+                match expr {
+                    Some(e) => self.compile(*e, body)?,
+                    None => body.emit(Instruction::Push(0)),
+                }
+                body.emit(Instruction::Return);
+            }
+
+            // ---------- definitions ----------
+            AST::FuncDef {
+                name,
+                params,
+                body: stmts,
+            } => {
+                let mut func = Body {
+                    loc: body.loc,
+                    ..Body::default()
+                };
+                self.compile_block(stmts, &mut func)?;
+                func.emit(Instruction::Return);
+                self.record_body(&name, &func);
+                body.emit(Instruction::StoreFunction(
+                    name,
+                    params,
+                    func.code,
+                    Visibility::Public,
+                ));
+            }
+            AST::StructDef { name, fields } => {
+                let fields = self.compile_struct_fields(fields, body.loc)?;
+                body.emit(Instruction::StoreStruct(name, fields, Visibility::Public));
+            }
+            AST::StructNew(name) => body.emit(Instruction::NewStruct(name)),
+            AST::Import(path) => self.compile_import(path, body)?,
+            AST::Located(line, stmt) => {
+                body.loc = Some(SourceLoc {
+                    file: self.file,
+                    line,
+                });
+                self.compile(*stmt, body)?;
+            }
+
+            AST::Program(items) => {
+                let has_main = items.iter().any(|item| {
+                    let item = match item {
+                        AST::Located(_, item) => item,
+                        item => item,
+                    };
+                    matches!(item, AST::FuncDef { name, .. } if name == "main")
+                });
+                for item in items {
+                    self.compile(item, body)?;
+                }
+                if !has_main {
+                    return Err("no `main` function defined".to_string());
+                }
+                body.emit(Instruction::Call("main".to_string(), 0));
+                body.emit(Instruction::Return);
+            }
+
+            // ---------- io ----------
+            AST::Print(e) => {
+                self.compile(*e, body)?;
+                body.emit(Instruction::Print);
+            }
+            AST::Println(e) => {
+                self.compile(*e, body)?;
+                body.emit(Instruction::Println);
+            }
+            AST::Assert(e) => {
+                self.compile(*e, body)?;
+                body.emit(Instruction::Assert);
+            }
+            AST::Error(message) => body.emit(Instruction::Error(message)),
+        }
+        Ok(())
+    }
+
+    fn compile_block(&mut self, stmts: Vec<AST>, body: &mut Body) -> Result<(), String> {
+        for stmt in stmts {
+            self.compile(stmt, body)?;
+        }
+        Ok(())
+    }
+
+    /// An else-if chain testing one variable against int literals, as a
+    /// single `Switch` on the variable. Each arm keeps its own immutable
+    /// context, and the final else (or nothing) is the default.
+    fn compile_switch(
+        &mut self,
+        cond: AST,
+        then_block: Vec<AST>,
+        else_block: Vec<AST>,
+        body: &mut Body,
+    ) -> Result<(), String> {
+        let (name, value) = switch_case(&cond).ok_or("compiler: not a switch case")?;
+        let name = name.to_string();
+        let mut arms = vec![(value, then_block)];
+        let mut default = else_block;
+        while let Some((value, then_block, else_block)) = take_switch_case(&name, &mut default) {
+            arms.push((value, then_block));
+            default = else_block;
+        }
+
+        let labels: Vec<String> = arms.iter().map(|_| body.fresh("case")).collect();
+        let default_lbl = body.fresh("switch_default");
+        let end_lbl = body.fresh("switch_end");
+
+        body.emit(Instruction::Load(name));
+        let cases = arms.iter().map(|(value, _)| *value).zip(labels.clone());
+        body.emit(Instruction::Switch(cases.collect(), default_lbl.clone()));
+
+        for ((_, block), label) in arms.into_iter().zip(labels) {
+            body.emit(Instruction::Label(label));
+            body.emit(Instruction::PushImmutableContext);
+            self.compile_block(block, body)?;
+            body.emit(Instruction::PopImmutableContext);
+            body.emit(Instruction::Jump(end_lbl.clone()));
+        }
+
+        body.emit(Instruction::Label(default_lbl));
+        body.emit(Instruction::PushImmutableContext);
+        self.compile_block(default, body)?;
+        body.emit(Instruction::PopImmutableContext);
+
+        body.emit(Instruction::Label(end_lbl));
+        Ok(())
+    }
+
+    fn compile_lvalue(&mut self, ast: AST, body: &mut Body) -> Result<(), String> {
+        match ast {
+            AST::Var(name) => body.emit(Instruction::Load(name)),
+            AST::Index(base, index) => {
+                self.compile_lvalue(*base, body)?;
+                self.compile(*index, body)?;
+                body.emit(Instruction::ArrayLValue);
+            }
+            AST::FieldAccess(base, field) => {
+                self.compile_lvalue(*base, body)?;
+                body.emit(Instruction::FieldLValue(field));
+            }
+            _ => return Err("compiler: invalid assignment target".to_string()),
+        }
+        Ok(())
+    }
+
+    fn compile_struct_fields(
+        &mut self,
+        fields: Vec<(String, Option<StructFieldInit>)>,
+        loc: Option<SourceLoc>,
+    ) -> Result<Vec<(String, Option<CompiledStructFieldInit>)>, String> {
+        fields
+            .into_iter()
+            .map(|(name, init)| {
+                let init = match init {
+                    Some(StructFieldInit::Mutable(ast)) => Some(CompiledStructFieldInit::Mutable(
+                        self.compile_expr(ast, loc)?,
+                    )),
+                    Some(StructFieldInit::Immutable(ast)) => Some(
+                        CompiledStructFieldInit::Immutable(self.compile_expr(ast, loc)?),
+                    ),
+                    Some(StructFieldInit::Reactive(ast)) => Some(
+                        CompiledStructFieldInit::Reactive(self.compile_reactive_expr(ast, loc)?),
+                    ),
+                    None => None,
+                };
+                Ok((name, init))
+            })
+            .collect()
+    }
+
+    /// Standalone code for one expression, ending in `Return`. `loc` is
+    /// where the expression appears.
+    fn compile_expr(
+        &mut self,
+        ast: AST,
+        loc: Option<SourceLoc>,
+    ) -> Result<Vec<Instruction>, String> {
+        let mut body = Body {
+            loc,
+            ..Body::default()
+        };
+        self.compile(ast, &mut body)?;
+        body.emit(Instruction::Return);
+        self.record_body("<expression>", &body);
+        Ok(body.code)
+    }
+
+    fn compile_reactive_expr(
+        &mut self,
+        ast: AST,
+        loc: Option<SourceLoc>,
+    ) -> Result<ReactiveExpr, String> {
+        let mut names = BTreeSet::new();
+        collect_free_vars(&ast, &mut names);
+        let code = self.compile_expr(ast, loc)?;
+        Ok(ReactiveExpr {
+            code,
+            captures: names.into_iter().collect(),
+        })
+    }
+
+    fn compile_import(&mut self, path: Vec<String>, body: &mut Body) -> Result<(), String> {
+        body.emit(Instruction::Import(path.clone()));
+
+        if !self.imported_modules.insert(path.join(".")) {
+            return Ok(());
+        }
+        if let Some(imports) = &mut self.separate_imports {
+            imports.push(path);
+            return Ok(());
+        }
+
+        let file_path = self.module_path(&path);
+        let (file, loc) = (self.file, body.loc);
+        let ast = self.parse_file(&file_path)?;
+        self.compile_module(ast, body)?;
+        self.file = file;
+        body.loc = loc;
+        Ok(())
+    }
+
+    /// Where `import a.b` is read from: the first search path holding
+    /// `a/b.rx`, else the root.
+    pub fn module_path(&self, path: &[String]) -> PathBuf {
+        let mut relative = PathBuf::from(path.join("/"));
+        relative.set_extension("rx");
+        self.search_paths
+            .iter()
+            .map(|dir| dir.join(&relative))
+            .find(|file| file.is_file() || self.sources.contains_key(file))
+            .unwrap_or_else(|| self.root.join(relative))
+    }
+}
+
+/// Instructions being emitted for one function body, expression or the
+/// top level, with its own label counter and loop targets.
+#[derive(Default)]
+struct Body {
+    code: Vec<Instruction>,
+    labels: usize,
+    break_stack: Vec<String>,
+    continue_stack: Vec<String>,
+    // statement being compiled, and the location of each emitted instruction
+    loc: Option<SourceLoc>,
+    locs: Vec<Option<SourceLoc>>,
+}
+
+impl Body {
+    fn emit(&mut self, instr: Instruction) {
+        self.code.push(instr);
+        self.locs.push(self.loc);
+    }
+
+    fn fresh(&mut self, prefix: &str) -> String {
+        let label = format!("{prefix}_{}", self.labels);
+        self.labels += 1;
+        label
+    }
+
+    /// Strings are char arrays built element by element in a temporary.
+    fn string_literal(&mut self, s: &str) {
+        self.emit(Instruction::Push(s.chars().count() as i64));
+        self.emit(Instruction::ArrayNew);
+
+        let tmp = self.fresh("__strlit");
+        self.emit(Instruction::Store(tmp.clone()));
+
+        for (i, ch) in s.chars().enumerate() {
+            self.emit(Instruction::Load(tmp.clone()));
+            self.emit(Instruction::Push(i as i64));
+            self.emit(Instruction::ArrayLValue);
+            self.emit(Instruction::PushChar(ch as u32));
+            self.emit(Instruction::StoreThrough);
+        }
+
+        self.emit(Instruction::Load(tmp));
+    }
+}
+
+fn operator_instruction(op: Operator) -> Instruction {
+    match op {
+        Operator::Addition => Instruction::Add,
+        Operator::Subtraction => Instruction::Sub,
+        Operator::Multiplication => Instruction::Mul,
+        Operator::Division => Instruction::Div,
+        Operator::Modulo => Instruction::Modulo,
+        Operator::Greater => Instruction::Greater,
+        Operator::Less => Instruction::Less,
+        Operator::GreaterEqual => Instruction::GreaterEqual,
+        Operator::LessEqual => Instruction::LessEqual,
+        Operator::Equal => Instruction::Equal,
+        Operator::NotEqual => Instruction::NotEqual,
+        Operator::And => Instruction::And,
+        Operator::Or => Instruction::Or,
+    }
+}
+
+/// Else-if chains with at least this many cases compile to a `Switch`.
+const SWITCH_MIN_CASES: usize = 3;
+
+/// The variable and value tested by `x == 3` (or `x == -3`).
+fn switch_case(cond: &AST) -> Option<(&str, i64)> {
+    let AST::Operation(l, Operator::Equal, r) = cond else {
+        return None;
+    };
+    let AST::Var(name) = l.as_ref() else {
+        return None;
+    };
+    let value = match r.as_ref() {
+        AST::Number(n) => *n,
+        AST::Neg(inner) => match inner.as_ref() {
+            AST::Number(n) => n.checked_neg()?,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some((name, value))
+}
+
+/// The `if` an else block holds, when it holds nothing else.
+fn else_if(block: &[AST]) -> Option<&AST> {
+    let stmt = match block {
+        [AST::Located(_, stmt)] => stmt.as_ref(),
+        [stmt] => stmt,
+        _ => return None,
+    };
+    matches!(stmt, AST::IfElse(..)).then_some(stmt)
+}
+
+/// How many cases of an else-if chain in a row test the same variable as
+/// `cond` against an int literal.
+fn switch_chain_len(cond: &AST, else_block: &[AST]) -> usize {
+    let Some((name, _)) = switch_case(cond) else {
+        return 0;
+    };
+    let mut len = 1;
+    let mut block = else_block;
+    while let Some(AST::IfElse(cond, _, next)) = else_if(block)
+        && switch_case(cond).is_some_and(|(other, _)| other == name)
+    {
+        len += 1;
+        block = next;
+    }
+    len
+}
+
+/// Takes the else-if out of `block` when it tests `name` against an int
+/// literal, returning its value, then block and else block.
+fn take_switch_case(name: &str, block: &mut Vec<AST>) -> Option<(i64, Vec<AST>, Vec<AST>)> {
+    let AST::IfElse(cond, ..) = else_if(block)? else {
+        return None;
+    };
+    let value = match switch_case(cond)? {
+        (other, value) if other == name => value,
+        _ => return None,
+    };
+    let stmt = match block.pop()? {
+        AST::Located(_, stmt) => *stmt,
+        stmt => stmt,
+    };
+    let AST::IfElse(_, then_block, else_block) = stmt else {
+        return None;
+    };
+    Some((value, then_block, else_block))
+}
+
+/// Names a reactive expression reads; they are captured when it is bound.
+fn collect_free_vars(ast: &AST, out: &mut BTreeSet<String>) {
+    match ast {
+        AST::Var(name) => {
+            out.insert(name.clone());
+        }
+        AST::Operation(l, _, r) | AST::Index(l, r) => {
+            collect_free_vars(l, out);
+            collect_free_vars(r, out);
+        }
+        AST::FieldAccess(base, _) | AST::Located(_, base) => collect_free_vars(base, out),
+        AST::Ternary {
+            cond,
+            then_expr,
+            else_expr,
+        } => {
+            collect_free_vars(cond, out);
+            collect_free_vars(then_expr, out);
+            collect_free_vars(else_expr, out);
+        }
+        AST::Call { args, .. } => {
+            for arg in args {
+                collect_free_vars(arg, out);
+            }
+        }
+        AST::ArrayNew(expr)
+        | AST::Neg(expr)
+        | AST::Not(expr)
+        | AST::Assign(_, expr)
+        | AST::ImmutableAssign(_, expr)
+        | AST::ReactiveAssign(_, expr)
+        | AST::ImmutableAssignTarget(_, expr)
+        | AST::Cast { expr, .. }
+        | AST::Assert(expr) => collect_free_vars(expr, out),
+        AST::AssignTarget(target, value) | AST::ReactiveAssignTarget(target, value) => {
+            collect_free_vars(target, out);
+            collect_free_vars(value, out);
+        }
+
+        AST::Number(_)
+        | AST::Char(_)
+        | AST::StringLiteral(_)
+        | AST::Program(_)
+        | AST::IfElse(_, _, _)
+        | AST::Loop(_)
+        | AST::Break
+        | AST::Continue
+        | AST::Return(_)
+        | AST::Print(_)
+        | AST::Println(_)
+        | AST::Error(_)
+        | AST::FuncDef { .. }
+        | AST::StructDef { .. }
+        | AST::StructNew(_)
+        | AST::Import(_) => {}
+    }
+}
@@ -0,0 +1,94 @@
+//! TOML and INI reading for the `std.config` natives. Both produce a tree
+//! of tables: TOML with the subset manifests use (`manifest::parse_toml`),
+//! INI with one table per `[section]` and string values.
+
+use crate::manifest::{Value, parse_toml};
+use std::collections::HashMap;
+
+/// Parses TOML text into nested tables: `[build]` then `paths = [...]`
+/// gives a `build` table holding `paths`.
+pub(crate) fn parse_toml_tree(text: &str) -> Result<HashMap<String, Value>, String> {
+    let mut entries: Vec<(String, Value)> = parse_toml(text)?.into_iter().collect();
+    // sorted, so a value set both as `a` and in `[a]` is reported the same
+    // way every time
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut root = HashMap::new();
+    for (key, value) in entries {
+        let path: Vec<&str> = key.split('.').collect();
+        insert(&mut root, &path, value)?;
+    }
+    Ok(root)
+}
+
+fn insert(table: &mut HashMap<String, Value>, path: &[&str], value: Value) -> Result<(), String> {
+    let (name, rest) = path.split_first().expect("keys are not empty");
+    if rest.is_empty() {
+        table.insert(name.to_string(), value);
+        return Ok(());
+    }
+    let entry = table
+        .entry(name.to_string())
+        .or_insert_with(|| Value::Table(HashMap::new()));
+    match entry {
+        Value::Table(inner) => insert(inner, rest, value),
+        _ => Err(format!("`{name}` is both a value and a table")),
+    }
+}
+
+/// Parses INI text: `[section]` headers, `key = value` or `key: value`
+/// lines, and `;` or `#` comment lines. Values are strings, with one pair of
+/// surrounding double quotes removed. Keys before the first section are at
+/// the top level; a key set twice keeps the last value.
+pub(crate) fn parse_ini(text: &str) -> Result<HashMap<String, Value>, String> {
+    let mut root = HashMap::new();
+    let mut section: Option<String> = None;
+    for (i, raw) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .ok_or_else(|| format!("line {line_no}: invalid section header"))?;
+            match root
+                .entry(name.to_string())
+                .or_insert_with(|| Value::Table(HashMap::new()))
+            {
+                Value::Table(_) => section = Some(name.to_string()),
+                _ => {
+                    return Err(format!(
+                        "line {line_no}: `{name}` is both a key and a section"
+                    ));
+                }
+            }
+            continue;
+        }
+
+        let split = line
+            .find(['=', ':'])
+            .ok_or_else(|| format!("line {line_no}: expected `key = value`"))?;
+        let key = line[..split].trim();
+        if key.is_empty() {
+            return Err(format!("line {line_no}: missing key"));
+        }
+        let value = line[split + 1..].trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+
+        let table = match &section {
+            Some(name) => match root.get_mut(name) {
+                Some(Value::Table(table)) => table,
+                _ => unreachable!("sections are tables"),
+            },
+            None => &mut root,
+        };
+        table.insert(key.to_string(), Value::Str(value.to_string()));
+    }
+    Ok(root)
+}
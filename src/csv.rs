@@ -0,0 +1,85 @@
+//! RFC 4180 style CSV reading and writing used by the `std.csv` natives.
+
+/// Split CSV text into rows of fields.
+///
+/// Fields may be quoted with `"`; inside quotes, `""` is a literal quote and
+/// commas and line breaks are kept. Rows end at `\n` or `\r\n`, and a trailing
+/// line break does not produce an empty final row.
+pub fn parse(input: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut chars = input.chars().peekable();
+    let mut line = 1;
+    let mut row_started = false;
+
+    while let Some(ch) = chars.next() {
+        row_started = true;
+        match ch {
+            '"' if field.is_empty() => {
+                let start = line;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                        None => return Err(format!("unterminated quoted field on line {start}")),
+                    }
+                }
+                match chars.peek() {
+                    None | Some(',') | Some('\n') | Some('\r') => {}
+                    Some(c) => {
+                        return Err(format!(
+                            "unexpected `{c}` after closing quote on line {line}"
+                        ));
+                    }
+                }
+            }
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+                row_started = false;
+                line += 1;
+            }
+            c => field.push(c),
+        }
+    }
+
+    if row_started {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Serialize rows as CSV, quoting fields that contain `,`, `"` or line breaks.
+/// Every row, including the last, ends with `\n`.
+pub fn write(rows: &[Vec<String>]) -> String {
+    let mut out = String::new();
+    for row in rows {
+        for (i, field) in row.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            if field.contains([',', '"', '\n', '\r']) {
+                out.push('"');
+                out.push_str(&field.replace('"', "\"\""));
+                out.push('"');
+            } else {
+                out.push_str(field);
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,657 @@
+use crate::bytecode::read_bytecode_file;
+use crate::compiler::Compiler;
+use crate::debug_info::{BodyMatcher, DebugInfo, SourceLoc};
+use crate::grammar::{Instruction, Type};
+use crate::json::{Json, read_framed, write_framed};
+use crate::vm::debug::Debugger;
+use crate::vm::{RuntimeError, VM, VmOptions};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufReader, Write};
+use std::panic::{AssertUnwindSafe, catch_unwind, resume_unwind};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+
+/// The VM is single threaded; tasks are not reported separately.
+const THREAD_ID: i64 = 1;
+/// Instructions between checks for requests (`pause`) while running.
+const POLL_EVERY: u32 = 4096;
+/// Longest value shown in the variables view, in characters.
+const VALUE_LIMIT: usize = 200;
+
+/// Serves the Debug Adapter Protocol on stdin/stdout (`reactive dap`) until
+/// the client disconnects. One `launch` per session: the program is
+/// compiled in-process with debug info and run on this thread, pausing in
+/// `before_instruction`; program output is forwarded as `output` events.
+pub fn serve() {
+    let (sender, messages) = mpsc::channel();
+    std::thread::spawn(move || read_messages(sender));
+    let session = Rc::new(RefCell::new(Session::new(messages)));
+
+    // configuration: initialize, launch, setBreakpoints, configurationDone
+    let code = loop {
+        let Ok(request) = session.borrow().messages.recv() else {
+            return;
+        };
+        let mut session = session.borrow_mut();
+        if let Control::Disconnect = session.handle(None, &request) {
+            return;
+        }
+        if session.configured
+            && let Some(code) = session.code.take()
+        {
+            break code;
+        }
+    };
+
+    let options = VmOptions {
+        unwind_on_error: true,
+        ..VmOptions::default()
+    };
+    let mut vm = VM::with_options(code, options);
+    vm.set_debug_info(session.borrow().info.clone());
+    let transport = session.borrow().transport.clone();
+    vm.set_stdout_sink(Box::new(OutputSink {
+        transport: transport.clone(),
+        category: "stdout",
+    }));
+    vm.set_stderr_sink(Box::new(OutputSink {
+        transport: transport.clone(),
+        category: "stderr",
+    }));
+    vm.attach_debugger(Box::new(Hook(session.clone())));
+
+    let exit_code = match catch_unwind(AssertUnwindSafe(|| vm.run())) {
+        Ok(()) => 0_i64,
+        Err(payload) => match payload.downcast::<RuntimeError>() {
+            Ok(error) => {
+                // The VM is left as it was at the error, so it can be inspected.
+                session
+                    .borrow_mut()
+                    .stop(&mut vm, "exception", Some(&error.message));
+                1
+            }
+            Err(payload) => resume_unwind(payload),
+        },
+    };
+    transport.event(
+        "exited",
+        Json::object([("exitCode", Json::from(exit_code))]),
+    );
+    transport.event("terminated", Json::object::<&str>([]));
+
+    loop {
+        let Ok(request) = session.borrow().messages.recv() else {
+            return;
+        };
+        if let Control::Disconnect = session.borrow_mut().handle(None, &request) {
+            return;
+        }
+    }
+}
+
+/// Reads framed requests from stdin until it closes.
+fn read_messages(sender: Sender<Json>) {
+    let mut input = BufReader::new(std::io::stdin().lock());
+    while let Some(message) = read_framed(&mut input) {
+        match message {
+            Ok(message) => {
+                if sender.send(message).is_err() {
+                    return;
+                }
+            }
+            Err(e) => eprintln!("dap: {e}"),
+        }
+    }
+}
+
+/// Writes framed messages to stdout, numbering them in order.
+#[derive(Clone, Default)]
+struct Transport {
+    seq: Arc<Mutex<i64>>,
+}
+
+impl Transport {
+    fn send(&self, kind: &str, mut fields: Vec<(String, Json)>) {
+        let mut seq = self.seq.lock().unwrap();
+        *seq += 1;
+        fields.insert(0, ("seq".to_string(), Json::from(*seq)));
+        fields.insert(1, ("type".to_string(), Json::from(kind)));
+        let _ = write_framed(&mut std::io::stdout().lock(), &Json::Object(fields));
+    }
+
+    fn event(&self, event: &str, body: Json) {
+        let mut fields = vec![("event".to_string(), Json::from(event))];
+        if body != Json::Null {
+            fields.push(("body".to_string(), body));
+        }
+        self.send("event", fields);
+    }
+
+    fn respond(&self, request: &Json, result: Result<Json, String>) {
+        let field = |name: &str| request.get(name).cloned().unwrap_or(Json::Null);
+        let mut fields = vec![
+            ("request_seq".to_string(), field("seq")),
+            ("command".to_string(), field("command")),
+            ("success".to_string(), Json::from(result.is_ok())),
+        ];
+        match result {
+            Ok(Json::Null) => {}
+            Ok(body) => fields.push(("body".to_string(), body)),
+            Err(message) => fields.push(("message".to_string(), Json::from(message))),
+        }
+        self.send("response", fields);
+    }
+}
+
+/// Program output, forwarded as `output` events.
+struct OutputSink {
+    transport: Transport,
+    category: &'static str,
+}
+
+impl Write for OutputSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let output = String::from_utf8_lossy(buf).into_owned();
+        self.transport.event(
+            "output",
+            Json::object([
+                ("category", Json::from(self.category)),
+                ("output", Json::from(output)),
+            ]),
+        );
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What the caller of `Session::handle` does next.
+enum Control {
+    Wait,
+    Resume,
+    Disconnect,
+}
+
+/// When to stop next, besides breakpoints.
+#[derive(Clone, Copy)]
+enum Step {
+    Entry,
+    Pause,
+    In,
+    /// The next statement at `depth` calls or fewer, not on `from`.
+    Over {
+        depth: usize,
+        from: Option<SourceLoc>,
+    },
+    Out {
+        depth: usize,
+    },
+}
+
+/// What a `variablesReference` expands to; valid until the program resumes.
+enum VarRef {
+    Locals(usize),
+    Globals,
+    Value(Type),
+}
+
+struct Session {
+    transport: Transport,
+    messages: Receiver<Json>,
+    // compiled program, until it starts running
+    code: Option<Vec<Instruction>>,
+    configured: bool,
+    info: DebugInfo,
+    matcher: BodyMatcher,
+    // body running last time, keyed by the address and length of its code
+    current: Option<(usize, usize, Option<usize>)>,
+    // breakpoint lines as the client sent them, by source path
+    requested: HashMap<PathBuf, Vec<usize>>,
+    breakpoints: HashSet<SourceLoc>,
+    step: Option<Step>,
+    polled: u32,
+    refs: Vec<VarRef>,
+}
+
+impl Session {
+    fn new(messages: Receiver<Json>) -> Self {
+        let info = DebugInfo::default();
+        Self {
+            transport: Transport::default(),
+            messages,
+            code: None,
+            configured: false,
+            matcher: BodyMatcher::new(&info.bodies),
+            info,
+            current: None,
+            requested: HashMap::new(),
+            breakpoints: HashSet::new(),
+            step: None,
+            polled: 0,
+            refs: Vec::new(),
+        }
+    }
+
+    /// Answers one request; `vm` is set while the program is stopped.
+    fn handle(&mut self, vm: Option<&mut VM>, request: &Json) -> Control {
+        let null = Json::Null;
+        let args = request.get("arguments").unwrap_or(&null);
+        let command = request.get("command").and_then(Json::as_str).unwrap_or("");
+        let mut control = Control::Wait;
+
+        let result = match command {
+            "initialize" => Ok(Json::object([
+                ("supportsConfigurationDoneRequest", Json::from(true)),
+                ("supportsEvaluateForHovers", Json::from(true)),
+            ])),
+            "launch" => self.launch(args),
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "setExceptionBreakpoints" => Ok(Json::Null),
+            "configurationDone" => {
+                self.configured = true;
+                Ok(Json::Null)
+            }
+            "threads" => Ok(Json::object([(
+                "threads",
+                Json::from(vec![Json::object([
+                    ("id", Json::from(THREAD_ID)),
+                    ("name", Json::from("main")),
+                ])]),
+            )])),
+            "continue" => {
+                self.step = None;
+                control = Control::Resume;
+                Ok(Json::object([("allThreadsContinued", Json::from(true))]))
+            }
+            "pause" => {
+                self.step = Some(Step::Pause);
+                Ok(Json::Null)
+            }
+            "disconnect" | "terminate" => {
+                control = Control::Disconnect;
+                Ok(Json::Null)
+            }
+            "next" | "stepIn" | "stepOut" | "stackTrace" | "scopes" | "variables" | "evaluate" => {
+                match vm {
+                    Some(vm) => {
+                        if matches!(command, "next" | "stepIn" | "stepOut") {
+                            control = Control::Resume;
+                        }
+                        self.handle_stopped(command, vm, args)
+                    }
+                    None => Err("the program is not stopped".to_string()),
+                }
+            }
+            _ => Err(format!("unsupported request `{command}`")),
+        };
+
+        let launched = command == "launch" && result.is_ok();
+        self.transport.respond(request, result);
+        if launched {
+            self.transport.event("initialized", Json::Null);
+        }
+        control
+    }
+
+    fn handle_stopped(&mut self, command: &str, vm: &mut VM, args: &Json) -> Result<Json, String> {
+        let int_arg = |name: &str| args.get(name).and_then(Json::as_i64).unwrap_or(0);
+        match command {
+            "next" => {
+                let from = self.current_loc(vm);
+                self.step = Some(Step::Over {
+                    depth: vm.call_depth(),
+                    from,
+                });
+                Ok(Json::Null)
+            }
+            "stepIn" => {
+                self.step = Some(Step::In);
+                Ok(Json::Null)
+            }
+            "stepOut" => {
+                self.step = Some(Step::Out {
+                    depth: vm.call_depth(),
+                });
+                Ok(Json::Null)
+            }
+            "stackTrace" => Ok(self.stack_trace(vm)),
+            "scopes" => {
+                let frame = int_arg("frameId").max(0) as usize;
+                let locals = self.reference(VarRef::Locals(frame));
+                let globals = self.reference(VarRef::Globals);
+                let scope = |name: &str, reference: usize, expensive: bool| {
+                    Json::object([
+                        ("name", Json::from(name)),
+                        ("variablesReference", Json::from(reference)),
+                        ("expensive", Json::from(expensive)),
+                    ])
+                };
+                Ok(Json::object([(
+                    "scopes",
+                    Json::from(vec![
+                        scope("Locals", locals, false),
+                        scope("Globals", globals, true),
+                    ]),
+                )]))
+            }
+            "variables" => {
+                let entries = match self.refs.get((int_arg("variablesReference") - 1) as usize) {
+                    Some(VarRef::Locals(frame)) => self.frame_locals(vm, *frame),
+                    Some(VarRef::Globals) => vm.globals(),
+                    Some(VarRef::Value(v)) => vm.children(v.clone()),
+                    None => return Err("unknown variablesReference".to_string()),
+                };
+                let variables = entries
+                    .into_iter()
+                    .map(|(name, v)| {
+                        let (value, reference) = self.show(vm, v);
+                        Json::object([
+                            ("name", Json::from(name)),
+                            ("value", Json::from(value)),
+                            ("variablesReference", Json::from(reference)),
+                        ])
+                    })
+                    .collect();
+                Ok(Json::object([("variables", Json::Array(variables))]))
+            }
+            "evaluate" => {
+                let name = args
+                    .get("expression")
+                    .and_then(Json::as_str)
+                    .unwrap_or("")
+                    .trim();
+                let frame = int_arg("frameId").max(0) as usize;
+                let found = self
+                    .frame_locals(vm, frame)
+                    .into_iter()
+                    .chain(vm.globals())
+                    .find(|(local, _)| local == name);
+                let Some((_, v)) = found else {
+                    return Err(format!("`{name}` is not a variable in scope"));
+                };
+                let (result, reference) = self.show(vm, v);
+                Ok(Json::object([
+                    ("result", Json::from(result)),
+                    ("variablesReference", Json::from(reference)),
+                ]))
+            }
+            _ => unreachable!("not a stopped-state request: {command}"),
+        }
+    }
+
+    fn launch(&mut self, args: &Json) -> Result<Json, String> {
+        if self.code.is_some() || self.configured {
+            return Err("the program is already launched".to_string());
+        }
+        let Some(program) = args.get("program").and_then(Json::as_str) else {
+            return Err("launch needs a `program` (.rx or .rxb file)".to_string());
+        };
+        let root = args.get("root").and_then(Json::as_str).unwrap_or("project");
+
+        let program = Path::new(program);
+        let code = if program.extension().is_some_and(|e| e == "rxb") {
+            // stripped bytecode, with the debug info split off next to it
+            let (code, _) = read_bytecode_file(&program.to_string_lossy())?;
+            let split = program.with_extension("rxd");
+            self.info = DebugInfo::read_split_file(&split.to_string_lossy(), &code)?;
+            code
+        } else {
+            let mut compiler = Compiler::new(root).with_debug_info();
+            let code = compiler.compile_file(program)?;
+            self.info = compiler.take_debug_info().unwrap_or_default();
+            code
+        };
+        self.matcher = BodyMatcher::new(&self.info.bodies);
+        self.code = Some(code);
+        if args.get("stopOnEntry").and_then(Json::as_bool) == Some(true) {
+            self.step = Some(Step::Entry);
+        }
+        self.resolve_breakpoints();
+        Ok(Json::Null)
+    }
+
+    fn set_breakpoints(&mut self, args: &Json) -> Json {
+        let path = args
+            .get("source")
+            .and_then(|source| source.get("path"))
+            .and_then(Json::as_str)
+            .unwrap_or("");
+        let lines: Vec<usize> = args
+            .get("breakpoints")
+            .map(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|bp| bp.get("line").and_then(Json::as_i64))
+            .map(|line| line.max(0) as usize)
+            .collect();
+
+        let statements = self
+            .info
+            .file_index(Path::new(path))
+            .map(|file| self.info.statement_lines(file))
+            .unwrap_or_default();
+        let breakpoints = lines
+            .iter()
+            .map(|&line| match statements.iter().find(|&&s| s >= line) {
+                Some(&actual) => {
+                    Json::object([("verified", Json::from(true)), ("line", Json::from(actual))])
+                }
+                None => Json::object([
+                    ("verified", Json::from(false)),
+                    ("line", Json::from(line)),
+                    ("message", Json::from("no code on or after this line")),
+                ]),
+            })
+            .collect();
+
+        self.requested.insert(PathBuf::from(path), lines);
+        self.resolve_breakpoints();
+        Json::object([("breakpoints", Json::Array(breakpoints))])
+    }
+
+    /// Each requested line moves to the first statement at or after it.
+    fn resolve_breakpoints(&mut self) {
+        self.breakpoints.clear();
+        for (path, lines) in &self.requested {
+            let Some(file) = self.info.file_index(path) else {
+                continue;
+            };
+            let statements = self.info.statement_lines(file);
+            for &line in lines {
+                if let Some(&line) = statements.iter().find(|&&s| s >= line) {
+                    self.breakpoints.insert(SourceLoc { file, line });
+                }
+            }
+        }
+    }
+
+    fn stack_trace(&mut self, vm: &VM) -> Json {
+        let frames: Vec<Json> = vm
+            .frames()
+            .iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                let mut fields = vec![("id", Json::from(id)), ("name", Json::from(frame.function))];
+                let loc = (!frame.native)
+                    .then(|| {
+                        self.matcher
+                            .find(&self.info.bodies, frame.code, frame.function)
+                    })
+                    .flatten()
+                    .and_then(|body| self.info.loc(body, frame.pointer));
+                match loc {
+                    Some(loc) => {
+                        let path = &self.info.files[loc.file];
+                        let path = path.canonicalize().unwrap_or_else(|_| path.clone());
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        fields.push((
+                            "source",
+                            Json::object([
+                                ("name", Json::from(name.into_owned())),
+                                ("path", Json::from(path.to_string_lossy().into_owned())),
+                            ]),
+                        ));
+                        fields.push(("line", Json::from(loc.line)));
+                        fields.push(("column", Json::from(1_usize)));
+                    }
+                    None => {
+                        fields.push(("line", Json::from(0_usize)));
+                        fields.push(("column", Json::from(0_usize)));
+                        fields.push(("presentationHint", Json::from("subtle")));
+                    }
+                }
+                Json::object(fields)
+            })
+            .collect();
+        Json::object([
+            ("totalFrames", Json::from(frames.len())),
+            ("stackFrames", Json::Array(frames)),
+        ])
+    }
+
+    /// Display text for `v` and a reference to its parts (0 if it has none).
+    fn show(&mut self, vm: &mut VM, v: Type) -> (String, usize) {
+        let v = vm.force(v);
+        let mut text = vm.describe(v.clone());
+        if let Some((cut, _)) = text.char_indices().nth(VALUE_LIMIT) {
+            text.truncate(cut);
+            text.push('…');
+        }
+        let reference = if vm.has_children(v.clone()) {
+            self.reference(VarRef::Value(v))
+        } else {
+            0
+        };
+        (text, reference)
+    }
+
+    fn reference(&mut self, var: VarRef) -> usize {
+        self.refs.push(var);
+        self.refs.len()
+    }
+
+    fn body_of(&mut self, code: &[Instruction], function: &str) -> Option<usize> {
+        let key = (code.as_ptr() as usize, code.len());
+        match self.current {
+            Some((ptr, len, body)) if (ptr, len) == key => body,
+            _ => {
+                let body = self.matcher.find(&self.info.bodies, code, function);
+                self.current = Some((key.0, key.1, body));
+                body
+            }
+        }
+    }
+
+    /// Locals of `frame`, under their source names when stripped code
+    /// renamed them.
+    fn frame_locals(&self, vm: &VM, frame: usize) -> Vec<(String, Type)> {
+        let Some(view) = vm.frames().into_iter().nth(frame) else {
+            return Vec::new();
+        };
+        let body = self
+            .matcher
+            .find(&self.info.bodies, view.code, view.function);
+        view.locals
+            .into_iter()
+            .map(|(name, v)| match body {
+                Some(body) => (self.info.original_name(body, &name).to_string(), v),
+                None => (name, v),
+            })
+            .collect()
+    }
+
+    fn current_loc(&mut self, vm: &VM) -> Option<SourceLoc> {
+        let (function, code, pointer) = vm.position();
+        let body = self.body_of(code, function)?;
+        self.info.loc(body, pointer)
+    }
+
+    /// Handles requests that arrived while running, such as `pause`.
+    fn poll(&mut self) {
+        loop {
+            match self.messages.try_recv() {
+                Ok(request) => {
+                    if let Control::Disconnect = self.handle(None, &request) {
+                        std::process::exit(0);
+                    }
+                }
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => std::process::exit(0),
+            }
+        }
+    }
+
+    /// Reports a stop and answers requests until the client resumes.
+    fn stop(&mut self, vm: &mut VM, reason: &str, text: Option<&str>) {
+        let mut body = vec![
+            ("reason", Json::from(reason)),
+            ("threadId", Json::from(THREAD_ID)),
+            ("allThreadsStopped", Json::from(true)),
+        ];
+        if let Some(text) = text {
+            body.push(("text", Json::from(text)));
+        }
+        self.transport.event("stopped", Json::object(body));
+
+        loop {
+            let Ok(request) = self.messages.recv() else {
+                std::process::exit(0);
+            };
+            match self.handle(Some(vm), &request) {
+                Control::Wait => {}
+                Control::Resume => break,
+                Control::Disconnect => std::process::exit(0),
+            }
+        }
+        self.refs.clear();
+    }
+
+    fn before_instruction(&mut self, vm: &mut VM) {
+        self.polled += 1;
+        if self.polled == POLL_EVERY {
+            self.polled = 0;
+            self.poll();
+        }
+
+        let (function, code, pointer) = vm.position();
+        let Some(body) = self.body_of(code, function) else {
+            return;
+        };
+        if !self.info.is_statement_start(body, pointer) {
+            return;
+        }
+        let loc = self.info.loc(body, pointer);
+        let depth = vm.call_depth();
+
+        let reason = if loc.is_some_and(|loc| self.breakpoints.contains(&loc)) {
+            "breakpoint"
+        } else {
+            match self.step {
+                Some(Step::Entry) => "entry",
+                Some(Step::Pause) => "pause",
+                Some(Step::In) => "step",
+                Some(Step::Over { depth: d, from }) if depth < d || (depth == d && loc != from) => {
+                    "step"
+                }
+                Some(Step::Out { depth: d }) if depth < d => "step",
+                _ => return,
+            }
+        };
+        self.step = None;
+        self.stop(vm, reason, None);
+    }
+}
+
+/// Gives the shared session control before each instruction.
+struct Hook(Rc<RefCell<Session>>);
+
+impl Debugger for Hook {
+    fn before_instruction(&mut self, vm: &mut VM) {
+        self.0.borrow_mut().before_instruction(vm);
+    }
+}
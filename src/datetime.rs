@@ -0,0 +1,175 @@
+//! UTC calendar conversions and a small strftime-style formatter/parser.
+//!
+//! Supported directives: `%Y`, `%m`, `%d`, `%H`, `%M`, `%S` (2 digits each)
+//! and `%%`. `%Y` formats at least 4 digits and parses an optional sign and
+//! one or more digits, or exactly 4 when another directive follows directly
+//! (as in `%Y%m%d`).
+
+/// Broken-down UTC time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    pub hour: i64,
+    pub minute: i64,
+    pub second: i64,
+    /// 0 = Sunday.
+    pub weekday: i64,
+}
+
+/// Converts seconds since the Unix epoch to UTC components.
+pub fn from_timestamp(ts: i64) -> DateTime {
+    let days = ts.div_euclid(86_400);
+    let secs = ts.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    DateTime {
+        year,
+        month,
+        day,
+        hour: secs / 3600,
+        minute: secs % 3600 / 60,
+        second: secs % 60,
+        weekday: (days + 4).rem_euclid(7),
+    }
+}
+
+/// Converts UTC components back to seconds since the Unix epoch.
+pub fn to_timestamp(year: i64, month: i64, day: i64, hour: i64, minute: i64, second: i64) -> i64 {
+    days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+}
+
+// Howard Hinnant's days_from_civil / civil_from_days (proleptic Gregorian).
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(z: i64) -> (i64, i64, i64) {
+    let z = z + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(m <= 2), m, d)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+pub fn format(ts: i64, fmt: &str) -> Result<String, String> {
+    let dt = from_timestamp(ts);
+    let mut out = String::new();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') if dt.year < 0 => out.push_str(&format!("-{:04}", -dt.year)),
+            Some('Y') => out.push_str(&format!("{:04}", dt.year)),
+            Some('m') => out.push_str(&format!("{:02}", dt.month)),
+            Some('d') => out.push_str(&format!("{:02}", dt.day)),
+            Some('H') => out.push_str(&format!("{:02}", dt.hour)),
+            Some('M') => out.push_str(&format!("{:02}", dt.minute)),
+            Some('S') => out.push_str(&format!("{:02}", dt.second)),
+            Some('%') => out.push('%'),
+            Some(other) => return Err(format!("unsupported format directive `%{other}`")),
+            None => return Err("format string ends with `%`".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+pub fn parse(input: &str, fmt: &str) -> Result<i64, String> {
+    let mut fields = [1970, 1, 1, 0, 0, 0];
+    let mut rest = input;
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            rest = rest
+                .strip_prefix(c)
+                .ok_or_else(|| format!("expected `{c}` in `{input}`"))?;
+            continue;
+        }
+        let (slot, width) = match chars.next() {
+            Some('Y') => {
+                let (year, after) = parse_year(rest, chars.as_str().starts_with('%'), input)?;
+                fields[0] = year;
+                rest = after;
+                continue;
+            }
+            Some('m') => (1, 2),
+            Some('d') => (2, 2),
+            Some('H') => (3, 2),
+            Some('M') => (4, 2),
+            Some('S') => (5, 2),
+            Some('%') => {
+                rest = rest
+                    .strip_prefix('%')
+                    .ok_or_else(|| format!("expected `%` in `{input}`"))?;
+                continue;
+            }
+            Some(other) => return Err(format!("unsupported format directive `%{other}`")),
+            None => return Err("format string ends with `%`".to_string()),
+        };
+        let digits = rest
+            .get(..width)
+            .filter(|d| d.bytes().all(|b| b.is_ascii_digit()));
+        let digits = digits.ok_or_else(|| format!("expected {width} digits in `{input}`"))?;
+        fields[slot] = digits.parse().unwrap_or(0);
+        rest = &rest[width..];
+    }
+    if !rest.is_empty() {
+        return Err(format!("unexpected trailing text `{rest}` in `{input}`"));
+    }
+
+    let [year, month, day, hour, minute, second] = fields;
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(format!("`{input}` is not a valid date and time"));
+    }
+    Ok(to_timestamp(year, month, day, hour, minute, second))
+}
+
+// Years whose timestamps still fit in an i64.
+const MAX_YEAR: i64 = 99_999_999_999;
+
+// `%Y`: an optional sign and a run of digits, or exactly 4 digits when
+// `fixed` (another directive follows, so the run has no end marker).
+fn parse_year<'a>(rest: &'a str, fixed: bool, input: &str) -> Result<(i64, &'a str), String> {
+    let unsigned = rest.strip_prefix(['-', '+']).unwrap_or(rest);
+    let run = unsigned.bytes().take_while(u8::is_ascii_digit).count();
+    let width = if fixed { 4 } else { run };
+    if run < width || width == 0 {
+        let wanted = if fixed { "4 digits" } else { "a year" };
+        return Err(format!("expected {wanted} in `{input}`"));
+    }
+    let end = rest.len() - unsigned.len() + width;
+    match rest[..end].parse::<i64>() {
+        Ok(year) if year.abs() <= MAX_YEAR => Ok((year, &rest[end..])),
+        _ => Err(format!(
+            "year `{}` is out of range in `{input}`",
+            &rest[..end]
+        )),
+    }
+}
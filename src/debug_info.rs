@@ -0,0 +1,268 @@
+use crate::bytecode::{code_bodies, quote, serialize_bytecode, source_hash, tokenize_line};
+use crate::grammar::Instruction;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const RXD_MAGIC: &str = "RXD1";
+
+/// A source position: index into `DebugInfo::files` and 1-based line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceLoc {
+    pub file: usize,
+    pub line: usize,
+}
+
+/// One instruction sequence (the top level, a function body, a field
+/// initializer or a reactive expression) with the source location of each
+/// instruction.
+#[derive(Debug, Clone)]
+pub struct DebugBody {
+    pub name: String,
+    pub code: Vec<Instruction>,
+    pub locs: Vec<Option<SourceLoc>>,
+    /// Original names of locals renamed by `reactive strip`, by slot name.
+    pub names: HashMap<String, String>,
+}
+
+/// Source locations for a compiled program, recorded by the native compiler
+/// (`Compiler::with_debug_info`). The bytecode itself is unchanged; the VM
+/// runs copies of these bodies, so `BodyMatcher` maps running code back.
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    pub files: Vec<PathBuf>,
+    pub bodies: Vec<DebugBody>,
+}
+
+impl DebugInfo {
+    pub fn file_index(&self, path: &Path) -> Option<usize> {
+        let wanted = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.files
+            .iter()
+            .position(|file| file.canonicalize().unwrap_or_else(|_| file.clone()) == wanted)
+    }
+
+    pub fn loc(&self, body: usize, pc: usize) -> Option<SourceLoc> {
+        self.bodies[body].locs.get(pc).copied().flatten()
+    }
+
+    /// Whether instruction `pc` of `body` is the first of a statement: its
+    /// location differs from the instruction before it.
+    pub fn is_statement_start(&self, body: usize, pc: usize) -> bool {
+        let locs = &self.bodies[body].locs;
+        match locs.get(pc).copied().flatten() {
+            Some(loc) => pc == 0 || locs[pc - 1] != Some(loc),
+            None => false,
+        }
+    }
+
+    /// The source name of local `name` in `body`, which differs from `name`
+    /// only in stripped code.
+    pub fn original_name<'a>(&'a self, body: usize, name: &'a str) -> &'a str {
+        self.bodies[body]
+            .names
+            .get(name)
+            .map_or(name, String::as_str)
+    }
+
+    /// `.rxd` text: the debug info of stripped bytecode `code`, kept apart
+    /// from it and tied to it by `code_hash`. Bodies are listed in
+    /// `code_bodies` order, one line each with the location of every
+    /// instruction (`file:line`, or `-`), then a line of renamed locals.
+    pub fn serialize_split(&self, code: &[Instruction]) -> String {
+        let mut lines = vec![RXD_MAGIC.to_string(), format!("@code {}", code_hash(code))];
+        for file in &self.files {
+            lines.push(format!("File {}", quote(&file.to_string_lossy())));
+        }
+        for body in &self.bodies {
+            let mut line = format!("Body {} {}", quote(&body.name), body.locs.len());
+            for loc in &body.locs {
+                match loc {
+                    Some(loc) => line.push_str(&format!(" {}:{}", loc.file, loc.line)),
+                    None => line.push_str(" -"),
+                }
+            }
+            lines.push(line);
+
+            let mut names: Vec<(&String, &String)> = body.names.iter().collect();
+            names.sort();
+            let mut line = format!("Names {}", names.len());
+            for (slot, name) in names {
+                line.push_str(&format!(" {} {}", quote(slot), quote(name)));
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+
+    /// Reattaches `.rxd` text to `code`, which must be the bytecode it was
+    /// split from.
+    pub fn deserialize_split(input: &str, code: &[Instruction]) -> Result<Self, String> {
+        let mut lines = input.lines().enumerate().map(|(i, line)| (i + 1, line));
+        if lines.next().map(|(_, l)| l.trim()) != Some(RXD_MAGIC) {
+            return Err(format!("invalid debug info header: expected {RXD_MAGIC}"));
+        }
+        let hash = lines
+            .next()
+            .and_then(|(_, l)| l.strip_prefix("@code "))
+            .ok_or("debug info line 2: expected `@code <hash>`")?;
+        if hash != code_hash(code) {
+            return Err("debug info belongs to different bytecode".to_string());
+        }
+
+        let mut info = DebugInfo::default();
+        let mut bodies = code_bodies(code, None).into_iter();
+        while let Some((line_no, line)) = lines.next() {
+            let error = |message: &str| format!("debug info line {line_no}: {message}");
+            let tokens = tokenize_line(line).map_err(|e| error(&e))?;
+            match tokens.first().map(String::as_str) {
+                Some("File") if tokens.len() == 2 => info.files.push(PathBuf::from(&tokens[1])),
+                Some("Body") if tokens.len() >= 3 => {
+                    let body = bodies
+                        .next()
+                        .ok_or_else(|| error("more bodies than the bytecode"))?;
+                    let count = tokens[2].parse::<usize>().ok();
+                    if tokens[1] != body.name
+                        || count != Some(tokens[3..].len())
+                        || tokens[3..].len() != body.code.len()
+                    {
+                        return Err(error(&format!(
+                            "body `{}` does not match the bytecode",
+                            tokens[1]
+                        )));
+                    }
+                    let locs = tokens[3..]
+                        .iter()
+                        .map(|token| parse_loc(token, info.files.len()))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| error("invalid source location"))?;
+
+                    let (names_no, names) =
+                        lines.next().ok_or_else(|| error("missing `Names` line"))?;
+                    let names = tokenize_line(names)
+                        .map_err(|e| format!("debug info line {names_no}: {e}"))?;
+                    if names.first().map(String::as_str) != Some("Names") || names.len() % 2 != 0 {
+                        return Err(format!(
+                            "debug info line {names_no}: expected `Names <count> <slot> <name>...`"
+                        ));
+                    }
+                    info.bodies.push(DebugBody {
+                        name: body.name,
+                        code: body.code,
+                        locs,
+                        names: names[2..]
+                            .chunks(2)
+                            .map(|pair| (pair[0].clone(), pair[1].clone()))
+                            .collect(),
+                    });
+                }
+                _ => return Err(error("expected `File` or `Body`")),
+            }
+        }
+        if bodies.next().is_some() {
+            return Err("debug info is missing bodies of the bytecode".to_string());
+        }
+        Ok(info)
+    }
+
+    pub fn read_split_file(path: &str, code: &[Instruction]) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read debug info `{path}`: {e}"))?;
+        Self::deserialize_split(&text, code).map_err(|e| format!("`{path}`: {e}"))
+    }
+
+    pub fn write_split_file(&self, path: &str, code: &[Instruction]) -> Result<(), String> {
+        fs::write(path, self.serialize_split(code))
+            .map_err(|e| format!("failed to write debug info `{path}`: {e}"))
+    }
+
+    /// Lines of `file` that start at least one statement.
+    pub fn statement_lines(&self, file: usize) -> Vec<usize> {
+        let mut lines: Vec<usize> = self
+            .bodies
+            .iter()
+            .flat_map(|body| body.locs.iter().flatten())
+            .filter(|loc| loc.file == file)
+            .map(|loc| loc.line)
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+}
+
+/// Identifies the bytecode a `.rxd` file was split from: the hash of its
+/// serialized instructions, metadata left out.
+pub fn code_hash(code: &[Instruction]) -> String {
+    source_hash(serialize_bytecode(code, None).as_bytes())
+}
+
+fn parse_loc(token: &str, files: usize) -> Option<Option<SourceLoc>> {
+    if token == "-" {
+        return Some(None);
+    }
+    let (file, line) = token.split_once(':')?;
+    let file: usize = file.parse().ok().filter(|&f| f < files)?;
+    Some(Some(SourceLoc {
+        file,
+        line: line.parse().ok()?,
+    }))
+}
+
+/// An instruction sequence a `BodyMatcher` can recognise.
+pub trait NamedCode {
+    fn name(&self) -> &str;
+    fn code(&self) -> &[Instruction];
+}
+
+impl NamedCode for DebugBody {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn code(&self) -> &[Instruction] {
+        &self.code
+    }
+}
+
+/// Finds which known body running code was copied from. Functions run from
+/// clones of their body, so bodies are compared by contents; among identical
+/// bodies the one named like the running function wins. Body 0 is the top
+/// level, which the host may have extended (the compiler's entry call), so
+/// it also matches as a prefix.
+pub struct BodyMatcher {
+    by_len: HashMap<usize, Vec<usize>>,
+}
+
+impl BodyMatcher {
+    pub fn new<B: NamedCode>(bodies: &[B]) -> Self {
+        let mut by_len: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (i, body) in bodies.iter().enumerate() {
+            by_len.entry(body.code().len()).or_default().push(i);
+        }
+        Self { by_len }
+    }
+
+    pub fn find<B: NamedCode>(
+        &self,
+        bodies: &[B],
+        code: &[Instruction],
+        function: &str,
+    ) -> Option<usize> {
+        let candidates = self.by_len.get(&code.len()).map(Vec::as_slice);
+        let mut matches = candidates
+            .unwrap_or_default()
+            .iter()
+            .copied()
+            .filter(|&i| bodies[i].code() == code);
+        let first = matches.next();
+        let named = first
+            .into_iter()
+            .chain(matches)
+            .find(|&i| bodies[i].name() == function);
+        named.or(first).or_else(|| {
+            let top = bodies.first()?;
+            code.starts_with(top.code()).then_some(0)
+        })
+    }
+}
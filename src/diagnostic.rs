@@ -0,0 +1,139 @@
+//! Machine-readable errors and warnings for `--error-format=json`.
+//!
+//! Each problem becomes one JSON object on its own line, with the fields
+//! `file`, `line`, `column` (`null` when unknown), `code`, `message` and
+//! `severity` (`error` or `warning`).
+
+use crate::check::Diagnostic;
+use crate::debug_info::DebugInfo;
+use crate::json::Json;
+use crate::lint::Lint;
+use std::path::Path;
+
+/// How a command reports problems: `human` text or `json` records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+impl ErrorFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "human" => Some(ErrorFormat::Human),
+            "json" => Some(ErrorFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// One problem, located as precisely as its source allows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub file: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub code: String,
+    pub message: String,
+    pub severity: Severity,
+}
+
+impl Record {
+    /// A problem found by `check` in `file`; `info` is the debug info the
+    /// code was compiled with, if any, which places it in the right file.
+    pub fn from_check(file: &Path, diagnostic: &Diagnostic, info: Option<&DebugInfo>) -> Self {
+        let loc = diagnostic.loc.as_ref();
+        let file = loc
+            .and_then(|loc| info?.files.get(loc.file))
+            .map_or(file, |path| path.as_path());
+        Record {
+            file: file.display().to_string(),
+            line: loc.map(|loc| loc.line),
+            column: None,
+            code: diagnostic.code.to_string(),
+            message: diagnostic.to_string(),
+            severity: Severity::Error,
+        }
+    }
+
+    pub fn from_lint(file: &Path, lint: &Lint) -> Self {
+        let message = match &lint.function {
+            Some(name) => format!("in {name}(): {}", lint.message),
+            None => format!("at top level: {}", lint.message),
+        };
+        Record {
+            file: file.display().to_string(),
+            line: lint.line,
+            column: None,
+            code: lint.kind.name().to_string(),
+            message,
+            severity: Severity::Warning,
+        }
+    }
+
+    /// A compile error for `file`. Errors from the tokenizer or parser of a
+    /// compiler with debug info start with `path:line:column: ` (the path of
+    /// the importing or imported file they occurred in) and get the code
+    /// `syntax`; any other error has the code `compile`.
+    pub fn from_compile_error(file: &Path, error: &str) -> Self {
+        let (file, line, column, message, code) = match split_location(error) {
+            Some((path, line, column, message)) => (
+                path.to_string(),
+                Some(line),
+                Some(column),
+                message,
+                "syntax",
+            ),
+            None => (file.display().to_string(), None, None, error, "compile"),
+        };
+        Record {
+            file,
+            line,
+            column,
+            code: code.to_string(),
+            message: message.to_string(),
+            severity: Severity::Error,
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        let position = |n: Option<usize>| n.map_or(Json::Null, |n| Json::Number(n as f64));
+        Json::object([
+            ("file", Json::String(self.file.clone())),
+            ("line", position(self.line)),
+            ("column", position(self.column)),
+            ("code", Json::String(self.code.clone())),
+            ("message", Json::String(self.message.clone())),
+            ("severity", Json::String(self.severity.name().to_string())),
+        ])
+    }
+}
+
+/// `(path, line, column, message)` from `path:line:column: message`. The
+/// path may itself contain colons, so the first `:N:N: ` is taken.
+fn split_location(error: &str) -> Option<(&str, usize, usize, &str)> {
+    error.match_indices(':').find_map(|(at, _)| {
+        let path = &error[..at];
+        let (line, rest) = error[at + 1..].split_once(':')?;
+        let (column, message) = rest.split_once(": ")?;
+        let line = line.parse().ok()?;
+        let column = column.parse().ok()?;
+        (!path.is_empty()).then_some((path, line, column, message))
+    })
+}
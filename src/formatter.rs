@@ -0,0 +1,210 @@
+use crate::grammar::{SourceError, Span, Token};
+use crate::parser::statement_starts;
+use crate::tokenizer::tokenize_spanned;
+use std::collections::HashSet;
+
+const INDENT: &str = "    ";
+
+/// Canonical layout of Reactive source (`reactive fmt`):
+///
+/// - one statement, item or struct field per line, indented four spaces per
+///   open brace; `{` ends its line and `}` gets its own, except `} else`
+/// - a space around binary operators, `=`, `:=`, `::=` and the ternary's
+///   `?` / `:`, after commas, and none inside brackets, before a call's `(`
+///   or after a unary operator or cast
+/// - blank lines between statements are kept (at most one in a row)
+/// - `# ... #` comments are kept verbatim, on their own line when they were
+///   on one
+///
+/// Only source the parser accepts is formatted, and the result must
+/// tokenize to the same tokens.
+pub fn format_source(source: &str) -> Result<String, SourceError> {
+    let tokens = tokenize_spanned(source)?;
+    let starts: HashSet<usize> = statement_starts(tokens.clone())?.into_iter().collect();
+
+    let mut out = Writer {
+        text: String::new(),
+        depth: 0,
+    };
+    let mut prev_end = 0;
+    let mut cast_end = None;
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|p| &tokens[p].0);
+        let blank_line = out.gap(&source[prev_end..span.start]);
+
+        if is_cast_start(&tokens, i) {
+            cast_end = Some(i + 2);
+        }
+        if *token == Token::RBrace {
+            out.depth = out.depth.saturating_sub(1);
+        }
+
+        if out.text.is_empty() {
+            // first token of the file
+        } else if out.line_ended() {
+            if blank_line && *token != Token::RBrace {
+                out.text.push('\n');
+            }
+            out.indent();
+        } else if *token == Token::RBrace {
+            if prev != Some(&Token::LBrace) {
+                out.newline(false);
+            }
+        } else if (starts.contains(&i) && prev != Some(&Token::Else))
+            || prev == Some(&Token::LBrace)
+            || prev == Some(&Token::RBrace) && *token != Token::Else && !is_closer(token)
+        {
+            out.newline(blank_line && prev != Some(&Token::LBrace));
+        } else if space_between(&tokens, i, cast_end) {
+            out.text.push(' ');
+        }
+
+        out.text.push_str(&source[span.start..span.end]);
+        if *token == Token::LBrace {
+            out.depth += 1;
+        }
+        prev_end = span.end;
+    }
+    out.gap(&source[prev_end..]);
+    let mut text = out.text.trim_end().to_string();
+    if !text.is_empty() {
+        text.push('\n');
+    }
+
+    let same = tokenize_spanned(&text).is_ok_and(|formatted| {
+        formatted.len() == tokens.len() && formatted.iter().zip(&tokens).all(|(a, b)| a.0 == b.0)
+    });
+    if !same {
+        return Err(SourceError {
+            message: "fmt: formatting changed the tokens (formatter bug)".to_string(),
+            span: Span::default(),
+        });
+    }
+    Ok(text)
+}
+
+struct Writer {
+    text: String,
+    depth: usize,
+}
+
+impl Writer {
+    /// Whether the last thing written was a newline (after an own-line
+    /// comment), so the next token only needs its indentation.
+    fn line_ended(&self) -> bool {
+        self.text.ends_with('\n')
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.text.push_str(INDENT);
+        }
+    }
+
+    fn newline(&mut self, blank_line: bool) {
+        self.text.push('\n');
+        if blank_line {
+            self.text.push('\n');
+        }
+        self.indent();
+    }
+
+    /// Writes the comments in the whitespace between two tokens and returns
+    /// whether that whitespace held a blank line (before the next token).
+    fn gap(&mut self, gap: &str) -> bool {
+        let mut newlines = 0;
+        let mut rest = gap;
+        while let Some(c) = rest.chars().next() {
+            if c != '#' {
+                if c == '\n' {
+                    newlines += 1;
+                }
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            let end = rest[1..].find('#').map_or(rest.len(), |e| e + 2);
+            let comment = &rest[..end];
+            rest = &rest[end..];
+
+            if self.text.is_empty() {
+                self.text.push_str(comment);
+                self.text.push('\n');
+            } else if newlines > 0 {
+                if !self.line_ended() {
+                    self.text.push('\n');
+                }
+                if newlines > 1 && !self.text.ends_with("{\n") {
+                    self.text.push('\n');
+                }
+                self.indent();
+                self.text.push_str(comment);
+                self.text.push('\n');
+            } else {
+                self.text.push(' ');
+                self.text.push_str(comment);
+            }
+            newlines = 0;
+        }
+        newlines > 1
+    }
+}
+
+/// `(int)` / `(char)` in operand position (after an identifier it is a
+/// call's argument list instead).
+fn is_cast_start(tokens: &[(Token, Span)], i: usize) -> bool {
+    let at = |j: usize| tokens.get(j).map(|t| &t.0);
+    at(i) == Some(&Token::LParen)
+        && matches!(at(i + 1), Some(Token::Ident(name)) if name == "int" || name == "char")
+        && at(i + 2) == Some(&Token::RParen)
+        && !ends_operand(i.checked_sub(1).and_then(at))
+}
+
+/// Tokens that end an operand, after which `-` is binary and `(` / `[`
+/// call or index.
+fn ends_operand(token: Option<&Token>) -> bool {
+    matches!(
+        token,
+        Some(
+            Token::Number(_)
+                | Token::Ident(_)
+                | Token::Char(_)
+                | Token::StringLiteral(_)
+                | Token::RParen
+                | Token::RSquare
+        )
+    )
+}
+
+fn is_closer(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Semicolon | Token::Comma | Token::RParen | Token::RSquare | Token::Dot
+    )
+}
+
+/// Whether tokens `i - 1` and `i`, on the same line, are separated by a
+/// space. `cast_end` is the `)` of the cast being written, if any.
+fn space_between(tokens: &[(Token, Span)], i: usize, cast_end: Option<usize>) -> bool {
+    let token = &tokens[i].0;
+    let prev = &tokens[i - 1].0;
+    let before_prev = i.checked_sub(2).map(|p| &tokens[p].0);
+
+    if is_closer(token) || matches!(prev, Token::LParen | Token::LSquare | Token::Dot) {
+        return false;
+    }
+    if cast_end == Some(i - 1) || *prev == Token::Not {
+        return false;
+    }
+    // unary minus
+    if *prev == Token::Sub && (!ends_operand(before_prev) || cast_end == Some(i - 2)) {
+        return false;
+    }
+    match token {
+        Token::LParen => !matches!(
+            prev,
+            Token::Ident(_) | Token::Print | Token::Println | Token::Assert
+        ),
+        Token::LSquare => !ends_operand(Some(prev)),
+        _ => true,
+    }
+}
@@ -1,64 +1,190 @@
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CastType {
     Int,
     Char,
 }
 
-#[derive(Debug, Clone)]
+/// Who may use a function or struct. A private one belongs to the module its
+/// qualified name (`a.b.f`) names, and only that module's functions may call,
+/// load or instantiate it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Visibility {
+    #[default]
+    Public,
+    Private,
+}
+
+/// A switch's cases keyed by value, each with the offset of its target from
+/// the switch's own index, and the `Switch` or `SwitchRel` it was resolved
+/// from (which is what gets written out). Shared, so running one costs a
+/// pointer copy and a single lookup.
+#[derive(Debug, PartialEq)]
+pub struct JumpTable {
+    pub cases: HashMap<i64, i64>,
+    pub default: i64,
+    pub source: Instruction,
+}
+
+impl JumpTable {
+    pub fn offset(&self, value: i64) -> i64 {
+        self.cases.get(&value).copied().unwrap_or(self.default)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ReactiveExpr {
     pub code: Vec<Instruction>,
     pub captures: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
-    Integer(i32),
+    Integer(i64),
     Char(u32),
+    Null,
+    Tuple(Vec<Type>),
+    Variant(Box<Variant>),
+    Iter(Box<Iter>),
 
     ArrayRef(usize),
     VecRef(usize),
     BufferRef(usize),
+    BytesRef(usize),
+    SetRef(usize),
+    PqRef(usize),
     StructRef(usize),
+    HandleRef(usize),
 
     Function {
         params: Vec<String>,
         code: Vec<Instruction>,
+        // module that defined it, whose private names its body may use
+        module: String,
     },
     NativeFunction(String),
 
-    LazyValue(ReactiveExpr, HashMap<String, Type>),
+    // expression, captured immutables, and the module that wrote it (`None`
+    // for top-level code)
+    LazyValue(ReactiveExpr, HashMap<String, Type>, Option<String>),
     LValue(LValue),
     Uninitialized,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LValue {
     ArrayElem { array_id: usize, index: usize },
     VecElem { vec_id: usize, index: usize },
     StructField { struct_id: usize, field: String },
 }
 
+/// Loop state pushed by `IterRange` / `IterNew` and advanced by `IterNext`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Iter {
+    Range { next: i64, end: i64 },
+    Array { id: usize, index: usize },
+    Vec { id: usize, index: usize },
+}
+
+/// A value of a declared enum: the variant's name plus its payload.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Variant {
+    pub enum_name: String,
+    pub name: String,
+    pub payload: Vec<Type>,
+}
+
+/// Renders values that need no heap: scalars, tuples and variants print their
+/// contents, references print as `array#3`, `struct#1` and so on. Use
+/// `VM::display` / `VM::format_value` to render references as their contents.
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |f: &mut std::fmt::Formatter<'_>, elems: &[Type]| {
+            for (i, elem) in elems.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{elem}")?;
+            }
+            Ok(())
+        };
+        match self {
+            Type::Integer(n) => write!(f, "{n}"),
+            Type::Char(c) => match char::from_u32(*c) {
+                Some(ch) => write!(f, "{ch:?}"),
+                None => write!(f, "char({c})"),
+            },
+            Type::Null => f.write_str("null"),
+            Type::Tuple(elems) => {
+                f.write_str("(")?;
+                list(f, elems)?;
+                f.write_str(")")
+            }
+            Type::Variant(variant) => {
+                write!(f, "{}.{}", variant.enum_name, variant.name)?;
+                if !variant.payload.is_empty() {
+                    f.write_str("(")?;
+                    list(f, &variant.payload)?;
+                    f.write_str(")")?;
+                }
+                Ok(())
+            }
+            Type::Iter(_) => f.write_str("iterator"),
+            Type::ArrayRef(id) => write!(f, "array#{id}"),
+            Type::VecRef(id) => write!(f, "vec#{id}"),
+            Type::BufferRef(id) => write!(f, "buffer#{id}"),
+            Type::BytesRef(id) => write!(f, "bytes#{id}"),
+            Type::SetRef(id) => write!(f, "set#{id}"),
+            Type::PqRef(id) => write!(f, "pq#{id}"),
+            Type::StructRef(id) => write!(f, "struct#{id}"),
+            Type::HandleRef(id) => write!(f, "handle#{id}"),
+            Type::Function { params, .. } => write!(f, "func({})", params.join(", ")),
+            Type::NativeFunction(name) => write!(f, "native {name}"),
+            Type::LazyValue(..) => f.write_str("<reactive>"),
+            Type::LValue(_) => f.write_str("<lvalue>"),
+            Type::Uninitialized => f.write_str("uninitialized"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct StructInstance {
+    pub name: String,
     pub fields: HashMap<String, Type>,
     pub immutables: HashSet<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CompiledStructFieldInit {
     Mutable(Vec<Instruction>),
     Immutable(Vec<Instruction>),
     Reactive(ReactiveExpr),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     // stack ops
-    Push(i32),
+    Push(i64),
     PushChar(u32),
+    PushNull,
     Load(String),
+    LoadLoad(String, String),
+    LoadPush(String, i64),
+    LoadField(String, String),
+    Dup,
+    Swap,
+    Drop,
 
     // variable storage
     Store(String),
@@ -71,6 +197,7 @@ pub enum Instruction {
     Mul,
     Div,
     Modulo,
+    Neg,
 
     // comparison / logic
     Greater,
@@ -79,24 +206,65 @@ pub enum Instruction {
     LessEqual,
     Equal,
     NotEqual,
+    DeepEqual,
     And,
     Or,
+    Not,
 
     // control flow
     Label(String),
     Jump(String),
     JumpIfZero(String),
+    // pops an int and jumps to the label paired with it, else (or for null)
+    // to the default
+    Switch(Vec<(i64, String)>, String),
+    // counted / for-each loops: `IterRange` pops end and start, `IterNew`
+    // pops an array or vec; `IterNext` pushes the next item or, when
+    // done, pops the iterator and jumps to its label
+    IterRange,
+    IterNew,
+    IterNext(String),
     Return,
+    // label-free forms of the jumps above, as loaded from RXB2: each target
+    // is an offset from the jump's own index in its (label-less) body
+    JumpRel(i64),
+    JumpIfZeroRel(i64),
+    SwitchRel(Vec<(i64, i64)>, i64),
+    IterNextRel(i64),
+    // a `Switch` or `SwitchRel` as the VM runs it, resolved when its code is
+    // loaded; written back out as the switch it came from
+    #[cfg_attr(feature = "serde", serde(skip))]
+    JumpTable(Arc<JumpTable>),
 
     // arrays
     ArrayNew,
     ArrayGet,
     ArrayLValue,
+    ArraySlice,
+    ArrayConcat,
+    DeepCopy,
+
+    // tuples
+    MakeTuple(usize),
+    TupleGet(usize),
+
+    // enums: declared variants with payload arity, construction, and a
+    // jump table from variant name to label (payload is pushed on match)
+    StoreEnum(String, Vec<(String, usize)>),
+    NewVariant(String, String),
+    MatchVariant(Vec<(String, String)>, String),
+    MatchVariantRel(Vec<(String, i64)>, i64),
     StoreIndex(String),
     StoreIndexReactive(String, ReactiveExpr),
 
-    // structs
-    StoreStruct(String, Vec<(String, Option<CompiledStructFieldInit>)>),
+    // structs; `ExtendStruct child parent` follows the child's `StoreStruct`
+    // and merges the parent's fields into it
+    StoreStruct(
+        String,
+        Vec<(String, Option<CompiledStructFieldInit>)>,
+        Visibility,
+    ),
+    ExtendStruct(String, String),
     NewStruct(String),
     FieldGet(String),
     FieldSet(String),
@@ -109,9 +277,13 @@ pub enum Instruction {
     StoreThroughImmutable,
 
     // functions
-    StoreFunction(String, Vec<String>, Vec<Instruction>),
+    StoreFunction(String, Vec<String>, Vec<Instruction>, Visibility),
     Call(String, usize),
 
+    // green threads: start a task calling a function / switch to the next task
+    Spawn(String, usize),
+    Yield,
+
     // immutable scopes
     PushImmutableContext,
     PopImmutableContext,
@@ -120,7 +292,11 @@ pub enum Instruction {
     // io
     Print,
     Println,
+    EPrint,
+    EPrintln,
     Assert,
+    AssertEq,
+    AssertNe,
     Error(String),
 
     // modules
@@ -128,4 +304,502 @@ pub enum Instruction {
 
     // casts
     Cast(CastType),
+    TypeOf,
+    // string interpolation: pops n values and pushes their text, joined
+    Interp(usize),
+    IsNull,
+}
+
+impl Instruction {
+    /// The label operands of a control-flow instruction.
+    pub fn labels_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Instruction::Label(label)
+            | Instruction::Jump(label)
+            | Instruction::JumpIfZero(label)
+            | Instruction::IterNext(label) => vec![label],
+            Instruction::Switch(cases, default) => cases
+                .iter_mut()
+                .map(|(_, label)| label)
+                .chain(std::iter::once(default))
+                .collect(),
+            Instruction::MatchVariant(arms, default) => arms
+                .iter_mut()
+                .map(|(_, label)| label)
+                .chain(std::iter::once(default))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The offset operands of a relative jump.
+    pub fn offsets(&self) -> Vec<i64> {
+        match self {
+            Instruction::JumpRel(offset)
+            | Instruction::JumpIfZeroRel(offset)
+            | Instruction::IterNextRel(offset) => vec![*offset],
+            Instruction::SwitchRel(cases, default) => cases
+                .iter()
+                .map(|(_, offset)| *offset)
+                .chain([*default])
+                .collect(),
+            Instruction::MatchVariantRel(arms, default) => arms
+                .iter()
+                .map(|(_, offset)| *offset)
+                .chain([*default])
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// `offsets`, for rewriting.
+    pub fn offsets_mut(&mut self) -> Vec<&mut i64> {
+        match self {
+            Instruction::JumpRel(offset)
+            | Instruction::JumpIfZeroRel(offset)
+            | Instruction::IterNextRel(offset) => vec![offset],
+            Instruction::SwitchRel(cases, default) => cases
+                .iter_mut()
+                .map(|(_, offset)| offset)
+                .chain(std::iter::once(default))
+                .collect(),
+            Instruction::MatchVariantRel(arms, default) => arms
+                .iter_mut()
+                .map(|(_, offset)| offset)
+                .chain(std::iter::once(default))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Every mnemonic `opcode` can return, in declaration order.
+    pub const OPCODES: &'static [&'static str] = &[
+        "Push",
+        "PushChar",
+        "PushNull",
+        "Load",
+        "LoadLoad",
+        "LoadPush",
+        "LoadField",
+        "Dup",
+        "Swap",
+        "Drop",
+        "Store",
+        "StoreImmutable",
+        "StoreReactive",
+        "Add",
+        "Sub",
+        "Mul",
+        "Div",
+        "Modulo",
+        "Neg",
+        "Greater",
+        "Less",
+        "GreaterEqual",
+        "LessEqual",
+        "Equal",
+        "NotEqual",
+        "DeepEqual",
+        "And",
+        "Or",
+        "Not",
+        "Label",
+        "Jump",
+        "JumpIfZero",
+        "Switch",
+        "IterRange",
+        "IterNew",
+        "IterNext",
+        "Return",
+        "JumpRel",
+        "JumpIfZeroRel",
+        "SwitchRel",
+        "IterNextRel",
+        "JumpTable",
+        "ArrayNew",
+        "ArrayGet",
+        "ArrayLValue",
+        "ArraySlice",
+        "ArrayConcat",
+        "DeepCopy",
+        "MakeTuple",
+        "TupleGet",
+        "StoreEnum",
+        "NewVariant",
+        "MatchVariant",
+        "MatchVariantRel",
+        "StoreIndex",
+        "StoreIndexReactive",
+        "StoreStruct",
+        "ExtendStruct",
+        "NewStruct",
+        "FieldGet",
+        "FieldSet",
+        "FieldSetReactive",
+        "FieldLValue",
+        "StoreThrough",
+        "StoreThroughReactive",
+        "StoreThroughImmutable",
+        "StoreFunction",
+        "Call",
+        "Spawn",
+        "Yield",
+        "PushImmutableContext",
+        "PopImmutableContext",
+        "ClearImmutableContext",
+        "Print",
+        "Println",
+        "EPrint",
+        "EPrintln",
+        "Assert",
+        "AssertEq",
+        "AssertNe",
+        "Error",
+        "Import",
+        "Cast",
+        "TypeOf",
+        "Interp",
+        "IsNull",
+    ];
+
+    /// Bytecode mnemonic of the instruction (the first token of its RXB line).
+    pub fn opcode(&self) -> &'static str {
+        match self {
+            Instruction::Push(_) => "Push",
+            Instruction::PushChar(_) => "PushChar",
+            Instruction::PushNull => "PushNull",
+            Instruction::Load(_) => "Load",
+            Instruction::LoadLoad(_, _) => "LoadLoad",
+            Instruction::LoadPush(_, _) => "LoadPush",
+            Instruction::LoadField(_, _) => "LoadField",
+            Instruction::Dup => "Dup",
+            Instruction::Swap => "Swap",
+            Instruction::Drop => "Drop",
+            Instruction::Store(_) => "Store",
+            Instruction::StoreImmutable(_) => "StoreImmutable",
+            Instruction::StoreReactive(_, _) => "StoreReactive",
+            Instruction::Add => "Add",
+            Instruction::Sub => "Sub",
+            Instruction::Mul => "Mul",
+            Instruction::Div => "Div",
+            Instruction::Modulo => "Modulo",
+            Instruction::Neg => "Neg",
+            Instruction::Greater => "Greater",
+            Instruction::Less => "Less",
+            Instruction::GreaterEqual => "GreaterEqual",
+            Instruction::LessEqual => "LessEqual",
+            Instruction::Equal => "Equal",
+            Instruction::NotEqual => "NotEqual",
+            Instruction::DeepEqual => "DeepEqual",
+            Instruction::And => "And",
+            Instruction::Or => "Or",
+            Instruction::Not => "Not",
+            Instruction::Label(_) => "Label",
+            Instruction::Jump(_) => "Jump",
+            Instruction::JumpIfZero(_) => "JumpIfZero",
+            Instruction::Switch(_, _) => "Switch",
+            Instruction::IterRange => "IterRange",
+            Instruction::IterNew => "IterNew",
+            Instruction::IterNext(_) => "IterNext",
+            Instruction::Return => "Return",
+            Instruction::JumpRel(_) => "JumpRel",
+            Instruction::JumpIfZeroRel(_) => "JumpIfZeroRel",
+            Instruction::SwitchRel(_, _) => "SwitchRel",
+            Instruction::IterNextRel(_) => "IterNextRel",
+            Instruction::JumpTable(_) => "JumpTable",
+            Instruction::ArrayNew => "ArrayNew",
+            Instruction::ArrayGet => "ArrayGet",
+            Instruction::ArrayLValue => "ArrayLValue",
+            Instruction::ArraySlice => "ArraySlice",
+            Instruction::ArrayConcat => "ArrayConcat",
+            Instruction::DeepCopy => "DeepCopy",
+            Instruction::MakeTuple(_) => "MakeTuple",
+            Instruction::TupleGet(_) => "TupleGet",
+            Instruction::StoreEnum(_, _) => "StoreEnum",
+            Instruction::NewVariant(_, _) => "NewVariant",
+            Instruction::MatchVariant(_, _) => "MatchVariant",
+            Instruction::MatchVariantRel(_, _) => "MatchVariantRel",
+            Instruction::StoreIndex(_) => "StoreIndex",
+            Instruction::StoreIndexReactive(_, _) => "StoreIndexReactive",
+            Instruction::StoreStruct(..) => "StoreStruct",
+            Instruction::ExtendStruct(_, _) => "ExtendStruct",
+            Instruction::NewStruct(_) => "NewStruct",
+            Instruction::FieldGet(_) => "FieldGet",
+            Instruction::FieldSet(_) => "FieldSet",
+            Instruction::FieldSetReactive(_, _) => "FieldSetReactive",
+            Instruction::FieldLValue(_) => "FieldLValue",
+            Instruction::StoreThrough => "StoreThrough",
+            Instruction::StoreThroughReactive(_) => "StoreThroughReactive",
+            Instruction::StoreThroughImmutable => "StoreThroughImmutable",
+            Instruction::StoreFunction(..) => "StoreFunction",
+            Instruction::Call(_, _) => "Call",
+            Instruction::Spawn(_, _) => "Spawn",
+            Instruction::Yield => "Yield",
+            Instruction::PushImmutableContext => "PushImmutableContext",
+            Instruction::PopImmutableContext => "PopImmutableContext",
+            Instruction::ClearImmutableContext => "ClearImmutableContext",
+            Instruction::Print => "Print",
+            Instruction::Println => "Println",
+            Instruction::EPrint => "EPrint",
+            Instruction::EPrintln => "EPrintln",
+            Instruction::Assert => "Assert",
+            Instruction::AssertEq => "AssertEq",
+            Instruction::AssertNe => "AssertNe",
+            Instruction::Error(_) => "Error",
+            Instruction::Import(_) => "Import",
+            Instruction::Cast(_) => "Cast",
+            Instruction::TypeOf => "TypeOf",
+            Instruction::Interp(_) => "Interp",
+            Instruction::IsNull => "IsNull",
+        }
+    }
+}
+
+//
+// ------------------------ SOURCE (compile-native) ------------------------
+//
+
+/// Where a token sits in its source: byte offsets `start..end` and the
+/// 1-based line and column (in chars) of its first character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A tokenizer or parser error with the position it was detected at.
+#[derive(Debug, Clone)]
+pub struct SourceError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl std::fmt::Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.line, self.span.column, self.message
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    // literals / identifiers
+    Number(i64),
+    Ident(String),
+    Char(u32),
+    StringLiteral(String),
+
+    // arithmetic
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Modulo,
+
+    // comparison / logic
+    Greater,
+    Less,
+    GreaterEqual,
+    LessEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+    Not,
+
+    // assignment
+    Assign,
+    ImmutableAssign,
+    ReactiveAssign,
+
+    // punctuation
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LSquare,
+    RSquare,
+    Semicolon,
+    Dot,
+    Comma,
+    Colon,
+    Question,
+
+    // keywords
+    If,
+    Else,
+    Loop,
+    Break,
+    Func,
+    Return,
+    Struct,
+    Import,
+    Print,
+    Println,
+    Continue,
+    Assert,
+    Error,
+}
+
+impl Token {
+    /// Name of the token's variant; the `kind` field of `reactive tokens`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Token::Number(_) => "Number",
+            Token::Ident(_) => "Ident",
+            Token::Char(_) => "Char",
+            Token::StringLiteral(_) => "StringLiteral",
+            Token::Add => "Add",
+            Token::Sub => "Sub",
+            Token::Mul => "Mul",
+            Token::Div => "Div",
+            Token::Modulo => "Modulo",
+            Token::Greater => "Greater",
+            Token::Less => "Less",
+            Token::GreaterEqual => "GreaterEqual",
+            Token::LessEqual => "LessEqual",
+            Token::Equal => "Equal",
+            Token::NotEqual => "NotEqual",
+            Token::And => "And",
+            Token::Or => "Or",
+            Token::Not => "Not",
+            Token::Assign => "Assign",
+            Token::ImmutableAssign => "ImmutableAssign",
+            Token::ReactiveAssign => "ReactiveAssign",
+            Token::LParen => "LParen",
+            Token::RParen => "RParen",
+            Token::LBrace => "LBrace",
+            Token::RBrace => "RBrace",
+            Token::LSquare => "LSquare",
+            Token::RSquare => "RSquare",
+            Token::Semicolon => "Semicolon",
+            Token::Dot => "Dot",
+            Token::Comma => "Comma",
+            Token::Colon => "Colon",
+            Token::Question => "Question",
+            Token::If => "If",
+            Token::Else => "Else",
+            Token::Loop => "Loop",
+            Token::Break => "Break",
+            Token::Func => "Func",
+            Token::Return => "Return",
+            Token::Struct => "Struct",
+            Token::Import => "Import",
+            Token::Print => "Print",
+            Token::Println => "Println",
+            Token::Continue => "Continue",
+            Token::Assert => "Assert",
+            Token::Error => "Error",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AST {
+    // literals
+    Number(i64),
+    Char(u32),
+    StringLiteral(String),
+
+    // variables
+    Var(String),
+
+    // expressions
+    Operation(Box<AST>, Operator, Box<AST>),
+    Neg(Box<AST>),
+    Not(Box<AST>),
+    Ternary {
+        cond: Box<AST>,
+        then_expr: Box<AST>,
+        else_expr: Box<AST>,
+    },
+
+    // arrays
+    ArrayNew(Box<AST>),
+    Index(Box<AST>, Box<AST>),
+
+    // assignment (binding-level)
+    Assign(String, Box<AST>),
+    ImmutableAssign(String, Box<AST>),
+    ReactiveAssign(String, Box<AST>),
+
+    // assignment (lvalue-level)
+    AssignTarget(Box<AST>, Box<AST>),
+    ReactiveAssignTarget(Box<AST>, Box<AST>),
+    ImmutableAssignTarget(Box<AST>, Box<AST>),
+
+    // control flow
+    Program(Vec<AST>),
+    IfElse(Box<AST>, Vec<AST>, Vec<AST>),
+    Loop(Vec<AST>),
+    Break,
+    Continue,
+    Return(Option<Box<AST>>),
+
+    // io
+    Print(Box<AST>),
+    Println(Box<AST>),
+    Assert(Box<AST>),
+    Error(String),
+
+    // functions
+    FuncDef {
+        name: String,
+        params: Vec<String>,
+        body: Vec<AST>,
+    },
+    Call {
+        name: String,
+        args: Vec<AST>,
+    },
+    Cast {
+        target: CastType,
+        expr: Box<AST>,
+    },
+
+    // structs
+    StructDef {
+        name: String,
+        fields: Vec<(String, Option<StructFieldInit>)>,
+    },
+    StructNew(String),
+    FieldAccess(Box<AST>, String),
+
+    // modules
+    Import(Vec<String>),
+
+    // debug info: the wrapped statement starts on this source line
+    // (only produced by `parse_spanned`)
+    Located(usize, Box<AST>),
+}
+
+#[derive(Debug, Clone)]
+pub enum StructFieldInit {
+    Mutable(AST),
+    Immutable(AST),
+    Reactive(AST),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Operator {
+    Addition,
+    Subtraction,
+    Multiplication,
+    Division,
+    Modulo,
+
+    Greater,
+    Less,
+    GreaterEqual,
+    LessEqual,
+    Equal,
+    NotEqual,
+
+    And,
+    Or,
 }
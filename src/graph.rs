@@ -0,0 +1,162 @@
+//! Module import and static call graphs for `reactive graph`.
+
+use crate::build::imports_of;
+use crate::grammar::{CompiledStructFieldInit, Instruction};
+use crate::json::Json;
+use std::collections::HashSet;
+
+pub struct Node {
+    pub name: String,
+    /// Referred to but not defined in the graph's input: a module that was
+    /// not compiled, or a function that is native or never declared.
+    pub external: bool,
+}
+
+/// A directed graph with nodes and edges in the order first seen.
+#[derive(Default)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<(String, String)>,
+    node_names: HashSet<String>,
+    edge_set: HashSet<(String, String)>,
+}
+
+impl Graph {
+    fn add_node(&mut self, name: &str, external: bool) {
+        if self.node_names.insert(name.to_string()) {
+            self.nodes.push(Node {
+                name: name.to_string(),
+                external,
+            });
+        }
+    }
+
+    fn add_edge(&mut self, from: &str, to: &str) {
+        let edge = (from.to_string(), to.to_string());
+        if self.edge_set.insert(edge.clone()) {
+            self.edges.push(edge);
+        }
+    }
+
+    /// Graphviz source for a `digraph` called `name`; external nodes are
+    /// dashed.
+    pub fn to_dot(&self, name: &str) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut out = format!("digraph {name} {{\n  rankdir=LR;\n  node [shape=box];\n");
+        for node in &self.nodes {
+            let style = if node.external { " [style=dashed]" } else { "" };
+            out.push_str(&format!("  {}{style};\n", quote(&node.name)));
+        }
+        for (from, to) in &self.edges {
+            out.push_str(&format!("  {} -> {};\n", quote(from), quote(to)));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// `{"nodes": [{"name", "external"}], "edges": [{"from", "to"}]}`.
+    pub fn to_json(&self) -> Json {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| {
+                Json::object([
+                    ("name", Json::String(node.name.clone())),
+                    ("external", Json::Bool(node.external)),
+                ])
+            })
+            .collect();
+        let edges = self
+            .edges
+            .iter()
+            .map(|(from, to)| {
+                Json::object([
+                    ("from", Json::String(from.clone())),
+                    ("to", Json::String(to.clone())),
+                ])
+            })
+            .collect();
+        Json::object([("nodes", Json::Array(nodes)), ("edges", Json::Array(edges))])
+    }
+}
+
+/// Which module imports which, for modules compiled with their imports
+/// left separate (as in an archive), named by dotted import path.
+pub fn import_graph(modules: &[(String, Vec<Instruction>)]) -> Graph {
+    let mut graph = Graph::default();
+    for (name, _) in modules {
+        graph.add_node(name, false);
+    }
+    for (name, code) in modules {
+        for import in imports_of(code) {
+            let import = import.join(".");
+            graph.add_node(&import, true);
+            graph.add_edge(name, &import);
+        }
+    }
+    graph
+}
+
+/// Which function calls or spawns which, by name, as written in the
+/// bytecode. Calls made from the top level come from `<top-level>`; calls
+/// inside reactive expressions and field initializers are attributed to
+/// the function or top level declaring them. Functions passed as values
+/// and called indirectly are not followed.
+pub fn call_graph(code: &[Instruction]) -> Graph {
+    let mut graph = Graph::default();
+    let mut functions = Vec::new();
+    collect_functions(code, &mut functions);
+    let defined: HashSet<String> = functions.iter().cloned().collect();
+
+    graph.add_node("<top-level>", false);
+    for name in &functions {
+        graph.add_node(name, false);
+    }
+    let mut calls = Vec::new();
+    collect_calls(code, "<top-level>", &mut calls);
+    for (caller, callee) in calls {
+        graph.add_node(&callee, !defined.contains(&callee));
+        graph.add_edge(&caller, &callee);
+    }
+    graph
+}
+
+fn collect_functions(code: &[Instruction], functions: &mut Vec<String>) {
+    for instr in code {
+        if let Instruction::StoreFunction(name, _, body, _) = instr {
+            functions.push(name.clone());
+            collect_functions(body, functions);
+        }
+    }
+}
+
+/// `(caller, callee)` pairs in `code`, which belongs to `caller`.
+fn collect_calls(code: &[Instruction], caller: &str, calls: &mut Vec<(String, String)>) {
+    for instr in code {
+        match instr {
+            Instruction::Call(name, _) | Instruction::Spawn(name, _) => {
+                calls.push((caller.to_string(), name.clone()));
+            }
+            Instruction::StoreFunction(name, _, body, _) => collect_calls(body, name, calls),
+            Instruction::StoreReactive(_, expr)
+            | Instruction::StoreIndexReactive(_, expr)
+            | Instruction::FieldSetReactive(_, expr)
+            | Instruction::StoreThroughReactive(expr) => collect_calls(&expr.code, caller, calls),
+            Instruction::StoreStruct(_, fields, _) => {
+                for (_, init) in fields {
+                    match init {
+                        Some(CompiledStructFieldInit::Mutable(code))
+                        | Some(CompiledStructFieldInit::Immutable(code)) => {
+                            collect_calls(code, caller, calls)
+                        }
+                        Some(CompiledStructFieldInit::Reactive(expr)) => {
+                            collect_calls(&expr.code, caller, calls)
+                        }
+                        None => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,3 +1,32 @@
+pub mod archive;
+pub mod bcdiff;
+pub mod build;
 pub mod bytecode;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod check;
+pub mod compiler;
+pub mod config;
+pub mod csv;
+pub mod dap;
+pub mod datetime;
+pub mod debug_info;
+pub mod diagnostic;
+pub mod formatter;
 pub mod grammar;
+pub mod graph;
+pub mod hash;
+pub mod json;
+pub mod linker;
+pub mod lint;
+pub mod log;
+pub mod lsp;
+pub mod manifest;
+pub mod package;
+pub mod parser;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod rxb2;
+pub mod strip;
+pub mod tokenizer;
 pub mod vm;
@@ -0,0 +1,260 @@
+use crate::grammar::{CompiledStructFieldInit, Instruction};
+use crate::rxb2::relocate_jumps;
+use std::collections::{HashMap, HashSet};
+
+/// Definitions dropped by `eliminate_dead_code`.
+#[derive(Debug, Default)]
+pub struct LinkStats {
+    pub removed_functions: Vec<String>,
+    pub removed_structs: Vec<String>,
+}
+
+/// Strips top-level `StoreFunction` / `StoreStruct` definitions that cannot be
+/// reached from the program's top-level code (globals and the `main` call).
+///
+/// Only static references count: `Call`, `Load` of a function name,
+/// `NewStruct`, the parent an `ExtendStruct` names, and any string literal
+/// containing a function's name (callbacks passed by name, such as
+/// `vec_sort(v, "cmp")`). Names built at runtime are not visible to this
+/// pass, so it is only applied to whole programs on request.
+pub fn eliminate_dead_code(code: Vec<Instruction>) -> (Vec<Instruction>, LinkStats) {
+    let mut functions: HashMap<&str, Vec<&[Instruction]>> = HashMap::new();
+    let mut structs: HashMap<&str, Vec<&[Instruction]>> = HashMap::new();
+    let mut parents: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut refs = References::default();
+
+    for instr in &code {
+        match instr {
+            Instruction::StoreFunction(name, _, body, _) => {
+                functions.entry(name).or_default().push(body);
+            }
+            Instruction::StoreStruct(name, fields, _) => {
+                let inits = structs.entry(name).or_default();
+                for (_, init) in fields {
+                    match init {
+                        Some(CompiledStructFieldInit::Mutable(code))
+                        | Some(CompiledStructFieldInit::Immutable(code)) => inits.push(code),
+                        Some(CompiledStructFieldInit::Reactive(expr)) => inits.push(&expr.code),
+                        None => {}
+                    }
+                }
+            }
+            // Part of the child's definition: the parent is live if it is.
+            Instruction::ExtendStruct(name, parent) => {
+                parents.entry(name).or_default().push(parent);
+            }
+            other => refs.scan_body(std::slice::from_ref(other)),
+        }
+    }
+    refs.finish_text();
+    let spelled = |refs: &mut References| {
+        for text in refs.strings.drain(..) {
+            for name in functions.keys() {
+                // a name qualified by its module may be passed unqualified
+                let short = name.rsplit('.').next().unwrap_or(name);
+                if text.contains(short) {
+                    refs.names.insert(name.to_string());
+                }
+            }
+        }
+    };
+    spelled(&mut refs);
+
+    // Worklist over newly discovered names until the reachable set is closed.
+    let mut live_functions: HashSet<String> = HashSet::new();
+    let mut live_structs: HashSet<String> = HashSet::new();
+    loop {
+        let pending_fns: Vec<String> = refs.names.difference(&live_functions).cloned().collect();
+        let pending_structs: Vec<String> =
+            refs.structs.difference(&live_structs).cloned().collect();
+        if pending_fns.is_empty() && pending_structs.is_empty() {
+            break;
+        }
+
+        let mut next = References::default();
+        for name in pending_fns {
+            for body in functions.get(name.as_str()).into_iter().flatten() {
+                next.scan(body);
+            }
+            live_functions.insert(name);
+        }
+        for name in pending_structs {
+            for init in structs.get(name.as_str()).into_iter().flatten() {
+                next.scan(init);
+            }
+            for parent in parents.get(name.as_str()).into_iter().flatten() {
+                next.structs.insert(parent.to_string());
+            }
+            live_structs.insert(name);
+        }
+        spelled(&mut next);
+        refs.names.extend(next.names);
+        refs.structs.extend(next.structs);
+    }
+
+    let mut stats = LinkStats::default();
+    let mut kept = Vec::with_capacity(code.len());
+    let mut origin = Vec::with_capacity(code.len());
+    let mut moved = Vec::with_capacity(code.len() + 1);
+    for (i, instr) in code.into_iter().enumerate() {
+        moved.push(kept.len());
+        let keep = match &instr {
+            Instruction::StoreFunction(name, _, _, _) if !live_functions.contains(name) => {
+                stats.removed_functions.push(name.clone());
+                false
+            }
+            Instruction::StoreStruct(name, _, _) if !live_structs.contains(name) => {
+                stats.removed_structs.push(name.clone());
+                false
+            }
+            Instruction::ExtendStruct(name, _) => live_structs.contains(name),
+            _ => true,
+        };
+        if keep {
+            origin.push(i);
+            kept.push(instr);
+        }
+    }
+    moved.push(kept.len());
+    // Code loaded from RXB2 jumps by offset rather than to labels.
+    relocate_jumps(&mut kept, &origin, &moved);
+
+    (kept, stats)
+}
+
+#[derive(Default)]
+struct References {
+    // names that may resolve to functions (calls and loads)
+    names: HashSet<String>,
+    structs: HashSet<String>,
+    // string literals, as the runs of `PushChar` that fill one array
+    strings: Vec<String>,
+    text: String,
+}
+
+impl References {
+    fn finish_text(&mut self) {
+        if !self.text.is_empty() {
+            self.strings.push(std::mem::take(&mut self.text));
+        }
+    }
+
+    /// Scans a whole body, ending the string literal it was reading.
+    fn scan(&mut self, code: &[Instruction]) {
+        self.scan_body(code);
+        self.finish_text();
+    }
+
+    fn scan_body(&mut self, code: &[Instruction]) {
+        for instr in code {
+            match instr {
+                Instruction::PushChar(c) => self.text.extend(char::from_u32(*c)),
+                Instruction::ArrayNew => self.finish_text(),
+                Instruction::Call(name, _)
+                | Instruction::Spawn(name, _)
+                | Instruction::Load(name) => {
+                    self.names.insert(name.clone());
+                }
+                Instruction::LoadLoad(a, b) => {
+                    self.names.insert(a.clone());
+                    self.names.insert(b.clone());
+                }
+                Instruction::LoadPush(name, _) | Instruction::LoadField(name, _) => {
+                    self.names.insert(name.clone());
+                }
+                Instruction::NewStruct(name) => {
+                    self.structs.insert(name.clone());
+                }
+                Instruction::StoreReactive(_, expr)
+                | Instruction::StoreIndexReactive(_, expr)
+                | Instruction::FieldSetReactive(_, expr)
+                | Instruction::StoreThroughReactive(expr) => self.scan(&expr.code),
+                // Nested definitions are only live if their enclosing code is.
+                Instruction::StoreFunction(_, _, body, _) => self.scan(body),
+                Instruction::StoreStruct(_, fields, _) => {
+                    for (_, init) in fields {
+                        match init {
+                            Some(CompiledStructFieldInit::Mutable(code))
+                            | Some(CompiledStructFieldInit::Immutable(code)) => self.scan(code),
+                            Some(CompiledStructFieldInit::Reactive(expr)) => self.scan(&expr.code),
+                            None => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::Visibility;
+    use Instruction::*;
+
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    /// The instructions the compilers emit for the string literal `text`.
+    fn literal(text: &str) -> Vec<Instruction> {
+        let mut code = vec![Push(text.len() as i64), ArrayNew, Store(s("__strlit_0"))];
+        for (i, ch) in text.chars().enumerate() {
+            code.extend([
+                Load(s("__strlit_0")),
+                Push(i as i64),
+                ArrayLValue,
+                PushChar(ch as u32),
+                StoreThrough,
+            ]);
+        }
+        code.push(Load(s("__strlit_0")));
+        code
+    }
+
+    fn function(name: &str, body: Vec<Instruction>) -> Instruction {
+        StoreFunction(s(name), vec![s("a"), s("b")], body, Visibility::Public)
+    }
+
+    #[test]
+    fn functions_named_by_string_literals_are_kept() {
+        // main() { v = vec_new(); vec_sort(v, "by_x"); q = pq_new("std.pq.cmp") }
+        let mut main = vec![Call(s("vec_new"), 0), Store(s("v")), Load(s("v"))];
+        main.extend(literal("by_x"));
+        main.extend([Call(s("vec_sort"), 2), Drop]);
+        main.extend(literal("std.pq.cmp"));
+        main.extend([Call(s("pq_new"), 1), Store(s("q"))]);
+        let code = vec![
+            function("vec_new", vec![ArrayNew, Return]),
+            function("vec_sort", vec![Load(s("b")), Return]),
+            function("pq_new", vec![Load(s("a")), Return]),
+            function("by_x", vec![Load(s("a")), Load(s("b")), Sub, Return]),
+            function("cmp", vec![Load(s("a")), Load(s("b")), Sub, Return]),
+            function("unused", vec![Push(0), Return]),
+            function("main", main),
+            Call(s("main"), 0),
+        ];
+
+        let (_, stats) = eliminate_dead_code(code);
+        assert_eq!(stats.removed_functions, vec![s("unused")]);
+    }
+
+    #[test]
+    fn names_spelled_in_a_live_function_are_kept() {
+        let mut main = literal("helper");
+        main.extend([Call(s("run_by_name"), 1), Drop]);
+        let code = vec![
+            function("run_by_name", vec![Load(s("a")), Return]),
+            function("helper", literal("inner")),
+            function("inner", vec![Return]),
+            function("never", literal("also_never")),
+            function("also_never", vec![Return]),
+            function("main", main),
+            Call(s("main"), 0),
+        ];
+
+        let (_, stats) = eliminate_dead_code(code);
+        assert_eq!(stats.removed_functions, vec![s("never"), s("also_never")]);
+    }
+}
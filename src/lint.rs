@@ -0,0 +1,330 @@
+use crate::grammar::AST;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// The warning classes of `reactive lint`, each of which can be allowed by
+/// name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+    /// A function local that is assigned but never read.
+    UnusedVariable,
+    /// A `:=` binding that hides an immutable of an enclosing scope (outer
+    /// block, parameter or global) of the same name.
+    ShadowedImmutable,
+    /// A plain assignment to a location bound with `::=`, which replaces the
+    /// relationship with a snapshot.
+    SeveredReactive,
+    /// Statements after `return`, `break`, `continue` or `error` in the same
+    /// block.
+    UnreachableCode,
+}
+
+impl LintKind {
+    pub const ALL: [LintKind; 4] = [
+        LintKind::UnusedVariable,
+        LintKind::ShadowedImmutable,
+        LintKind::SeveredReactive,
+        LintKind::UnreachableCode,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LintKind::UnusedVariable => "unused-variable",
+            LintKind::ShadowedImmutable => "shadowed-immutable",
+            LintKind::SeveredReactive => "severed-reactive",
+            LintKind::UnreachableCode => "unreachable-code",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+/// A warning from `lint`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub kind: LintKind,
+    /// Source line of the statement, when the AST came from `parse_spanned`.
+    pub line: Option<usize>,
+    /// Enclosing function, or `None` for top-level code.
+    pub function: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "warning[{}]: ", self.kind.name())?;
+        match &self.function {
+            Some(name) => write!(f, "in {name}(): {}", self.message),
+            None => write!(f, "at top level: {}", self.message),
+        }
+    }
+}
+
+/// Finds likely mistakes in a parsed program (`reactive lint`) that are not
+/// errors: see `LintKind`. Works on one file's AST; imported modules are
+/// not followed, so globals they define are unknown.
+pub fn lint(program: &AST) -> Vec<Lint> {
+    let items = match program {
+        AST::Program(items) => items.as_slice(),
+        other => std::slice::from_ref(other),
+    };
+
+    let mut linter = Linter {
+        globals: HashMap::new(),
+        lints: Vec::new(),
+    };
+    for (line, item) in items.iter().map(unlocated) {
+        if let AST::ImmutableAssign(name, _) = item {
+            linter.globals.entry(name.clone()).or_insert(line);
+        }
+    }
+    for (line, item) in items.iter().map(unlocated) {
+        if let AST::FuncDef { name, params, body } = item {
+            linter.function(name, params, body, line);
+        }
+    }
+    linter.lints.sort_by_key(|lint| lint.line);
+    linter.lints
+}
+
+/// The statement inside any `Located` wrappers, with its line.
+fn unlocated(ast: &AST) -> (Option<usize>, &AST) {
+    let mut line = None;
+    let mut ast = ast;
+    while let AST::Located(l, inner) = ast {
+        line = Some(*l);
+        ast = inner;
+    }
+    (line, ast)
+}
+
+struct Linter {
+    // top-level `:=` bindings and their lines
+    globals: HashMap<String, Option<usize>>,
+    lints: Vec<Lint>,
+}
+
+/// What is known about the function being linted.
+struct Function<'a> {
+    name: &'a str,
+    // immutable scopes, innermost last; the first holds the parameters
+    scopes: Vec<HashMap<&'a str, Option<usize>>>,
+    // names assigned, in order, with the line of the first assignment
+    assigned: Vec<(&'a str, Option<usize>)>,
+    read: HashSet<&'a str>,
+    // locations bound with `::=` (see `target_key`) and their lines
+    reactive: HashMap<String, Option<usize>>,
+}
+
+impl Linter {
+    fn function<'a>(
+        &mut self,
+        name: &'a str,
+        params: &'a [String],
+        body: &'a [AST],
+        line: Option<usize>,
+    ) {
+        let mut f = Function {
+            name,
+            scopes: vec![params.iter().map(|p| (p.as_str(), line)).collect()],
+            assigned: Vec::new(),
+            read: HashSet::new(),
+            reactive: HashMap::new(),
+        };
+        self.block(body, &mut f);
+
+        let mut reported = HashSet::new();
+        for &(var, line) in &f.assigned {
+            if !f.read.contains(var) && !var.starts_with('_') && reported.insert(var) {
+                self.report(
+                    LintKind::UnusedVariable,
+                    line,
+                    &f,
+                    format!("`{var}` is assigned but never read"),
+                );
+            }
+        }
+    }
+
+    fn block<'a>(&mut self, stmts: &'a [AST], f: &mut Function<'a>) {
+        f.scopes.push(HashMap::new());
+        let mut exit = None;
+        let mut reported = false;
+        for stmt in stmts {
+            let (line, inner) = unlocated(stmt);
+            if let Some(exit) = exit
+                && !reported
+            {
+                reported = true;
+                self.report(
+                    LintKind::UnreachableCode,
+                    line,
+                    f,
+                    format!("unreachable statement after `{exit}`"),
+                );
+            }
+            self.statement(inner, line, f);
+            exit = exit.or(match inner {
+                AST::Return(_) => Some("return"),
+                AST::Break => Some("break"),
+                AST::Continue => Some("continue"),
+                AST::Error(_) => Some("error"),
+                _ => None,
+            });
+        }
+        f.scopes.pop();
+    }
+
+    fn statement<'a>(&mut self, stmt: &'a AST, line: Option<usize>, f: &mut Function<'a>) {
+        match stmt {
+            AST::Located(line, inner) => self.statement(inner, Some(*line), f),
+            AST::Assign(name, rhs) => {
+                expr(rhs, f);
+                self.severs(name.clone(), line, f);
+                f.assigned.push((name, line));
+            }
+            AST::ImmutableAssign(name, rhs) => {
+                expr(rhs, f);
+                if let Some(bound) = self.outer_immutable(name, f) {
+                    self.report(
+                        LintKind::ShadowedImmutable,
+                        line,
+                        f,
+                        format!("`{name}` shadows the immutable{}", at_line(bound)),
+                    );
+                }
+                if let Some(scope) = f.scopes.last_mut() {
+                    scope.entry(name).or_insert(line);
+                }
+                f.assigned.push((name, line));
+            }
+            AST::ReactiveAssign(name, rhs) => {
+                expr(rhs, f);
+                f.reactive.insert(name.clone(), line);
+                f.assigned.push((name, line));
+            }
+            AST::AssignTarget(target, rhs) => {
+                expr(target, f);
+                expr(rhs, f);
+                if let Some(key) = target_key(target) {
+                    self.severs(key, line, f);
+                }
+            }
+            AST::ReactiveAssignTarget(target, rhs) => {
+                expr(target, f);
+                expr(rhs, f);
+                if let Some(key) = target_key(target) {
+                    f.reactive.insert(key, line);
+                }
+            }
+            AST::IfElse(cond, then_block, else_block) => {
+                expr(cond, f);
+                self.block(then_block, f);
+                self.block(else_block, f);
+            }
+            AST::Loop(body) => self.block(body, f),
+            AST::FuncDef { name, params, body } => self.function(name, params, body, line),
+            // field initializers run against the struct, not this scope
+            AST::StructDef { .. } => {}
+            other => expr(other, f),
+        }
+    }
+
+    /// Line of an immutable named `name` bound outside the innermost scope
+    /// (`None` inside if it has no line).
+    fn outer_immutable(&self, name: &str, f: &Function) -> Option<Option<usize>> {
+        f.scopes[..f.scopes.len() - 1]
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+            .or_else(|| self.globals.get(name).copied())
+    }
+
+    fn severs(&mut self, key: String, line: Option<usize>, f: &mut Function) {
+        if let Some(bound) = f.reactive.remove(&key) {
+            self.report(
+                LintKind::SeveredReactive,
+                line,
+                f,
+                format!(
+                    "`=` replaces the reactive binding of `{key}`{} with its current value",
+                    at_line(bound)
+                ),
+            );
+        }
+    }
+
+    fn report(&mut self, kind: LintKind, line: Option<usize>, f: &Function, message: String) {
+        self.lints.push(Lint {
+            kind,
+            line,
+            function: Some(f.name.to_string()),
+            message,
+        });
+    }
+}
+
+fn at_line(line: Option<usize>) -> String {
+    match line {
+        Some(line) => format!(" (bound at line {line})"),
+        None => String::new(),
+    }
+}
+
+/// Records the variables an expression reads.
+fn expr<'a>(ast: &'a AST, f: &mut Function<'a>) {
+    match ast {
+        AST::Var(name) => {
+            f.read.insert(name);
+        }
+        AST::Operation(l, _, r) | AST::Index(l, r) => {
+            expr(l, f);
+            expr(r, f);
+        }
+        AST::Ternary {
+            cond,
+            then_expr,
+            else_expr,
+        } => {
+            expr(cond, f);
+            expr(then_expr, f);
+            expr(else_expr, f);
+        }
+        AST::ArrayNew(e)
+        | AST::Neg(e)
+        | AST::Not(e)
+        | AST::FieldAccess(e, _)
+        | AST::Cast { expr: e, .. }
+        | AST::Print(e)
+        | AST::Println(e)
+        | AST::Assert(e)
+        | AST::Return(Some(e))
+        | AST::Located(_, e) => expr(e, f),
+        AST::Call { args, .. } => {
+            for arg in args {
+                expr(arg, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// A name for an assignment target built from variables, fields and
+/// literal or variable indices (`p.pos[i]`); `None` for anything else.
+fn target_key(target: &AST) -> Option<String> {
+    match target {
+        AST::Var(name) => Some(name.clone()),
+        AST::FieldAccess(base, field) => Some(format!("{}.{field}", target_key(base)?)),
+        AST::Index(base, index) => {
+            let index = match index.as_ref() {
+                AST::Number(n) => n.to_string(),
+                AST::Var(name) => name.clone(),
+                _ => return None,
+            };
+            Some(format!("{}[{index}]", target_key(base)?))
+        }
+        _ => None,
+    }
+}
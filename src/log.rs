@@ -0,0 +1,114 @@
+//! Informational messages from the CLI, the compilers and the VM: what was
+//! built, loaded or reused. They go to stderr, so a program's stdout holds
+//! only what the program prints, and `-q` / `-v` choose how many are shown.
+//! This is separate from `std.log`, which programs use for their own logs.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Errors and warnings only (`-q`).
+    Quiet,
+    /// Also what each command did.
+    #[default]
+    Normal,
+    /// Also each step: modules imported, cache hits, compiler phases (`-v`).
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Sets the verbosity for the whole process, threads included.
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+/// Writes `[Reactive] <message>` to stderr if `level` is shown.
+pub fn write(level: Verbosity, message: fmt::Arguments) {
+    if level <= verbosity() {
+        let _ = writeln!(io::stderr().lock(), "[Reactive] {message}");
+    }
+}
+
+/// Logs what a command did, unless `-q` is given.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Verbosity::Normal, format_args!($($arg)*))
+    };
+}
+
+/// Logs a step of the work, only with `-v`.
+#[macro_export]
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Verbosity::Verbose, format_args!($($arg)*))
+    };
+}
+
+/// Output of a program run for the CLI (such as a bootstrap compiler) that
+/// reports its phases as `[Reactive] ...` lines: those are logged at
+/// `level`, everything else (such as a runtime error report) goes to
+/// `inner`.
+pub struct ProgressSink {
+    inner: Box<dyn Write>,
+    level: Verbosity,
+    line: Vec<u8>,
+}
+
+impl ProgressSink {
+    pub fn new(inner: Box<dyn Write>, level: Verbosity) -> Self {
+        Self {
+            inner,
+            level,
+            line: Vec::new(),
+        }
+    }
+
+    fn emit_line(&mut self) -> io::Result<()> {
+        let line = std::mem::take(&mut self.line);
+        let text = String::from_utf8_lossy(&line);
+        match text.trim_end_matches('\n').strip_prefix("[Reactive] ") {
+            Some(message) => {
+                write(self.level, format_args!("{message}"));
+                Ok(())
+            }
+            None => self.inner.write_all(&line),
+        }
+    }
+}
+
+impl Write for ProgressSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.line.push(byte);
+            if byte == b'\n' {
+                self.emit_line()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Drop for ProgressSink {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            let _ = self.emit_line();
+        }
+        let _ = self.inner.flush();
+    }
+}
@@ -0,0 +1,516 @@
+use crate::check::check_located;
+use crate::compiler::Compiler;
+use crate::grammar::{Span, Token};
+use crate::json::{Json, read_framed, write_framed};
+use crate::parser::parse_spanned;
+use crate::tokenizer::tokenize_spanned;
+use crate::vm::VM;
+use std::collections::{HashMap, HashSet};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+// LSP enum values
+const SEVERITY_ERROR: i64 = 1;
+const COMPLETION_FUNCTION: i64 = 3;
+const COMPLETION_VARIABLE: i64 = 6;
+const COMPLETION_STRUCT: i64 = 22;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Serves the Language Server Protocol on stdin/stdout (`reactive lsp`)
+/// until the client sends `exit`. Open documents are compiled with the
+/// native compiler on every change and run through `check_located` for
+/// diagnostics; definitions and completions come from the top-level items
+/// of the document and the modules it imports.
+pub fn serve() {
+    let mut input = BufReader::new(std::io::stdin().lock());
+    let mut server = Server {
+        root: PathBuf::from("project"),
+        documents: HashMap::new(),
+    };
+    while let Some(message) = read_framed(&mut input) {
+        match message {
+            Ok(message) => {
+                if !server.handle(&message) {
+                    return;
+                }
+            }
+            Err(e) => eprintln!("lsp: {e}"),
+        }
+    }
+}
+
+struct Server {
+    // where `import a.b` looks for `a/b.rx`
+    root: PathBuf,
+    // open documents by path, as last sent by the client
+    documents: HashMap<PathBuf, String>,
+}
+
+/// A top-level item: `func`, `struct` or `name := value`.
+struct Symbol {
+    name: String,
+    kind: i64,
+    detail: String,
+    path: PathBuf,
+    span: Span,
+}
+
+impl Server {
+    /// Answers a request or applies a notification; `false` on `exit`.
+    fn handle(&mut self, message: &Json) -> bool {
+        let null = Json::Null;
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&null);
+        let document = params
+            .get("textDocument")
+            .and_then(|doc| doc.get("uri"))
+            .and_then(Json::as_str)
+            .map(uri_to_path);
+
+        let result = match (method, document) {
+            ("initialize", _) => {
+                self.initialize(params);
+                Ok(Json::object([
+                    (
+                        "capabilities",
+                        Json::object([
+                            ("textDocumentSync", Json::from(1_i64)),
+                            ("definitionProvider", Json::from(true)),
+                            ("completionProvider", Json::object::<&str>([])),
+                        ]),
+                    ),
+                    (
+                        "serverInfo",
+                        Json::object([("name", Json::from("reactive"))]),
+                    ),
+                ]))
+            }
+            ("shutdown", _) => Ok(Json::Null),
+            ("exit", _) => return false,
+            ("textDocument/didOpen", Some(path)) => {
+                let text = params
+                    .get("textDocument")
+                    .and_then(|doc| doc.get("text"))
+                    .and_then(Json::as_str);
+                self.documents
+                    .insert(path.clone(), text.unwrap_or("").to_string());
+                self.publish_diagnostics(&path);
+                return true;
+            }
+            ("textDocument/didChange", Some(path)) => {
+                // full sync: the last change holds the whole text
+                let changes = params.get("contentChanges").map(Json::as_array);
+                if let Some(text) = changes
+                    .and_then(<[Json]>::last)
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str)
+                {
+                    self.documents.insert(path.clone(), text.to_string());
+                }
+                self.publish_diagnostics(&path);
+                return true;
+            }
+            ("textDocument/didSave", Some(path)) => {
+                self.publish_diagnostics(&path);
+                return true;
+            }
+            ("textDocument/didClose", Some(path)) => {
+                self.documents.remove(&path);
+                self.notify(
+                    "textDocument/publishDiagnostics",
+                    Json::object([
+                        ("uri", Json::from(path_to_uri(&path))),
+                        ("diagnostics", Json::Array(Vec::new())),
+                    ]),
+                );
+                return true;
+            }
+            ("textDocument/definition", Some(path)) => Ok(self.definition(&path, params)),
+            ("textDocument/completion", Some(path)) => Ok(self.completion(&path)),
+            _ => Err(format!("unsupported method `{method}`")),
+        };
+
+        // notifications get no reply
+        if let Some(id) = message.get("id") {
+            let outcome = match result {
+                Ok(result) => ("result", result),
+                Err(text) => (
+                    "error",
+                    Json::object([
+                        ("code", Json::from(METHOD_NOT_FOUND)),
+                        ("message", Json::from(text)),
+                    ]),
+                ),
+            };
+            send(Json::object([
+                ("jsonrpc", Json::from("2.0")),
+                ("id", id.clone()),
+                outcome,
+            ]));
+        }
+        true
+    }
+
+    /// Imports resolve against `initializationOptions.root`, else the
+    /// workspace's `project/` directory when it has one, else the workspace.
+    fn initialize(&mut self, params: &Json) {
+        let workspace = params
+            .get("rootUri")
+            .and_then(Json::as_str)
+            .map(uri_to_path)
+            .or_else(|| {
+                params
+                    .get("rootPath")
+                    .and_then(Json::as_str)
+                    .map(PathBuf::from)
+            });
+        let configured = params
+            .get("initializationOptions")
+            .and_then(|options| options.get("root"))
+            .and_then(Json::as_str)
+            .map(PathBuf::from);
+
+        if let Some(root) = configured {
+            self.root = match &workspace {
+                Some(workspace) => workspace.join(root),
+                None => root,
+            };
+        } else if let Some(workspace) = workspace {
+            let project = workspace.join("project");
+            self.root = if project.is_dir() { project } else { workspace };
+        }
+        if let Ok(root) = self.root.canonicalize() {
+            self.root = root;
+        }
+    }
+
+    fn notify(&self, method: &str, params: Json) {
+        send(Json::object([
+            ("jsonrpc", Json::from("2.0")),
+            ("method", Json::from(method)),
+            ("params", params),
+        ]));
+    }
+
+    fn publish_diagnostics(&self, path: &Path) {
+        let text = self.text(path).unwrap_or_default();
+        let diagnostics = self
+            .diagnose(path, &text)
+            .into_iter()
+            .map(|(span, message)| {
+                Json::object([
+                    ("range", line_range(&text, span)),
+                    ("severity", Json::from(SEVERITY_ERROR)),
+                    ("source", Json::from("reactive")),
+                    ("message", Json::from(message)),
+                ])
+            })
+            .collect();
+        self.notify(
+            "textDocument/publishDiagnostics",
+            Json::object([
+                ("uri", Json::from(path_to_uri(path))),
+                ("diagnostics", Json::Array(diagnostics)),
+            ]),
+        );
+    }
+
+    /// Problems in `path`, the way `reactive check` finds them: syntax
+    /// errors, then compile errors, then the bytecode checks. Positions are
+    /// spans of the document (line 1 when unknown).
+    fn diagnose(&self, path: &Path, text: &str) -> Vec<(Span, String)> {
+        let tokens = match tokenize_spanned(text) {
+            Ok(tokens) => tokens,
+            Err(e) => return vec![(e.span, e.message)],
+        };
+        let module = !tokens
+            .windows(2)
+            .any(|w| w[0].0 == Token::Func && w[1].0 == Token::Ident("main".to_string()));
+        if let Err(e) = parse_spanned(tokens) {
+            return vec![(e.span, e.message)];
+        }
+
+        let mut compiler = Compiler::new(&self.root).with_debug_info();
+        for (open, contents) in &self.documents {
+            compiler = compiler.with_source(open, contents.clone());
+        }
+        let compiled = if module {
+            compiler.compile_module_file(path)
+        } else {
+            compiler.compile_file(path)
+        };
+        let code = match compiled {
+            Ok(code) => code,
+            Err(e) => return vec![(Span::default(), e)],
+        };
+        let info = compiler.take_debug_info().unwrap_or_default();
+
+        check_located(&code, &info, module)
+            .into_iter()
+            .filter_map(|diagnostic| {
+                let span = match diagnostic.loc {
+                    // problems inside imported modules are theirs to report
+                    Some(loc) if info.files[loc.file] != path => return None,
+                    Some(loc) => Span {
+                        line: loc.line,
+                        ..Span::default()
+                    },
+                    None => Span::default(),
+                };
+                Some((span, diagnostic.to_string()))
+            })
+            .collect()
+    }
+
+    fn definition(&self, path: &Path, params: &Json) -> Json {
+        let text = self.text(path).unwrap_or_default();
+        let position = params.get("position");
+        let line = position.and_then(|p| p.get("line")).and_then(Json::as_i64);
+        let character = position
+            .and_then(|p| p.get("character"))
+            .and_then(Json::as_i64);
+        let (Some(line), Some(character)) = (line, character) else {
+            return Json::Null;
+        };
+        let Some(word) = word_at(&text, line as usize, character as usize) else {
+            return Json::Null;
+        };
+
+        let (symbols, _) = self.symbols(path);
+        let locations = symbols
+            .iter()
+            .filter(|symbol| symbol.name == word)
+            .map(|symbol| {
+                let text = self.text(&symbol.path).unwrap_or_default();
+                Json::object([
+                    ("uri", Json::from(path_to_uri(&symbol.path))),
+                    ("range", token_range(&text, symbol.span)),
+                ])
+            })
+            .collect();
+        Json::Array(locations)
+    }
+
+    fn completion(&self, path: &Path) -> Json {
+        let (symbols, imports) = self.symbols(path);
+        let mut seen = HashSet::new();
+        let mut items = Vec::new();
+        for symbol in &symbols {
+            if seen.insert(symbol.name.clone()) {
+                items.push(completion_item(&symbol.name, symbol.kind, &symbol.detail));
+            }
+        }
+        let mut natives: Vec<String> = VM::native_names(&imports).into_iter().collect();
+        natives.sort();
+        for name in natives {
+            if seen.insert(name.clone()) {
+                items.push(completion_item(&name, COMPLETION_FUNCTION, "native"));
+            }
+        }
+        Json::Array(items)
+    }
+
+    /// Top-level items of `path` and of every module it imports, with the
+    /// import paths seen.
+    fn symbols(&self, path: &Path) -> (Vec<Symbol>, Vec<Vec<String>>) {
+        let mut symbols = Vec::new();
+        let mut imports = Vec::new();
+        let mut pending = vec![path.to_path_buf()];
+        let mut visited = HashSet::new();
+        while let Some(file) = pending.pop() {
+            if !visited.insert(file.clone()) {
+                continue;
+            }
+            let Some(text) = self.text(&file) else {
+                continue;
+            };
+            for import in scan(&file, &text, &mut symbols) {
+                let mut module = self.root.clone();
+                module.extend(&import);
+                pending.push(module.with_extension("rx"));
+                imports.push(import);
+            }
+        }
+        (symbols, imports)
+    }
+
+    fn text(&self, path: &Path) -> Option<String> {
+        match self.documents.get(path) {
+            Some(text) => Some(text.clone()),
+            None => std::fs::read_to_string(path).ok(),
+        }
+    }
+}
+
+/// Collects the top-level items of one file into `symbols`, mirroring
+/// `Parser::parse_toplevel`, and returns its imports. Works on tokens only,
+/// so it still finds items while the file does not parse.
+fn scan(path: &Path, text: &str, symbols: &mut Vec<Symbol>) -> Vec<Vec<String>> {
+    let Ok(tokens) = tokenize_spanned(text) else {
+        return Vec::new();
+    };
+    let ident = |i: usize| match tokens.get(i) {
+        Some((Token::Ident(name), span)) => Some((name.clone(), *span)),
+        _ => None,
+    };
+    let mut imports = Vec::new();
+    let mut depth = 0usize;
+    for (i, (token, _)) in tokens.iter().enumerate() {
+        match token {
+            Token::LBrace => depth += 1,
+            Token::RBrace => depth = depth.saturating_sub(1),
+            _ if depth > 0 => {}
+            Token::Import => {
+                let mut module = Vec::new();
+                let mut j = i + 1;
+                while let Some((name, _)) = ident(j) {
+                    module.push(name);
+                    if tokens.get(j + 1).map(|t| &t.0) != Some(&Token::Dot) {
+                        break;
+                    }
+                    j += 2;
+                }
+                imports.push(module);
+            }
+            Token::Func => {
+                if let Some((name, span)) = ident(i + 1) {
+                    let params: Vec<String> = (i + 3..)
+                        .map_while(|j| match tokens.get(j).map(|t| &t.0) {
+                            Some(Token::Ident(param)) => Some(Some(param.clone())),
+                            Some(Token::Comma) => Some(None),
+                            _ => None,
+                        })
+                        .flatten()
+                        .collect();
+                    let detail = format!("func {name}({})", params.join(", "));
+                    symbols.push(symbol(path, name, COMPLETION_FUNCTION, detail, span));
+                }
+            }
+            Token::Struct if tokens.get(i + 2).map(|t| &t.0) == Some(&Token::LBrace) => {
+                if let Some((name, span)) = ident(i + 1) {
+                    let detail = format!("struct {name}");
+                    symbols.push(symbol(path, name, COMPLETION_STRUCT, detail, span));
+                }
+            }
+            Token::Ident(name)
+                if tokens.get(i + 1).map(|t| &t.0) == Some(&Token::ImmutableAssign) =>
+            {
+                let detail = format!("{name} :=");
+                let span = tokens[i].1;
+                symbols.push(symbol(
+                    path,
+                    name.clone(),
+                    COMPLETION_VARIABLE,
+                    detail,
+                    span,
+                ));
+            }
+            _ => {}
+        }
+    }
+    imports
+}
+
+fn symbol(path: &Path, name: String, kind: i64, detail: String, span: Span) -> Symbol {
+    Symbol {
+        name,
+        kind,
+        detail,
+        path: path.to_path_buf(),
+        span,
+    }
+}
+
+fn completion_item(label: &str, kind: i64, detail: &str) -> Json {
+    Json::object([
+        ("label", Json::from(label)),
+        ("kind", Json::from(kind)),
+        ("detail", Json::from(detail)),
+    ])
+}
+
+fn send(message: Json) {
+    let _ = write_framed(&mut std::io::stdout().lock(), &message);
+}
+
+/// The identifier under 0-based `line` / `character`.
+fn word_at(text: &str, line: usize, character: usize) -> Option<String> {
+    let chars: Vec<char> = text.lines().nth(line)?.chars().collect();
+    let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+    let at = character.min(chars.len());
+    let start = chars[..at].iter().rev().take_while(|c| is_word(c)).count();
+    let end = chars[at..].iter().take_while(|c| is_word(c)).count();
+    let word: String = chars[at - start..at + end].iter().collect();
+    (!word.is_empty()).then_some(word)
+}
+
+fn position(line: usize, character: usize) -> Json {
+    Json::object([
+        ("line", Json::from(line)),
+        ("character", Json::from(character)),
+    ])
+}
+
+/// From the span's column (or the line's indentation when it has none) to
+/// the end of its line; line 1 for `Span::default()`.
+fn line_range(text: &str, span: Span) -> Json {
+    let line = span.line.max(1) - 1;
+    let source = text.lines().nth(line).unwrap_or("");
+    let length = source.chars().count();
+    let start = match span.column {
+        0 => source.chars().take_while(|c| c.is_whitespace()).count(),
+        column => column - 1,
+    };
+    Json::object([
+        ("start", position(line, start.min(length))),
+        ("end", position(line, length)),
+    ])
+}
+
+/// The span of a single-line token.
+fn token_range(text: &str, span: Span) -> Json {
+    let length = text
+        .get(span.start..span.end)
+        .map_or(0, |t| t.chars().count());
+    let line = span.line.max(1) - 1;
+    let start = span.column.max(1) - 1;
+    Json::object([
+        ("start", position(line, start)),
+        ("end", position(line, start + length)),
+    ])
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+    let encoded = uri.strip_prefix("file://").unwrap_or(uri).as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let escaped = (encoded[i] == b'%')
+            .then(|| encoded.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            None => {
+                bytes.push(encoded[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    uri
+}
@@ -1,12 +1,64 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use std::env;
+use std::io::{Read, Write};
+use std::panic::AssertUnwindSafe;
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use reactive::bytecode::read_instructions_from_file;
+use reactive::archive::{Archive, ArchiveModule, has_archive_header, is_archive};
+use reactive::bcdiff::diff_bytecode;
+use reactive::build::{artifact_path, build};
+use reactive::bytecode::{
+    COMPILER_VERSION, Metadata, is_bytecode, read_bytecode_file, read_instructions_from_file,
+    source_hash, verify_round_trip_file, write_bytecode_file,
+};
+use reactive::check::{check, check_located, check_module};
+use reactive::compiler::Compiler;
+use reactive::debug_info::DebugInfo;
+use reactive::diagnostic::{ErrorFormat, Record};
+use reactive::formatter::format_source;
 use reactive::grammar::Instruction;
-use reactive::vm::VM;
+use reactive::graph::{call_graph, import_graph};
+use reactive::info;
+use reactive::json::Json;
+use reactive::linker::eliminate_dead_code;
+use reactive::lint::{LintKind, lint};
+use reactive::log::{ProgressSink, Verbosity, set_verbosity, verbosity};
+use reactive::manifest::{CompilerChoice, MANIFEST_FILE, Manifest};
+use reactive::package::fetch;
+use reactive::parser::parse_spanned;
+use reactive::strip::strip_names;
+use reactive::tokenizer::{tokenize_spanned, tokens_json};
+use reactive::vm::superinstr::{NgramProfile, fuse};
+use reactive::vm::timing::time_table;
+use reactive::vm::{
+    LogLevel, OverflowPolicy, RuntimeError, ShadowPolicy, VM, VM_STACK_SIZE, VmOptions,
+};
 
 fn main() {
-    let args: Vec<String> = env::args().skip(1).collect();
+    // The VM needs a deeper native stack than the main thread gets.
+    let cli = std::thread::Builder::new()
+        .stack_size(VM_STACK_SIZE)
+        .spawn(cli)
+        .unwrap_or_else(|e| exit_error(&format!("failed to start: {e}")));
+    if cli.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+fn cli() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let quiet = take_flag(&mut args, "-q") | take_flag(&mut args, "--quiet");
+    let verbose = take_flag(&mut args, "-v");
+    match (quiet, verbose) {
+        (true, true) => exit_error("-q and -v cannot be used together"),
+        (true, false) => set_verbosity(Verbosity::Quiet),
+        (false, true) => set_verbosity(Verbosity::Verbose),
+        (false, false) => {}
+    }
     if args.is_empty() {
         print_help();
     }
@@ -33,12 +85,14 @@ fn main() {
                 return;
             }
 
-            Some("rxb") => {
+            Some("rxb" | "rxb2" | "rxz" | "rxa") => {
                 let path = resolve_path(&args[0], "rxb");
-                let code = read_instructions_from_file(path.to_str().unwrap())
-                    .unwrap_or_else(|e| exit_error(&e));
+                let (code, metadata, modules) = load_program(&path);
+                report_metadata(&path, metadata.as_ref(), false);
 
-                VM::new(code).run();
+                let mut vm = VM::new(code);
+                vm.set_archive_modules(modules);
+                vm.run();
                 return;
             }
 
@@ -54,6 +108,92 @@ fn main() {
             print_help();
         }
 
+        // ------------------------------------------------------------
+        // Scaffold a new project directory
+        // ------------------------------------------------------------
+        "new" => {
+            if args.len() != 2 {
+                exit_error("Usage: reactive new <dir>");
+            }
+
+            let dir = PathBuf::from(&args[1]);
+            new_project(&dir).unwrap_or_else(|e| exit_error(&e));
+            info!("Created project in {}", dir.display());
+        }
+
+        // ------------------------------------------------------------
+        // Vendor the project's dependencies
+        // ------------------------------------------------------------
+        "fetch" => {
+            let update = take_flag(&mut args, "--update");
+            if args.len() != 1 {
+                exit_error("Usage: reactive fetch [--update]");
+            }
+
+            let project = load_project();
+            let locked = fetch(&project, update).unwrap_or_else(|e| exit_error(&e));
+            for entry in &locked {
+                let source = match &entry.commit {
+                    Some(commit) => {
+                        format!("{} @ {}", entry.source, &commit[..commit.len().min(12)])
+                    }
+                    None => entry.source.clone(),
+                };
+                info!("Fetched {} ({source}, {} file(s))", entry.name, entry.files);
+            }
+            info!(
+                "{} dependency(ies) in {}",
+                locked.len(),
+                project.deps_dir().display()
+            );
+        }
+
+        // ------------------------------------------------------------
+        // Incremental build of the project into target/
+        // ------------------------------------------------------------
+        "build" => {
+            if args.len() != 1 {
+                exit_error("Usage: reactive build");
+            }
+
+            let project = load_project();
+            verify_dependencies(&project).unwrap_or_else(|e| exit_error(&e));
+            if project.compiler != CompilerChoice::Native {
+                eprintln!("warning: reactive build always uses the native compiler");
+            }
+            let built = build(&project, Path::new("project")).unwrap_or_else(|e| exit_error(&e));
+            let output = artifact_path(&project);
+            let metadata = Metadata::new("native", &read_source(&project.entry), 0);
+            write_bytecode_file(path_str(&output), &built.code, Some(&metadata))
+                .unwrap_or_else(|e| exit_error(&e));
+            if project.opt_level >= 1 {
+                strip_dead_code(&output);
+            }
+
+            let rows: Vec<(String, Duration)> = built
+                .modules
+                .iter()
+                .map(|module| {
+                    let state = if module.cached { "cached" } else { "compiled" };
+                    (format!("{} ({state})", module.name), module.time)
+                })
+                .chain([("link".to_string(), built.link_time)])
+                .collect();
+            let rows: Vec<(&str, Duration)> = rows
+                .iter()
+                .map(|(name, time)| (name.as_str(), *time))
+                .collect();
+            let compiled = built.modules.iter().filter(|m| !m.cached).count();
+            if verbosity() > Verbosity::Quiet {
+                eprint!("{}", time_table(&rows));
+            }
+            info!(
+                "Built {} ({} module(s), {compiled} compiled)",
+                output.display(),
+                built.modules.len()
+            );
+        }
+
         // ------------------------------------------------------------
         // Bootstrap experimental compiler using stable compiler
         // ------------------------------------------------------------
@@ -67,81 +207,1048 @@ fn main() {
             let output = PathBuf::from("project/bootstrap/experimental/compiler.rxb");
 
             run_compiler_vm_entry(&compiler, &input, &output, "compile_file_module");
+
+            // A serializer bug would otherwise only surface when the next
+            // compiler built from this one misbehaves.
+            verify_round_trip_file(output.to_str().unwrap()).unwrap_or_else(|e| {
+                exit_error(&format!("bootstrap output failed round-trip check: {e}"))
+            });
         }
 
         // ------------------------------------------------------------
         // Compile program with stable compiler (requires main)
         // ------------------------------------------------------------
         "compile" => {
-            if args.len() < 2 || args.len() > 3 {
-                exit_error("Usage: reactive compile <input.rx> [output.rxb]");
+            let reports = RunReports::take(&mut args);
+            let strip_dead = take_flag(&mut args, "--strip-dead");
+            let output_flag = take_flag_value(&mut args, "-o");
+            let format = take_error_format(&mut args);
+            if args.len() == 1 && output_flag.is_none() {
+                let project = load_project();
+                let output = build_project(&project);
+                info!("Built {} into {}", project.name, output.display());
+                return;
+            }
+            if args.len() < 2 || args.len() > 3 || (args.len() == 3 && output_flag.is_some()) {
+                exit_error(
+                    "Usage: reactive compile [<input.rx|-> [output.rxb|-]] [-o <output.rxb|->] \
+                     [--strip-dead] [--error-format=<human|json>] [--time] [--coverage <out>]",
+                );
             }
 
             let compiler = PathBuf::from("project/bootstrap/stable/compiler.rxb");
-            let input = resolve_path(&args[1], "rx");
-            let output = output_path(&input, args.get(2));
+            let input = source_input(&args[1]);
+            let output =
+                BytecodeOutput::new(&input, &args[1], output_flag.as_ref().or(args.get(2)));
+            if format == ErrorFormat::Json {
+                // the bootstrap compiler reports errors as runtime errors,
+                // so find them with the (equivalent) native compiler first
+                if let Err(e) = Compiler::new("project")
+                    .with_debug_info()
+                    .compile_file(&input)
+                {
+                    compile_failed(&input, &args[1], &e, format);
+                }
+            }
 
-            run_compiler_vm_entry(&compiler, &input, &output, "compile_file");
+            run_compiler_vm(
+                &compiler,
+                &input,
+                &output.path,
+                "compile_file",
+                &reports,
+                output.log(),
+            );
+            stamp_metadata(&compiler, &input, &output.path);
+            if strip_dead {
+                strip_dead_code(&output.path);
+            }
+            output.finish();
+            remove_stdin_input(&input, &args[1]);
         }
 
         // ------------------------------------------------------------
         // Compile module with stable compiler (no main required)
         // ------------------------------------------------------------
         "compile-module" => {
+            let reports = RunReports::take(&mut args);
             if args.len() < 2 || args.len() > 3 {
-                exit_error("Usage: reactive compile-module <input.rx> [output.rxb]");
+                exit_error(
+                    "Usage: reactive compile-module <input.rx> [output.rxb] [--time] [--coverage <out>]",
+                );
             }
 
             let compiler = PathBuf::from("project/bootstrap/stable/compiler.rxb");
             let input = resolve_path(&args[1], "rx");
             let output = output_path(&input, args.get(2));
 
-            run_compiler_vm_entry(&compiler, &input, &output, "compile_file_module");
+            run_compiler_vm(
+                &compiler,
+                &input,
+                &output,
+                "compile_file_module",
+                &reports,
+                Box::new(std::io::stdout()),
+            );
+            stamp_metadata(&compiler, &input, &output);
         }
 
         // ------------------------------------------------------------
         // Compile program with expiermental compiler (requires main)
         // ------------------------------------------------------------
         "compile-expi" => {
+            let reports = RunReports::take(&mut args);
+            let strip_dead = take_flag(&mut args, "--strip-dead");
             if args.len() < 2 || args.len() > 3 {
-                exit_error("Usage: reactive compile-experimental <input.rx> [output.rxb]");
+                exit_error(
+                    "Usage: reactive compile-experimental <input.rx> [output.rxb] [--strip-dead] [--time] \
+                     [--coverage <out>]",
+                );
             }
 
             let compiler = PathBuf::from("project/bootstrap/experimental/compiler.rxb");
             let input = resolve_path(&args[1], "rx");
             let output = output_path(&input, args.get(2));
 
-            run_compiler_vm_entry(&compiler, &input, &output, "compile_file");
+            run_compiler_vm(
+                &compiler,
+                &input,
+                &output,
+                "compile_file",
+                &reports,
+                Box::new(std::io::stdout()),
+            );
+            stamp_metadata(&compiler, &input, &output);
+            if strip_dead {
+                strip_dead_code(&output);
+            }
         }
 
         // ------------------------------------------------------------
         // Compile module with experimental compiler (no main required)
         // ------------------------------------------------------------
         "compile-expi-module" => {
+            let reports = RunReports::take(&mut args);
             if args.len() < 2 || args.len() > 3 {
-                exit_error("Usage: reactive compile-experimental <input.rx> [output.rxb]");
+                exit_error(
+                    "Usage: reactive compile-experimental <input.rx> [output.rxb] [--time] [--coverage <out>]",
+                );
             }
 
             let compiler = PathBuf::from("project/bootstrap/experimental/compiler.rxb");
             let input = resolve_path(&args[1], "rx");
             let output = output_path(&input, args.get(2));
 
-            run_compiler_vm_entry(&compiler, &input, &output, "compile_file_module");
+            run_compiler_vm(
+                &compiler,
+                &input,
+                &output,
+                "compile_file_module",
+                &reports,
+                Box::new(std::io::stdout()),
+            );
+            stamp_metadata(&compiler, &input, &output);
+        }
+
+        // ------------------------------------------------------------
+        // Compile program with the native Rust compiler (requires main)
+        // ------------------------------------------------------------
+        "compile-native" => {
+            let time = take_flag(&mut args, "--time");
+            let strip_dead = take_flag(&mut args, "--strip-dead");
+            let output_flag = take_flag_value(&mut args, "-o");
+            let format = take_error_format(&mut args);
+            if args.len() < 2 || args.len() > 3 || (args.len() == 3 && output_flag.is_some()) {
+                exit_error(
+                    "Usage: reactive compile-native <input.rx|-> [output.rxb|-] [-o <output.rxb|->] \
+                     [--strip-dead] [--error-format=<human|json>] [--time]",
+                );
+            }
+
+            let input = source_input(&args[1]);
+            let output =
+                BytecodeOutput::new(&input, &args[1], output_flag.as_ref().or(args.get(2)));
+
+            let start = Instant::now();
+            let mut compiler = Compiler::new("project");
+            if format == ErrorFormat::Json {
+                compiler = compiler.with_debug_info();
+            }
+            let code = compiler
+                .compile_file(&input)
+                .unwrap_or_else(|e| compile_failed(&input, &args[1], &e, format));
+            let compiled = Instant::now();
+            let metadata = Metadata::new("native", &read_source(&input), 0);
+            write_bytecode_file(path_str(&output.path), &code, Some(&metadata))
+                .unwrap_or_else(|e| exit_error(&e));
+            if time {
+                eprint!(
+                    "[Reactive] Time:\n{}",
+                    time_table(&[
+                        ("compile", compiled - start),
+                        ("write bytecode", compiled.elapsed()),
+                    ])
+                );
+            }
+            if strip_dead {
+                strip_dead_code(&output.path);
+            }
+            output.finish();
+            remove_stdin_input(&input, &args[1]);
+        }
+
+        // ------------------------------------------------------------
+        // Compile module with the native Rust compiler (no main required)
+        // ------------------------------------------------------------
+        "compile-native-module" => {
+            let time = take_flag(&mut args, "--time");
+            if args.len() < 2 || args.len() > 3 {
+                exit_error(
+                    "Usage: reactive compile-native-module <input.rx> [output.rxb] [--time]",
+                );
+            }
+
+            let input = resolve_path(&args[1], "rx");
+            let output = output_path(&input, args.get(2));
+
+            let start = Instant::now();
+            let code = Compiler::new("project")
+                .compile_module_file(&input)
+                .unwrap_or_else(|e| exit_error(&e));
+            let compiled = Instant::now();
+            let metadata = Metadata::new("native", &read_source(&input), 0);
+            write_bytecode_file(output.to_str().unwrap(), &code, Some(&metadata))
+                .unwrap_or_else(|e| exit_error(&e));
+            if time {
+                eprint!(
+                    "[Reactive] Time:\n{}",
+                    time_table(&[
+                        ("compile", compiled - start),
+                        ("write bytecode", compiled.elapsed()),
+                    ])
+                );
+            }
+        }
+
+        // ------------------------------------------------------------
+        // Compile and validate without running
+        // ------------------------------------------------------------
+        "check" => {
+            let experimental = take_flag(&mut args, "--expi");
+            let module = take_flag(&mut args, "--module");
+            let format = take_error_format(&mut args);
+            if args.len() != 2 {
+                exit_error(
+                    "Usage: reactive check <input.rx|input.rxb> [--expi] [--module] \
+                     [--error-format=<human|json>]",
+                );
+            }
+
+            let input = resolve_path(&args[1], "rx");
+            let compiler = if experimental {
+                PathBuf::from("project/bootstrap/experimental/compiler.rxb")
+            } else {
+                PathBuf::from("project/bootstrap/stable/compiler.rxb")
+            };
+            let entry = if module {
+                "compile_file_module"
+            } else {
+                "compile_file"
+            };
+            // JSON records carry lines, so compile natively with debug info
+            // unless the experimental compiler is asked for
+            let native = format == ErrorFormat::Json
+                && !experimental
+                && input.extension().is_some_and(|e| e == "rx");
+            let (code, info) = if native {
+                let mut compiler = Compiler::new("project").with_debug_info();
+                let code = if module {
+                    compiler.compile_module_file(&input)
+                } else {
+                    compiler.compile_file(&input)
+                }
+                .unwrap_or_else(|e| compile_failed(&input, &args[1], &e, format));
+                (code, compiler.take_debug_info())
+            } else {
+                (load_or_compile(&compiler, &input, entry), None)
+            };
+
+            let diagnostics = match &info {
+                Some(info) => check_located(&code, info, module),
+                None if module => check_module(&code),
+                None => check(&code),
+            };
+            if diagnostics.is_empty() {
+                info!("Check: no problems found");
+                return;
+            }
+            for diagnostic in &diagnostics {
+                match format {
+                    ErrorFormat::Human => eprintln!("{}: error: {diagnostic}", input.display()),
+                    ErrorFormat::Json => eprintln!(
+                        "{}",
+                        Record::from_check(&input, diagnostic, info.as_ref()).to_json()
+                    ),
+                }
+            }
+            if format == ErrorFormat::Json {
+                std::process::exit(1);
+            }
+            exit_error(&format!(
+                "[Reactive] Check: {} problem(s) found",
+                diagnostics.len()
+            ));
+        }
+
+        // ------------------------------------------------------------
+        // Differential test: stable vs experimental compiler
+        // ------------------------------------------------------------
+        "conformance" => {
+            let timeout = take_flag_value(&mut args, "--timeout");
+            if args.len() != 2 {
+                exit_error("Usage: reactive conformance <dir> [--timeout <secs>]");
+            }
+
+            let timeout = match timeout {
+                Some(secs) => secs.parse::<u64>().unwrap_or_else(|_| {
+                    exit_error(&format!("invalid timeout `{secs}` (expected seconds)"))
+                }),
+                None => 10,
+            };
+
+            let mut files = Vec::new();
+            collect_rx_files(Path::new(&args[1]), &mut files);
+            files.sort();
+            if files.is_empty() {
+                exit_error(&format!("no .rx files found in `{}`", args[1]));
+            }
+
+            let mut divergences = 0;
+            for file in &files {
+                match conformance_divergence(file, Duration::from_secs(timeout)) {
+                    None => println!("ok       {}", file.display()),
+                    Some(report) => {
+                        divergences += 1;
+                        println!("DIVERGE  {}", file.display());
+                        for line in report.lines() {
+                            println!("    {line}");
+                        }
+                    }
+                }
+            }
+
+            if divergences > 0 {
+                exit_error(&format!(
+                    "[Reactive] Conformance: {divergences} of {} file(s) diverged",
+                    files.len()
+                ));
+            }
+            info!("Conformance: {} file(s), no divergences", files.len());
+        }
+
+        // ------------------------------------------------------------
+        // Golden-output tests: compare stdout with `.expected` files
+        // ------------------------------------------------------------
+        "test" => {
+            let golden = take_flag_value(&mut args, "--golden");
+            let update = take_flag(&mut args, "--update");
+            if args.len() != 1 {
+                exit_error("Usage: reactive test [--golden <dir>] [--update]");
+            }
+            let project = golden.is_none().then(load_project);
+            if let Some(project) = &project {
+                verify_dependencies(project).unwrap_or_else(|e| exit_error(&e));
+            }
+            let dir = match (&golden, &project) {
+                (Some(dir), _) => dir.clone(),
+                (None, Some(project)) => path_str(&project.test_dir).to_string(),
+                (None, None) => unreachable!(),
+            };
+
+            let mut files = Vec::new();
+            collect_rx_files(Path::new(&dir), &mut files);
+            files.sort();
+            if files.is_empty() {
+                exit_error(&format!("no .rx files found in `{dir}`"));
+            }
+
+            let mut failed = 0;
+            for file in &files {
+                let expected_path = file.with_extension("expected");
+                let actual = match golden_output(file, project.as_ref()) {
+                    Ok(actual) => actual,
+                    Err(e) => {
+                        failed += 1;
+                        println!("FAIL     {}", file.display());
+                        println!("    compile error: {e}");
+                        continue;
+                    }
+                };
+
+                if update {
+                    std::fs::write(&expected_path, &actual).unwrap_or_else(|e| {
+                        exit_error(&format!(
+                            "failed to write `{}`: {e}",
+                            expected_path.display()
+                        ))
+                    });
+                    println!("updated  {}", expected_path.display());
+                    continue;
+                }
+
+                match std::fs::read_to_string(&expected_path) {
+                    Ok(expected) if expected == actual => println!("ok       {}", file.display()),
+                    Ok(expected) => {
+                        failed += 1;
+                        println!("FAIL     {}", file.display());
+                        let diff = first_difference(&expected, &actual, ["expected", "actual"]);
+                        for line in diff.lines() {
+                            println!("    {line}");
+                        }
+                    }
+                    Err(_) => {
+                        failed += 1;
+                        println!("FAIL     {}", file.display());
+                        println!(
+                            "    missing `{}` (run with --update to create it)",
+                            expected_path.display()
+                        );
+                    }
+                }
+            }
+
+            if update {
+                info!("Golden: updated {} file(s)", files.len() - failed);
+            } else {
+                info!("Golden: {} passed, {failed} failed", files.len() - failed);
+            }
+            if failed > 0 {
+                std::process::exit(1);
+            }
+        }
+
+        // ------------------------------------------------------------
+        // Canonical source formatting
+        // ------------------------------------------------------------
+        "fmt" => {
+            let check = take_flag(&mut args, "--check");
+            if args.len() < 2 {
+                exit_error("Usage: reactive fmt [--check] <input.rx|dir>...");
+            }
+
+            let mut files = Vec::new();
+            for arg in &args[1..] {
+                let path = PathBuf::from(arg);
+                if path.is_dir() {
+                    collect_rx_files(&path, &mut files);
+                } else {
+                    files.push(resolve_path(arg, "rx"));
+                }
+            }
+            files.sort();
+
+            let mut changed = 0;
+            let mut failed = 0;
+            for file in &files {
+                let source = match std::fs::read_to_string(file) {
+                    Ok(source) => source,
+                    Err(e) => {
+                        eprintln!("{}: error: failed to read: {e}", file.display());
+                        failed += 1;
+                        continue;
+                    }
+                };
+                let formatted = match format_source(&source) {
+                    Ok(formatted) => formatted,
+                    Err(e) => {
+                        eprintln!("{}:{e}", file.display());
+                        failed += 1;
+                        continue;
+                    }
+                };
+                if formatted == source {
+                    continue;
+                }
+                changed += 1;
+                if check {
+                    eprintln!("{}: not formatted", file.display());
+                } else {
+                    std::fs::write(file, formatted).unwrap_or_else(|e| {
+                        exit_error(&format!("failed to write `{}`: {e}", file.display()))
+                    });
+                }
+            }
+
+            if failed > 0 {
+                exit_error(&format!(
+                    "[Reactive] Fmt: {failed} file(s) could not be formatted"
+                ));
+            }
+            if check && changed > 0 {
+                exit_error(&format!(
+                    "[Reactive] Fmt: {changed} file(s) need formatting"
+                ));
+            }
+            if check {
+                info!("Fmt: {} file(s) formatted", files.len());
+            } else {
+                info!("Fmt: reformatted {changed} of {} file(s)", files.len());
+            }
+        }
+
+        // ------------------------------------------------------------
+        // Warnings for likely mistakes
+        // ------------------------------------------------------------
+        "lint" => {
+            let deny = take_flag(&mut args, "--deny");
+            let allow = take_flag_value(&mut args, "--allow");
+            let format = take_error_format(&mut args);
+            if args.len() < 2 {
+                exit_error(
+                    "Usage: reactive lint <input.rx|dir>... [--allow <lints>] [--deny] \
+                     [--error-format=<human|json>]",
+                );
+            }
+
+            let mut allowed = Vec::new();
+            for name in allow.iter().flat_map(|a| a.split(',')) {
+                match LintKind::parse(name.trim()) {
+                    Some(kind) => allowed.push(kind),
+                    None => exit_error(&format!(
+                        "unknown lint `{name}` (expected one of: {})",
+                        LintKind::ALL.map(LintKind::name).join(", ")
+                    )),
+                }
+            }
+
+            let mut files = Vec::new();
+            for arg in &args[1..] {
+                let path = PathBuf::from(arg);
+                if path.is_dir() {
+                    collect_rx_files(&path, &mut files);
+                } else {
+                    files.push(resolve_path(arg, "rx"));
+                }
+            }
+            files.sort();
+
+            let mut warnings = 0;
+            for file in &files {
+                let source = std::fs::read_to_string(file).unwrap_or_else(|e| {
+                    exit_error(&format!("failed to read `{}`: {e}", file.display()))
+                });
+                let ast = tokenize_spanned(&source)
+                    .and_then(parse_spanned)
+                    .unwrap_or_else(|e| {
+                        let error = format!("{}:{e}", file.display());
+                        compile_failed(file, &file.to_string_lossy(), &error, format)
+                    });
+                for warning in lint(&ast) {
+                    if allowed.contains(&warning.kind) {
+                        continue;
+                    }
+                    warnings += 1;
+                    match (format, warning.line) {
+                        (ErrorFormat::Json, _) => {
+                            eprintln!("{}", Record::from_lint(file, &warning).to_json())
+                        }
+                        (_, Some(line)) => eprintln!("{}:{line}: {warning}", file.display()),
+                        (_, None) => eprintln!("{}: {warning}", file.display()),
+                    }
+                }
+            }
+
+            if warnings == 0 {
+                info!("Lint: no warnings");
+            } else if deny && format == ErrorFormat::Json {
+                std::process::exit(1);
+            } else if deny {
+                exit_error(&format!("[Reactive] Lint: {warnings} warning(s)"));
+            } else {
+                info!("Lint: {warnings} warning(s)");
+            }
+        }
+
+        // ------------------------------------------------------------
+        // Dump tokens with spans as JSON
+        // ------------------------------------------------------------
+        "tokens" => {
+            if args.len() != 2 {
+                exit_error("Usage: reactive tokens <input.rx>");
+            }
+
+            let path = resolve_path(&args[1], "rx");
+            let source = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+                exit_error(&format!("failed to read `{}`: {e}", path.display()))
+            });
+            let tokens = tokenize_spanned(&source)
+                .unwrap_or_else(|e| exit_error(&format!("{}:{e}", path.display())));
+            let lines: Vec<String> = tokens_json(&tokens).iter().map(Json::to_string).collect();
+            if lines.is_empty() {
+                println!("[]");
+            } else {
+                println!("[\n{}\n]", lines.join(",\n"));
+            }
+        }
+
+        // ------------------------------------------------------------
+        // Bundle a program and the modules it imports into one archive
+        // ------------------------------------------------------------
+        "pack" => {
+            if args.len() < 2 || args.len() > 3 {
+                exit_error("Usage: reactive pack <input.rx> [output.rxa]");
+            }
+            let input = resolve_path(&args[1], "rx");
+            let output = args
+                .get(2)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| input.with_extension("rxa"));
+
+            let archive = pack(&input, false).unwrap_or_else(|e| exit_error(&e));
+            archive
+                .write_file(path_str(&output))
+                .unwrap_or_else(|e| exit_error(&e));
+            info!(
+                "Packed {} module(s) into {}",
+                archive.modules.len(),
+                output.display()
+            );
+        }
+
+        // ------------------------------------------------------------
+        // Import or call graph as Graphviz dot or JSON
+        // ------------------------------------------------------------
+        "graph" => {
+            let imports = take_flag(&mut args, "--imports");
+            let calls = take_flag(&mut args, "--calls");
+            let json = take_flag(&mut args, "--json");
+            let module = take_flag(&mut args, "--module");
+            let output = take_flag_value(&mut args, "-o");
+            if args.len() != 2 || imports == calls {
+                exit_error(
+                    "Usage: reactive graph <input.rx|input.rxb|input.rxa> (--imports | --calls) \
+                     [--module] [--json] [-o <out>]",
+                );
+            }
+
+            let input = resolve_path(&args[1], "rx");
+            let is_source = input.extension().is_some_and(|e| e == "rx");
+            let (name, graph) = if imports {
+                let archive = if is_source {
+                    pack(&input, module)
+                } else if is_archive(path_str(&input)) {
+                    Archive::read_file(path_str(&input))
+                } else {
+                    Err(format!(
+                        "`{}` has its imports compiled in; pass the source or an archive",
+                        input.display()
+                    ))
+                }
+                .unwrap_or_else(|e| exit_error(&e));
+                let modules: Vec<(String, Vec<Instruction>)> = archive
+                    .modules
+                    .into_iter()
+                    .map(|module| (module.name, module.code))
+                    .collect();
+                ("imports", import_graph(&modules))
+            } else {
+                let code = if is_source {
+                    let mut compiler = Compiler::new("project");
+                    if module {
+                        compiler.compile_module_file(&input)
+                    } else {
+                        compiler.compile_file(&input)
+                    }
+                    .unwrap_or_else(|e| exit_error(&e))
+                } else {
+                    let (mut code, _, modules) = load_program(&input);
+                    code.extend(modules.into_values().flatten());
+                    code
+                };
+                ("calls", call_graph(&code))
+            };
+
+            let text = if json {
+                format!("{}\n", graph.to_json())
+            } else {
+                graph.to_dot(name)
+            };
+            match output {
+                Some(path) => std::fs::write(&path, text)
+                    .unwrap_or_else(|e| exit_error(&format!("failed to write `{path}`: {e}"))),
+                None => print!("{text}"),
+            }
+        }
+
+        // ------------------------------------------------------------
+        // Verify bytecode survives serialize -> deserialize -> serialize
+        // ------------------------------------------------------------
+        "roundtrip" => {
+            if args.len() < 2 {
+                exit_error("Usage: reactive roundtrip <input.rxb>...");
+            }
+
+            let mut failed = false;
+            for name in &args[1..] {
+                let path = resolve_path(name, "rxb");
+                match verify_round_trip_file(path.to_str().unwrap()) {
+                    Ok(count) => println!("ok       {} ({count} instructions)", path.display()),
+                    Err(e) => {
+                        failed = true;
+                        println!("FAIL     {}", path.display());
+                        println!("    {e}");
+                    }
+                }
+            }
+            if failed {
+                std::process::exit(1);
+            }
+        }
+
+        // ------------------------------------------------------------
+        // Structural diff of two bytecode files
+        // ------------------------------------------------------------
+        "bcdiff" => {
+            if args.len() != 3 {
+                exit_error("Usage: reactive bcdiff <a.rxb> <b.rxb>");
+            }
+
+            let left = resolve_path(&args[1], "rxb");
+            let right = resolve_path(&args[2], "rxb");
+            let (left_code, _) =
+                read_bytecode_file(path_str(&left)).unwrap_or_else(|e| exit_error(&e));
+            let (right_code, _) =
+                read_bytecode_file(path_str(&right)).unwrap_or_else(|e| exit_error(&e));
+
+            let diff = diff_bytecode(&left_code, &right_code, [path_str(&left), path_str(&right)]);
+            if diff.is_empty() {
+                println!("[Reactive] No structural differences");
+            } else {
+                print!("{}", diff.text);
+                println!("[Reactive] {} section(s) differ", diff.changed);
+                std::process::exit(1);
+            }
+        }
+
+        // ------------------------------------------------------------
+        // Strip local names, optionally splitting debug info off
+        // ------------------------------------------------------------
+        "strip" => {
+            let debug_out = take_flag_value(&mut args, "--debug-out");
+            if args.len() < 2 || args.len() > 3 {
+                exit_error(
+                    "Usage: reactive strip <input.rxb> [output.rxb] [--debug-out <out.rxd>]",
+                );
+            }
+
+            let input = resolve_path(&args[1], "rxb");
+            let output = args
+                .get(2)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| input.clone());
+            let (code, mut metadata) =
+                read_bytecode_file(path_str(&input)).unwrap_or_else(|e| exit_error(&e));
+
+            // Line tables come from the source, so read them before the
+            // bytecode stops matching it.
+            let info = debug_out.as_ref().map(|_| {
+                sibling_debug_info(&input, &code).unwrap_or_else(|| {
+                    exit_error(&format!(
+                        "no debug info for {}: {} must compile to it with compile-native",
+                        input.display(),
+                        input.with_extension("rx").display()
+                    ))
+                })
+            });
+
+            let stripped = strip_names(&code);
+            if let Some(meta) = &mut metadata {
+                meta.source_hash = None;
+            }
+            write_bytecode_file(path_str(&output), &stripped.code, metadata.as_ref())
+                .unwrap_or_else(|e| exit_error(&e));
+            info!(
+                "Stripped {} local name(s) into {}",
+                stripped.renamed(),
+                output.display()
+            );
+
+            if let (Some(out), Some(info)) = (debug_out, info) {
+                stripped
+                    .debug_info(&code, &info)
+                    .write_split_file(&out, &stripped.code)
+                    .unwrap_or_else(|e| exit_error(&e));
+                info!("Wrote debug info to {out}");
+            }
+        }
+
+        // ------------------------------------------------------------
+        // Micro-benchmarks: time every `bench_` function
+        // ------------------------------------------------------------
+        "bench" => {
+            let warmup = take_flag_value(&mut args, "--warmup");
+            let iterations = take_flag_value(&mut args, "--iterations");
+            if args.len() != 2 {
+                exit_error(
+                    "Usage: reactive bench <input.rx|input.rxb> [--warmup <n>] [--iterations <n>]",
+                );
+            }
+
+            let warmup = parse_count(warmup, "--warmup", 3);
+            let iterations = parse_count(iterations, "--iterations", 10).max(1);
+
+            let input = resolve_path(&args[1], "rx");
+            let code = if input.extension().is_some_and(|e| e == "rxb") {
+                read_instructions_from_file(path_str(&input)).unwrap_or_else(|e| exit_error(&e))
+            } else {
+                Compiler::new("project")
+                    .compile_module_file(&input)
+                    .unwrap_or_else(|e| exit_error(&e))
+            };
+
+            let mut vm = VM::new(code);
+            vm.run();
+
+            let benches: Vec<String> = vm
+                .function_names()
+                .into_iter()
+                .filter(|name| name.starts_with("bench_"))
+                .collect();
+            if benches.is_empty() {
+                exit_error(&format!(
+                    "no bench_ functions found in `{}`",
+                    input.display()
+                ));
+            }
+            if let Some(name) = benches
+                .iter()
+                .find(|name| vm.function_arity(name) != Some(0))
+            {
+                exit_error(&format!("benchmark `{name}` must take no parameters"));
+            }
+
+            let width = benches.iter().map(String::len).max().unwrap_or(0);
+            for name in &benches {
+                for _ in 0..warmup {
+                    vm.call_global(name, Vec::new());
+                }
+                let samples: Vec<f64> = (0..iterations)
+                    .map(|_| {
+                        let start = Instant::now();
+                        vm.call_global(name, Vec::new());
+                        start.elapsed().as_secs_f64()
+                    })
+                    .collect();
+
+                let (mean, stddev) = mean_stddev(&samples);
+                println!(
+                    "{name:width$}  mean {:>10}  stddev {:>10}  {:>12.1} ops/sec  ({iterations} iterations)",
+                    format_seconds(mean),
+                    format_seconds(stddev),
+                    if mean > 0.0 {
+                        1.0 / mean
+                    } else {
+                        f64::INFINITY
+                    },
+                );
+            }
         }
 
         // ------------------------------------------------------------
         // Run bytecode
         // ------------------------------------------------------------
         "run" => {
+            let profile_out = take_flag_value(&mut args, "--profile-ngrams");
+            let fuse_profile = take_flag_value(&mut args, "--fuse");
+            let overflow = take_flag_value(&mut args, "--overflow");
+            let trace = take_flag(&mut args, "--trace");
+            let trace_out = take_flag_value(&mut args, "--trace-out");
+            let trace_fn = take_flag_value(&mut args, "--trace-fn");
+            let record = take_flag_value(&mut args, "--record");
+            let replay = take_flag_value(&mut args, "--replay");
+            let log_level = take_flag_value(&mut args, "--log-level");
+            let shadowing = take_flag_value(&mut args, "--shadowing");
+            let max_call_depth = take_flag_value(&mut args, "--max-call-depth");
+            let debug_info = take_flag(&mut args, "--debug-info");
+            let strict = take_flag(&mut args, "--strict");
+            let verbose = take_flag(&mut args, "--verbose");
+            let reports = RunReports::take(&mut args);
+
+            let project = (args.len() == 1).then(load_project);
+            if project.is_none() && args.len() != 2 {
+                exit_error(
+                    "Usage: reactive run [<input.rxb|->] [--profile-ngrams <out>] [--fuse <profile>] \
+                     [--overflow <wrapping|checked>] [--trace] [--trace-out <file>] \
+                     [--trace-fn <name>] [--record <log> | --replay <log>] \
+                     [--log-level <debug|info|warn|error>] [--shadowing <allow|warn|error>] \
+                     [--max-call-depth <n>] [--debug-info] [--strict] [--verbose] [--time] \
+                     [--coverage <out>]",
+                );
+            }
+            if record.is_some() && replay.is_some() {
+                exit_error("--record and --replay cannot be used together");
+            }
+            if reports.coverage.is_some() && fuse_profile.is_some() {
+                exit_error("--coverage and --fuse cannot be used together");
+            }
+
+            let mut options = VmOptions {
+                strict,
+                ..VmOptions::default()
+            };
+            match overflow.as_deref() {
+                None | Some("wrapping") => {}
+                Some("checked") => options.overflow = OverflowPolicy::Checked,
+                Some(other) => exit_error(&format!(
+                    "unknown overflow policy `{other}` (expected `wrapping` or `checked`)"
+                )),
+            }
+            if let Some(level) = log_level {
+                options.log_level = LogLevel::parse(&level).unwrap_or_else(|| {
+                    exit_error(&format!(
+                        "unknown log level `{level}` (expected debug, info, warn or error)"
+                    ))
+                });
+            }
+            if let Some(policy) = shadowing {
+                options.shadowing = ShadowPolicy::parse(&policy).unwrap_or_else(|| {
+                    exit_error(&format!(
+                        "unknown shadowing policy `{policy}` (expected allow, warn or error)"
+                    ))
+                });
+            }
+            if let Some(depth) = max_call_depth {
+                options.max_call_depth = depth
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&d| d > 0)
+                    .unwrap_or_else(|| {
+                        exit_error(&format!(
+                            "invalid call depth `{depth}` (expected a positive number)"
+                        ))
+                    });
+            }
+
+            let start = Instant::now();
+            let path = match &project {
+                Some(project) => {
+                    options.allowed_imports = project.allowed_imports.clone();
+                    build_project(project)
+                }
+                None if args[1] == "-" => stdin_program(),
+                None => resolve_path(&args[1], "rxb"),
+            };
+            let (mut code, metadata, modules) = load_program(&path);
+            if project.is_none() && args[1] == "-" {
+                let _ = std::fs::remove_file(&path);
+            }
+            report_metadata(&path, metadata.as_ref(), verbose);
+
+            if let Some(profile_path) = fuse_profile {
+                let profile =
+                    NgramProfile::read_from_file(&profile_path).unwrap_or_else(|e| exit_error(&e));
+                code = fuse(code, &profile);
+            }
+
+            let loaded = Instant::now();
+            let locate = debug_info || options.shadowing != ShadowPolicy::Allow;
+            let mut vm = VM::with_options(code.clone(), options);
+            vm.set_archive_modules(modules);
+            if locate && let Some(info) = load_debug_info(&path, &code) {
+                vm.set_debug_info(info);
+            }
+            let setup = loaded.elapsed();
+            reports.enable(&mut vm, &code, metadata.as_ref());
+            if profile_out.is_some() {
+                vm.enable_ngram_profile();
+            }
+            if let Some(out) = trace_out {
+                let file = std::fs::File::create(&out).unwrap_or_else(|e| {
+                    exit_error(&format!("failed to create trace file `{}`: {}", out, e))
+                });
+                vm.enable_trace(Box::new(std::io::LineWriter::new(file)), trace_fn);
+            } else if trace || trace_fn.is_some() {
+                vm.enable_trace(Box::new(std::io::stderr()), trace_fn);
+            }
+            if let Some(log) = record {
+                vm.enable_record(&log).unwrap_or_else(|e| exit_error(&e));
+            }
+            if let Some(log) = replay {
+                vm.enable_replay(&log).unwrap_or_else(|e| exit_error(&e));
+            }
+
+            let executing = Instant::now();
+            vm.run();
+            reports.finish(&vm, &path, loaded - start, setup, executing.elapsed());
+
+            if let (Some(out), Some(profile)) = (profile_out, vm.ngram_profile()) {
+                profile
+                    .write_to_file(&out)
+                    .unwrap_or_else(|e| exit_error(&e));
+            }
+        }
+
+        // ------------------------------------------------------------
+        // Run bytecode and report per-function / per-opcode counts
+        // ------------------------------------------------------------
+        "profile" => {
+            let collapsed = take_flag_value(&mut args, "--collapsed");
+            let speedscope = take_flag_value(&mut args, "--speedscope");
+            let interval = take_flag_value(&mut args, "--sample-us");
             if args.len() != 2 {
-                exit_error("Usage: reactive run <input.rxb>");
+                exit_error(
+                    "Usage: reactive profile <input.rxb> [--collapsed <out>] \
+                     [--speedscope <out>] [--sample-us <n>]",
+                );
             }
 
+            let interval = parse_count(interval, "--sample-us", 1000).max(1);
+
             let path = resolve_path(&args[1], "rxb");
             let code = read_instructions_from_file(path.to_str().unwrap())
                 .unwrap_or_else(|e| exit_error(&e));
 
-            VM::new(code).run();
+            let mut vm = VM::new(code);
+            vm.enable_profiler();
+            if collapsed.is_some() || speedscope.is_some() {
+                vm.enable_stack_sampling(Duration::from_micros(interval as u64));
+            }
+            vm.run();
+
+            if let Some(profiler) = vm.profiler() {
+                eprint!("{}", profiler.report());
+                let outputs = [
+                    (collapsed, profiler.collapsed_stacks()),
+                    (speedscope, profiler.speedscope_json(path_str(&path))),
+                ];
+                for (out, contents) in outputs {
+                    if let Some(out) = out {
+                        std::fs::write(&out, contents).unwrap_or_else(|e| {
+                            exit_error(&format!("failed to write profile `{out}`: {e}"))
+                        });
+                    }
+                }
+            }
+        }
+
+        // ------------------------------------------------------------
+        // Debug Adapter Protocol server
+        // ------------------------------------------------------------
+        "dap" => {
+            if args.len() != 1 {
+                exit_error("Usage: reactive dap");
+            }
+            reactive::dap::serve();
+        }
+
+        // ------------------------------------------------------------
+        // Language Server Protocol server
+        // ------------------------------------------------------------
+        "lsp" => {
+            if args.len() != 1 {
+                exit_error("Usage: reactive lsp");
+            }
+            reactive::lsp::serve();
         }
 
         _ => {
@@ -154,6 +1261,242 @@ fn main() {
 // Core VM compiler runner (single source of truth)
 // ================================================================
 fn run_compiler_vm_entry(compiler_path: &Path, input_path: &Path, output_path: &Path, entry: &str) {
+    run_compiler_vm(
+        compiler_path,
+        input_path,
+        output_path,
+        entry,
+        &RunReports::default(),
+        Box::new(std::io::stdout()),
+    );
+}
+
+/// Adds a metadata block to the bytecode `compiler_path` wrote to `output`,
+/// naming the compiler after its directory (`stable`, `experimental`).
+fn stamp_metadata(compiler_path: &Path, input: &Path, output: &Path) {
+    let compiler = compiler_path
+        .parent()
+        .and_then(|dir| dir.file_name())
+        .map_or("unknown".into(), |name| name.to_string_lossy());
+    let output = path_str(output);
+    let code = read_instructions_from_file(output).unwrap_or_else(|e| exit_error(&e));
+    let metadata = Metadata::new(&compiler, &read_source(input), 0);
+    write_bytecode_file(output, &code, Some(&metadata)).unwrap_or_else(|e| exit_error(&e));
+}
+
+/// Bytecode to run from `path`: a plain or compressed `.rxb`, or the entry
+/// module of an archive along with the modules it may import.
+fn load_program(
+    path: &Path,
+) -> (
+    Vec<Instruction>,
+    Option<Metadata>,
+    HashMap<String, Vec<Instruction>>,
+) {
+    let path = path_str(path);
+    if is_archive(path) {
+        let archive = Archive::read_file(path).unwrap_or_else(|e| exit_error(&e));
+        let (entry, modules) = archive.split_entry().unwrap_or_else(|e| exit_error(&e));
+        return (entry.code, entry.metadata, modules);
+    }
+    let (code, metadata) = read_bytecode_file(path).unwrap_or_else(|e| exit_error(&e));
+    (code, metadata, HashMap::new())
+}
+
+/// Temporary file for data passing through stdin or stdout, since the
+/// compilers and loaders work on paths.
+fn stdio_temp(ext: &str) -> PathBuf {
+    env::temp_dir().join(format!("reactive-stdio-{}.{ext}", std::process::id()))
+}
+
+fn read_stdin() -> Vec<u8> {
+    let mut data = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut data)
+        .unwrap_or_else(|e| exit_error(&format!("failed to read stdin: {e}")));
+    data
+}
+
+fn write_temp(path: &Path, data: &[u8]) {
+    std::fs::write(path, data)
+        .unwrap_or_else(|e| exit_error(&format!("failed to write `{}`: {e}", path.display())));
+}
+
+/// The source to compile for the argument `name`; `-` reads it from stdin.
+fn source_input(name: &str) -> PathBuf {
+    if name != "-" {
+        return resolve_path(name, "rx");
+    }
+    let path = stdio_temp("rx");
+    write_temp(&path, &read_stdin());
+    path
+}
+
+fn remove_stdin_input(input: &Path, name: &str) {
+    if name == "-" {
+        let _ = std::fs::remove_file(input);
+    }
+}
+
+/// The program for `reactive run -`, saved to a temporary file: bytecode or
+/// an archive read from stdin as is, or source compiled with the stable
+/// compiler (its progress going to stderr, to keep stdout for the program).
+fn stdin_program() -> PathBuf {
+    let data = read_stdin();
+    let output = stdio_temp("rxb");
+    if is_bytecode(&data) || has_archive_header(&data) {
+        write_temp(&output, &data);
+    } else {
+        let input = stdio_temp("rx");
+        write_temp(&input, &data);
+        let compiler = Path::new("project/bootstrap/stable/compiler.rxb");
+        run_compiler_vm(
+            compiler,
+            &input,
+            &output,
+            "compile_file",
+            &RunReports::default(),
+            Box::new(std::io::stderr()),
+        );
+        let _ = std::fs::remove_file(&input);
+    }
+    output
+}
+
+/// Where a compile command writes bytecode: the given path, next to the
+/// input by default, or stdout for `-` and for input read from stdin.
+struct BytecodeOutput {
+    path: PathBuf,
+    stdout: bool,
+}
+
+impl BytecodeOutput {
+    fn new(input: &Path, input_name: &str, arg: Option<&String>) -> Self {
+        let stdout = arg.map_or(input_name == "-", |arg| arg == "-");
+        let path = if stdout {
+            stdio_temp("rxb")
+        } else {
+            output_path(input, arg)
+        };
+        Self { path, stdout }
+    }
+
+    /// Where the compiler's own output (such as a runtime error report)
+    /// goes, so it stays out of bytecode on stdout.
+    fn log(&self) -> Box<dyn Write> {
+        if self.stdout {
+            Box::new(std::io::stderr())
+        } else {
+            Box::new(std::io::stdout())
+        }
+    }
+
+    /// Copies the bytecode to stdout if it goes there.
+    fn finish(self) {
+        if !self.stdout {
+            return;
+        }
+        let data = read_source(&self.path);
+        let _ = std::fs::remove_file(&self.path);
+        std::io::stdout()
+            .write_all(&data)
+            .unwrap_or_else(|e| exit_error(&format!("failed to write stdout: {e}")));
+    }
+}
+
+/// Compiles `entry` (as a module if `module`, otherwise as a program) and,
+/// each on its own, every module it imports directly or indirectly, into an
+/// archive whose entry is named after `entry`.
+fn pack(entry: &Path, module: bool) -> Result<Archive, String> {
+    let root = Path::new("project");
+    let mut compiler = Compiler::new(root).with_separate_imports();
+    let name = entry
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let code = if module {
+        compiler.compile_module_file(entry)?
+    } else {
+        compiler.compile_file(entry)?
+    };
+    let mut modules = vec![ArchiveModule {
+        name: name.clone(),
+        code,
+        metadata: Some(Metadata::new("native", &read_source(entry), 0)),
+    }];
+    let mut pending: VecDeque<Vec<String>> = compiler.take_imports().into();
+    while let Some(import) = pending.pop_front() {
+        let module = import.join(".");
+        if module == name {
+            return Err(format!(
+                "`{}` has the same name as the module `{module}` it imports; rename it",
+                entry.display()
+            ));
+        }
+        let file = compiler.module_path(&import);
+        let code = compiler.compile_module_file(&file)?;
+        pending.extend(compiler.take_imports());
+        modules.push(ArchiveModule {
+            name: module,
+            code,
+            metadata: Some(Metadata::new("native", &read_source(&file), 0)),
+        });
+    }
+    Ok(Archive {
+        entry: name,
+        modules,
+    })
+}
+
+/// Warns when `rxb` was written by another compiler version; with
+/// `verbose` or the global `-v`, also prints its metadata and whether the
+/// sibling `.rx` source still matches the recorded hash.
+fn report_metadata(rxb: &Path, metadata: Option<&Metadata>, verbose: bool) {
+    let verbose = verbose || verbosity() == Verbosity::Verbose;
+    let Some(meta) = metadata else {
+        if verbose {
+            eprintln!("[Reactive] {}: no metadata", rxb.display());
+        }
+        return;
+    };
+    if verbose {
+        eprintln!("[Reactive] {}: {meta}", rxb.display());
+        let source = rxb.with_extension("rx");
+        if let (Some(hash), Ok(text)) = (&meta.source_hash, std::fs::read(&source))
+            && *hash != source_hash(&text)
+        {
+            eprintln!(
+                "[Reactive] {} has changed since it was compiled",
+                source.display()
+            );
+        }
+    }
+    if meta.version != COMPILER_VERSION {
+        eprintln!(
+            "warning: {} was compiled by {} {}, this is version {COMPILER_VERSION}; recompile it if it misbehaves",
+            rxb.display(),
+            meta.compiler,
+            meta.version
+        );
+    }
+}
+
+fn read_source(path: &Path) -> Vec<u8> {
+    std::fs::read(path)
+        .unwrap_or_else(|e| exit_error(&format!("failed to read `{}`: {e}", path.display())))
+}
+
+/// Runs `entry` of the compiler bytecode on `input_path`, then prints the
+/// requested reports about the compiler's own run.
+fn run_compiler_vm(
+    compiler_path: &Path,
+    input_path: &Path,
+    output_path: &Path,
+    entry: &str,
+    reports: &RunReports,
+    log: Box<dyn Write>,
+) {
     if !compiler_path.exists() {
         exit_error(&format!(
             "compiler bytecode missing: `{}`",
@@ -161,8 +1504,10 @@ fn run_compiler_vm_entry(compiler_path: &Path, input_path: &Path, output_path: &
         ));
     }
 
-    let mut bytecode = read_instructions_from_file(compiler_path.to_str().unwrap())
-        .unwrap_or_else(|e| exit_error(&e));
+    let start = Instant::now();
+    let (source, metadata) =
+        read_bytecode_file(path_str(compiler_path)).unwrap_or_else(|e| exit_error(&e));
+    let mut bytecode = source.clone();
 
     emit_string_literal(&mut bytecode, &input_path.to_string_lossy());
     emit_string_literal(&mut bytecode, &output_path.to_string_lossy());
@@ -170,7 +1515,467 @@ fn run_compiler_vm_entry(compiler_path: &Path, input_path: &Path, output_path: &
     bytecode.push(Instruction::Call(entry.to_string(), 2));
     bytecode.push(Instruction::Return);
 
-    VM::new(bytecode).run();
+    let loaded = Instant::now();
+    let mut vm = VM::new(bytecode);
+    // the compiler reports its phases as `[Reactive] ...` lines
+    vm.set_stdout_sink(Box::new(ProgressSink::new(log, Verbosity::Verbose)));
+    let setup = loaded.elapsed();
+    reports.enable(&mut vm, &source, metadata.as_ref());
+    let executing = Instant::now();
+    vm.run();
+    reports.finish(
+        &vm,
+        compiler_path,
+        loaded - start,
+        setup,
+        executing.elapsed(),
+    );
+}
+
+/// Opt-in reports about a VM run: `--time` and `--coverage <out>`.
+#[derive(Default)]
+struct RunReports {
+    time: bool,
+    coverage: Option<String>,
+}
+
+impl RunReports {
+    fn take(args: &mut Vec<String>) -> Self {
+        Self {
+            time: take_flag(args, "--time"),
+            coverage: take_flag_value(args, "--coverage"),
+        }
+    }
+
+    /// `source` is the program as stored in its `.rxb` file.
+    fn enable(&self, vm: &mut VM, source: &[Instruction], metadata: Option<&Metadata>) {
+        if self.time {
+            vm.enable_timing();
+        }
+        if self.coverage.is_some() {
+            vm.enable_coverage(source, metadata);
+        }
+    }
+
+    fn finish(&self, vm: &VM, source: &Path, load: Duration, setup: Duration, execute: Duration) {
+        if let Some(timings) = vm.timings() {
+            eprint!("[Reactive] Time:\n{}", timings.report(load, setup, execute));
+        }
+        if let (Some(out), Some(coverage)) = (&self.coverage, vm.coverage()) {
+            std::fs::write(out, coverage.lcov(path_str(source)))
+                .unwrap_or_else(|e| exit_error(&format!("failed to write coverage `{out}`: {e}")));
+            let (total, hit) = coverage.summary();
+            eprintln!(
+                "[Reactive] Coverage: {hit} of {total} instruction(s) executed ({:.1}%), written to {out}",
+                hit as f64 * 100.0 / total.max(1) as f64
+            );
+        }
+    }
+}
+
+/// Reads `.rxb` input as is; compiles anything else with `compiler` into a
+/// temporary file first.
+fn load_or_compile(compiler_path: &Path, input: &Path, entry: &str) -> Vec<Instruction> {
+    if input.extension().is_some_and(|e| e == "rxb") {
+        return read_instructions_from_file(input.to_str().unwrap())
+            .unwrap_or_else(|e| exit_error(&e));
+    }
+
+    let output = env::temp_dir().join(format!("reactive-build-{}.rxb", std::process::id()));
+    run_compiler_vm_entry(compiler_path, input, &output, entry);
+    let code = read_instructions_from_file(output.to_str().unwrap());
+    let _ = std::fs::remove_file(&output);
+    code.unwrap_or_else(|e| exit_error(&e))
+}
+
+// ================================================================
+// Projects: scaffolding and manifest-driven builds
+// ================================================================
+
+/// The manifest of the project containing the working directory.
+fn load_project() -> Manifest {
+    let path = Manifest::find(Path::new(".")).unwrap_or_else(|| {
+        exit_error(&format!(
+            "no input given and no {MANIFEST_FILE} in this directory or its parents"
+        ))
+    });
+    Manifest::load(&path).unwrap_or_else(|e| exit_error(&e))
+}
+
+/// `package::verify`, skipped for projects without dependencies.
+fn verify_dependencies(project: &Manifest) -> Result<(), String> {
+    reactive::package::verify(project)
+}
+
+/// Compiles the project's entry point next to it with the manifest's
+/// compiler and optimization level, returning the bytecode path.
+fn build_project(project: &Manifest) -> PathBuf {
+    verify_dependencies(project).unwrap_or_else(|e| exit_error(&e));
+    let input = &project.entry;
+    let output = input.with_extension("rxb");
+    match project.compiler {
+        CompilerChoice::Native => {
+            let code = Compiler::new("project")
+                .with_search_paths(project.search_paths())
+                .compile_file(input)
+                .unwrap_or_else(|e| exit_error(&e));
+            let metadata = Metadata::new("native", &read_source(input), 0);
+            write_bytecode_file(path_str(&output), &code, Some(&metadata))
+                .unwrap_or_else(|e| exit_error(&e));
+        }
+        choice => {
+            if !project.search_paths().is_empty() {
+                eprintln!(
+                    "warning: the bootstrap compilers only import from project/; \
+                     build.paths and dependencies need compiler = \"native\""
+                );
+            }
+            let compiler = match choice {
+                CompilerChoice::Experimental => "project/bootstrap/experimental/compiler.rxb",
+                _ => "project/bootstrap/stable/compiler.rxb",
+            };
+            let compiler = PathBuf::from(compiler);
+            run_compiler_vm_entry(&compiler, input, &output, "compile_file");
+            stamp_metadata(&compiler, input, &output);
+        }
+    }
+    if project.opt_level >= 1 {
+        strip_dead_code(&output);
+    }
+    output
+}
+
+/// Creates `dir` with a manifest, an entry program, an empty `modules`
+/// folder for imports and a `tests` folder holding one golden test.
+fn new_project(dir: &Path) -> Result<(), String> {
+    if dir.exists() {
+        return Err(format!("`{}` already exists", dir.display()));
+    }
+    let name = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("`{}` has no directory name", dir.display()))?;
+
+    let manifest = format!(
+        r#"[project]
+name = "{name}"
+entry = "main.rx"
+
+[build]
+# directories searched for `import a.b` (as a/b.rx), in order
+paths = ["modules"]
+# stable, experimental or native; only native reads `paths`
+compiler = "native"
+opt-level = 0
+
+[test]
+dir = "tests"
+"#
+    );
+    let main = format!(
+        r#"# Entry point of {name} #
+
+func main() {{
+    println "Hello from {name}!";
+}}
+"#
+    );
+    let test = r#"func main() {
+    assert 1 + 1 == 2;
+    println "ok";
+}
+"#;
+
+    let files = [
+        (PathBuf::from("reactive.toml"), manifest.as_str()),
+        (PathBuf::from("main.rx"), main.as_str()),
+        (Path::new("modules").join(".gitkeep"), ""),
+        (Path::new("tests").join("smoke.rx"), test),
+        (Path::new("tests").join("smoke.expected"), "ok\n"),
+    ];
+    for (file, contents) in files {
+        let path = dir.join(file);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("failed to create `{}`: {e}", parent.display()))?;
+        }
+        std::fs::write(&path, contents)
+            .map_err(|e| format!("failed to write `{}`: {e}", path.display()))?;
+    }
+    Ok(())
+}
+
+// ================================================================
+// Dead code elimination over emitted program bytecode
+// ================================================================
+fn strip_dead_code(path: &Path) {
+    let path = path.to_str().unwrap();
+    let (code, mut metadata) = read_bytecode_file(path).unwrap_or_else(|e| exit_error(&e));
+    let (code, stats) = eliminate_dead_code(code);
+    if let Some(meta) = &mut metadata {
+        meta.opt_level = meta.opt_level.max(1);
+    }
+    write_bytecode_file(path, &code, metadata.as_ref()).unwrap_or_else(|e| exit_error(&e));
+
+    info!(
+        "Strip dead code: removed {} function(s), {} struct(s)",
+        stats.removed_functions.len(),
+        stats.removed_structs.len()
+    );
+}
+
+// ================================================================
+// Differential testing (stable vs experimental compiler)
+// ================================================================
+// Each step runs in a child `reactive` process: compile errors and runtime
+// errors exit the process, and programs may loop forever.
+
+/// What a child process did: exit code (`None` if killed) and its stdout.
+#[derive(PartialEq)]
+struct Outcome {
+    status: Option<i32>,
+    stdout: String,
+    timed_out: bool,
+}
+
+impl Outcome {
+    fn passed(&self) -> bool {
+        self.status == Some(0)
+    }
+
+    fn describe(&self) -> String {
+        match (self.timed_out, self.status) {
+            (true, _) => "timed out".to_string(),
+            (false, Some(code)) => format!("exit {code}"),
+            (false, None) => "killed by signal".to_string(),
+        }
+    }
+}
+
+fn collect_rx_files(dir: &Path, out: &mut Vec<PathBuf>) {
+    let entries = std::fs::read_dir(dir)
+        .unwrap_or_else(|e| exit_error(&format!("failed to read `{}`: {e}", dir.display())));
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_rx_files(&path, out);
+        } else if path.extension().is_some_and(|e| e == "rx") {
+            out.push(path);
+        }
+    }
+}
+
+/// Compiles `file` with both compilers and runs both outputs. Returns a
+/// description of the first difference, or `None` if they behave the same.
+fn conformance_divergence(file: &Path, timeout: Duration) -> Option<String> {
+    let tmp = env::temp_dir();
+    let pid = std::process::id();
+    let stable_out = tmp.join(format!("reactive-conformance-{pid}-stable.rxb"));
+    let expi_out = tmp.join(format!("reactive-conformance-{pid}-expi.rxb"));
+
+    let stable_compile = run_self(&["compile", path_str(file), path_str(&stable_out)], timeout);
+    let expi_compile = run_self(
+        &["compile-expi", path_str(file), path_str(&expi_out)],
+        timeout,
+    );
+
+    let report = if stable_compile.passed() != expi_compile.passed() {
+        Some(format!(
+            "compile: stable {}, experimental {}\n{}",
+            stable_compile.describe(),
+            expi_compile.describe(),
+            first_difference(
+                &stable_compile.stdout,
+                &expi_compile.stdout,
+                ["stable", "experimental"]
+            )
+        ))
+    } else if !stable_compile.passed() {
+        // Both rejected it (e.g. a module without `main`): consistent.
+        None
+    } else {
+        let stable_run = run_self(&["run", path_str(&stable_out)], timeout);
+        let expi_run = run_self(&["run", path_str(&expi_out)], timeout);
+        if stable_run == expi_run {
+            None
+        } else if stable_run.stdout == expi_run.stdout {
+            Some(format!(
+                "run: stable {}, experimental {}",
+                stable_run.describe(),
+                expi_run.describe()
+            ))
+        } else {
+            Some(format!(
+                "run: stable {}, experimental {}\n{}",
+                stable_run.describe(),
+                expi_run.describe(),
+                first_difference(
+                    &stable_run.stdout,
+                    &expi_run.stdout,
+                    ["stable", "experimental"]
+                )
+            ))
+        }
+    };
+
+    let _ = std::fs::remove_file(&stable_out);
+    let _ = std::fs::remove_file(&expi_out);
+    report
+}
+
+/// Runs this executable with `args`, stdin closed and stdout captured, killing
+/// it after `timeout`.
+fn run_self(args: &[&str], timeout: Duration) -> Outcome {
+    let exe = env::current_exe().unwrap_or_else(|e| exit_error(&e.to_string()));
+    let mut child = Command::new(exe)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap_or_else(|e| exit_error(&format!("failed to spawn reactive: {e}")));
+
+    // Drain stdout on a thread so a chatty child cannot block on a full pipe.
+    let mut pipe = child.stdout.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        buf
+    });
+
+    let start = Instant::now();
+    let mut timed_out = false;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status.code(),
+            Ok(None) if start.elapsed() >= timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                timed_out = true;
+                break None;
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(5)),
+            Err(e) => exit_error(&format!("failed to wait for reactive: {e}")),
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&reader.join().unwrap_or_default()).into_owned();
+    Outcome {
+        status,
+        stdout,
+        timed_out,
+    }
+}
+
+/// Shows the first line where two outputs differ.
+fn first_difference(left: &str, right: &str, labels: [&str; 2]) -> String {
+    let width = labels[0].len().max(labels[1].len()) + 1;
+    let mut left_lines = left.lines();
+    let mut right_lines = right.lines();
+    let mut line = 1;
+    loop {
+        match (left_lines.next(), right_lines.next()) {
+            (Some(a), Some(b)) if a == b => line += 1,
+            (None, None) => return "stdout identical".to_string(),
+            (a, b) => {
+                return format!(
+                    "stdout line {line}:\n  {:width$} {}\n  {:width$} {}",
+                    format!("{}:", labels[0]),
+                    a.unwrap_or("<end of output>"),
+                    format!("{}:", labels[1]),
+                    b.unwrap_or("<end of output>")
+                );
+            }
+        }
+    }
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+// ================================================================
+// Golden-output tests
+// ================================================================
+
+/// In-memory stdout sink shared between the VM and the test runner.
+#[derive(Clone, Default)]
+struct CapturedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for CapturedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compiles `file` with the native compiler and runs it in-process, returning
+/// everything it printed. A runtime error ends the run; its report is part of
+/// the output, as it would be on the terminal.
+/// Stdout of `file`, compiled natively; within a project, imports also
+/// resolve through its search paths and its import permissions apply.
+fn golden_output(file: &Path, project: Option<&Manifest>) -> Result<String, String> {
+    let paths = project.map(Manifest::search_paths).unwrap_or_default();
+    let code = Compiler::new("project")
+        .with_search_paths(paths)
+        .compile_file(file)?;
+    let captured = CapturedOutput::default();
+
+    let options = VmOptions {
+        unwind_on_error: true,
+        allowed_imports: project.and_then(|p| p.allowed_imports.clone()),
+        ..VmOptions::default()
+    };
+    let mut vm = VM::with_options(code, options);
+    vm.set_stdout_sink(Box::new(captured.clone()));
+    if let Err(payload) = std::panic::catch_unwind(AssertUnwindSafe(move || vm.run()))
+        && !payload.is::<RuntimeError>()
+    {
+        std::panic::resume_unwind(payload);
+    }
+
+    let bytes = captured.0.borrow();
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// ================================================================
+// Benchmarks
+// ================================================================
+
+fn parse_count(value: Option<String>, flag: &str, default: usize) -> usize {
+    match value {
+        Some(n) => n
+            .parse()
+            .unwrap_or_else(|_| exit_error(&format!("invalid {flag} `{n}` (expected a count)"))),
+        None => default,
+    }
+}
+
+/// Mean and sample standard deviation of `samples` (non-empty).
+fn mean_stddev(samples: &[f64]) -> (f64, f64) {
+    let n = samples.len() as f64;
+    let mean = samples.iter().sum::<f64>() / n;
+    if samples.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, variance.sqrt())
+}
+
+fn format_seconds(secs: f64) -> String {
+    if secs >= 1.0 {
+        format!("{secs:.3} s")
+    } else if secs >= 1e-3 {
+        format!("{:.3} ms", secs * 1e3)
+    } else if secs >= 1e-6 {
+        format!("{:.3} us", secs * 1e6)
+    } else {
+        format!("{:.0} ns", secs * 1e9)
+    }
 }
 
 // ================================================================
@@ -180,28 +1985,198 @@ fn print_help() -> ! {
     println!(
         "Reactive Language CLI
 
+Options (anywhere on the command line):
+  -q, --quiet   only report errors and warnings
+  -v            also log each step (imports, cache hits, compiler phases)
+                and the metadata of bytecode being run; messages go to
+                stderr, never to a program's stdout
+
 Commands:
+  new <dir>
+      Create a project: reactive.toml (entry, module paths, compiler),
+      main.rx, a modules/ folder and tests/ with a golden test. compile,
+      run and test without an input then work on the project
+
+  fetch [--update]
+      Vendor the [dependencies] of the nearest reactive.toml (each a path,
+      or a git URL with an optional rev) into deps/ and pin them in
+      reactive.lock; `import name.a` then reads deps/name/a.rx. Locked git
+      commits are reused unless --update is given
+
+  build
+      Compile the nearest reactive.toml's entry and every module it
+      imports, each on its own with the native compiler, and link them into
+      target/<name>.rxb. Modules are cached in target/cache/ and only
+      recompiled when their source changes; prints per-module timings
+
   bootstrap
       Build experimental compiler from stable compiler
 
-  compile <input.rx> [output.rxb]
-      Compile a program (requires main) using stable compiler
+  compile [<input.rx> [output.rxb]] [-o <output.rxb>] [--strip-dead] [--time] [--coverage <out>]
+      Compile a program (requires main) using stable compiler. Without an
+      input, build the entry of the nearest reactive.toml as it specifies.
+      An input of - reads source from stdin; an output of - (the default
+      for stdin input) writes bytecode to stdout, and progress to stderr
+      --strip-dead  drop functions and structs unreachable from main
+      --error-format=json
+                    report compile errors as JSON lines on stderr: file,
+                    line, column, code, message and severity (null when a
+                    position is unknown); check, lint and compile-native
+                    accept it too
+      --time        report time spent loading, linking, dispatching and in
+                    natives (fs vs compute) to stderr; every compile command
+                    accepts it
+      --coverage    write an lcov report of which lines of the compiler's
+                    .rxb ran to <out> (bytecode-compiler commands only)
 
   compile-module <input.rx> [output.rxb]
       Compile a module using stable compiler (no main required)
 
-  compile-expi <input.rx> [output.rxb]
+  compile-expi <input.rx> [output.rxb] [--strip-dead]
       Compile a program using experimental compiler
 
   compile-expi-module <input.rx> [output.rxb]
       Compile a program using experimental compiler
 
-  run <input.rxb>
-      Run bytecode
+  compile-native <input.rx> [output.rxb] [-o <output.rxb>] [--strip-dead] [--error-format=json]
+      Compile a program with the built-in Rust compiler (no bootstrap
+      bytecode needed); emits the same bytecode as the stable compiler,
+      except Neg and Not for unary - and ! and Switch for else-if chains
+      on one variable. Takes - for stdin and stdout like compile
+
+  compile-native-module <input.rx> [output.rxb]
+      Compile a module with the built-in Rust compiler
+
+  check <input.rx|input.rxb> [--expi] [--module] [--error-format=json]
+      Compile and report unknown names, arity mismatches and stores to
+      immutables without running the program
+      --expi        compile with the experimental compiler
+      --module      check a module (no main required)
+      --error-format=json
+                    one JSON record per problem on stderr, with its line
+                    (source is then compiled natively, unless --expi)
+
+  conformance <dir> [--timeout <secs>]
+      Compile every .rx under <dir> with both the stable and experimental
+      compilers, run both outputs and report any difference in exit status
+      or stdout (stdin is closed; runs are killed after 10s by default)
+
+  test [--golden <dir>] [--update]
+      Compile and run every .rx under <dir> (default: the test.dir of the
+      nearest reactive.toml) and compare its stdout with the sibling
+      .expected file
+      --update      rewrite the .expected files from the current output
+
+  fmt [--check] <input.rx|dir>...
+      Rewrite sources in the canonical layout: four-space indentation, one
+      statement per line, braces at line ends, spaced operators; comments
+      and blank lines are kept
+      --check       only list files that would change (exit status 1)
+
+  lint <input.rx|dir>... [--allow <lints>] [--deny] [--error-format=json]
+      Warn about likely mistakes: unused-variable, shadowed-immutable (`:=`
+      hiding an immutable of an enclosing scope), severed-reactive (`=`
+      replacing a `::=` relationship) and unreachable-code
+      --allow       comma-separated lints to skip
+      --deny        exit with status 1 when there are warnings
+      --error-format=json
+                    one JSON record per warning on stderr
+
+  tokens <input.rx>
+      Print the tokens of a source file as a JSON array, one object per
+      line: kind, value (literals and names), line, column and byte offsets
+
+  graph <input.rx|input.rxb|input.rxa> (--imports | --calls) [--module] [--json] [-o <out>]
+      Print a Graphviz digraph (or JSON nodes and edges) of which module
+      imports which (from source or an archive), or of which function calls
+      which (from any input). Natives and modules not compiled are dashed.
+      --module compiles a source without main
+
+  pack <input.rx> [output.rxa]
+      Compile a program and every module it imports, each on its own with
+      the native compiler, into one archive (default: <input>.rxa). `run`
+      executes archives; their imports run the archived modules, and
+      nothing is read from project/
+
+  roundtrip <input.rxb>...
+      Check that bytecode decodes and re-encodes byte-identically
+
+  bcdiff <a.rxb> <b.rxb>
+      Compare two programs structurally: declarations are paired by name and
+      labels renumbered in order of use, so only real changes are shown.
+      Exits with status 1 when they differ
+
+  strip <input.rxb> [output.rxb] [--debug-out <out.rxd>]
+      Rename function locals to slot names ($0, $1, ...) and drop the source
+      hash, in place unless an output is given. --debug-out writes the line
+      table (from the sibling .rx) and the original names to a separate
+      file; `run --debug-info` and the debugger pick up <program>.rxd
+
+  bench <input.rx|input.rxb> [--warmup <n>] [--iterations <n>]
+      Call every function named bench_* (no parameters) after a warmup and
+      report mean / standard deviation / ops per second over the timed runs
+      --warmup      untimed calls before measuring (default 3)
+      --iterations  timed calls per benchmark (default 10)
+
+  run [<input.rxb>] [options]
+      Run bytecode. Without an input, build and run the project in the
+      nearest reactive.toml, under its [permissions]. An input of - reads
+      bytecode, an archive or source (compiled with the stable compiler)
+      from stdin, which the program then cannot read input from
+      --profile-ngrams  record hot opcode pairs to <out>
+      --fuse            fuse superinstructions chosen from a recorded profile
+      --overflow        integer overflow policy: wrapping (default) or checked
+      --trace           log every executed instruction to stderr
+      --trace-out       log every executed instruction to <file> instead
+      --trace-fn        only trace instructions inside function <name>
+      --record          log nondeterministic inputs (files, stdin, keys, time, randomness) to <log>
+      --replay          feed the inputs logged by --record back from <log>
+      --log-level       minimum level printed by std.log (default info)
+      --max-call-depth  active calls (and nested reactive evaluations) allowed
+                        before a stack overflow error (default 4096)
+      --shadowing       assignments in functions that hide an immutable
+                        (parameter, outer or global :=): allow (default),
+                        warn once per site on stderr, or error
+      --debug-info      compile the .rx next to the input to give runtime
+                        errors source lines (implied by --shadowing)
+      --strict          reject implicit coercions: arrays as ints (their
+                        length), chars in arithmetic, chars compared with
+                        ints; (int) casts still convert
+      --verbose         print the compiler, version, source hash and
+                        optimization level recorded in the bytecode
+      --time            report time spent loading, linking, dispatching and in natives to stderr
+      --coverage        write an lcov report of executed .rxb lines to <out>
+
+  profile <input.rxb> [--collapsed <out>] [--speedscope <out>] [--sample-us <n>]
+      Run bytecode and print a per-function / per-opcode profile to stderr
+      --collapsed   sample the call stack and write collapsed stacks
+                    (flamegraph.pl / inferno input) to <out>
+      --speedscope  sample the call stack and write a speedscope JSON profile
+      --sample-us   sampling interval in microseconds (default 1000)
+
+  dap
+      Serve the Debug Adapter Protocol on stdin/stdout for editors. Launch
+      arguments: program (.rx), stopOnEntry, root (import root, default
+      project); supports line breakpoints, stepping, stack and variables
+
+  lsp
+      Serve the Language Server Protocol on stdin/stdout for editors:
+      diagnostics from the native compiler and check, go-to-definition of
+      top-level items and completion of globals and natives
 
 Shortcuts:
   reactive file.rx     Compile with stable compiler and run
-  reactive file.rxb    Run bytecode directly
+  reactive file.rxb    Run bytecode directly (also .rxb2, .rxz and .rxa)
+
+Compressed bytecode:
+  Any bytecode output named *.rxz is written zstd-compressed, and every
+  command reading bytecode detects compression by content. Requires
+  building with --features zstd.
+
+Binary bytecode:
+  Any bytecode output named *.rxb2 is written in the RXB2 binary format,
+  with jumps stored as relative offsets instead of labels. Every command
+  reading bytecode detects it by content.
 "
     );
     std::process::exit(0);
@@ -215,8 +2190,92 @@ fn resolve_path(name: &str, default_ext: &str) -> PathBuf {
     }
 }
 
+/// Removes a boolean `flag` from `args`, returning whether it was present.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|a| a == flag) {
+        Some(pos) => {
+            args.remove(pos);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Removes `flag <value>` from `args`, returning the value if present.
+/// Debug info for `rxb`: a `.rxd` split off by `reactive strip` when one
+/// sits next to it, else `sibling_debug_info`.
+fn load_debug_info(rxb: &Path, code: &[Instruction]) -> Option<DebugInfo> {
+    let split = rxb.with_extension("rxd");
+    if !split.is_file() {
+        return sibling_debug_info(rxb, code);
+    }
+    match DebugInfo::read_split_file(path_str(&split), code) {
+        Ok(info) => Some(info),
+        Err(e) => {
+            eprintln!("warning: {e}");
+            None
+        }
+    }
+}
+
+/// Source locations for `rxb`, from compiling the `.rx` next to it with the
+/// native compiler; `None` unless that reproduces `code` exactly.
+fn sibling_debug_info(rxb: &Path, code: &[Instruction]) -> Option<DebugInfo> {
+    let source = rxb.with_extension("rx");
+    if !source.is_file() {
+        return None;
+    }
+    let mut compiler = Compiler::new("project").with_debug_info();
+    let compiled = compiler.compile_file(&source).ok()?;
+    if compiled != code {
+        return None;
+    }
+    compiler.take_debug_info()
+}
+
+/// `--error-format=<human|json>` (or `--error-format <...>`), human by
+/// default.
+fn take_error_format(args: &mut Vec<String>) -> ErrorFormat {
+    let value = match args.iter().position(|a| a.starts_with("--error-format=")) {
+        Some(pos) => Some(args.remove(pos)["--error-format=".len()..].to_string()),
+        None => take_flag_value(args, "--error-format"),
+    };
+    value.map_or(ErrorFormat::Human, |name| {
+        ErrorFormat::parse(&name).unwrap_or_else(|| {
+            exit_error(&format!(
+                "unknown error format `{name}` (expected human or json)"
+            ))
+        })
+    })
+}
+
+/// Reports `error` from compiling `input` (given on the command line as
+/// `name`, `-` for stdin) and exits.
+fn compile_failed(input: &Path, name: &str, error: &str, format: ErrorFormat) -> ! {
+    remove_stdin_input(input, name);
+    if format == ErrorFormat::Human {
+        exit_error(error);
+    }
+    let mut record = Record::from_compile_error(input, error);
+    if Path::new(&record.file) == input {
+        record.file = if name == "-" { "<stdin>" } else { name }.to_string();
+    }
+    eprintln!("{}", record.to_json());
+    std::process::exit(1);
+}
+
+fn take_flag_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let pos = args.iter().position(|a| a == flag)?;
+    if pos + 1 >= args.len() {
+        exit_error(&format!("missing value for `{flag}`"));
+    }
+    let value = args.remove(pos + 1);
+    args.remove(pos);
+    Some(value)
+}
+
 fn emit_string_literal(code: &mut Vec<Instruction>, value: &str) {
-    code.push(Instruction::Push(value.chars().count() as i32));
+    code.push(Instruction::Push(value.chars().count() as i64));
     code.push(Instruction::ArrayNew);
 
     let tmp = "__cli_str".to_string();
@@ -224,7 +2283,7 @@ fn emit_string_literal(code: &mut Vec<Instruction>, value: &str) {
 
     for (i, ch) in value.chars().enumerate() {
         code.push(Instruction::Load(tmp.clone()));
-        code.push(Instruction::Push(i as i32));
+        code.push(Instruction::Push(i as i64));
         code.push(Instruction::ArrayLValue);
         code.push(Instruction::PushChar(ch as u32));
         code.push(Instruction::StoreThrough);
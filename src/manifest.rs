@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_FILE: &str = "reactive.toml";
+pub const LOCK_FILE: &str = "reactive.lock";
+
+/// Which compiler builds the project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompilerChoice {
+    /// `project/bootstrap/stable/compiler.rxb`
+    Stable,
+    /// `project/bootstrap/experimental/compiler.rxb`
+    Experimental,
+    /// The compiler built into the binary (`Compiler`).
+    Native,
+}
+
+impl CompilerChoice {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "stable" => Some(Self::Stable),
+            "experimental" => Some(Self::Experimental),
+            "native" => Some(Self::Native),
+            _ => None,
+        }
+    }
+}
+
+/// A project's `reactive.toml`, as written by `reactive new`:
+///
+/// ```toml
+/// [project]
+/// name = "app"
+/// entry = "main.rx"
+///
+/// [build]
+/// paths = ["modules"]
+/// compiler = "native"
+/// opt-level = 0
+///
+/// [test]
+/// dir = "tests"
+///
+/// [permissions]
+/// allow = ["std.vec", "std.file"]
+///
+/// [dependencies]
+/// shapes = { path = "../shapes" }
+/// json = { git = "https://example.com/json.git", rev = "v1.2" }
+/// ```
+///
+/// Relative paths are resolved against the manifest's directory. Every key
+/// but `project.name` is optional.
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    /// Directory holding the manifest.
+    pub dir: PathBuf,
+    pub name: String,
+    pub entry: PathBuf,
+    /// Searched for `import a.b` before the default `project` root.
+    pub paths: Vec<PathBuf>,
+    pub compiler: CompilerChoice,
+    /// 0: as compiled; 1: unreachable code stripped.
+    pub opt_level: u32,
+    pub test_dir: PathBuf,
+    /// Native std modules the program may import; `None` allows all.
+    pub allowed_imports: Option<Vec<String>>,
+    /// Module trees vendored into `deps_dir` by `reactive fetch`, by name.
+    pub dependencies: Vec<Dependency>,
+}
+
+/// Where a dependency's `.rx` files come from. `import <name>.a.b` reads
+/// `a/b.rx` of the vendored tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dependency {
+    pub name: String,
+    pub source: DependencySource,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DependencySource {
+    /// A directory, resolved against the manifest's directory.
+    Path(PathBuf),
+    /// A git repository at `rev` (a branch, tag or commit), or its default
+    /// branch.
+    Git { url: String, rev: Option<String> },
+}
+
+impl Manifest {
+    /// The nearest `reactive.toml` in `start` or one of its ancestors.
+    pub fn find(start: &Path) -> Option<PathBuf> {
+        let start = start.canonicalize().unwrap_or_else(|_| start.to_path_buf());
+        start
+            .ancestors()
+            .map(|dir| dir.join(MANIFEST_FILE))
+            .find(|path| path.is_file())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read `{}`: {e}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        Self::parse(&text, dir).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn parse(text: &str, dir: PathBuf) -> Result<Self, String> {
+        let mut table = parse_toml(text)?;
+        let mut take = |key: &str| table.remove(key);
+
+        let name = match take("project.name") {
+            Some(value) => value.into_string("project.name")?,
+            None => return Err("missing `project.name`".to_string()),
+        };
+        let entry = match take("project.entry") {
+            Some(value) => value.into_string("project.entry")?,
+            None => "main.rx".to_string(),
+        };
+        let paths = match take("build.paths") {
+            Some(value) => value.into_strings("build.paths")?,
+            None => Vec::new(),
+        };
+        let compiler = match take("build.compiler") {
+            Some(value) => {
+                let name = value.into_string("build.compiler")?;
+                CompilerChoice::parse(&name).ok_or_else(|| {
+                    format!("unknown compiler `{name}` (expected stable, experimental or native)")
+                })?
+            }
+            None => CompilerChoice::Stable,
+        };
+        let opt_level = match take("build.opt-level") {
+            Some(Value::Int(level @ 0..=1)) => level as u32,
+            Some(_) => return Err("`build.opt-level` must be 0 or 1".to_string()),
+            None => 0,
+        };
+        let test_dir = match take("test.dir") {
+            Some(value) => value.into_string("test.dir")?,
+            None => "tests".to_string(),
+        };
+        let allowed_imports = match take("permissions.allow") {
+            Some(value) => Some(value.into_strings("permissions.allow")?),
+            None => None,
+        };
+
+        // `[dependencies.name]` sections give `dependencies.name.path`
+        // entries; gather them into the same table as `name = { path = ... }`
+        let mut names: Vec<String> = table
+            .keys()
+            .filter_map(|key| key.strip_prefix("dependencies."))
+            .map(|rest| rest.split('.').next().unwrap_or(rest).to_string())
+            .collect();
+        names.sort();
+        names.dedup();
+        let mut dependencies = Vec::new();
+        for name in names {
+            let key = format!("dependencies.{name}");
+            let prefix = format!("{key}.");
+            let fields: Vec<String> = table
+                .keys()
+                .filter(|k| k.starts_with(&prefix))
+                .cloned()
+                .collect();
+            let spec = match table.remove(&key) {
+                None if !fields.is_empty() => Some(Value::Table(HashMap::new())),
+                Some(_) if !fields.is_empty() => {
+                    return Err(format!("`{key}` is set twice"));
+                }
+                spec => spec,
+            };
+            let Some(Value::Table(mut spec)) = spec else {
+                return Err(format!(
+                    "`{key}` must be a table like {{ path = \"...\" }} or {{ git = \"...\" }}"
+                ));
+            };
+            for field in fields {
+                let value = table.remove(&field).expect("listed above");
+                spec.insert(field[prefix.len()..].to_string(), value);
+            }
+            let source = match (spec.remove("path"), spec.remove("git")) {
+                (Some(path), None) => {
+                    DependencySource::Path(dir.join(path.into_string(&format!("{key}.path"))?))
+                }
+                (None, Some(url)) => DependencySource::Git {
+                    url: url.into_string(&format!("{key}.git"))?,
+                    rev: match spec.remove("rev") {
+                        Some(rev) => Some(rev.into_string(&format!("{key}.rev"))?),
+                        None => None,
+                    },
+                },
+                _ => return Err(format!("`{key}` needs exactly one of `path` or `git`")),
+            };
+            if let Some(extra) = spec.keys().min() {
+                return Err(format!("unknown key `{key}.{extra}`"));
+            }
+            dependencies.push(Dependency { name, source });
+        }
+
+        let mut unknown: Vec<String> = table.into_keys().collect();
+        unknown.sort();
+        if let Some(key) = unknown.first() {
+            return Err(format!("unknown key `{key}`"));
+        }
+
+        Ok(Self {
+            entry: dir.join(entry),
+            paths: paths.iter().map(|path| dir.join(path)).collect(),
+            test_dir: dir.join(test_dir),
+            dir,
+            name,
+            compiler,
+            opt_level,
+            allowed_imports,
+            dependencies,
+        })
+    }
+
+    /// Where `reactive fetch` vendors dependencies, one directory each.
+    pub fn deps_dir(&self) -> PathBuf {
+        self.dir.join("deps")
+    }
+
+    pub fn lock_path(&self) -> PathBuf {
+        self.dir.join(LOCK_FILE)
+    }
+
+    /// Directories searched for imports: `build.paths`, then the vendored
+    /// dependencies.
+    pub fn search_paths(&self) -> Vec<PathBuf> {
+        let mut paths = self.paths.clone();
+        if !self.dependencies.is_empty() {
+            paths.push(self.deps_dir());
+        }
+        paths
+    }
+}
+
+/// A value in the TOML subset manifests use.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Array(Vec<Value>),
+    /// An inline `{ key = value, ... }` table.
+    Table(HashMap<String, Value>),
+}
+
+impl Value {
+    pub(crate) fn into_string(self, key: &str) -> Result<String, String> {
+        match self {
+            Value::Str(s) => Ok(s),
+            _ => Err(format!("`{key}` must be a string")),
+        }
+    }
+
+    fn into_strings(self, key: &str) -> Result<Vec<String>, String> {
+        let Value::Array(items) = self else {
+            return Err(format!("`{key}` must be an array of strings"));
+        };
+        items
+            .into_iter()
+            .map(|item| match item {
+                Value::Str(s) => Ok(s),
+                _ => Err(format!("`{key}` must be an array of strings")),
+            })
+            .collect()
+    }
+}
+
+/// Parses `[table]` headers and `key = value` lines (strings, integers,
+/// booleans, and single-line arrays and inline tables of them) into
+/// `table.key` entries. Headers and keys may be dotted (`[a.b]`, `a.b = 1`);
+/// quoted keys and `[[array]]` tables are not supported.
+pub(crate) fn parse_toml(text: &str) -> Result<HashMap<String, Value>, String> {
+    let mut entries = HashMap::new();
+    let mut table = String::new();
+    for (i, raw) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = strip_comment(raw).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with("[[") {
+            return Err(format!(
+                "line {line_no}: arrays of tables (`[[...]]`) are not supported"
+            ));
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .and_then(dotted_key)
+                .ok_or_else(|| format!("line {line_no}: invalid table header"))?;
+            table = name;
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {line_no}: expected `key = value`"))?;
+        let key = key.trim();
+        if key.starts_with(['"', '\'']) {
+            return Err(format!(
+                "line {line_no}: quoted keys are not supported; use a bare key of letters, digits, `_` and `-`"
+            ));
+        }
+        let key = dotted_key(key).ok_or_else(|| format!("line {line_no}: invalid key `{key}`"))?;
+        let mut rest = value.trim();
+        let value = parse_value(&mut rest).map_err(|e| format!("line {line_no}: {e}"))?;
+        if !rest.trim().is_empty() {
+            return Err(format!("line {line_no}: unexpected `{}`", rest.trim()));
+        }
+
+        let full = if table.is_empty() {
+            key
+        } else {
+            format!("{table}.{key}")
+        };
+        if entries.insert(full.clone(), value).is_some() {
+            return Err(format!("line {line_no}: `{full}` is set twice"));
+        }
+    }
+    Ok(entries)
+}
+
+/// `a . b` as `a.b`, if every part is a bare key.
+fn dotted_key(key: &str) -> Option<String> {
+    let parts: Vec<&str> = key.split('.').map(str::trim).collect();
+    parts
+        .iter()
+        .all(|part| is_bare_key(part))
+        .then(|| parts.join("."))
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// `line` up to a `#` that is not inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Parses one value from the front of `input`, leaving the rest.
+fn parse_value(input: &mut &str) -> Result<Value, String> {
+    let s = input.trim_start();
+    if let Some(rest) = s.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    *input = &rest[i + 1..];
+                    return Ok(Value::Str(out));
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('\\') => out.push('\\'),
+                    Some('"') => out.push('"'),
+                    _ => return Err("invalid escape in string".to_string()),
+                },
+                c => out.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+    if let Some(rest) = s.strip_prefix('[') {
+        let mut items = Vec::new();
+        let mut rest = rest.trim_start();
+        loop {
+            if let Some(after) = rest.strip_prefix(']') {
+                *input = after;
+                return Ok(Value::Array(items));
+            }
+            items.push(parse_value(&mut rest)?);
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after.trim_start();
+            } else if !rest.starts_with(']') {
+                return Err("expected `,` or `]` in array".to_string());
+            }
+        }
+    }
+
+    if let Some(rest) = s.strip_prefix('{') {
+        let mut entries = HashMap::new();
+        let mut rest = rest.trim_start();
+        loop {
+            if let Some(after) = rest.strip_prefix('}') {
+                *input = after;
+                return Ok(Value::Table(entries));
+            }
+            let (key, after) = rest
+                .split_once('=')
+                .ok_or("expected `key = value` in inline table")?;
+            let key = key.trim();
+            if !is_bare_key(key) {
+                return Err(format!("invalid key `{key}`"));
+            }
+            rest = after;
+            let value = parse_value(&mut rest)?;
+            if entries.insert(key.to_string(), value).is_some() {
+                return Err(format!("`{key}` is set twice"));
+            }
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after.trim_start();
+            } else if !rest.starts_with('}') {
+                return Err("expected `,` or `}` in inline table".to_string());
+            }
+        }
+    }
+
+    let end = s
+        .find(|c: char| c == ',' || c == ']' || c == '}' || c.is_whitespace())
+        .unwrap_or(s.len());
+    let (token, rest) = s.split_at(end);
+    let value = match token {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => Value::Int(
+            token
+                .replace('_', "")
+                .parse()
+                .map_err(|_| format!("invalid value `{token}`"))?,
+        ),
+    };
+    *input = rest;
+    Ok(value)
+}
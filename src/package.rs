@@ -0,0 +1,297 @@
+use crate::hash::{sha256, to_hex};
+use crate::manifest::{DependencySource, LOCK_FILE, Manifest, Value, parse_toml};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// What `reactive.lock` pins for one dependency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Locked {
+    pub name: String,
+    /// Repository URL or directory, as written in the manifest.
+    pub source: String,
+    /// Requested git revision, if any.
+    pub rev: Option<String>,
+    /// Commit the git revision resolved to.
+    pub commit: Option<String>,
+    /// `sha256:<hex>` over the vendored `.rx` files (see `tree_hash`).
+    pub hash: String,
+    /// Number of vendored `.rx` files (not written to the lockfile).
+    pub files: usize,
+}
+
+/// Vendors every dependency of `manifest` into `Manifest::deps_dir` and
+/// rewrites the lockfile. Git dependencies already in the lockfile check
+/// out the locked commit unless their URL or `rev` changed or `update` is
+/// set. Vendored trees of dependencies no longer listed are removed.
+pub fn fetch(manifest: &Manifest, update: bool) -> Result<Vec<Locked>, String> {
+    let previous = read_lock(&manifest.lock_path())?;
+    let deps_dir = manifest.deps_dir();
+    fs::create_dir_all(&deps_dir)
+        .map_err(|e| format!("failed to create `{}`: {e}", deps_dir.display()))?;
+
+    let mut locked = Vec::new();
+    for dep in &manifest.dependencies {
+        let dest = deps_dir.join(&dep.name);
+        let entry = match &dep.source {
+            DependencySource::Path(path) => {
+                let (files, hash) = vendor(path, &dest)?;
+                Locked {
+                    name: dep.name.clone(),
+                    source: path
+                        .strip_prefix(&manifest.dir)
+                        .unwrap_or(path)
+                        .to_string_lossy()
+                        .into_owned(),
+                    rev: None,
+                    commit: None,
+                    hash,
+                    files,
+                }
+            }
+            DependencySource::Git { url, rev } => {
+                let pinned = previous
+                    .iter()
+                    .find(|l| l.name == dep.name && l.source == *url && l.rev == *rev)
+                    .and_then(|l| l.commit.clone())
+                    .filter(|_| !update);
+                let checkout = deps_dir.join(format!(".fetch-{}", dep.name));
+                let result = clone(url, pinned.as_deref().or(rev.as_deref()), &checkout).and_then(
+                    |commit| {
+                        let (files, hash) = vendor(&checkout, &dest)?;
+                        Ok((commit, files, hash))
+                    },
+                );
+                let _ = fs::remove_dir_all(&checkout);
+                let (commit, files, hash) =
+                    result.map_err(|e| format!("dependency `{}`: {e}", dep.name))?;
+                Locked {
+                    name: dep.name.clone(),
+                    source: url.clone(),
+                    rev: rev.clone(),
+                    commit: Some(commit),
+                    hash,
+                    files,
+                }
+            }
+        };
+        locked.push(entry);
+    }
+
+    remove_unlisted(manifest, &deps_dir)?;
+    write_lock(&manifest.lock_path(), &locked)?;
+    Ok(locked)
+}
+
+/// Checks that every dependency is vendored and unchanged since the last
+/// `reactive fetch`.
+pub fn verify(manifest: &Manifest) -> Result<(), String> {
+    if manifest.dependencies.is_empty() {
+        return Ok(());
+    }
+    let lock = read_lock(&manifest.lock_path())?;
+    for dep in &manifest.dependencies {
+        let Some(locked) = lock.iter().find(|l| l.name == dep.name) else {
+            return Err(format!(
+                "dependency `{}` is not in {LOCK_FILE}; run `reactive fetch`",
+                dep.name
+            ));
+        };
+        let dest = manifest.deps_dir().join(&dep.name);
+        if !dest.is_dir() {
+            return Err(format!(
+                "dependency `{}` is not fetched; run `reactive fetch`",
+                dep.name
+            ));
+        }
+        let (_, hash) = tree_hash(&dest)?;
+        if hash != locked.hash {
+            return Err(format!(
+                "vendored dependency `{}` differs from {LOCK_FILE}; run `reactive fetch`",
+                dep.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Clones `url` into `dest` at `rev` (default branch if `None`) and returns
+/// the commit checked out.
+fn clone(url: &str, rev: Option<&str>, dest: &Path) -> Result<String, String> {
+    let _ = fs::remove_dir_all(dest);
+    crate::verbose!("cloning {url}");
+    git(&["clone", "--quiet", url, &dest.to_string_lossy()], None)?;
+    if let Some(rev) = rev {
+        crate::verbose!("checking out {rev}");
+        git(&["checkout", "--quiet", rev], Some(dest))?;
+    }
+    git(&["rev-parse", "HEAD"], Some(dest)).map(|out| out.trim().to_string())
+}
+
+fn git(args: &[&str], dir: Option<&Path>) -> Result<String, String> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+    let output = command
+        .args(args)
+        .output()
+        .map_err(|e| format!("failed to run git: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Replaces `dest` with the `.rx` files under `src`, keeping their layout.
+/// Hidden directories (such as `.git`) are skipped.
+fn vendor(src: &Path, dest: &Path) -> Result<(usize, String), String> {
+    if !src.is_dir() {
+        return Err(format!("`{}` is not a directory", src.display()));
+    }
+    let mut files = Vec::new();
+    collect_sources(src, src, &mut files)?;
+    if dest.exists() {
+        fs::remove_dir_all(dest)
+            .map_err(|e| format!("failed to clear `{}`: {e}", dest.display()))?;
+    }
+    for relative in &files {
+        let target = dest.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("failed to create `{}`: {e}", parent.display()))?;
+        }
+        fs::copy(src.join(relative), &target)
+            .map_err(|e| format!("failed to copy to `{}`: {e}", target.display()))?;
+    }
+    tree_hash(dest)
+}
+
+/// Number of `.rx` files under `dir`, and a hash of their relative paths
+/// and contents in path order.
+pub fn tree_hash(dir: &Path) -> Result<(usize, String), String> {
+    let mut files = Vec::new();
+    collect_sources(dir, dir, &mut files)?;
+    let mut data = Vec::new();
+    for relative in &files {
+        let path = relative.to_string_lossy().replace('\\', "/");
+        let contents = fs::read(dir.join(relative))
+            .map_err(|e| format!("failed to read `{}`: {e}", dir.join(relative).display()))?;
+        data.extend_from_slice(path.as_bytes());
+        data.push(0);
+        data.extend_from_slice(&(contents.len() as u64).to_le_bytes());
+        data.extend_from_slice(&contents);
+    }
+    Ok((files.len(), format!("sha256:{}", to_hex(&sha256(&data)))))
+}
+
+/// `.rx` files under `dir`, relative to `root`, sorted.
+fn collect_sources(root: &Path, dir: &Path, out: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("failed to read `{}`: {e}", dir.display()))?;
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    paths.sort();
+    for path in paths {
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if path.is_dir() {
+            if !hidden {
+                collect_sources(root, &path, out)?;
+            }
+        } else if path.extension().is_some_and(|e| e == "rx") {
+            out.push(path.strip_prefix(root).unwrap_or(&path).to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+fn remove_unlisted(manifest: &Manifest, deps_dir: &Path) -> Result<(), String> {
+    let entries = fs::read_dir(deps_dir)
+        .map_err(|e| format!("failed to read `{}`: {e}", deps_dir.display()))?;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let listed = manifest.dependencies.iter().any(|dep| dep.name == name);
+        if entry.path().is_dir() && !listed && !name.starts_with('.') {
+            fs::remove_dir_all(entry.path())
+                .map_err(|e| format!("failed to remove `{}`: {e}", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Entries of the lockfile at `path`; none if it does not exist.
+pub fn read_lock(path: &Path) -> Result<Vec<Locked>, String> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read `{}`: {e}", path.display()))?;
+    let mut tables: HashMap<String, HashMap<String, Value>> = HashMap::new();
+    for (key, value) in parse_toml(&text).map_err(|e| format!("{}: {e}", path.display()))? {
+        let Some((name, field)) = key.split_once('.') else {
+            return Err(format!("{}: unexpected key `{key}`", path.display()));
+        };
+        tables
+            .entry(name.to_string())
+            .or_default()
+            .insert(field.to_string(), value);
+    }
+
+    let mut locked = Vec::new();
+    for (name, mut fields) in tables {
+        let mut text = |field: &str| -> Result<Option<String>, String> {
+            fields
+                .remove(field)
+                .map(|v| v.into_string(&format!("{name}.{field}")))
+                .transpose()
+        };
+        let source = match (text("git")?, text("path")?) {
+            (Some(source), _) | (None, Some(source)) => source,
+            (None, None) => {
+                return Err(format!("{}: `{name}` has no source", path.display()));
+            }
+        };
+        let rev = text("rev")?;
+        let commit = text("commit")?;
+        let hash =
+            text("hash")?.ok_or_else(|| format!("{}: `{name}` has no hash", path.display()))?;
+        locked.push(Locked {
+            name,
+            source,
+            rev,
+            commit,
+            hash,
+            files: 0,
+        });
+    }
+    locked.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(locked)
+}
+
+fn write_lock(path: &Path, locked: &[Locked]) -> Result<(), String> {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut out = String::from("# Written by `reactive fetch`; commit it to pin dependencies.\n");
+    for entry in locked {
+        out.push_str(&format!("\n[{}]\n", entry.name));
+        let kind = if entry.commit.is_some() {
+            "git"
+        } else {
+            "path"
+        };
+        out.push_str(&format!("{kind} = {}\n", quote(&entry.source)));
+        if let Some(rev) = &entry.rev {
+            out.push_str(&format!("rev = {}\n", quote(rev)));
+        }
+        if let Some(commit) = &entry.commit {
+            out.push_str(&format!("commit = {}\n", quote(commit)));
+        }
+        out.push_str(&format!("hash = {}\n", quote(&entry.hash)));
+    }
+    fs::write(path, out).map_err(|e| format!("failed to write `{}`: {e}", path.display()))
+}
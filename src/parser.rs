@@ -0,0 +1,526 @@
+use crate::grammar::{AST, CastType, Operator, SourceError, Span, StructFieldInit, Token};
+
+/// Parses a token stream into an `AST::Program`. Mirrors the stable
+/// self-hosted parser (`project/bootstrap/stable/parser.rx`).
+pub fn parse(tokens: Vec<Token>) -> Result<AST, String> {
+    let mut parser = Parser::new(tokens, Vec::new());
+    let ast = parser.parse_program()?;
+    if parser.index != parser.tokens.len() {
+        return Err("parser did not consume all tokens".to_string());
+    }
+    Ok(ast)
+}
+
+/// `parse` for tokens with spans: every statement and top-level item comes
+/// wrapped in `AST::Located` with its first line, and errors point at the
+/// token the parser stopped on.
+pub fn parse_spanned(tokens: Vec<(Token, Span)>) -> Result<AST, SourceError> {
+    let (tokens, spans) = tokens.into_iter().unzip();
+    let mut parser = Parser::new(tokens, spans);
+    match parser.parse_program() {
+        Ok(ast) => Ok(ast),
+        Err(message) => Err(SourceError {
+            message,
+            span: parser.error_span(),
+        }),
+    }
+}
+
+/// Token indices where each statement, top-level item and struct field
+/// starts, in order; the formatter breaks lines there.
+pub fn statement_starts(tokens: Vec<(Token, Span)>) -> Result<Vec<usize>, SourceError> {
+    let (tokens, spans) = tokens.into_iter().unzip();
+    let mut parser = Parser::new(tokens, spans);
+    match parser.parse_program() {
+        Ok(_) => {
+            parser.starts.sort_unstable();
+            Ok(parser.starts)
+        }
+        Err(message) => Err(SourceError {
+            message,
+            span: parser.error_span(),
+        }),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    // empty unless parsing with spans
+    spans: Vec<Span>,
+    index: usize,
+    // first token of every statement, item and field parsed
+    starts: Vec<usize>,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>, spans: Vec<Span>) -> Self {
+        Self {
+            tokens,
+            spans,
+            index: 0,
+            starts: Vec::new(),
+        }
+    }
+
+    /// Span of the token that was being looked at when parsing failed (the
+    /// end of the last token at end of input).
+    fn error_span(&self) -> Span {
+        let index = self.index.saturating_sub(1);
+        match self.spans.get(index) {
+            Some(span) => *span,
+            None => self.spans.last().copied().unwrap_or_default(),
+        }
+    }
+
+    /// Wraps `stmt` with the line of the token at `index`, when parsing
+    /// with spans.
+    fn located(&mut self, index: usize, stmt: AST) -> AST {
+        self.starts.push(index);
+        match self.spans.get(index) {
+            Some(span) => AST::Located(span.line, Box::new(stmt)),
+            None => stmt,
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn peek_n(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.index + n)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let tok = self.tokens.get(self.index).cloned();
+        self.index += 1;
+        tok
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(got) if got == expected => Ok(()),
+            got => Err(format!("parser: expected {expected:?}, got {got:?}")),
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Ident(s)) => Ok(s),
+            other => Err(format!("parser: expected identifier, got {other:?}")),
+        }
+    }
+
+    // ---------------- expressions ----------------
+
+    fn parse_factor(&mut self) -> Result<AST, String> {
+        match self.next() {
+            Some(Token::Ident(name)) => {
+                if !matches!(self.peek(), Some(Token::LParen)) {
+                    return Ok(AST::Var(name));
+                }
+                self.next();
+                let mut args = Vec::new();
+                if !matches!(self.peek(), Some(Token::RParen)) {
+                    loop {
+                        args.push(self.parse_ternary()?);
+                        if matches!(self.peek(), Some(Token::Comma)) {
+                            self.next();
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                self.expect(Token::RParen)?;
+                Ok(AST::Call { name, args })
+            }
+
+            Some(Token::Number(n)) => Ok(AST::Number(n)),
+            Some(Token::Char(c)) => Ok(AST::Char(c)),
+            Some(Token::StringLiteral(s)) => Ok(AST::StringLiteral(s)),
+
+            Some(Token::LParen) => {
+                let expr = self.parse_ternary()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+
+            Some(Token::LSquare) => {
+                let size = self.parse_ternary()?;
+                self.expect(Token::RSquare)?;
+                Ok(AST::ArrayNew(Box::new(size)))
+            }
+
+            Some(Token::Struct) => Ok(AST::StructNew(self.expect_ident()?)),
+
+            other => Err(format!("parser: invalid factor {other:?}")),
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<AST, String> {
+        let mut expr = self.parse_factor()?;
+        loop {
+            match self.peek() {
+                Some(Token::LSquare) => {
+                    self.next();
+                    let idx = self.parse_ternary()?;
+                    self.expect(Token::RSquare)?;
+                    expr = AST::Index(Box::new(expr), Box::new(idx));
+                }
+                Some(Token::Dot) => {
+                    self.next();
+                    let field = self.expect_ident()?;
+                    expr = AST::FieldAccess(Box::new(expr), field);
+                }
+                _ => break,
+            }
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<AST, String> {
+        if let (Some(Token::LParen), Some(Token::Ident(name)), Some(Token::RParen)) =
+            (self.peek(), self.peek_n(1), self.peek_n(2))
+        {
+            let target = match name.as_str() {
+                "int" => Some(CastType::Int),
+                "char" => Some(CastType::Char),
+                _ => None,
+            };
+            if let Some(target) = target {
+                self.index += 3;
+                let expr = self.parse_unary()?;
+                return Ok(AST::Cast {
+                    target,
+                    expr: Box::new(expr),
+                });
+            }
+        }
+
+        match self.peek() {
+            Some(Token::Sub) => {
+                self.next();
+                Ok(AST::Neg(Box::new(self.parse_unary()?)))
+            }
+            Some(Token::Not) => {
+                self.next();
+                Ok(AST::Not(Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    fn parse_mul(&mut self) -> Result<AST, String> {
+        let mut expr = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Mul) => Operator::Multiplication,
+                Some(Token::Div) => Operator::Division,
+                Some(Token::Modulo) => Operator::Modulo,
+                _ => break,
+            };
+            self.next();
+            let rhs = self.parse_unary()?;
+            expr = AST::Operation(Box::new(expr), op, Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_add(&mut self) -> Result<AST, String> {
+        let mut expr = self.parse_mul()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Add) => Operator::Addition,
+                Some(Token::Sub) => Operator::Subtraction,
+                _ => break,
+            };
+            self.next();
+            let rhs = self.parse_mul()?;
+            expr = AST::Operation(Box::new(expr), op, Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_cmp(&mut self) -> Result<AST, String> {
+        let mut expr = self.parse_add()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Greater) => Operator::Greater,
+                Some(Token::Less) => Operator::Less,
+                Some(Token::Equal) => Operator::Equal,
+                Some(Token::GreaterEqual) => Operator::GreaterEqual,
+                Some(Token::LessEqual) => Operator::LessEqual,
+                Some(Token::NotEqual) => Operator::NotEqual,
+                _ => break,
+            };
+            self.next();
+            let rhs = self.parse_add()?;
+            expr = AST::Operation(Box::new(expr), op, Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<AST, String> {
+        let mut expr = self.parse_cmp()?;
+        while matches!(self.peek(), Some(Token::And)) {
+            self.next();
+            let rhs = self.parse_cmp()?;
+            expr = AST::Operation(Box::new(expr), Operator::And, Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_or(&mut self) -> Result<AST, String> {
+        let mut expr = self.parse_and()?;
+        while matches!(self.peek(), Some(Token::Or)) {
+            self.next();
+            let rhs = self.parse_and()?;
+            expr = AST::Operation(Box::new(expr), Operator::Or, Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_ternary(&mut self) -> Result<AST, String> {
+        let cond = self.parse_or()?;
+        if !matches!(self.peek(), Some(Token::Question)) {
+            return Ok(cond);
+        }
+        self.next();
+        let then_expr = self.parse_ternary()?;
+        self.expect(Token::Colon)?;
+        let else_expr = self.parse_ternary()?;
+        Ok(AST::Ternary {
+            cond: Box::new(cond),
+            then_expr: Box::new(then_expr),
+            else_expr: Box::new(else_expr),
+        })
+    }
+
+    // ---------------- statements ----------------
+
+    fn parse_block(&mut self) -> Result<Vec<AST>, String> {
+        self.expect(Token::LBrace)?;
+        let mut stmts = Vec::new();
+        while !matches!(self.peek(), Some(Token::RBrace)) {
+            if self.peek().is_none() {
+                return Err("parser: expected RBrace, got None".to_string());
+            }
+            let start = self.index;
+            let stmt = self.parse_statement()?;
+            stmts.push(self.located(start, stmt));
+            if matches!(self.peek(), Some(Token::Semicolon)) {
+                self.next();
+            }
+        }
+        self.expect(Token::RBrace)?;
+        Ok(stmts)
+    }
+
+    fn parse_if(&mut self) -> Result<AST, String> {
+        self.next();
+
+        let cond = self.parse_ternary()?;
+        let then_block = self.parse_block()?;
+
+        let else_block = if matches!(self.peek(), Some(Token::Else)) {
+            self.next();
+            if matches!(self.peek(), Some(Token::If)) {
+                let start = self.index;
+                let stmt = self.parse_if()?;
+                vec![self.located(start, stmt)]
+            } else {
+                self.parse_block()?
+            }
+        } else {
+            Vec::new()
+        };
+
+        Ok(AST::IfElse(Box::new(cond), then_block, else_block))
+    }
+
+    fn parse_import(&mut self) -> Result<AST, String> {
+        self.next();
+        let mut path = vec![self.expect_ident()?];
+        while matches!(self.peek(), Some(Token::Dot)) {
+            self.next();
+            path.push(self.expect_ident()?);
+        }
+        Ok(AST::Import(path))
+    }
+
+    fn parse_func_def(&mut self) -> Result<AST, String> {
+        self.next();
+        let name = self.expect_ident()?;
+        self.expect(Token::LParen)?;
+        let mut params = Vec::new();
+        if !matches!(self.peek(), Some(Token::RParen)) {
+            loop {
+                params.push(self.expect_ident()?);
+                if matches!(self.peek(), Some(Token::Comma)) {
+                    self.next();
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect(Token::RParen)?;
+        let body = self.parse_block()?;
+        Ok(AST::FuncDef { name, params, body })
+    }
+
+    fn parse_struct_def(&mut self) -> Result<AST, String> {
+        self.next();
+        let name = self.expect_ident()?;
+        self.expect(Token::LBrace)?;
+
+        let mut fields = Vec::new();
+        while !matches!(self.peek(), Some(Token::RBrace)) {
+            self.starts.push(self.index);
+            let fname = self.expect_ident()?;
+            let init = match self.peek() {
+                Some(Token::Assign) => {
+                    self.next();
+                    Some(StructFieldInit::Mutable(self.parse_ternary()?))
+                }
+                Some(Token::ImmutableAssign) => {
+                    self.next();
+                    Some(StructFieldInit::Immutable(self.parse_ternary()?))
+                }
+                Some(Token::ReactiveAssign) => {
+                    self.next();
+                    Some(StructFieldInit::Reactive(self.parse_ternary()?))
+                }
+                _ => None,
+            };
+            fields.push((fname, init));
+            if matches!(self.peek(), Some(Token::Semicolon)) {
+                self.next();
+            }
+        }
+
+        self.expect(Token::RBrace)?;
+        Ok(AST::StructDef { name, fields })
+    }
+
+    fn parse_return(&mut self) -> Result<AST, String> {
+        self.next();
+        if matches!(self.peek(), Some(Token::Semicolon | Token::RBrace) | None) {
+            Ok(AST::Return(None))
+        } else {
+            Ok(AST::Return(Some(Box::new(self.parse_ternary()?))))
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<AST, String> {
+        match self.peek() {
+            Some(Token::Import) => self.parse_import(),
+            Some(Token::Func) => self.parse_func_def(),
+            Some(Token::Struct) if matches!(self.peek_n(2), Some(Token::LBrace)) => {
+                self.parse_struct_def()
+            }
+            Some(Token::Return) => self.parse_return(),
+            Some(Token::Break) => {
+                self.next();
+                Ok(AST::Break)
+            }
+            Some(Token::Continue) => {
+                self.next();
+                Ok(AST::Continue)
+            }
+            Some(Token::If) => self.parse_if(),
+            Some(Token::Print) => {
+                self.next();
+                Ok(AST::Print(Box::new(self.parse_ternary()?)))
+            }
+            Some(Token::Println) => {
+                self.next();
+                Ok(AST::Println(Box::new(self.parse_ternary()?)))
+            }
+            Some(Token::Assert) => {
+                self.next();
+                Ok(AST::Assert(Box::new(self.parse_ternary()?)))
+            }
+            Some(Token::Error) => {
+                self.next();
+                match self.next() {
+                    Some(Token::StringLiteral(s)) => Ok(AST::Error(s)),
+                    _ => Err("error expects a string literal".to_string()),
+                }
+            }
+            Some(Token::Loop) => {
+                self.next();
+                Ok(AST::Loop(self.parse_block()?))
+            }
+
+            Some(Token::Ident(_))
+                if matches!(
+                    self.peek_n(1),
+                    Some(Token::Assign | Token::ReactiveAssign | Token::ImmutableAssign)
+                ) =>
+            {
+                let name = self.expect_ident()?;
+                let op = self.next();
+                let rhs = Box::new(self.parse_ternary()?);
+                Ok(match op {
+                    Some(Token::Assign) => AST::Assign(name, rhs),
+                    Some(Token::ReactiveAssign) => AST::ReactiveAssign(name, rhs),
+                    _ => AST::ImmutableAssign(name, rhs),
+                })
+            }
+
+            _ => {
+                let lhs = Box::new(self.parse_ternary()?);
+                match self.peek() {
+                    Some(Token::Assign) => {
+                        self.next();
+                        Ok(AST::AssignTarget(lhs, Box::new(self.parse_ternary()?)))
+                    }
+                    Some(Token::ReactiveAssign) => {
+                        self.next();
+                        Ok(AST::ReactiveAssignTarget(
+                            lhs,
+                            Box::new(self.parse_ternary()?),
+                        ))
+                    }
+                    Some(Token::ImmutableAssign) => {
+                        self.next();
+                        Ok(AST::ImmutableAssignTarget(
+                            lhs,
+                            Box::new(self.parse_ternary()?),
+                        ))
+                    }
+                    _ => Ok(*lhs),
+                }
+            }
+        }
+    }
+
+    fn parse_toplevel(&mut self) -> Result<AST, String> {
+        match self.peek() {
+            Some(Token::Import) => self.parse_import(),
+            Some(Token::Func) => self.parse_func_def(),
+            Some(Token::Struct) if matches!(self.peek_n(2), Some(Token::LBrace)) => {
+                self.parse_struct_def()
+            }
+            Some(Token::Ident(_)) if matches!(self.peek_n(1), Some(Token::ImmutableAssign)) => {
+                let name = self.expect_ident()?;
+                self.expect(Token::ImmutableAssign)?;
+                let rhs = self.parse_ternary()?;
+                Ok(AST::ImmutableAssign(name, Box::new(rhs)))
+            }
+            _ => Err("parser: invalid top-level item".to_string()),
+        }
+    }
+
+    fn parse_program(&mut self) -> Result<AST, String> {
+        let mut items = Vec::new();
+        while self.peek().is_some() {
+            let start = self.index;
+            let item = self.parse_toplevel()?;
+            items.push(self.located(start, item));
+            if matches!(self.peek(), Some(Token::Semicolon)) {
+                self.next();
+            }
+        }
+        Ok(AST::Program(items))
+    }
+}
@@ -0,0 +1,267 @@
+//! Python bindings (`--features python`): a `reactive` extension module
+//! whose `ReactiveVM` class loads a program and calls its functions, so
+//! scripts can drive Reactive code in tests and pipelines.
+//!
+//! ```python
+//! import reactive
+//! vm = reactive.ReactiveVM()
+//! vm.load("main.rx")
+//! vm.call("total", [1, 2, 3])
+//! ```
+//!
+//! Values are copied across, through the same detached form worker threads
+//! use (`Message`):
+//!
+//! - `None`, `int` (and `bool`) and `bytes` map to null, ints and bytes.
+//! - `str` maps to a string (an array of chars). Arrays and vecs of chars
+//!   come back as `str`, a lone char as a one-character `str`, and other
+//!   arrays and vecs as `list`; an empty one is `[]`.
+//! - `list` and `tuple` map to arrays and tuples. A set comes back as a
+//!   `set`.
+//! - A struct is a `dict` of its fields plus `"__struct__"`, its name; a
+//!   `dict` passed in becomes a struct named by its `"__struct__"` (`Dict`
+//!   if absent). An enum variant is `{"__enum__", "__variant__",
+//!   "payload"}`, and such a `dict` passed in becomes a variant.
+//!
+//! Functions, handles and priority queues cannot cross. A runtime error
+//! raises `reactive.ReactiveError`; the VM stays usable.
+
+use crate::bytecode;
+use crate::compiler::Compiler;
+use crate::vm::worker::Message;
+use crate::vm::{VM, VmOptions};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyRuntimeError, PyTypeError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyInt, PyList, PySet, PyString, PyTuple};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+create_exception!(reactive, ReactiveError, PyRuntimeError);
+
+/// `ReactiveVM()`: a VM with the default options and no program loaded.
+#[pyclass(name = "ReactiveVM", module = "reactive", unsendable)]
+pub struct ReactiveVm {
+    vm: VM,
+}
+
+#[pymethods]
+impl ReactiveVm {
+    #[new]
+    fn new() -> Self {
+        let options = VmOptions {
+            unwind_on_error: true,
+            ..VmOptions::default()
+        };
+        ReactiveVm {
+            vm: VM::with_options(Vec::new(), options),
+        }
+    }
+
+    /// `load(path, root="project")`: runs a program's top level, `main`
+    /// included. A `.rx` file is compiled with the native compiler, which
+    /// finds imports under `root`; anything else is read as bytecode.
+    #[pyo3(signature = (path, root = PathBuf::from("project")))]
+    fn load(&mut self, path: PathBuf, root: PathBuf) -> PyResult<()> {
+        let code = if path.extension().is_some_and(|ext| ext == "rx") {
+            Compiler::new(root).compile_file(&path)
+        } else {
+            bytecode::read_instructions_from_file(&path.to_string_lossy())
+        };
+        let code = code.map_err(ReactiveError::new_err)?;
+        let name = path.display().to_string();
+        self.vm
+            .catch_runtime_error(|vm| {
+                vm.run_module(name, code);
+            })
+            .map_err(|error| ReactiveError::new_err(error.message))
+    }
+
+    /// `call(name, *args)`: calls global function `name` and returns its
+    /// result.
+    #[pyo3(signature = (name, *args))]
+    fn call(
+        &mut self,
+        py: Python<'_>,
+        name: &str,
+        args: &Bound<'_, PyTuple>,
+    ) -> PyResult<PyObject> {
+        let args = args
+            .iter()
+            .map(|arg| to_message(&arg))
+            .collect::<PyResult<Vec<_>>>()?;
+        let result = self.vm.catch_runtime_error(|vm| {
+            let args = args.into_iter().map(|arg| vm.import_value(arg)).collect();
+            let value = vm.call_global(name, args);
+            vm.export_value(value)
+        });
+        match result {
+            Ok(Ok(message)) => from_message(py, message),
+            Ok(Err(error)) => Err(PyTypeError::new_err(format!(
+                "cannot return the result of `{name}` to Python: {error}"
+            ))),
+            Err(error) => Err(ReactiveError::new_err(error.message)),
+        }
+    }
+
+    /// The global functions defined so far, sorted.
+    fn functions(&self) -> Vec<String> {
+        self.vm.function_names()
+    }
+}
+
+#[pymodule]
+fn reactive(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<ReactiveVm>()?;
+    m.add("ReactiveError", m.py().get_type::<ReactiveError>())?;
+    Ok(())
+}
+
+fn string_message(text: &str) -> Message {
+    let chars = text.chars().map(|c| Message::Char(c as u32)).collect();
+    Message::Array(chars, HashSet::new())
+}
+
+fn to_message(value: &Bound<'_, PyAny>) -> PyResult<Message> {
+    if value.is_none() {
+        Ok(Message::Null)
+    } else if value.is_instance_of::<PyInt>() {
+        Ok(Message::Integer(value.extract()?))
+    } else if let Ok(text) = value.downcast::<PyString>() {
+        Ok(string_message(text.to_str()?))
+    } else if let Ok(bytes) = value.downcast::<PyBytes>() {
+        Ok(Message::Bytes(bytes.as_bytes().to_vec()))
+    } else if let Ok(list) = value.downcast::<PyList>() {
+        let elems = list
+            .iter()
+            .map(|e| to_message(&e))
+            .collect::<PyResult<_>>()?;
+        Ok(Message::Array(elems, HashSet::new()))
+    } else if let Ok(tuple) = value.downcast::<PyTuple>() {
+        let elems = tuple
+            .iter()
+            .map(|e| to_message(&e))
+            .collect::<PyResult<_>>()?;
+        Ok(Message::Tuple(elems))
+    } else if let Ok(dict) = value.downcast::<PyDict>() {
+        dict_message(dict)
+    } else {
+        Err(PyTypeError::new_err(format!(
+            "cannot pass a value of type `{}` to Reactive",
+            value.get_type().name()?
+        )))
+    }
+}
+
+fn dict_message(dict: &Bound<'_, PyDict>) -> PyResult<Message> {
+    if let Some(enum_name) = dict.get_item("__enum__")? {
+        let name = dict
+            .get_item("__variant__")?
+            .ok_or_else(|| PyTypeError::new_err("variant dict has no `__variant__`"))?;
+        let payload = match dict.get_item("payload")? {
+            Some(payload) => payload
+                .try_iter()?
+                .map(|e| to_message(&e?))
+                .collect::<PyResult<_>>()?,
+            None => Vec::new(),
+        };
+        return Ok(Message::Variant {
+            enum_name: enum_name.extract()?,
+            name: name.extract()?,
+            payload,
+        });
+    }
+    let mut name = "Dict".to_string();
+    let mut fields = Vec::new();
+    for (key, value) in dict.iter() {
+        let key: String = key
+            .extract()
+            .map_err(|_| PyTypeError::new_err("struct field names must be `str`"))?;
+        if key == "__struct__" {
+            name = value.extract()?;
+        } else {
+            fields.push((key, to_message(&value)?));
+        }
+    }
+    Ok(Message::Struct {
+        name,
+        fields,
+        immutables: HashSet::new(),
+    })
+}
+
+fn from_message(py: Python<'_>, message: Message) -> PyResult<PyObject> {
+    let object = match message {
+        Message::Integer(n) => n.into_pyobject(py)?.into_any(),
+        Message::Char(c) => match char::from_u32(c) {
+            Some(c) => c.into_pyobject(py)?.into_any(),
+            None => c.into_pyobject(py)?.into_any(),
+        },
+        Message::Null => py.None().into_bound(py),
+        Message::Tuple(elems) => PyTuple::new(py, from_messages(py, elems)?)?.into_any(),
+        Message::Array(elems, _) | Message::Vec(elems, _) => match message_text(&elems) {
+            Some(text) => text.into_pyobject(py)?.into_any(),
+            None => PyList::new(py, from_messages(py, elems)?)?.into_any(),
+        },
+        Message::Buffer(chars) => chars
+            .into_iter()
+            .filter_map(char::from_u32)
+            .collect::<String>()
+            .into_pyobject(py)?
+            .into_any(),
+        Message::Bytes(bytes) => PyBytes::new(py, &bytes).into_any(),
+        Message::Set(elems) => PySet::new(py, from_messages(py, elems)?)?.into_any(),
+        Message::Struct {
+            name, mut fields, ..
+        } => {
+            let dict = PyDict::new(py);
+            dict.set_item("__struct__", name)?;
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            for (field, value) in fields {
+                dict.set_item(field, from_message(py, value)?)?;
+            }
+            dict.into_any()
+        }
+        Message::Variant {
+            enum_name,
+            name,
+            payload,
+        } => {
+            let dict = PyDict::new(py);
+            dict.set_item("__enum__", enum_name)?;
+            dict.set_item("__variant__", name)?;
+            dict.set_item("payload", PyList::new(py, from_messages(py, payload)?)?)?;
+            dict.into_any()
+        }
+        Message::Function { .. } | Message::NativeFunction(_) => {
+            return Err(PyTypeError::new_err("cannot pass a function to Python"));
+        }
+        Message::Channel(_) => {
+            return Err(PyTypeError::new_err("cannot pass a channel to Python"));
+        }
+        Message::Pq(..) => {
+            return Err(PyTypeError::new_err(
+                "cannot pass a priority queue to Python",
+            ));
+        }
+    };
+    Ok(object.unbind())
+}
+
+fn from_messages(py: Python<'_>, messages: Vec<Message>) -> PyResult<Vec<PyObject>> {
+    messages.into_iter().map(|m| from_message(py, m)).collect()
+}
+
+/// The text of a non-empty array or vec holding only chars.
+fn message_text(elems: &[Message]) -> Option<String> {
+    if elems.is_empty() {
+        return None;
+    }
+    elems
+        .iter()
+        .map(|elem| match elem {
+            Message::Char(c) => char::from_u32(*c),
+            _ => None,
+        })
+        .collect()
+}
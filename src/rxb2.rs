@@ -0,0 +1,803 @@
+//! RXB2, the binary bytecode format. It carries the same instructions as
+//! RXB1 text, but jumps are stored as offsets from the jump to its target
+//! within the same body, resolved when the file is written. `Label`s are not
+//! stored, so loaded code has no labels to link and keeps working wherever
+//! its bodies end up.
+//!
+//! Layout: the `RXB2` magic, a metadata flag byte (followed by the metadata
+//! when it is 1), the top-level body, and the little-endian CRC-32 of all
+//! bytes before it. A body is an instruction count followed by that many
+//! instructions; an instruction is its index in `OPCODES` followed by its
+//! operands. Integers are LEB128 (signed ones zigzag-encoded first), strings
+//! a byte length and UTF-8.
+
+use crate::bytecode::{MAX_NESTING, Metadata};
+use crate::grammar::{CastType, CompiledStructFieldInit, Instruction, ReactiveExpr, Visibility};
+use crate::hash::crc32;
+use std::collections::{HashMap, HashSet};
+
+pub const MAGIC: &[u8; 4] = b"RXB2";
+
+/// Opcodes by their RXB2 tag. Labelled jumps are written in their `Rel`
+/// form, so `Label`, `Jump`, `JumpIfZero`, `Switch`, `IterNext` and
+/// `MatchVariant` have no tag. New opcodes go at the end.
+const OPCODES: &[&str] = &[
+    "Push",
+    "PushChar",
+    "PushNull",
+    "Load",
+    "LoadLoad",
+    "LoadPush",
+    "LoadField",
+    "Dup",
+    "Swap",
+    "Drop",
+    "Store",
+    "StoreImmutable",
+    "StoreReactive",
+    "Add",
+    "Sub",
+    "Mul",
+    "Div",
+    "Modulo",
+    "Neg",
+    "Greater",
+    "Less",
+    "GreaterEqual",
+    "LessEqual",
+    "Equal",
+    "NotEqual",
+    "DeepEqual",
+    "And",
+    "Or",
+    "Not",
+    "JumpRel",
+    "JumpIfZeroRel",
+    "SwitchRel",
+    "IterRange",
+    "IterNew",
+    "IterNextRel",
+    "Return",
+    "ArrayNew",
+    "ArrayGet",
+    "ArrayLValue",
+    "ArraySlice",
+    "ArrayConcat",
+    "DeepCopy",
+    "MakeTuple",
+    "TupleGet",
+    "StoreEnum",
+    "NewVariant",
+    "MatchVariantRel",
+    "StoreIndex",
+    "StoreIndexReactive",
+    "StoreStruct",
+    "ExtendStruct",
+    "NewStruct",
+    "FieldGet",
+    "FieldSet",
+    "FieldSetReactive",
+    "FieldLValue",
+    "StoreThrough",
+    "StoreThroughReactive",
+    "StoreThroughImmutable",
+    "StoreFunction",
+    "Call",
+    "Spawn",
+    "Yield",
+    "PushImmutableContext",
+    "PopImmutableContext",
+    "ClearImmutableContext",
+    "Print",
+    "Println",
+    "EPrint",
+    "EPrintln",
+    "Assert",
+    "AssertEq",
+    "AssertNe",
+    "Error",
+    "Import",
+    "Cast",
+    "TypeOf",
+    "Interp",
+    "IsNull",
+];
+
+/// RXB2 bytes for `code`. Fails if a jump names a label its body does not
+/// define.
+pub fn encode(code: &[Instruction], metadata: Option<&Metadata>) -> Result<Vec<u8>, String> {
+    let mut out = MAGIC.to_vec();
+    match metadata {
+        None => out.push(0),
+        Some(meta) => {
+            out.push(1);
+            put_str(&mut out, &meta.compiler);
+            put_str(&mut out, &meta.version);
+            match &meta.source_hash {
+                None => out.push(0),
+                Some(hash) => {
+                    out.push(1);
+                    put_str(&mut out, hash);
+                }
+            }
+            put_uint(&mut out, meta.opt_level.into());
+        }
+    }
+    put_body(&mut out, code)?;
+    let crc = crc32(&out);
+    out.extend_from_slice(&crc.to_le_bytes());
+    Ok(out)
+}
+
+/// Instructions and metadata (if any) of RXB2 bytes. Every jump is in its
+/// `Rel` form and lands inside its own body.
+pub fn decode(data: &[u8]) -> Result<(Vec<Instruction>, Option<Metadata>), String> {
+    if !data.starts_with(MAGIC) {
+        return Err("invalid bytecode header: expected RXB2".to_string());
+    }
+    if data.len() < MAGIC.len() + 4 {
+        return Err("bytecode corrupted: file is truncated".to_string());
+    }
+    let (content, trailer) = data.split_at(data.len() - 4);
+    let recorded = u32::from_le_bytes(trailer.try_into().unwrap());
+    let computed = crc32(content);
+    if recorded != computed {
+        return Err(format!(
+            "bytecode corrupted: checksum mismatch (recorded {recorded:08x}, computed \
+             {computed:08x}); the file was truncated or modified"
+        ));
+    }
+
+    let mut reader = Reader {
+        data: content,
+        pos: MAGIC.len(),
+        depth: 0,
+    };
+    let metadata = match reader.byte()? {
+        0 => None,
+        1 => Some(Metadata {
+            compiler: reader.string()?,
+            version: reader.string()?,
+            source_hash: match reader.byte()? {
+                0 => None,
+                1 => Some(reader.string()?),
+                _ => return Err(reader.error("invalid source hash flag")),
+            },
+            opt_level: u32::try_from(reader.uint()?)
+                .map_err(|_| reader.error("optimization level out of range"))?,
+        }),
+        _ => return Err(reader.error("invalid metadata flag")),
+    };
+    let code = reader.body()?;
+    if reader.pos != content.len() {
+        return Err(reader.error("trailing bytes after the top-level code"));
+    }
+    Ok((code, metadata))
+}
+
+/// `code` with its labels resolved: each labelled jump becomes its `Rel`
+/// form and `Label`s are dropped. A label marks the instruction after it;
+/// if a body defines one twice, the last definition wins, as when labels are
+/// linked at runtime. Nested bodies are left as they are.
+pub fn resolve_labels(code: &[Instruction]) -> Result<Vec<Instruction>, String> {
+    let mut labels = HashMap::new();
+    let mut index = 0i64;
+    for instr in code {
+        match instr {
+            Instruction::Label(name) => {
+                labels.insert(name.as_str(), index);
+            }
+            _ => index += 1,
+        }
+    }
+
+    code.iter()
+        .filter(|instr| !matches!(instr, Instruction::Label(_)))
+        .enumerate()
+        .map(|(at, instr)| {
+            let instr = match instr {
+                Instruction::JumpTable(table) => &table.source,
+                other => other,
+            };
+            let offset = |label: &String| {
+                labels
+                    .get(label.as_str())
+                    .map(|target| target - at as i64)
+                    .ok_or_else(|| format!("jump to undefined label `{label}`"))
+            };
+            Ok(match instr {
+                Instruction::Jump(label) => Instruction::JumpRel(offset(label)?),
+                Instruction::JumpIfZero(label) => Instruction::JumpIfZeroRel(offset(label)?),
+                Instruction::IterNext(label) => Instruction::IterNextRel(offset(label)?),
+                Instruction::Switch(cases, default) => Instruction::SwitchRel(
+                    cases
+                        .iter()
+                        .map(|(value, label)| Ok((*value, offset(label)?)))
+                        .collect::<Result<_, String>>()?,
+                    offset(default)?,
+                ),
+                Instruction::MatchVariant(arms, default) => Instruction::MatchVariantRel(
+                    arms.iter()
+                        .map(|(variant, label)| Ok((variant.clone(), offset(label)?)))
+                        .collect::<Result<_, String>>()?,
+                    offset(default)?,
+                ),
+                other => other.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Indexes the relative jumps of `code` can land on.
+pub fn jump_targets(code: &[Instruction]) -> HashSet<usize> {
+    let mut targets = HashSet::new();
+    for (at, instr) in code.iter().enumerate() {
+        for offset in instr.offsets() {
+            if let Some(target) = at.checked_add_signed(offset as isize) {
+                targets.insert(target);
+            }
+        }
+    }
+    targets
+}
+
+/// Re-aims the relative jumps of `code`, a body rebuilt from an older one by
+/// dropping or merging instructions: `origin[p]` is the old index of
+/// instruction `p`, and `moved[i]` the new index of old instruction `i`, with
+/// one extra entry for the end of the body. A jump to a dropped instruction
+/// lands on whatever replaced it. Offsets that were already out of range are
+/// left for the VM to report.
+pub fn relocate_jumps(code: &mut [Instruction], origin: &[usize], moved: &[usize]) {
+    for (at, instr) in code.iter_mut().enumerate() {
+        let from = origin[at];
+        for offset in instr.offsets_mut() {
+            let target = from
+                .checked_add_signed(*offset as isize)
+                .and_then(|target| moved.get(target));
+            if let Some(&to) = target {
+                *offset = to as i64 - at as i64;
+            }
+        }
+    }
+}
+
+fn put_uint(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn put_int(out: &mut Vec<u8>, n: i64) {
+    put_uint(out, ((n << 1) ^ (n >> 63)) as u64);
+}
+
+fn put_len(out: &mut Vec<u8>, n: usize) {
+    put_uint(out, n as u64);
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_len(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn put_strs(out: &mut Vec<u8>, strs: &[String]) {
+    put_len(out, strs.len());
+    for s in strs {
+        put_str(out, s);
+    }
+}
+
+fn put_visibility(out: &mut Vec<u8>, visibility: Visibility) {
+    out.push(match visibility {
+        Visibility::Public => 0,
+        Visibility::Private => 1,
+    });
+}
+
+fn put_body(out: &mut Vec<u8>, code: &[Instruction]) -> Result<(), String> {
+    let code = resolve_labels(code)?;
+    put_len(out, code.len());
+    for instr in &code {
+        put_instruction(out, instr)?;
+    }
+    Ok(())
+}
+
+fn put_reactive(out: &mut Vec<u8>, expr: &ReactiveExpr) -> Result<(), String> {
+    put_strs(out, &expr.captures);
+    put_body(out, &expr.code)
+}
+
+fn put_instruction(out: &mut Vec<u8>, instr: &Instruction) -> Result<(), String> {
+    let op = instr.opcode();
+    let tag = OPCODES
+        .iter()
+        .position(|&name| name == op)
+        .unwrap_or_else(|| unreachable!("`{op}` is resolved before encoding"));
+    out.push(tag as u8);
+    match instr {
+        Instruction::Push(n)
+        | Instruction::JumpRel(n)
+        | Instruction::JumpIfZeroRel(n)
+        | Instruction::IterNextRel(n) => put_int(out, *n),
+        Instruction::PushChar(c) => put_uint(out, (*c).into()),
+        Instruction::MakeTuple(n) | Instruction::TupleGet(n) | Instruction::Interp(n) => {
+            put_len(out, *n)
+        }
+        Instruction::LoadPush(name, n) => {
+            put_str(out, name);
+            put_int(out, *n);
+        }
+
+        Instruction::Load(name)
+        | Instruction::Store(name)
+        | Instruction::StoreImmutable(name)
+        | Instruction::StoreIndex(name)
+        | Instruction::NewStruct(name)
+        | Instruction::FieldGet(name)
+        | Instruction::FieldSet(name)
+        | Instruction::FieldLValue(name)
+        | Instruction::Error(name) => put_str(out, name),
+
+        Instruction::LoadLoad(a, b)
+        | Instruction::LoadField(a, b)
+        | Instruction::NewVariant(a, b)
+        | Instruction::ExtendStruct(a, b) => {
+            put_str(out, a);
+            put_str(out, b);
+        }
+
+        Instruction::StoreReactive(name, expr)
+        | Instruction::StoreIndexReactive(name, expr)
+        | Instruction::FieldSetReactive(name, expr) => {
+            put_str(out, name);
+            put_reactive(out, expr)?;
+        }
+        Instruction::StoreThroughReactive(expr) => put_reactive(out, expr)?,
+
+        Instruction::StoreEnum(name, variants) => {
+            put_str(out, name);
+            put_len(out, variants.len());
+            for (variant, arity) in variants {
+                put_str(out, variant);
+                put_len(out, *arity);
+            }
+        }
+        Instruction::SwitchRel(cases, default) => {
+            put_len(out, cases.len());
+            for (value, offset) in cases {
+                put_int(out, *value);
+                put_int(out, *offset);
+            }
+            put_int(out, *default);
+        }
+        Instruction::MatchVariantRel(arms, default) => {
+            put_len(out, arms.len());
+            for (variant, offset) in arms {
+                put_str(out, variant);
+                put_int(out, *offset);
+            }
+            put_int(out, *default);
+        }
+
+        Instruction::StoreStruct(name, fields, visibility) => {
+            put_str(out, name);
+            put_visibility(out, *visibility);
+            put_len(out, fields.len());
+            for (field, init) in fields {
+                put_str(out, field);
+                match init {
+                    None => out.push(0),
+                    Some(CompiledStructFieldInit::Mutable(code)) => {
+                        out.push(1);
+                        put_body(out, code)?;
+                    }
+                    Some(CompiledStructFieldInit::Immutable(code)) => {
+                        out.push(2);
+                        put_body(out, code)?;
+                    }
+                    Some(CompiledStructFieldInit::Reactive(expr)) => {
+                        out.push(3);
+                        put_reactive(out, expr)?;
+                    }
+                }
+            }
+        }
+        Instruction::StoreFunction(name, params, body, visibility) => {
+            put_str(out, name);
+            put_visibility(out, *visibility);
+            put_strs(out, params);
+            put_body(out, body)?;
+        }
+        Instruction::Call(name, argc) | Instruction::Spawn(name, argc) => {
+            put_str(out, name);
+            put_len(out, *argc);
+        }
+        Instruction::Import(path) => put_strs(out, path),
+        Instruction::Cast(target) => out.push(match target {
+            CastType::Int => 0,
+            CastType::Char => 1,
+        }),
+        _ => {}
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    // code bodies currently open
+    depth: usize,
+}
+
+impl Reader<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("bytecode corrupted at byte {}: {message}", self.pos)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| self.error("unexpected end of bytecode"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn uint(&mut self) -> Result<u64, String> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u64::from(byte & 0x7f);
+            if shift == 63 && bits > 1 {
+                return Err(self.error("integer out of range"));
+            }
+            n |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(self.error("integer out of range"))
+    }
+
+    fn int(&mut self) -> Result<i64, String> {
+        let n = self.uint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let n = self.uint()?;
+        u32::try_from(n).map_err(|_| self.error(&format!("invalid u32 `{n}`")))
+    }
+
+    fn usize(&mut self) -> Result<usize, String> {
+        let n = self.uint()?;
+        usize::try_from(n).map_err(|_| self.error(&format!("invalid usize `{n}`")))
+    }
+
+    /// A length of items still to be read; each takes at least one byte, so
+    /// a corrupted length fails here instead of allocating.
+    fn len(&mut self) -> Result<usize, String> {
+        let n = self.usize()?;
+        if n > self.data.len() - self.pos {
+            return Err(self.error(&format!("length {n} runs past the end of the file")));
+        }
+        Ok(n)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.len()?;
+        let bytes = &self.data[self.pos..self.pos + len];
+        let s = std::str::from_utf8(bytes).map_err(|_| self.error("string is not UTF-8"))?;
+        self.pos += len;
+        Ok(s.to_string())
+    }
+
+    fn strings(&mut self) -> Result<Vec<String>, String> {
+        let len = self.len()?;
+        (0..len).map(|_| self.string()).collect()
+    }
+
+    fn visibility(&mut self) -> Result<Visibility, String> {
+        match self.byte()? {
+            0 => Ok(Visibility::Public),
+            1 => Ok(Visibility::Private),
+            _ => Err(self.error("invalid visibility")),
+        }
+    }
+
+    fn body(&mut self) -> Result<Vec<Instruction>, String> {
+        if self.depth >= MAX_NESTING {
+            return Err(self.error(&format!("code bodies nested more than {MAX_NESTING} deep")));
+        }
+        self.depth += 1;
+        let start = self.pos;
+        let len = self.len()?;
+        let code = (0..len)
+            .map(|_| self.instruction())
+            .collect::<Result<Vec<_>, _>>()?;
+        self.depth -= 1;
+
+        for (at, instr) in code.iter().enumerate() {
+            for offset in instr.offsets() {
+                let inside = at
+                    .checked_add_signed(offset as isize)
+                    .is_some_and(|target| target <= code.len());
+                if !inside {
+                    return Err(format!(
+                        "bytecode corrupted at byte {start}: instruction {at} of the body \
+                         jumps {offset} instruction(s), outside the body"
+                    ));
+                }
+            }
+        }
+        Ok(code)
+    }
+
+    fn reactive(&mut self) -> Result<ReactiveExpr, String> {
+        let captures = self.strings()?;
+        let code = self.body()?;
+        Ok(ReactiveExpr { code, captures })
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, String> {
+        let tag = self.byte()?;
+        let op = *OPCODES
+            .get(usize::from(tag))
+            .ok_or_else(|| self.error(&format!("unknown opcode {tag}")))?;
+        let instr = match op {
+            "Push" => Instruction::Push(self.int()?),
+            "PushChar" => Instruction::PushChar(self.u32()?),
+            "PushNull" => Instruction::PushNull,
+            "Load" => Instruction::Load(self.string()?),
+            "LoadLoad" => Instruction::LoadLoad(self.string()?, self.string()?),
+            "LoadPush" => Instruction::LoadPush(self.string()?, self.int()?),
+            "LoadField" => Instruction::LoadField(self.string()?, self.string()?),
+            "Dup" => Instruction::Dup,
+            "Swap" => Instruction::Swap,
+            "Drop" => Instruction::Drop,
+            "Store" => Instruction::Store(self.string()?),
+            "StoreImmutable" => Instruction::StoreImmutable(self.string()?),
+            "StoreReactive" => Instruction::StoreReactive(self.string()?, self.reactive()?),
+            "Add" => Instruction::Add,
+            "Sub" => Instruction::Sub,
+            "Mul" => Instruction::Mul,
+            "Div" => Instruction::Div,
+            "Modulo" => Instruction::Modulo,
+            "Neg" => Instruction::Neg,
+            "Greater" => Instruction::Greater,
+            "Less" => Instruction::Less,
+            "GreaterEqual" => Instruction::GreaterEqual,
+            "LessEqual" => Instruction::LessEqual,
+            "Equal" => Instruction::Equal,
+            "NotEqual" => Instruction::NotEqual,
+            "DeepEqual" => Instruction::DeepEqual,
+            "And" => Instruction::And,
+            "Or" => Instruction::Or,
+            "Not" => Instruction::Not,
+            "JumpRel" => Instruction::JumpRel(self.int()?),
+            "JumpIfZeroRel" => Instruction::JumpIfZeroRel(self.int()?),
+            "SwitchRel" => {
+                let len = self.len()?;
+                let cases = (0..len)
+                    .map(|_| Ok((self.int()?, self.int()?)))
+                    .collect::<Result<_, String>>()?;
+                Instruction::SwitchRel(cases, self.int()?)
+            }
+            "IterRange" => Instruction::IterRange,
+            "IterNew" => Instruction::IterNew,
+            "IterNextRel" => Instruction::IterNextRel(self.int()?),
+            "Return" => Instruction::Return,
+            "ArrayNew" => Instruction::ArrayNew,
+            "ArrayGet" => Instruction::ArrayGet,
+            "ArrayLValue" => Instruction::ArrayLValue,
+            "ArraySlice" => Instruction::ArraySlice,
+            "ArrayConcat" => Instruction::ArrayConcat,
+            "DeepCopy" => Instruction::DeepCopy,
+            "MakeTuple" => Instruction::MakeTuple(self.usize()?),
+            "TupleGet" => Instruction::TupleGet(self.usize()?),
+            "StoreEnum" => {
+                let name = self.string()?;
+                let len = self.len()?;
+                let variants = (0..len)
+                    .map(|_| Ok((self.string()?, self.usize()?)))
+                    .collect::<Result<_, String>>()?;
+                Instruction::StoreEnum(name, variants)
+            }
+            "NewVariant" => Instruction::NewVariant(self.string()?, self.string()?),
+            "MatchVariantRel" => {
+                let len = self.len()?;
+                let arms = (0..len)
+                    .map(|_| Ok((self.string()?, self.int()?)))
+                    .collect::<Result<_, String>>()?;
+                Instruction::MatchVariantRel(arms, self.int()?)
+            }
+            "StoreIndex" => Instruction::StoreIndex(self.string()?),
+            "StoreIndexReactive" => {
+                Instruction::StoreIndexReactive(self.string()?, self.reactive()?)
+            }
+            "StoreStruct" => {
+                let name = self.string()?;
+                let visibility = self.visibility()?;
+                let len = self.len()?;
+                let fields = (0..len)
+                    .map(|_| {
+                        let field = self.string()?;
+                        let init = match self.byte()? {
+                            0 => None,
+                            1 => Some(CompiledStructFieldInit::Mutable(self.body()?)),
+                            2 => Some(CompiledStructFieldInit::Immutable(self.body()?)),
+                            3 => Some(CompiledStructFieldInit::Reactive(self.reactive()?)),
+                            _ => return Err(self.error("invalid field initializer kind")),
+                        };
+                        Ok((field, init))
+                    })
+                    .collect::<Result<_, String>>()?;
+                Instruction::StoreStruct(name, fields, visibility)
+            }
+            "ExtendStruct" => Instruction::ExtendStruct(self.string()?, self.string()?),
+            "NewStruct" => Instruction::NewStruct(self.string()?),
+            "FieldGet" => Instruction::FieldGet(self.string()?),
+            "FieldSet" => Instruction::FieldSet(self.string()?),
+            "FieldSetReactive" => Instruction::FieldSetReactive(self.string()?, self.reactive()?),
+            "FieldLValue" => Instruction::FieldLValue(self.string()?),
+            "StoreThrough" => Instruction::StoreThrough,
+            "StoreThroughReactive" => Instruction::StoreThroughReactive(self.reactive()?),
+            "StoreThroughImmutable" => Instruction::StoreThroughImmutable,
+            "StoreFunction" => {
+                let name = self.string()?;
+                let visibility = self.visibility()?;
+                let params = self.strings()?;
+                Instruction::StoreFunction(name, params, self.body()?, visibility)
+            }
+            "Call" => Instruction::Call(self.string()?, self.usize()?),
+            "Spawn" => Instruction::Spawn(self.string()?, self.usize()?),
+            "Yield" => Instruction::Yield,
+            "PushImmutableContext" => Instruction::PushImmutableContext,
+            "PopImmutableContext" => Instruction::PopImmutableContext,
+            "ClearImmutableContext" => Instruction::ClearImmutableContext,
+            "Print" => Instruction::Print,
+            "Println" => Instruction::Println,
+            "EPrint" => Instruction::EPrint,
+            "EPrintln" => Instruction::EPrintln,
+            "Assert" => Instruction::Assert,
+            "AssertEq" => Instruction::AssertEq,
+            "AssertNe" => Instruction::AssertNe,
+            "Error" => Instruction::Error(self.string()?),
+            "Import" => Instruction::Import(self.strings()?),
+            "Cast" => Instruction::Cast(match self.byte()? {
+                0 => CastType::Int,
+                1 => CastType::Char,
+                other => return Err(self.error(&format!("unknown cast type {other}"))),
+            }),
+            "TypeOf" => Instruction::TypeOf,
+            "Interp" => Instruction::Interp(self.usize()?),
+            "IsNull" => Instruction::IsNull,
+            _ => unreachable!("every entry of OPCODES is decoded"),
+        };
+        Ok(instr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linker::eliminate_dead_code;
+    use Instruction::*;
+
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    fn round_trip(code: &[Instruction]) -> Vec<Instruction> {
+        let bytes = encode(code, None).unwrap();
+        let (decoded, _) = decode(&bytes).unwrap();
+        assert_eq!(encode(&decoded, None).unwrap(), bytes);
+        decoded
+    }
+
+    #[test]
+    fn labels_become_offsets() {
+        // i = 0; while i < 3 { if i == 0 { println 7 } i = i + 1 }
+        let code = vec![
+            Push(0),
+            Store(s("i")),
+            Label(s("top")),
+            Load(s("i")),
+            Push(3),
+            Less,
+            JumpIfZero(s("end")),
+            Load(s("i")),
+            Switch(vec![(0, s("zero"))], s("next")),
+            Label(s("zero")),
+            Push(7),
+            Println,
+            Label(s("next")),
+            LoadPush(s("i"), 1),
+            Add,
+            Store(s("i")),
+            Jump(s("top")),
+            Label(s("end")),
+        ];
+        let expected = vec![
+            Push(0),
+            Store(s("i")),
+            Load(s("i")),
+            Push(3),
+            Less,
+            JumpIfZeroRel(9),
+            Load(s("i")),
+            SwitchRel(vec![(0, 1)], 3),
+            Push(7),
+            Println,
+            LoadPush(s("i"), 1),
+            Add,
+            Store(s("i")),
+            JumpRel(-11),
+        ];
+        assert_eq!(round_trip(&code), expected);
+    }
+
+    #[test]
+    fn nested_bodies_resolve_their_own_labels() {
+        let body = vec![
+            Label(s("again")),
+            Load(s("it")),
+            IterNext(s("done")),
+            Println,
+            Jump(s("again")),
+            Label(s("done")),
+            Return,
+        ];
+        let code = vec![
+            Label(s("again")),
+            StoreFunction(s("f"), vec![s("it")], body, Visibility::Public),
+            Jump(s("again")),
+        ];
+        let expected_body = vec![Load(s("it")), IterNextRel(3), Println, JumpRel(-3), Return];
+        assert_eq!(
+            round_trip(&code),
+            vec![
+                StoreFunction(s("f"), vec![s("it")], expected_body, Visibility::Public),
+                JumpRel(-1),
+            ]
+        );
+    }
+
+    #[test]
+    fn undefined_labels_and_stray_offsets_are_rejected() {
+        let err = encode(&[Jump(s("nowhere"))], None).unwrap_err();
+        assert!(err.contains("undefined label `nowhere`"), "{err}");
+
+        let bytes = encode(&[Push(1), JumpRel(2)], None).unwrap();
+        let err = decode(&bytes).unwrap_err();
+        assert!(err.contains("outside the body"), "{err}");
+
+        let mut bytes = encode(&[Push(1), JumpRel(-1)], None).unwrap();
+        bytes[MAGIC.len() + 2] ^= 1;
+        let err = decode(&bytes).unwrap_err();
+        assert!(err.contains("checksum mismatch"), "{err}");
+    }
+
+    #[test]
+    fn dead_code_elimination_keeps_offsets_on_target() {
+        let code = round_trip(&[
+            Label(s("top")),
+            Push(0),
+            JumpIfZero(s("end")),
+            StoreFunction(s("unused"), Vec::new(), vec![Return], Visibility::Public),
+            Jump(s("top")),
+            Label(s("end")),
+            Push(1),
+        ]);
+        let (code, stats) = eliminate_dead_code(code);
+        assert_eq!(stats.removed_functions, vec![s("unused")]);
+        assert_eq!(code, vec![Push(0), JumpIfZeroRel(2), JumpRel(-2), Push(1)]);
+    }
+}
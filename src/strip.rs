@@ -0,0 +1,239 @@
+use crate::bytecode::code_bodies;
+use crate::debug_info::{BodyMatcher, DebugBody, DebugInfo};
+use crate::grammar::{CompiledStructFieldInit, Instruction, ReactiveExpr};
+use std::collections::{HashMap, HashSet};
+
+/// Bytecode with function locals renamed to slot names (`$0`, `$1`, ... in
+/// order of binding, parameters first) by `strip_names`.
+pub struct Stripped {
+    pub code: Vec<Instruction>,
+    /// Slot name -> original name, per body in `code_bodies` order.
+    pub names: Vec<HashMap<String, String>>,
+}
+
+impl Stripped {
+    pub fn renamed(&self) -> usize {
+        self.names.iter().map(HashMap::len).sum()
+    }
+
+    /// Debug info for the stripped code, with the source locations `info`
+    /// records for `original` (the code before stripping) and the renames.
+    pub fn debug_info(&self, original: &[Instruction], info: &DebugInfo) -> DebugInfo {
+        let matcher = BodyMatcher::new(&info.bodies);
+        let originals = code_bodies(original, None);
+        let bodies = code_bodies(&self.code, None)
+            .into_iter()
+            .zip(originals)
+            .zip(&self.names)
+            .map(|((body, before), names)| {
+                let mut locs = matcher
+                    .find(&info.bodies, &before.code, &before.name)
+                    .map(|i| info.bodies[i].locs.clone())
+                    .unwrap_or_default();
+                locs.resize(body.code.len(), None);
+                DebugBody {
+                    name: body.name,
+                    code: body.code,
+                    locs,
+                    names: names.clone(),
+                }
+            })
+            .collect();
+        DebugInfo {
+            files: info.files.clone(),
+            bodies,
+        }
+    }
+}
+
+/// Renames the locals of every function to slot names. Only names a
+/// function binds itself (parameters and stores) are renamed, and only when
+/// no other code can see them by name: globals, called or declared names,
+/// and anything a reactive expression or struct field initializer mentions
+/// keep their names, since those are resolved wherever they are evaluated.
+pub fn strip_names(code: &[Instruction]) -> Stripped {
+    let mut shared = HashSet::new();
+    collect_shared(code, true, &mut shared);
+    let mut names = Vec::new();
+    let code = rewrite_body(code, &HashMap::new(), &shared, &mut names);
+    Stripped { code, names }
+}
+
+/// Names visible beyond a single function. `top` marks code whose loads and
+/// stores are global (the top level, initializers, reactive expressions).
+fn collect_shared(code: &[Instruction], top: bool, shared: &mut HashSet<String>) {
+    for instr in code {
+        match instr {
+            Instruction::Load(name)
+            | Instruction::LoadPush(name, _)
+            | Instruction::LoadField(name, _)
+            | Instruction::Store(name)
+            | Instruction::StoreImmutable(name)
+            | Instruction::StoreIndex(name)
+                if top =>
+            {
+                shared.insert(name.clone());
+            }
+            Instruction::LoadLoad(a, b) if top => {
+                shared.insert(a.clone());
+                shared.insert(b.clone());
+            }
+            Instruction::StoreReactive(name, expr)
+            | Instruction::StoreIndexReactive(name, expr) => {
+                if top {
+                    shared.insert(name.clone());
+                }
+                collect_reactive(expr, shared);
+            }
+            Instruction::FieldSetReactive(_, expr) | Instruction::StoreThroughReactive(expr) => {
+                collect_reactive(expr, shared);
+            }
+            Instruction::StoreFunction(name, _, body, _) => {
+                shared.insert(name.clone());
+                collect_shared(body, false, shared);
+            }
+            Instruction::StoreStruct(name, fields, _) => {
+                shared.insert(name.clone());
+                for (_, init) in fields {
+                    match init {
+                        Some(CompiledStructFieldInit::Mutable(code))
+                        | Some(CompiledStructFieldInit::Immutable(code)) => {
+                            collect_shared(code, true, shared)
+                        }
+                        Some(CompiledStructFieldInit::Reactive(expr)) => {
+                            collect_reactive(expr, shared)
+                        }
+                        None => {}
+                    }
+                }
+            }
+            Instruction::StoreEnum(name, _)
+            | Instruction::Call(name, _)
+            | Instruction::Spawn(name, _) => {
+                shared.insert(name.clone());
+            }
+            _ => {}
+        }
+    }
+}
+
+fn collect_reactive(expr: &ReactiveExpr, shared: &mut HashSet<String>) {
+    shared.extend(expr.captures.iter().cloned());
+    collect_shared(&expr.code, true, shared);
+}
+
+/// Copies `code` with `renames` applied, recording them (inverted) as the
+/// body's entry in `names` and visiting nested bodies in `code_bodies`
+/// order.
+fn rewrite_body(
+    code: &[Instruction],
+    renames: &HashMap<String, String>,
+    shared: &HashSet<String>,
+    names: &mut Vec<HashMap<String, String>>,
+) -> Vec<Instruction> {
+    names.push(
+        renames
+            .iter()
+            .map(|(name, slot)| (slot.clone(), name.clone()))
+            .collect(),
+    );
+    let none = HashMap::new();
+    let rename = |name: &String| renames.get(name).unwrap_or(name).clone();
+
+    code.iter()
+        .map(|instr| match instr {
+            Instruction::Load(name) => Instruction::Load(rename(name)),
+            Instruction::LoadLoad(a, b) => Instruction::LoadLoad(rename(a), rename(b)),
+            Instruction::LoadPush(name, n) => Instruction::LoadPush(rename(name), *n),
+            Instruction::LoadField(name, field) => {
+                Instruction::LoadField(rename(name), field.clone())
+            }
+            Instruction::Store(name) => Instruction::Store(rename(name)),
+            Instruction::StoreImmutable(name) => Instruction::StoreImmutable(rename(name)),
+            Instruction::StoreIndex(name) => Instruction::StoreIndex(rename(name)),
+            Instruction::StoreReactive(name, expr) => {
+                Instruction::StoreReactive(rename(name), rewrite_reactive(expr, shared, names))
+            }
+            Instruction::StoreIndexReactive(name, expr) => {
+                Instruction::StoreIndexReactive(rename(name), rewrite_reactive(expr, shared, names))
+            }
+            Instruction::FieldSetReactive(field, expr) => {
+                Instruction::FieldSetReactive(field.clone(), rewrite_reactive(expr, shared, names))
+            }
+            Instruction::StoreThroughReactive(expr) => {
+                Instruction::StoreThroughReactive(rewrite_reactive(expr, shared, names))
+            }
+            Instruction::StoreStruct(name, fields, visibility) => {
+                let fields = fields
+                    .iter()
+                    .map(|(field, init)| {
+                        let init = init.as_ref().map(|init| match init {
+                            CompiledStructFieldInit::Mutable(code) => {
+                                CompiledStructFieldInit::Mutable(rewrite_body(
+                                    code, &none, shared, names,
+                                ))
+                            }
+                            CompiledStructFieldInit::Immutable(code) => {
+                                CompiledStructFieldInit::Immutable(rewrite_body(
+                                    code, &none, shared, names,
+                                ))
+                            }
+                            CompiledStructFieldInit::Reactive(expr) => {
+                                CompiledStructFieldInit::Reactive(rewrite_reactive(
+                                    expr, shared, names,
+                                ))
+                            }
+                        });
+                        (field.clone(), init)
+                    })
+                    .collect();
+                Instruction::StoreStruct(name.clone(), fields, *visibility)
+            }
+            Instruction::StoreFunction(name, params, body, visibility) => {
+                let slots = local_slots(params, body, shared);
+                let params = params
+                    .iter()
+                    .map(|p| slots.get(p).unwrap_or(p).clone())
+                    .collect();
+                let body = rewrite_body(body, &slots, shared, names);
+                Instruction::StoreFunction(name.clone(), params, body, *visibility)
+            }
+            other => other.clone(),
+        })
+        .collect()
+}
+
+fn rewrite_reactive(
+    expr: &ReactiveExpr,
+    shared: &HashSet<String>,
+    names: &mut Vec<HashMap<String, String>>,
+) -> ReactiveExpr {
+    ReactiveExpr {
+        code: rewrite_body(&expr.code, &HashMap::new(), shared, names),
+        captures: expr.captures.clone(),
+    }
+}
+
+/// Slot names for the locals a function binds directly that nothing else
+/// refers to by name.
+fn local_slots(
+    params: &[String],
+    body: &[Instruction],
+    shared: &HashSet<String>,
+) -> HashMap<String, String> {
+    let stored = body.iter().filter_map(|instr| match instr {
+        Instruction::Store(name)
+        | Instruction::StoreImmutable(name)
+        | Instruction::StoreReactive(name, _) => Some(name),
+        _ => None,
+    });
+
+    let mut slots = HashMap::new();
+    for name in params.iter().chain(stored) {
+        if !shared.contains(name) && !slots.contains_key(name) {
+            let slot = format!("${}", slots.len());
+            slots.insert(name.clone(), slot);
+        }
+    }
+    slots
+}
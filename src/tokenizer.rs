@@ -0,0 +1,289 @@
+use crate::grammar::{SourceError, Span, Token};
+use crate::json::Json;
+use std::str::Chars;
+
+/// Splits Reactive source into tokens. Mirrors the stable self-hosted
+/// tokenizer (`project/bootstrap/stable/tokenizer.rx`), including its error
+/// messages.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    match tokenize_spanned(input) {
+        Ok(tokens) => Ok(tokens.into_iter().map(|(token, _)| token).collect()),
+        Err(e) => Err(e.message),
+    }
+}
+
+/// `tokenize`, keeping the span of every token. Errors point at the start of
+/// the token that failed.
+pub fn tokenize_spanned(input: &str) -> Result<Vec<(Token, Span)>, SourceError> {
+    let mut tokens = Vec::new();
+    let mut chars = Cursor::new(input);
+
+    loop {
+        let mut span = chars.span();
+        let Some(c) = chars.next() else {
+            break;
+        };
+        let token = match tokenize_one(c, &mut chars) {
+            Ok(Some(token)) => token,
+            Ok(None) => continue,
+            Err(message) => return Err(SourceError { message, span }),
+        };
+        span.end = chars.offset;
+        tokens.push((token, span));
+    }
+
+    Ok(tokens)
+}
+
+/// One JSON object per token, the stable format of `reactive tokens`:
+/// `kind` (`Token::kind`), `value` for numbers, identifiers, chars and
+/// strings (decoded), the 1-based `line` / `column` (in chars) of its first
+/// character and the byte offsets `start..end` into the source.
+pub fn tokens_json(tokens: &[(Token, Span)]) -> Vec<Json> {
+    tokens
+        .iter()
+        .map(|(token, span)| {
+            let mut fields = vec![("kind", Json::from(token.kind()))];
+            let value = match token {
+                Token::Number(n) => Some(Json::from(*n)),
+                Token::Ident(s) | Token::StringLiteral(s) => Some(Json::from(s.as_str())),
+                Token::Char(c) => char::from_u32(*c).map(|c| Json::from(c.to_string())),
+                _ => None,
+            };
+            if let Some(value) = value {
+                fields.push(("value", value));
+            }
+            fields.extend([
+                ("line", Json::from(span.line)),
+                ("column", Json::from(span.column)),
+                ("start", Json::from(span.start)),
+                ("end", Json::from(span.end)),
+            ]);
+            Json::object(fields)
+        })
+        .collect()
+}
+
+/// Characters of the source, tracking where the next one starts.
+struct Cursor<'a> {
+    rest: Chars<'a>,
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            rest: input.chars(),
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest.clone().next()
+    }
+
+    fn span(&self) -> Span {
+        Span {
+            start: self.offset,
+            end: self.offset,
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.rest.next()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+}
+
+/// The token starting with `c`, or `None` for whitespace and comments.
+fn tokenize_one(c: char, chars: &mut Cursor) -> Result<Option<Token>, String> {
+    let token = match c {
+        c if is_space(c) => return Ok(None),
+        '0'..='9' => read_number(c, chars),
+        'a'..='z' | 'A'..='Z' | '_' => read_ident(c, chars),
+        '#' => {
+            skip_comment(chars);
+            return Ok(None);
+        }
+        '"' => read_string(chars)?,
+        '\'' => read_char(chars)?,
+        '+' => Token::Add,
+        '-' => Token::Sub,
+        '*' => Token::Mul,
+        '/' => Token::Div,
+        '%' => Token::Modulo,
+        '(' => Token::LParen,
+        ')' => Token::RParen,
+        '{' => Token::LBrace,
+        '}' => Token::RBrace,
+        '[' => Token::LSquare,
+        ']' => Token::RSquare,
+        ';' => Token::Semicolon,
+        '.' => Token::Dot,
+        ',' => Token::Comma,
+        '?' => Token::Question,
+
+        ':' => match chars.peek() {
+            Some(':') => {
+                chars.next();
+                match chars.next() {
+                    Some('=') => Token::ReactiveAssign,
+                    _ => return Err("tokenizer: expected '=' after '::'".to_string()),
+                }
+            }
+            Some('=') => {
+                chars.next();
+                Token::ImmutableAssign
+            }
+            _ => Token::Colon,
+        },
+
+        '=' => followed_by(chars, '=', Token::Equal, Token::Assign),
+        '!' => followed_by(chars, '=', Token::NotEqual, Token::Not),
+        '>' => followed_by(chars, '=', Token::GreaterEqual, Token::Greater),
+        '<' => followed_by(chars, '=', Token::LessEqual, Token::Less),
+
+        '|' => match chars.next() {
+            Some('|') => Token::Or,
+            _ => return Err("tokenizer: expected '||'".to_string()),
+        },
+        '&' => match chars.next() {
+            Some('&') => Token::And,
+            _ => return Err("tokenizer: expected '&&'".to_string()),
+        },
+
+        other => return Err(format!("tokenizer: invalid character {other:?}")),
+    };
+    Ok(Some(token))
+}
+
+fn is_space(c: char) -> bool {
+    matches!(c, ' ' | '\n' | '\t' | '\r')
+}
+
+fn followed_by(chars: &mut Cursor, next: char, double: Token, single: Token) -> Token {
+    if chars.peek() == Some(next) {
+        chars.next();
+        double
+    } else {
+        single
+    }
+}
+
+fn read_number(first: char, chars: &mut Cursor) -> Token {
+    let mut value = first.to_digit(10).unwrap() as i64;
+    while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+        chars.next();
+        value = value.wrapping_mul(10).wrapping_add(d as i64);
+    }
+    Token::Number(value)
+}
+
+fn read_ident(first: char, chars: &mut Cursor) -> Token {
+    let mut s = String::new();
+    s.push(first);
+
+    while let Some(c) = chars.peek() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            chars.next();
+            s.push(c);
+        } else {
+            break;
+        }
+    }
+
+    match s.as_str() {
+        "print" => Token::Print,
+        "println" => Token::Println,
+        "if" => Token::If,
+        "else" => Token::Else,
+        "loop" => Token::Loop,
+        "break" => Token::Break,
+        "func" => Token::Func,
+        "return" => Token::Return,
+        "continue" => Token::Continue,
+        "struct" => Token::Struct,
+        "import" => Token::Import,
+        "assert" => Token::Assert,
+        "error" => Token::Error,
+        _ => Token::Ident(s),
+    }
+}
+
+fn read_char(chars: &mut Cursor) -> Result<Token, String> {
+    let ch = match chars.next() {
+        Some('\\') => read_escape(chars)?,
+        Some(c) => c,
+        None => return Err("tokenizer: unterminated char".to_string()),
+    };
+
+    match chars.next() {
+        Some('\'') => Ok(Token::Char(ch as u32)),
+        _ => Err("tokenizer: unterminated char".to_string()),
+    }
+}
+
+fn read_string(chars: &mut Cursor) -> Result<Token, String> {
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => break,
+            Some('\\') => s.push(read_escape(chars)?),
+            Some(c) => s.push(c),
+            None => return Err("tokenizer: unterminated string".to_string()),
+        }
+    }
+    Ok(Token::StringLiteral(s))
+}
+
+fn read_escape(chars: &mut Cursor) -> Result<char, String> {
+    match chars.next() {
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some('r') => Ok('\r'),
+        Some('"') => Ok('"'),
+        Some('\'') => Ok('\''),
+        Some('\\') => Ok('\\'),
+        Some(c @ '0'..='7') => {
+            let mut value = c.to_digit(8).unwrap();
+            for _ in 0..2 {
+                match chars.peek().and_then(|d| d.to_digit(8)) {
+                    Some(d) => {
+                        chars.next();
+                        value = value * 8 + d;
+                    }
+                    None => break,
+                }
+            }
+            // at most 0o777, always a valid scalar value
+            Ok(char::from_u32(value).unwrap())
+        }
+        Some(_) => Err("tokenizer: invalid escape".to_string()),
+        None => Err("tokenizer: unterminated escape".to_string()),
+    }
+}
+
+fn skip_comment(chars: &mut Cursor) {
+    for c in chars.by_ref() {
+        if c == '#' {
+            break;
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use super::VM;
+use crate::grammar::Type;
+use std::collections::HashSet;
+
+// Slots released with `internal_array_free` / `internal_vec_free` are kept on
+// free lists and handed out again by the next allocation, so programs that
+// build temporaries in a loop do not grow the heaps without bound.
+impl VM {
+    pub(crate) fn alloc_array(&mut self, elems: Vec<Type>, immutables: HashSet<usize>) -> usize {
+        if let Some(id) = self.free_arrays.pop() {
+            self.array_freed[id] = false;
+            self.array_heap[id] = elems;
+            self.array_immutables[id] = immutables;
+            return id;
+        }
+        self.array_heap.push(elems);
+        self.array_immutables.push(immutables);
+        self.array_freed.push(false);
+        self.array_heap.len() - 1
+    }
+
+    pub(crate) fn alloc_vec(&mut self, elems: Vec<Type>, immutables: HashSet<usize>) -> usize {
+        if let Some(id) = self.free_vecs.pop() {
+            self.vec_freed[id] = false;
+            self.vec_heap[id] = elems;
+            self.vec_immutables[id] = immutables;
+            return id;
+        }
+        self.vec_heap.push(elems);
+        self.vec_immutables.push(immutables);
+        self.vec_freed.push(false);
+        self.vec_heap.len() - 1
+    }
+
+    /// Releases an array slot for reuse. Any reference still held to it
+    /// will observe whatever array is allocated there next.
+    pub(crate) fn free_array(&mut self, id: usize) {
+        if id >= self.array_heap.len() {
+            self.runtime_error(&format!("invalid array reference array#{id}"));
+        }
+        if self.array_freed[id] {
+            self.runtime_error(&format!("double free of array#{id}"));
+        }
+        self.array_heap[id] = Vec::new();
+        self.array_immutables[id].clear();
+        self.array_freed[id] = true;
+        self.free_arrays.push(id);
+    }
+
+    pub(crate) fn free_vec(&mut self, id: usize) {
+        if id >= self.vec_heap.len() {
+            self.runtime_error(&format!("invalid vec reference vec#{id}"));
+        }
+        if self.vec_freed[id] {
+            self.runtime_error(&format!("double free of vec#{id}"));
+        }
+        self.vec_heap[id] = Vec::new();
+        self.vec_immutables[id].clear();
+        self.vec_freed[id] = true;
+        self.free_vecs.push(id);
+    }
+
+    pub(crate) fn is_freed_array(&self, id: usize) -> bool {
+        self.array_freed.get(id).copied().unwrap_or(false)
+    }
+
+    pub(crate) fn is_freed_vec(&self, id: usize) -> bool {
+        self.vec_freed.get(id).copied().unwrap_or(false)
+    }
+}
@@ -1,42 +1,328 @@
-use super::VM;
+use super::{HostFunction, RuntimeError, VM};
 use crate::{
-    grammar::{Instruction, Type},
+    grammar::{Instruction, Type, Visibility},
     vm::CallFrame,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::panic::{AssertUnwindSafe, catch_unwind, resume_unwind};
+use std::time::Instant;
 
 impl VM {
     // =========================================================
     // Instruction entry point
     // =========================================================
-    pub(crate) fn exec_call(&mut self, name: String, argc: usize) {
+    /// Returns `true` if a bytecode function was entered: its frame is pushed
+    /// and execution continues at its first instruction. Natives run to
+    /// completion and push their result.
+    pub(crate) fn exec_call(&mut self, name: String, argc: usize) -> bool {
+        self.check_visibility(&name, "function");
         let args = self.pop_args(argc);
 
         let f = self.global_env.get(&name).cloned().unwrap_or_else(|| {
-            self.runtime_error(&format!(
-                "call error: `{}` is not defined (attempted to call with {} argument(s))",
-                name, argc
-            ))
+            self.unknown_name_error(
+                &format!(
+                    "call error: `{}` is not defined (attempted to call with {} argument(s))",
+                    name, argc
+                ),
+                &name,
+                true,
+            )
         });
 
-        let ret = match f {
-            Type::Function { .. } => self.call_function(name, f, args),
-            Type::NativeFunction(native_name) => self.call_native(native_name, args),
+        let started = self.profiler.as_ref().map(|_| Instant::now());
+
+        match f {
+            Type::Function { .. } => {
+                self.enter_function(name, f, args);
+                if let Some(frame) = self.call_stack.last_mut() {
+                    frame.started = started;
+                }
+                true
+            }
+            Type::NativeFunction(native_name) => {
+                let ret = self.call_native(native_name, args);
+                if let (Some(start), Some(profiler)) = (started, &mut self.profiler) {
+                    profiler.record_call(&name, start.elapsed());
+                }
+                self.stack.push(ret);
+                false
+            }
+            other => self.runtime_error(&format!(
+                "call error: `{}` is not a function (found {})",
+                name,
+                self.format_value(&other)
+            )),
+        }
+    }
+
+    // =========================================================
+    // Visibility
+    // =========================================================
+
+    /// Records whether the function or struct `name` being defined is
+    /// private; a public redefinition lifts the restriction.
+    pub(crate) fn set_visibility(&mut self, name: &str, visibility: Visibility) {
+        match visibility {
+            Visibility::Private => {
+                self.private_symbols.insert(name.to_string());
+            }
+            Visibility::Public => {
+                self.private_symbols.remove(name);
+            }
+        }
+    }
+
+    /// Fails unless the running code may use `name`. A private definition
+    /// belongs to the module its qualified name is prefixed with (`a.b` for
+    /// `a.b.helper`) and is only usable from code that module wrote; see
+    /// `current_module`. Top-level code is not checked: imported modules' top
+    /// levels run inlined into the program's, and the compiler already
+    /// rejects uses it can see.
+    pub(crate) fn check_visibility(&self, name: &str, what: &str) {
+        if self.private_symbols.is_empty() || !self.private_symbols.contains(name) {
+            return;
+        }
+        let Some(caller) = self.current_module() else {
+            return;
+        };
+        let module = module_of(name);
+        if caller != module {
+            self.runtime_error(&format!("{what} `{name}` is private to module `{module}`"));
+        }
+    }
+
+    /// `check_visibility` for a function a native was handed by name. A
+    /// string does not say which module wrote it, so the name is usable if
+    /// the function's module is anywhere in the native's callers, e.g. when
+    /// it passes its own helper through a std wrapper.
+    pub(crate) fn check_callback_visibility(&self, name: &str) {
+        if self.private_symbols.is_empty() || !self.private_symbols.contains(name) {
+            return;
+        }
+        let module = module_of(name);
+        let passed_by_module = self
+            .call_stack
+            .iter()
+            .any(|frame| frame.module.as_deref() == Some(module));
+        if !passed_by_module {
+            self.check_visibility(name, "function");
+        }
+    }
+
+    /// The module that wrote the running code, or `None` for top-level code.
+    /// A function's body belongs to the module it was defined in, however it
+    /// was reached (by name, alias or as a callback); a native to the code
+    /// that called it; and reactive or initializer code running inline to the
+    /// module that wrote the expression or struct, not to the frame reading
+    /// it.
+    pub(crate) fn current_module(&self) -> Option<&str> {
+        match &self.inline_module {
+            Some((depth, module)) if *depth == self.call_stack.len() => module.as_deref(),
+            _ => self.call_stack.last()?.module.as_deref(),
+        }
+    }
+
+    // =========================================================
+    // Indirect calls (callbacks from natives)
+    // =========================================================
+
+    /// Calls a function passed as a value: either a function value (`Load`
+    /// of a function name) or a string naming a global function.
+    pub(crate) fn call_value(&mut self, callee: Type, args: Vec<Type>, what: &str) -> Type {
+        let (name, f) = match self.force(callee) {
+            f @ Type::Function { .. } => ("<callback>".to_string(), f),
+            f @ Type::NativeFunction(_) => ("<callback>".to_string(), f),
+            s @ (Type::ArrayRef(_) | Type::VecRef(_)) => {
+                let name = self.value_to_string(s, what);
+                self.check_callback_visibility(&name);
+                let f = self.global_env.get(&name).cloned().unwrap_or_else(|| {
+                    self.unknown_name_error(
+                        &format!("{what}: function `{name}` is not defined"),
+                        &name,
+                        true,
+                    )
+                });
+                (name, f)
+            }
             other => self.runtime_error(&format!(
-                "call error: `{}` is not a function (found {:?})",
-                name, other
+                "{what} expects a function, found {}",
+                self.format_value(&other)
             )),
         };
 
-        self.stack.push(ret);
+        match f {
+            Type::Function { ref params, .. } => {
+                if params.len() != args.len() {
+                    self.runtime_error(&format!(
+                        "{what}: callback takes {} parameter(s), called with {}",
+                        params.len(),
+                        args.len()
+                    ));
+                }
+                self.call_function(name, f, args)
+            }
+            Type::NativeFunction(native_name) => self.call_native(native_name, args),
+            other => self.runtime_error(&format!(
+                "{what}: `{name}` is not a function (found {})",
+                self.format_value(&other)
+            )),
+        }
+    }
+
+    // =========================================================
+    // Embedding
+    // =========================================================
+
+    /// Names of the global functions defined so far, sorted. Run the
+    /// program's top level first to define them.
+    pub fn function_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .global_env
+            .iter()
+            .filter(|(_, v)| matches!(v, Type::Function { .. }))
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Number of parameters of global function `name`, if it is one.
+    pub fn function_arity(&self, name: &str) -> Option<usize> {
+        match self.global_env.get(name) {
+            Some(Type::Function { params, .. }) => Some(params.len()),
+            _ => None,
+        }
+    }
+
+    /// Calls global function `name` as a `Call` instruction would and
+    /// returns its result.
+    pub fn call_global(&mut self, name: &str, args: Vec<Type>) -> Type {
+        let f = self.global_env.get(name).cloned().unwrap_or_else(|| {
+            self.unknown_name_error(&format!("function `{name}` is not defined"), name, true)
+        });
+        if let Type::Function { params, .. } = &f
+            && params.len() != args.len()
+        {
+            self.runtime_error(&format!(
+                "`{name}` takes {} parameter(s), called with {}",
+                params.len(),
+                args.len()
+            ));
+        }
+        match f {
+            Type::NativeFunction(native_name) => self.call_native(native_name, args),
+            f => self.call_function(name.to_string(), f, args),
+        }
+    }
+
+    /// Defines global function `name` as `f`, which programs call like a
+    /// native. It replaces any function of that name.
+    pub fn register_host_function(&mut self, name: &str, f: HostFunction) {
+        self.host_functions.insert(name.to_string(), f);
+        self.global_env
+            .insert(name.to_string(), Type::NativeFunction(name.to_string()));
+    }
+
+    /// Runs `f`; if it raises a runtime error (which needs
+    /// `VmOptions::unwind_on_error`), returns the error and puts the call
+    /// stack, operand stack, current code and busy objects back as they
+    /// were, so the VM can be used again. Globals and heaps keep any changes
+    /// made before the error. Other panics carry on unwinding.
+    pub fn catch_runtime_error<T>(
+        &mut self,
+        f: impl FnOnce(&mut VM) -> T,
+    ) -> Result<T, RuntimeError> {
+        let frames = self.call_stack.len();
+        let stack = self.stack.len();
+        let run_depth = self.run_depth;
+        let code = self.code.clone();
+        let labels = self.labels.clone();
+        let pointer = self.pointer;
+        let local_env = self.local_env.clone();
+        let immutable_stack = self.immutable_stack.clone();
+        let busy = self.busy.clone();
+
+        match catch_unwind(AssertUnwindSafe(|| f(self))) {
+            Ok(value) => Ok(value),
+            Err(payload) => {
+                let error = match payload.downcast::<RuntimeError>() {
+                    Ok(error) => *error,
+                    Err(payload) => resume_unwind(payload),
+                };
+                self.call_stack.truncate(frames);
+                self.stack.truncate(stack);
+                self.run_depth = run_depth;
+                self.code = code;
+                self.labels = labels;
+                self.pointer = pointer;
+                self.local_env = local_env;
+                self.immutable_stack = immutable_stack;
+                self.busy = busy;
+                Err(error)
+            }
+        }
+    }
+
+    /// `catch_runtime_error` around `call_value`, for natives that must
+    /// clean up after a callback. Nothing is copied up front: the callback's
+    /// frame holds the caller's code and environments, so an error pops the
+    /// frames it left behind to put them back.
+    pub(crate) fn catch_callback_error(
+        &mut self,
+        f: Type,
+        args: Vec<Type>,
+        what: &str,
+    ) -> Result<Type, RuntimeError> {
+        let frames = self.call_stack.len();
+        let stack = self.stack.len();
+        let run_depth = self.run_depth;
+        // usually empty, which clones without allocating
+        let busy = self.busy.clone();
+
+        match catch_unwind(AssertUnwindSafe(|| self.call_value(f, args, what))) {
+            Ok(value) => Ok(value),
+            Err(payload) => {
+                let error = match payload.downcast::<RuntimeError>() {
+                    Ok(error) => *error,
+                    Err(payload) => resume_unwind(payload),
+                };
+                while self.call_stack.len() > frames {
+                    let frame = self.call_stack.pop().unwrap();
+                    // native frames save no state
+                    if !frame.immutable_stack.is_empty() {
+                        self.code = frame.code;
+                        self.labels = frame.labels;
+                        self.pointer = frame.pointer;
+                        self.local_env = frame.local_env;
+                        self.immutable_stack = frame.immutable_stack;
+                    }
+                }
+                self.stack.truncate(stack);
+                self.run_depth = run_depth;
+                self.busy = busy;
+                Err(error)
+            }
+        }
     }
 
     // =========================================================
     // Function execution
     // =========================================================
     pub(crate) fn call_function(&mut self, name: String, f: Type, args: Vec<Type>) -> Type {
+        self.enter_function(name, f, args);
+        self.run();
+        self.pop_frame()
+    }
+
+    /// Binds `args` to the parameters of `f` and pushes its frame; the next
+    /// instruction executed is the first of its body.
+    fn enter_function(&mut self, name: String, f: Type, args: Vec<Type>) {
         match f {
-            Type::Function { params, code } => {
+            Type::Function {
+                params,
+                code,
+                module,
+            } => {
                 // Build immutable stack: global + params
                 let global_immutables = self.immutable_stack[0].clone();
                 let mut imm_stack = vec![global_immutables, HashMap::new()];
@@ -50,37 +336,37 @@ impl VM {
 
                 let local_env = Some(HashMap::new());
 
-                let labels = Self::build_labels(&code);
-
-                // Push call frame
-                self.push_frame(name, code, labels, local_env, imm_stack);
+                let labels = self.link_labels(&code);
 
-                // Execute
-                self.run();
-
-                // Pop frame and return value
-                self.pop_frame()
+                self.push_frame(name, Some(module), code, labels, local_env, imm_stack);
             }
             _ => self.runtime_error("attempted to call non-function"),
         }
     }
 
     fn call_native(&mut self, name: String, args: Vec<Type>) -> Type {
-        let f = self
-            .native_functions
-            .get(&name)
-            .copied()
-            .unwrap_or_else(|| {
-                self.runtime_error(&format!(
-                    "call error: native function `{}` is not registered",
-                    name
-                ))
-            });
+        let native = self.native_functions.get(&name).copied();
+        let host = match native {
+            Some(_) => None,
+            None => self.host_functions.get(&name).cloned(),
+        };
+        if native.is_none() && host.is_none() {
+            self.runtime_error(&format!(
+                "call error: native function `{}` is not registered",
+                name
+            ));
+        }
 
+        let timer = self.native_timer();
         self.push_native_frame(name);
-        let result = f(self, args);
-        if self.call_stack.pop().is_none() {
-            self.runtime_error("call stack underflow after native call");
+        let result = match (native, host) {
+            (Some(f), _) => f(self, args),
+            (None, Some(f)) => f(self, args),
+            (None, None) => unreachable!(),
+        };
+        match self.call_stack.pop() {
+            Some(frame) => self.record_native_time(&frame.function_name, timer),
+            None => self.runtime_error("call stack underflow after native call"),
         }
         result
     }
@@ -88,11 +374,13 @@ impl VM {
     fn push_frame(
         &mut self,
         function_name: String,
+        module: Option<String>,
         code: Vec<Instruction>,
         labels: HashMap<String, usize>,
         local_env: Option<HashMap<String, Type>>,
         immutable_stack: Vec<HashMap<String, Type>>,
     ) {
+        self.check_call_depth(&function_name);
         let frame = CallFrame {
             code: std::mem::replace(&mut self.code, code),
             labels: std::mem::replace(&mut self.labels, labels),
@@ -103,6 +391,8 @@ impl VM {
 
             stack_base: self.stack.len(),
             function_name,
+            module,
+            started: None,
         };
 
         self.pointer = 0;
@@ -110,6 +400,9 @@ impl VM {
     }
 
     fn push_native_frame(&mut self, function_name: String) {
+        self.check_call_depth(&function_name);
+        // callbacks a native resolves by name are checked against its caller
+        let module = self.current_module().map(str::to_string);
         let frame = CallFrame {
             code: Vec::new(),
             labels: HashMap::new(),
@@ -118,21 +411,40 @@ impl VM {
             immutable_stack: Vec::new(),
             stack_base: self.stack.len(),
             function_name,
+            module,
+            started: None,
         };
         self.call_stack.push(frame);
     }
 
-    fn pop_frame(&mut self) -> Type {
+    /// Raises the "stack overflow" error before a call would exceed
+    /// `VmOptions::max_call_depth`.
+    fn check_call_depth(&self, callee: &str) {
+        let max = self.options.max_call_depth;
+        if self.call_stack.len() >= max {
+            self.runtime_error(&format!(
+                "stack overflow: calling `{callee}` exceeds the maximum call depth of {max}"
+            ));
+        }
+    }
+
+    pub(crate) fn pop_frame(&mut self) -> Type {
         let frame = match self.call_stack.pop() {
             Some(frame) => frame,
             None => self.runtime_error("call stack underflow"),
         };
 
+        if let (Some(start), Some(profiler)) = (frame.started, &mut self.profiler) {
+            profiler.record_call(&frame.function_name, start.elapsed());
+        }
+
         let ret = if self.stack.len() > frame.stack_base {
             self.stack.pop().unwrap()
         } else {
             Type::Integer(0)
         };
+        // values call statements left behind must not leak into the caller
+        self.stack.truncate(frame.stack_base);
 
         self.code = frame.code;
         self.labels = frame.labels;
@@ -146,18 +458,239 @@ impl VM {
     // =========================================================
     // Module imports
     // =========================================================
-    pub(crate) fn import_module(&mut self, path: Vec<String>) {
-        if path.len() == 2 && path[0] == "std" && path[1] == "file" {
-            self.install_native_fs();
-        }
-        if path.len() == 2 && path[0] == "std" && path[1] == "buf" {
-            self.install_native_buf();
-        }
-        if path.len() == 2 && path[0] == "std" && path[1] == "vec" {
-            self.install_native_vec();
+    /// Names of the natives a program gets from its `Import` instructions.
+    pub(crate) fn native_names(imports: &[Vec<String>]) -> HashSet<String> {
+        let mut vm = VM::new(Vec::new());
+        for path in imports {
+            vm.import_module(path.clone());
         }
-        if path.len() == 2 && path[0] == "std" && path[1] == "input" {
-            self.install_native_input();
+        vm.native_functions.keys().cloned().collect()
+    }
+
+    /// Modules of an archive (`reactive pack`) by import path; `import a.b`
+    /// runs the module named `a.b` once, at top level, before the code after
+    /// the import.
+    pub fn set_archive_modules(&mut self, modules: HashMap<String, Vec<Instruction>>) {
+        self.archive_modules = modules;
+    }
+
+    /// Runs a module's top level. Its definitions land in the globals. Returns
+    /// the value its top level returned (`main`'s result, for a compiled
+    /// program) or else `0`; other values left on the operand stack are
+    /// dropped.
+    pub(crate) fn run_module(&mut self, name: String, code: Vec<Instruction>) -> Type {
+        let code = super::jumptable::resolve_switches(code);
+        let base = self.stack.len();
+        let labels = self.link_labels(&code);
+        let globals = self.immutable_stack[0].clone();
+        self.push_frame(
+            format!("<module {name}>"),
+            None,
+            code,
+            labels,
+            None,
+            vec![globals],
+        );
+        self.run();
+        // `:=` bindings the module made at its top level are globals too
+        let module_globals = std::mem::take(&mut self.immutable_stack[0]);
+        let result = self.pop_frame();
+        self.immutable_stack[0].extend(module_globals);
+        self.stack.truncate(base);
+        result
+    }
+
+    pub(crate) fn import_module(&mut self, path: Vec<String>) {
+        let module = path.join(".");
+        let install: fn(&mut VM) = match module.as_str() {
+            "std.file" => VM::install_native_fs,
+            "std.buf" => VM::install_native_buf,
+            "std.bytes" => VM::install_native_bytes,
+            "std.vec" => VM::install_native_vec,
+            "std.set" => VM::install_native_set,
+            "std.pq" => VM::install_native_pq,
+            "std.array" => VM::install_native_array,
+            "std.finalize" => VM::install_native_finalize,
+            "std.maths" => VM::install_native_math,
+            "std.input" => VM::install_native_input,
+            "std.datetime" => VM::install_native_datetime,
+            "std.uuid" => VM::install_native_uuid,
+            "std.hash" => VM::install_native_hash,
+            "std.csv" => VM::install_native_csv,
+            "std.config" => VM::install_native_config,
+            "std.eval" => VM::install_native_eval,
+            "std.gzip" => VM::install_native_gzip,
+            "std.null" => VM::install_native_null,
+            "std.tuple" => VM::install_native_tuple,
+            "std.log" => VM::install_native_log,
+            "std.io" => VM::install_native_io,
+            "std.term" => VM::install_native_term,
+            "std.debug" => VM::install_native_debug,
+            "std.thread" => VM::install_native_thread,
+            "std.net" => VM::install_native_net,
+            "std.ws" => VM::install_native_ws,
+            _ => return,
+        };
+        if let Some(allowed) = &self.options.allowed_imports
+            && !allowed.contains(&module)
+        {
+            self.runtime_error(&format!(
+                "import of `{module}` is not permitted (allowed: {})",
+                if allowed.is_empty() {
+                    "none".to_string()
+                } else {
+                    allowed.join(", ")
+                }
+            ));
         }
+        install(self);
+        crate::verbose!("import {module}: installed natives");
+    }
+}
+
+/// The module prefix of a qualified name, `""` for a plain one.
+pub(crate) fn module_of(name: &str) -> &str {
+    name.rsplit_once('.').map_or("", |(module, _)| module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::{Instruction::*, ReactiveExpr};
+    use crate::vm::VmOptions;
+
+    const PRIVATE: &str = "function `lib.secret` is private to module `lib`";
+
+    fn function(name: &str, params: &[&str], body: Vec<Instruction>) -> Instruction {
+        let params = params.iter().map(|p| p.to_string()).collect();
+        let visibility = if name == "lib.secret" {
+            Visibility::Private
+        } else {
+            Visibility::Public
+        };
+        StoreFunction(name.to_string(), params, body, visibility)
+    }
+
+    fn load(name: &str) -> Instruction {
+        Load(name.to_string())
+    }
+
+    fn call(name: &str, argc: usize) -> Instruction {
+        Call(name.to_string(), argc)
+    }
+
+    /// `b.v := lib.secret(1)` as a function of `b`.
+    fn fill(name: &str) -> Instruction {
+        let expr = ReactiveExpr {
+            code: vec![Push(1), call("lib.secret", 1)],
+            captures: Vec::new(),
+        };
+        let body = vec![load("b"), FieldSetReactive("v".to_string(), expr), Return];
+        function(name, &["b"], body)
+    }
+
+    fn read(name: &str) -> Instruction {
+        function(
+            name,
+            &["b"],
+            vec![load("b"), FieldGet("v".to_string()), Return],
+        )
+    }
+
+    fn map(name: &str) -> Instruction {
+        let body = vec![load("v"), load("f"), call("internal_vec_map", 2), Return];
+        function(name, &["v", "f"], body)
+    }
+
+    /// `lib.secret` is private to `lib`; `wrap` and the main program (no
+    /// prefix) are other modules.
+    fn program() -> VM {
+        let code = vec![
+            Import(vec!["std".to_string(), "vec".to_string()]),
+            StoreStruct(
+                "Box".to_string(),
+                vec![("v".to_string(), None)],
+                Visibility::Public,
+            ),
+            function("lib.secret", &["x"], vec![load("x"), Return]),
+            function(
+                "lib.map_own",
+                &["v", "name"],
+                vec![load("v"), load("name"), call("wrap.map", 2), Return],
+            ),
+            fill("lib.fill"),
+            read("lib.read"),
+            map("wrap.map"),
+            function(
+                "cheat",
+                &["x"],
+                vec![load("x"), call("lib.secret", 1), Return],
+            ),
+            map("map_with"),
+            fill("fill"),
+            read("read"),
+            function("new_box", &[], vec![NewStruct("Box".to_string()), Return]),
+        ];
+        let options = VmOptions {
+            unwind_on_error: true,
+            ..VmOptions::default()
+        };
+        let mut vm = VM::with_options(code, options);
+        vm.set_stdout_sink(Box::new(std::io::sink()));
+        vm.run();
+        vm
+    }
+
+    fn run(vm: &mut VM, name: &str, args: Vec<Type>) -> Result<Type, RuntimeError> {
+        vm.catch_runtime_error(|vm| {
+            let ret = vm.call_global(name, args);
+            vm.force(ret)
+        })
+    }
+
+    fn ones(vm: &mut VM) -> Type {
+        Type::VecRef(vm.alloc_vec(vec![Type::Integer(1)], HashSet::new()))
+    }
+
+    #[test]
+    fn callback_body_is_checked_against_its_own_module() {
+        let mut vm = program();
+        let v = ones(&mut vm);
+        let cheat = vm.global_env["cheat"].clone();
+        let err = run(&mut vm, "map_with", vec![v, cheat]).unwrap_err();
+        assert_eq!(err.message, PRIVATE);
+    }
+
+    #[test]
+    fn module_may_pass_its_own_helper_by_name_through_a_wrapper() {
+        let mut vm = program();
+        let v = ones(&mut vm);
+        let name = vm.string_to_array("lib.secret");
+        assert!(run(&mut vm, "lib.map_own", vec![v, name]).is_ok());
+    }
+
+    #[test]
+    fn other_module_may_not_pass_a_private_helper_by_name() {
+        let mut vm = program();
+        let v = ones(&mut vm);
+        let name = vm.string_to_array("lib.secret");
+        let err = run(&mut vm, "map_with", vec![v, name]).unwrap_err();
+        assert_eq!(err.message, PRIVATE);
+    }
+
+    #[test]
+    fn reactive_code_belongs_to_the_module_that_wrote_it() {
+        let mut vm = program();
+        let b = run(&mut vm, "new_box", Vec::new()).unwrap();
+        run(&mut vm, "lib.fill", vec![b.clone()]).unwrap();
+        assert!(matches!(
+            run(&mut vm, "read", vec![b]),
+            Ok(Type::Integer(1))
+        ));
+
+        let b = run(&mut vm, "new_box", Vec::new()).unwrap();
+        run(&mut vm, "fill", vec![b.clone()]).unwrap();
+        let err = run(&mut vm, "lib.read", vec![b]).unwrap_err();
+        assert_eq!(err.message, PRIVATE);
     }
 }
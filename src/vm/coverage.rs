@@ -0,0 +1,112 @@
+use super::VM;
+use crate::bytecode::{CodeBody, Metadata, code_bodies};
+use crate::debug_info::BodyMatcher;
+use crate::grammar::Instruction;
+
+/// Execution counts for every instruction of a program, keyed by the RXB
+/// line it was loaded from; collected by `reactive run --coverage`.
+pub struct Coverage {
+    bodies: Vec<CodeBody>,
+    hits: Vec<Vec<u64>>,
+    matcher: BodyMatcher,
+    // body running last time, keyed by the address and length of its code
+    current: Option<(usize, usize, Option<usize>)>,
+}
+
+impl Coverage {
+    /// `source` is the program as written in the `.rxb` file.
+    pub(crate) fn new(source: &[Instruction], metadata: Option<&Metadata>) -> Self {
+        let bodies = code_bodies(source, metadata);
+        let hits = bodies.iter().map(|b| vec![0; b.code.len()]).collect();
+        let matcher = BodyMatcher::new(&bodies);
+        Self {
+            bodies,
+            hits,
+            matcher,
+            current: None,
+        }
+    }
+
+    fn record(&mut self, code: &[Instruction], pointer: usize, function: &str) {
+        let key = (code.as_ptr() as usize, code.len());
+        let body = match self.current {
+            Some((ptr, len, body)) if (ptr, len) == key => body,
+            _ => {
+                let body = self.matcher.find(&self.bodies, code, function);
+                self.current = Some((key.0, key.1, body));
+                body
+            }
+        };
+        if let Some(hit) = body.and_then(|b| self.hits[b].get_mut(pointer)) {
+            *hit += 1;
+        }
+    }
+
+    /// Instructions in the program and how many of them ran.
+    pub fn summary(&self) -> (usize, usize) {
+        let total = self.hits.iter().map(Vec::len).sum();
+        let hit = self.hits.iter().flatten().filter(|&&n| n > 0).count();
+        (total, hit)
+    }
+
+    /// lcov tracefile treating the `.rxb` at `source_path` as the source:
+    /// one `DA` per instruction line and one `FN` per function, field
+    /// initializer and reactive expression.
+    pub fn lcov(&self, source_path: &str) -> String {
+        let mut out = format!("TN:\nSF:{source_path}\n");
+
+        let mut functions_hit = 0;
+        for (body, hits) in self.bodies.iter().zip(&self.hits).skip(1) {
+            let name = body.name.replace(',', "_");
+            let calls = hits.first().copied().unwrap_or(0);
+            if calls > 0 {
+                functions_hit += 1;
+            }
+            out.push_str(&format!(
+                "FN:{},{name}\nFNDA:{calls},{name}\n",
+                body.decl_line
+            ));
+        }
+        out.push_str(&format!(
+            "FNF:{}\nFNH:{functions_hit}\n",
+            self.bodies.len() - 1
+        ));
+
+        let mut lines: Vec<(usize, u64)> = self
+            .bodies
+            .iter()
+            .zip(&self.hits)
+            .flat_map(|(body, hits)| body.lines.iter().copied().zip(hits.iter().copied()))
+            .collect();
+        lines.sort_unstable();
+        for (line, count) in &lines {
+            out.push_str(&format!("DA:{line},{count}\n"));
+        }
+        let (total, hit) = self.summary();
+        out.push_str(&format!("LF:{total}\nLH:{hit}\nend_of_record\n"));
+        out
+    }
+}
+
+impl VM {
+    /// Starts counting executed instructions of `source`, the program as it
+    /// appears in its `.rxb` file, after its `metadata` block if any.
+    pub fn enable_coverage(&mut self, source: &[Instruction], metadata: Option<&Metadata>) {
+        self.coverage = Some(Coverage::new(source, metadata));
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub(crate) fn record_coverage(&mut self) {
+        let function = self
+            .call_stack
+            .last()
+            .map(|frame| frame.function_name.as_str())
+            .unwrap_or(super::profile::TOP_LEVEL);
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(&self.code, self.pointer, function);
+        }
+    }
+}
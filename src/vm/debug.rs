@@ -0,0 +1,234 @@
+use super::VM;
+use super::profile::TOP_LEVEL;
+use crate::debug_info::{BodyMatcher, DebugInfo};
+use crate::grammar::{Instruction, Type};
+use std::collections::HashMap;
+
+/// Gets control before every instruction while attached with
+/// `VM::attach_debugger`; it may inspect the VM and block to pause it.
+pub trait Debugger {
+    fn before_instruction(&mut self, vm: &mut VM);
+}
+
+/// One activation on the call stack, innermost first. Natives have no code
+/// position; `pointer` is the instruction about to run (innermost frame) or
+/// the `Call` in progress (outer frames).
+pub struct FrameView<'a> {
+    pub function: &'a str,
+    pub code: &'a [Instruction],
+    pub pointer: usize,
+    pub native: bool,
+    /// Parameters, `:=` bindings and locals visible in the frame.
+    pub locals: Vec<(String, Type)>,
+}
+
+impl VM {
+    pub fn attach_debugger(&mut self, debugger: Box<dyn Debugger>) {
+        self.debugger = Some(debugger);
+    }
+
+    /// Source locations of the running program (`Compiler::with_debug_info`),
+    /// used to place code positions in error reports.
+    pub fn set_debug_info(&mut self, info: DebugInfo) {
+        let matcher = BodyMatcher::new(&info.bodies);
+        self.debug_info = Some((info, matcher));
+    }
+
+    /// `file:line` of instruction `pc` of `code` when debug info covers it,
+    /// else the instruction index within the function.
+    pub(crate) fn describe_site(&self, function: &str, code: &[Instruction], pc: usize) -> String {
+        self.source_loc(function, code, pc)
+            .unwrap_or_else(|| format!("instruction {pc} of {function}"))
+    }
+
+    /// `file:line` of the running instruction, if debug info covers it.
+    pub(crate) fn source_site(&self) -> Option<String> {
+        self.source_loc(self.current_function_name(), &self.code, self.pointer)
+    }
+
+    fn source_loc(&self, function: &str, code: &[Instruction], pc: usize) -> Option<String> {
+        let (info, matcher) = self.debug_info.as_ref()?;
+        let body = matcher.find(&info.bodies, code, function)?;
+        let loc = info.loc(body, pc)?;
+        Some(format!("{}:{}", info.files[loc.file].display(), loc.line))
+    }
+
+    pub(crate) fn debugger_hook(&mut self) {
+        // Detached while it runs, so it can borrow the VM mutably (and so
+        // values it forces do not re-enter it).
+        if let Some(mut debugger) = self.debugger.take() {
+            debugger.before_instruction(self);
+            self.debugger = Some(debugger);
+        }
+    }
+
+    /// Function, code and instruction pointer of the running activation;
+    /// cheaper than `frames` for a check before every instruction.
+    pub fn position(&self) -> (&str, &[Instruction], usize) {
+        (self.current_function_name(), &self.code, self.pointer)
+    }
+
+    /// Number of active calls, bytecode and native.
+    pub fn call_depth(&self) -> usize {
+        self.call_stack.len()
+    }
+
+    /// The call stack, innermost activation first, ending with the top
+    /// level (unless running a spawned task).
+    pub fn frames(&self) -> Vec<FrameView<'_>> {
+        let mut views = Vec::new();
+        // state of the innermost bytecode activation not yet listed
+        let mut state = Some((
+            self.code.as_slice(),
+            self.pointer,
+            &self.local_env,
+            &self.immutable_stack,
+            self.call_stack.is_empty(),
+        ));
+
+        for (i, frame) in self.call_stack.iter().enumerate().rev() {
+            // Native frames save no state: the caller's keeps running in
+            // the VM until the native returns or calls back into bytecode.
+            if frame.immutable_stack.is_empty()
+                && self.native_functions.contains_key(&frame.function_name)
+            {
+                views.push(FrameView {
+                    function: &frame.function_name,
+                    code: &[],
+                    pointer: 0,
+                    native: true,
+                    locals: Vec::new(),
+                });
+                continue;
+            }
+            if let Some(state) = state.take() {
+                views.push(frame_view(&frame.function_name, state));
+            }
+            // Task entry frames have nothing below them.
+            if !frame.immutable_stack.is_empty() {
+                state = Some((
+                    frame.code.as_slice(),
+                    frame.pointer,
+                    &frame.local_env,
+                    &frame.immutable_stack,
+                    i == 0,
+                ));
+            }
+        }
+        if let Some(state) = state {
+            views.push(frame_view(TOP_LEVEL, state));
+        }
+        views
+    }
+
+    /// Top-level bindings, mutable and immutable, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Type)> {
+        let mut globals: Vec<(String, Type)> = self
+            .global_env
+            .iter()
+            .chain(self.global_immutables())
+            .map(|(name, v)| (name.clone(), v.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    fn global_immutables(&self) -> &HashMap<String, Type> {
+        match self.call_stack.first() {
+            Some(frame) if !frame.immutable_stack.is_empty() => &frame.immutable_stack[0],
+            _ => &self.immutable_stack[0],
+        }
+    }
+
+    /// `format_value` of `v` (`[1, 2]`, `"text"`, `Point { x: 1 }`) after
+    /// forcing it, which may run code.
+    pub fn describe(&mut self, v: Type) -> String {
+        let v = self.force(v);
+        self.format_value(&v)
+    }
+
+    /// Whether `children` of `v` would be non-empty.
+    pub fn has_children(&mut self, v: Type) -> bool {
+        match self.force(v) {
+            Type::ArrayRef(id) => !self.array_heap[id].is_empty(),
+            Type::VecRef(id) => !self.vec_heap[id].is_empty(),
+            Type::Tuple(elems) => !elems.is_empty(),
+            Type::Variant(variant) => !variant.payload.is_empty(),
+            Type::StructRef(id) => !self.heap[id].fields.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Named parts of a compound value: elements of arrays, vecs and tuples,
+    /// fields of structs and payloads of variants.
+    pub fn children(&mut self, v: Type) -> Vec<(String, Type)> {
+        let indexed = |elems: Vec<Type>| -> Vec<(String, Type)> {
+            elems
+                .into_iter()
+                .enumerate()
+                .map(|(i, e)| (format!("[{i}]"), e))
+                .collect()
+        };
+        match self.force(v) {
+            Type::ArrayRef(id) => indexed(self.array_heap[id].clone()),
+            Type::VecRef(id) => indexed(self.vec_heap[id].clone()),
+            Type::Tuple(elems) => indexed(elems),
+            Type::Variant(variant) => indexed(variant.payload),
+            Type::StructRef(id) => {
+                let mut fields: Vec<(String, Type)> = self.heap[id]
+                    .fields
+                    .iter()
+                    .map(|(name, v)| (name.clone(), v.clone()))
+                    .collect();
+                fields.sort_by(|a, b| a.0.cmp(&b.0));
+                fields
+                    .into_iter()
+                    .map(|(name, v)| {
+                        let v = self.force_struct_field(id, v);
+                        (name, v)
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+type FrameState<'a> = (
+    &'a [Instruction],
+    usize,
+    &'a Option<HashMap<String, Type>>,
+    &'a Vec<HashMap<String, Type>>,
+    bool,
+);
+
+fn frame_view<'a>(function: &'a str, state: FrameState<'a>) -> FrameView<'a> {
+    let (code, pointer, local_env, immutable_stack, top_level) = state;
+    // Scope 0 of a function's immutables is its copy of the globals; at the
+    // top level every scope is global.
+    let scopes = if top_level {
+        &[][..]
+    } else {
+        &immutable_stack[1..]
+    };
+    let mut locals: HashMap<&String, &Type> = HashMap::new();
+    for scope in scopes {
+        locals.extend(scope.iter());
+    }
+    if let Some(env) = local_env {
+        locals.extend(env.iter());
+    }
+    let mut locals: Vec<(String, Type)> = locals
+        .into_iter()
+        .map(|(name, v)| (name.clone(), v.clone()))
+        .collect();
+    locals.sort_by(|a, b| a.0.cmp(&b.0));
+
+    FrameView {
+        function,
+        code,
+        pointer,
+        native: false,
+        locals,
+    }
+}
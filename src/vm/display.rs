@@ -0,0 +1,162 @@
+use super::VM;
+use super::runtime::Container;
+use crate::grammar::{LValue, Type};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Formats a value with access to the VM's heaps, so references render as
+/// their contents: `"HELLO"`, `[1, 2]`, `Screen { width: 80 }`. Returned by
+/// `VM::display`. Reactive values are shown as `<reactive>` rather than
+/// evaluated, so formatting never runs code.
+pub struct ValueDisplay<'a> {
+    vm: &'a VM,
+    value: &'a Type,
+}
+
+impl fmt::Display for ValueDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // arrays, vecs and structs being rendered, to cut cycles
+        let mut path = HashSet::new();
+        write_value(self.vm, self.value, &mut path, f)
+    }
+}
+
+impl VM {
+    /// `v` formatted against this VM's heaps; see `ValueDisplay`.
+    pub fn display<'a>(&'a self, v: &'a Type) -> ValueDisplay<'a> {
+        ValueDisplay { vm: self, value: v }
+    }
+
+    /// `v` rendered for error messages and embedders, e.g. `"HELLO"`,
+    /// `[1, 2]` or `Screen { width: 80 }`. Does not evaluate reactive values.
+    pub fn format_value(&self, v: &Type) -> String {
+        self.display(v).to_string()
+    }
+}
+
+fn write_value(
+    vm: &VM,
+    v: &Type,
+    path: &mut HashSet<Container>,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    match v {
+        Type::ArrayRef(id) => match vm.array_heap.get(*id) {
+            Some(elems) => write_sequence(vm, Container::Array(*id), elems, path, f),
+            None => write!(f, "{v}"),
+        },
+        Type::VecRef(id) => match vm.vec_heap.get(*id) {
+            Some(elems) => write_sequence(vm, Container::Vec(*id), elems, path, f),
+            None => write!(f, "{v}"),
+        },
+        Type::Tuple(elems) => {
+            f.write_str("(")?;
+            write_list(vm, elems, path, f)?;
+            f.write_str(")")
+        }
+        Type::Variant(variant) => {
+            write!(f, "{}.{}", variant.enum_name, variant.name)?;
+            if !variant.payload.is_empty() {
+                f.write_str("(")?;
+                write_list(vm, &variant.payload, path, f)?;
+                f.write_str(")")?;
+            }
+            Ok(())
+        }
+        Type::StructRef(id) => {
+            let Some(instance) = vm.heap.get(*id) else {
+                return write!(f, "{v}");
+            };
+            let node = Container::Struct(*id);
+            if !path.insert(node) {
+                return write!(f, "{} {{ ... }}", instance.name);
+            }
+            let mut fields: Vec<(&String, &Type)> = instance.fields.iter().collect();
+            fields.sort_by(|a, b| a.0.cmp(b.0));
+            write!(f, "{} {{ ", instance.name)?;
+            for (i, (name, value)) in fields.into_iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{name}: ")?;
+                write_value(vm, value, path, f)?;
+            }
+            path.remove(&node);
+            f.write_str(" }")
+        }
+        Type::BufferRef(id) => match vm.buffer_heap.get(*id) {
+            Some(buf) => write!(f, "buffer(len={})", buf.len()),
+            None => write!(f, "{v}"),
+        },
+        Type::BytesRef(id) => match vm.bytes_heap.get(*id) {
+            Some(bytes) => write!(f, "bytes(len={})", bytes.len()),
+            None => write!(f, "{v}"),
+        },
+        Type::SetRef(id) => match vm.set_heap.get(*id) {
+            Some(set) => write!(f, "set(len={})", set.len()),
+            None => write!(f, "{v}"),
+        },
+        Type::PqRef(id) => match vm.pq_heap.get(*id) {
+            Some(pq) => write!(f, "pq(len={})", pq.items.len()),
+            None => write!(f, "{v}"),
+        },
+        Type::LValue(lv) => match lvalue_target(vm, lv) {
+            Some(target) => write_value(vm, target, path, f),
+            None => write!(f, "{v}"),
+        },
+        other => write!(f, "{other}"),
+    }
+}
+
+/// Arrays and vecs of chars are strings and render quoted. One already being
+/// rendered further up renders as `[...]`.
+fn write_sequence(
+    vm: &VM,
+    node: Container,
+    elems: &[Type],
+    path: &mut HashSet<Container>,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    let text: Option<String> = elems
+        .iter()
+        .map(|e| match e {
+            Type::Char(c) => char::from_u32(*c),
+            _ => None,
+        })
+        .collect();
+    match text {
+        Some(text) if !text.is_empty() => write!(f, "{text:?}"),
+        _ => {
+            if !path.insert(node) {
+                return f.write_str("[...]");
+            }
+            f.write_str("[")?;
+            write_list(vm, elems, path, f)?;
+            path.remove(&node);
+            f.write_str("]")
+        }
+    }
+}
+
+fn write_list(
+    vm: &VM,
+    elems: &[Type],
+    path: &mut HashSet<Container>,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    for (i, elem) in elems.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write_value(vm, elem, path, f)?;
+    }
+    Ok(())
+}
+
+fn lvalue_target<'a>(vm: &'a VM, lv: &LValue) -> Option<&'a Type> {
+    match lv {
+        LValue::ArrayElem { array_id, index } => vm.array_heap.get(*array_id)?.get(*index),
+        LValue::VecElem { vec_id, index } => vm.vec_heap.get(*vec_id)?.get(*index),
+        LValue::StructField { struct_id, field } => vm.heap.get(*struct_id)?.fields.get(field),
+    }
+}
@@ -1,5 +1,7 @@
-use super::VM;
-use crate::grammar::Type;
+use super::profile::TOP_LEVEL;
+use super::{ShadowPolicy, VM};
+use crate::grammar::{Instruction, Type};
+use std::io::Write;
 
 impl VM {
     pub(crate) fn lookup_var(&self, name: &str) -> Option<&Type> {
@@ -10,6 +12,38 @@ impl VM {
             .or_else(|| self.global_env.get(name))
     }
 
+    /// `lookup_var` for instructions that require the name to exist.
+    pub(crate) fn resolve_var(&self, name: &str) -> Type {
+        match self.lookup_var(name) {
+            Some(v) => v.clone(),
+            None => self.unknown_name_error(&format!("undefined variable: {name}"), name, false),
+        }
+    }
+
+    /// Reports an unresolved name, suggesting the closest visible binding.
+    /// Calls only resolve through the global environment, so `globals_only`
+    /// restricts the candidates accordingly.
+    pub(crate) fn unknown_name_error(&self, message: &str, name: &str, globals_only: bool) -> ! {
+        let mut candidates: Vec<&str> = self.global_env.keys().map(String::as_str).collect();
+        if !globals_only {
+            candidates.extend(
+                self.local_env
+                    .iter()
+                    .flat_map(|e| e.keys().map(String::as_str)),
+            );
+            candidates.extend(
+                self.immutable_stack
+                    .iter()
+                    .flat_map(|s| s.keys().map(String::as_str)),
+            );
+        }
+
+        match closest_name(name, candidates) {
+            Some(hint) => self.runtime_error(&format!("{message}, did you mean `{hint}`?")),
+            None => self.runtime_error(message),
+        }
+    }
+
     pub(crate) fn find_immutable(&self, name: &str) -> Option<&Type> {
         self.immutable_stack.iter().rev().find_map(|s| s.get(name))
     }
@@ -31,4 +65,98 @@ impl VM {
             self.runtime_error(&format!("cannot assign to immutable variable `{name}`"));
         }
     }
+
+    /// Applies `VmOptions::shadowing` to a `Store` / `StoreReactive` of
+    /// `name` inside a function, which creates a local hiding any immutable
+    /// of that name. Only the store that creates the local is reported.
+    pub(crate) fn check_shadowing(&mut self, name: &str) {
+        if self.options.shadowing == ShadowPolicy::Allow
+            || self.local_env.as_ref().is_none_or(|e| e.contains_key(name))
+        {
+            return;
+        }
+        let Some(scope) = self
+            .immutable_stack
+            .iter()
+            .rposition(|s| s.contains_key(name))
+        else {
+            return;
+        };
+
+        let function = self.current_function_name().to_string();
+        let store = self.describe_site(&function, &self.code, self.pointer);
+        let binding = if scope == 0 {
+            // Scope 0 is the function's copy of the globals; the top-level
+            // code is saved in the first frame pushed from bytecode.
+            let top = self
+                .call_stack
+                .iter()
+                .find(|f| !f.immutable_stack.is_empty())
+                .map_or(&self.code, |f| &f.code);
+            match top.iter().position(|i| is_immutable_store(i, name)) {
+                Some(pc) => format!("global bound at {}", self.describe_site(TOP_LEVEL, top, pc)),
+                None => "global".to_string(),
+            }
+        } else {
+            match self.code[..self.pointer]
+                .iter()
+                .rposition(|i| is_immutable_store(i, name))
+            {
+                Some(pc) => format!("bound at {}", self.describe_site(&function, &self.code, pc)),
+                None => format!("parameter of {function}()"),
+            }
+        };
+        let message = format!("assignment at {store} shadows immutable `{name}` ({binding})");
+
+        match self.options.shadowing {
+            ShadowPolicy::Error => self.runtime_error(&message),
+            _ => {
+                if self.shadow_warnings.insert(message.clone()) {
+                    let _ = writeln!(self.stderr, "warning: {message}");
+                }
+            }
+        }
+    }
+}
+
+fn is_immutable_store(instr: &Instruction, name: &str) -> bool {
+    matches!(instr, Instruction::StoreImmutable(n) if n == name)
+}
+
+fn closest_name<'a>(name: &str, candidates: Vec<&'a str>) -> Option<&'a str> {
+    // Allow roughly one typo per three characters.
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|c| *c != name)
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= limit)
+        .min()
+        .map(|(_, c)| c)
+}
+
+// Optimal string alignment distance: like Levenshtein, but an adjacent
+// transposition (`pritn` / `print`) costs one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
 }
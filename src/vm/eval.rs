@@ -0,0 +1,168 @@
+//! Evaluating and compiling Reactive code from a running program
+//! (`std.eval`). Source is compiled with the stable bootstrap compiler, run
+//! in a VM of its own, so no native compiler is involved; bytecode text is
+//! loaded as is.
+
+use super::{VM, VmOptions};
+use crate::bytecode::{deserialize_instructions, is_bytecode, read_instructions_from_file};
+use crate::grammar::{Instruction, Type};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// The stable compiler's bytecode, relative to the working directory as for
+/// `reactive compile`.
+pub const STABLE_COMPILER: &str = "project/bootstrap/stable/compiler.rxb";
+
+/// Compiles the file `input` into bytecode at `output` with the bootstrap
+/// compiler `compiler`, calling its `entry` (`compile_file`, which requires
+/// `main`, or `compile_file_module`) in a fresh VM. The compiler's progress
+/// lines and error report go to `log`; a compile error is returned as its
+/// message.
+pub fn run_bootstrap_compiler(
+    compiler: Vec<Instruction>,
+    entry: &str,
+    input: &Path,
+    output: &Path,
+    log: Box<dyn Write>,
+) -> Result<(), String> {
+    let options = VmOptions {
+        unwind_on_error: true,
+        ..VmOptions::default()
+    };
+    let mut vm = VM::with_options(compiler, options);
+    vm.set_stdout_sink(log);
+    vm.catch_runtime_error(|vm| {
+        vm.run();
+        let input = vm.string_to_array(&input.to_string_lossy());
+        let output = vm.string_to_array(&output.to_string_lossy());
+        vm.call_global(entry, vec![input, output]);
+    })
+    .map_err(|error| error.message)
+}
+
+/// `STABLE_COMPILER`, read once per process.
+fn stable_compiler() -> Result<Vec<Instruction>, String> {
+    static COMPILER: OnceLock<Result<Vec<Instruction>, String>> = OnceLock::new();
+    COMPILER
+        .get_or_init(|| read_instructions_from_file(STABLE_COMPILER))
+        .clone()
+}
+
+impl VM {
+    /// Bytecode for `code`: RXB text is parsed, anything else is compiled as
+    /// a program (it must define `main`) through a file in the temp root.
+    fn eval_bytecode(&mut self, code: &str) -> Result<Vec<Instruction>, String> {
+        if is_bytecode(code.as_bytes()) {
+            return deserialize_instructions(code);
+        }
+        let compiler = stable_compiler()?;
+        let temp_error = |e: io::Error| format!("cannot create a temp file: {e}");
+        let input = self.temp_path("eval").map_err(temp_error)?;
+        let output = self.temp_path("eval").map_err(temp_error)?;
+        std::fs::write(&input, code).map_err(temp_error)?;
+        let compiled = run_bootstrap_compiler(
+            compiler,
+            "compile_file",
+            &input,
+            &output,
+            Box::new(io::sink()),
+        )
+        .and_then(|()| read_instructions_from_file(&output.to_string_lossy()));
+        let _ = std::fs::remove_file(&input);
+        let _ = std::fs::remove_file(&output);
+        compiled
+    }
+
+    /// Runs `code` in a fresh VM with this VM's options, writing to this VM's
+    /// stdout and stderr, and copies its result back.
+    fn eval_isolated(&mut self, code: Vec<Instruction>) -> Result<Type, String> {
+        let options = VmOptions {
+            unwind_on_error: true,
+            ..self.options.clone()
+        };
+        let mut child = VM::with_options(Vec::new(), options);
+        std::mem::swap(&mut child.stdout, &mut self.stdout);
+        std::mem::swap(&mut child.stderr, &mut self.stderr);
+        let result = child.catch_runtime_error(|vm| {
+            let v = vm.run_module("<eval>".to_string(), code);
+            let v = vm.force(v);
+            vm.export_value(v)
+                .map_err(|e| format!("cannot return the result: {e}"))
+        });
+        std::mem::swap(&mut child.stdout, &mut self.stdout);
+        std::mem::swap(&mut child.stderr, &mut self.stderr);
+        let message = result.map_err(|error| error.message)??;
+        Ok(self.import_value(message))
+    }
+
+    pub(crate) fn install_native_eval(&mut self) {
+        self.register_native("internal_eval", native_eval);
+        self.register_native("internal_compile", native_compile);
+    }
+}
+
+fn native_eval(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_eval expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let code = vm.value_to_string(args[0].clone(), "internal_eval code");
+    let isolated = vm.as_int(args[1].clone()) != 0;
+    let code = vm
+        .eval_bytecode(&code)
+        .unwrap_or_else(|e| vm.runtime_error(&format!("internal_eval: {e}")));
+    if isolated {
+        vm.eval_isolated(code)
+            .unwrap_or_else(|e| vm.runtime_error(&format!("internal_eval: {e}")))
+    } else {
+        vm.run_module("<eval>".to_string(), code)
+    }
+}
+
+/// `internal_compile(input, output, program)`: compiles the source file
+/// `input` with the stable compiler, as a program (requiring `main`) if
+/// `program` is non-zero and as a module otherwise. An empty `output` picks a
+/// file in the temp root. Returns `(1, output)`, or `(0, message)` if the
+/// compiler reported an error.
+fn native_compile(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 3 {
+        vm.runtime_error(&format!(
+            "internal_compile expects 3 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let input = vm.value_to_string(args[0].clone(), "internal_compile input");
+    let output = vm.value_to_string(args[1].clone(), "internal_compile output");
+    let entry = match vm.as_int(args[2].clone()) {
+        0 => "compile_file_module",
+        _ => "compile_file",
+    };
+    let output = if output.is_empty() {
+        vm.temp_path("compile")
+            .map(|path| path.with_extension("rxb"))
+            .unwrap_or_else(|e| vm.runtime_error(&format!("internal_compile: {e}")))
+    } else {
+        output.into()
+    };
+    let compiler =
+        stable_compiler().unwrap_or_else(|e| vm.runtime_error(&format!("internal_compile: {e}")));
+
+    let result = run_bootstrap_compiler(
+        compiler,
+        entry,
+        Path::new(&input),
+        &output,
+        Box::new(io::sink()),
+    );
+    let (status, text) = match result {
+        Ok(()) => (1, output.to_string_lossy().into_owned()),
+        Err(message) => (0, message),
+    };
+    let text = vm.string_to_array(&text);
+    Type::Tuple(vec![Type::Integer(status), text])
+}
@@ -1,21 +1,84 @@
-use super::VM;
-use crate::grammar::{CastType, Instruction, ReactiveExpr, Type};
+use super::call::module_of;
+use super::{MAX_OPERAND_STACK, OverflowPolicy, VM};
+use crate::grammar::{CastType, Instruction, Iter, ReactiveExpr, Type, Variant, Visibility};
 
 impl VM {
+    /// Executes until the frame that was current on entry returns. Calls made
+    /// from bytecode push a frame and continue in this loop rather than
+    /// recursing; at the outermost level, finishing a task resumes the next
+    /// queued one.
     pub fn run(&mut self) {
-        while self.pointer < self.code.len() {
+        // Each nested run (reactive evaluation, callbacks from natives) is
+        // Rust recursion, which must stop before the native stack does.
+        if self.run_depth >= self.options.max_call_depth {
+            let max = self.options.max_call_depth;
+            self.runtime_error(&format!(
+                "stack overflow: nested evaluation exceeds the maximum depth of {max} \
+                 (is a reactive value defined in terms of itself?)"
+            ));
+        }
+        self.run_depth += 1;
+        let mut base = self.call_stack.len();
+
+        loop {
+            if self.pointer >= self.code.len() {
+                if self.finish_frame(&mut base) {
+                    continue;
+                }
+                break;
+            }
+            if self.debugger.is_some() {
+                self.debugger_hook();
+            }
             let instr = self.code[self.pointer].clone();
 
+            if let Some(profile) = &mut self.ngram_profile {
+                profile.record(instr.opcode());
+            }
+            if self.tracer.is_some() {
+                self.trace_instruction(&instr);
+            }
+            if self.coverage.is_some() {
+                self.record_coverage();
+            }
+            if self.profiler.is_some() {
+                let function = self.current_function_name().to_string();
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.record_instruction(&function, instr.opcode());
+                }
+                self.sample_stack();
+            }
+
             match instr {
                 Instruction::Push(n) => self.stack.push(Type::Integer(n)),
                 Instruction::PushChar(c) => self.stack.push(Type::Char(c)),
-                Instruction::Load(name) => {
-                    let v = self.lookup_var(&name).cloned().unwrap_or_else(|| {
-                        self.runtime_error(&format!("undefined variable: {name}"))
-                    });
-
-                    let value = self.force(v);
-                    self.stack.push(value);
+                Instruction::PushNull => self.stack.push(Type::Null),
+                Instruction::Load(name) => self.exec_load(name),
+                Instruction::LoadLoad(a, b) => {
+                    self.exec_load(a);
+                    self.exec_load(b);
+                }
+                Instruction::LoadPush(name, n) => {
+                    self.exec_load(name);
+                    self.stack.push(Type::Integer(n));
+                }
+                Instruction::LoadField(name, field) => {
+                    self.exec_load(name);
+                    self.exec_field_get(field);
+                }
+                Instruction::Dup => {
+                    let v = self.pop();
+                    self.stack.push(v.clone());
+                    self.stack.push(v);
+                }
+                Instruction::Swap => {
+                    let a = self.pop();
+                    let b = self.pop();
+                    self.stack.push(a);
+                    self.stack.push(b);
+                }
+                Instruction::Drop => {
+                    self.pop();
                 }
                 Instruction::Store(name) => self.exec_store(name),
                 Instruction::StoreImmutable(name) => self.exec_store_immutable(name),
@@ -25,14 +88,25 @@ impl VM {
                 Instruction::Mul => self.exec_mul(),
                 Instruction::Div => self.exec_div(),
                 Instruction::Modulo => self.exec_modulo(),
-                Instruction::Greater => self.exec_cmp(|b, a| (b > a) as i32),
-                Instruction::Less => self.exec_cmp(|b, a| (b < a) as i32),
-                Instruction::Equal => self.exec_cmp(|b, a| (b == a) as i32),
-                Instruction::NotEqual => self.exec_cmp(|b, a| (b != a) as i32),
-                Instruction::GreaterEqual => self.exec_cmp(|b, a| (b >= a) as i32),
-                Instruction::LessEqual => self.exec_cmp(|b, a| (b <= a) as i32),
-                Instruction::And => self.exec_cmp(|b, a| ((b > 0) && (a > 0)) as i32),
-                Instruction::Or => self.exec_cmp(|b, a| ((b > 0) || (a > 0)) as i32),
+                Instruction::Neg => self.exec_neg(),
+                Instruction::Greater => self.exec_cmp("Greater", |b, a| (b > a) as i64),
+                Instruction::Less => self.exec_cmp("Less", |b, a| (b < a) as i64),
+                Instruction::Equal => self.exec_eq(true),
+                Instruction::NotEqual => self.exec_eq(false),
+                Instruction::GreaterEqual => self.exec_cmp("GreaterEqual", |b, a| (b >= a) as i64),
+                Instruction::LessEqual => self.exec_cmp("LessEqual", |b, a| (b <= a) as i64),
+                Instruction::And => self.exec_logic(|b, a| ((b > 0) && (a > 0)) as i64),
+                Instruction::Or => self.exec_logic(|b, a| ((b > 0) || (a > 0)) as i64),
+                Instruction::Not => {
+                    let a = self.pop_truth();
+                    self.stack.push(Type::Integer((a == 0) as i64));
+                }
+                Instruction::DeepEqual => {
+                    let a = self.pop();
+                    let b = self.pop();
+                    let eq = self.deep_equal(b, a);
+                    self.stack.push(Type::Integer(eq as i64));
+                }
                 Instruction::Print => {
                     let v = self.pop();
                     self.print_value(v, false);
@@ -41,12 +115,30 @@ impl VM {
                     let v = self.pop();
                     self.print_value(v, true);
                 }
+                Instruction::EPrint => {
+                    let v = self.pop();
+                    self.eprint_value(v, false);
+                }
+                Instruction::EPrintln => {
+                    let v = self.pop();
+                    self.eprint_value(v, true);
+                }
                 Instruction::Assert => {
                     let v = self.pop_int();
                     if v == 0 {
                         self.runtime_error("assertion failed");
                     }
                 }
+                Instruction::AssertEq => {
+                    let actual = self.pop();
+                    let expected = self.pop();
+                    self.assert_compare(expected, actual, true);
+                }
+                Instruction::AssertNe => {
+                    let actual = self.pop();
+                    let expected = self.pop();
+                    self.assert_compare(expected, actual, false);
+                }
                 Instruction::Error(message) => {
                     self.runtime_error(&message);
                 }
@@ -56,19 +148,43 @@ impl VM {
                 Instruction::StoreIndexReactive(name, expr) => {
                     self.exec_store_index_reactive(name, expr)
                 }
-                Instruction::StoreFunction(name, params, body) => {
-                    self.global_env
-                        .insert(name, Type::Function { params, code: body });
+                Instruction::StoreFunction(name, params, body, visibility) => {
+                    self.set_visibility(&name, visibility);
+                    let function = Type::Function {
+                        params,
+                        code: body,
+                        module: module_of(&name).to_string(),
+                    };
+                    self.global_env.insert(name, function);
                 }
-                Instruction::Call(name, argc) => self.exec_call(name, argc),
-                Instruction::StoreStruct(name, fields) => {
+                Instruction::Call(name, argc) => {
+                    if self.exec_call(name, argc) {
+                        continue;
+                    }
+                }
+                Instruction::Spawn(name, argc) => self.exec_spawn(name, argc),
+                Instruction::Yield => {
+                    self.pointer += 1;
+                    self.exec_yield(&mut base);
+                    continue;
+                }
+                Instruction::StoreStruct(name, fields, visibility) => {
+                    // only private struct names are qualified with their module
+                    let module = match visibility {
+                        Visibility::Private => Some(module_of(&name).to_string()),
+                        Visibility::Public => self.current_module().map(str::to_string),
+                    };
+                    self.set_visibility(&name, visibility);
+                    self.struct_modules.insert(name.clone(), module);
                     self.struct_defs.insert(name, fields);
                 }
+                Instruction::ExtendStruct(name, parent) => self.exec_extend_struct(name, parent),
                 Instruction::NewStruct(name) => {
+                    self.check_visibility(&name, "struct");
                     let def = self.struct_defs.get(&name).cloned().unwrap_or_else(|| {
                         self.runtime_error(&format!("unknown struct type `{name}`"))
                     });
-                    let inst = self.instantiate_struct(def);
+                    let inst = self.instantiate_struct(name, def);
                     self.stack.push(inst);
                 }
                 Instruction::FieldGet(field) => self.exec_field_get(field),
@@ -101,7 +217,7 @@ impl VM {
                     continue;
                 }
                 Instruction::JumpIfZero(label) => {
-                    let n = self.pop_int();
+                    let n = self.pop_truth();
                     if n == 0 {
                         self.pointer = *self.labels.get(&label).unwrap_or_else(|| {
                             self.runtime_error(&format!("unknown label `{label}`"))
@@ -109,8 +225,138 @@ impl VM {
                         continue;
                     }
                 }
-                Instruction::Return => return,
+                // Loaded code runs switches as `JumpTable`s (see
+                // `jumptable::resolve_switches`); these arms are the slow path
+                // for code that skipped loading.
+                Instruction::Switch(cases, default) => {
+                    let n = self.pop_arith_operand("Switch");
+                    let label = cases
+                        .iter()
+                        .find(|(value, _)| Some(*value) == n)
+                        .map_or(&default, |(_, label)| label);
+                    self.pointer = *self
+                        .labels
+                        .get(label)
+                        .unwrap_or_else(|| self.runtime_error(&format!("unknown label `{label}`")));
+                    continue;
+                }
+                Instruction::JumpRel(offset) => {
+                    self.jump_by(offset);
+                    continue;
+                }
+                Instruction::JumpIfZeroRel(offset) => {
+                    if self.pop_truth() == 0 {
+                        self.jump_by(offset);
+                        continue;
+                    }
+                }
+                Instruction::SwitchRel(cases, default) => {
+                    let n = self.pop_arith_operand("Switch");
+                    let offset = cases
+                        .iter()
+                        .find(|(value, _)| Some(*value) == n)
+                        .map_or(default, |(_, offset)| *offset);
+                    self.jump_by(offset);
+                    continue;
+                }
+                Instruction::JumpTable(table) => {
+                    let offset = match self.pop_arith_operand("Switch") {
+                        Some(n) => table.offset(n),
+                        None => table.default,
+                    };
+                    self.jump_by(offset);
+                    continue;
+                }
+                Instruction::IterRange => {
+                    let end = self.pop_int();
+                    let start = self.pop_int();
+                    self.stack
+                        .push(Type::Iter(Box::new(Iter::Range { next: start, end })));
+                }
+                Instruction::IterNew => {
+                    let v = self.pop();
+                    let iter = match self.force(v) {
+                        Type::ArrayRef(id) => Iter::Array { id, index: 0 },
+                        Type::VecRef(id) => Iter::Vec { id, index: 0 },
+                        other => self.runtime_error(&format!(
+                            "IterNew expects array or vec, found {}",
+                            self.format_value(&other)
+                        )),
+                    };
+                    self.stack.push(Type::Iter(Box::new(iter)));
+                }
+                Instruction::IterNext(label) => {
+                    if let Some(item) = self.iter_next() {
+                        self.stack.push(item);
+                    } else {
+                        self.pop();
+                        self.pointer = *self.labels.get(&label).unwrap_or_else(|| {
+                            self.runtime_error(&format!("unknown label `{label}`"))
+                        });
+                        continue;
+                    }
+                }
+                Instruction::IterNextRel(offset) => {
+                    if let Some(item) = self.iter_next() {
+                        self.stack.push(item);
+                    } else {
+                        self.pop();
+                        self.jump_by(offset);
+                        continue;
+                    }
+                }
+                Instruction::Return => {
+                    if self.finish_frame(&mut base) {
+                        continue;
+                    }
+                    break;
+                }
                 Instruction::ArrayLValue => self.exec_array_lvalue(),
+                Instruction::ArraySlice => {
+                    let end = self.pop();
+                    let start = self.pop();
+                    let array = self.pop();
+                    let slice = self.array_slice(array, start, end);
+                    self.stack.push(slice);
+                }
+                Instruction::ArrayConcat => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let joined = self.array_concat(a, b);
+                    self.stack.push(joined);
+                }
+                Instruction::DeepCopy => {
+                    let v = self.pop();
+                    let v = self.force(v);
+                    let copy = self.deep_copy(v);
+                    self.stack.push(copy);
+                }
+                Instruction::MakeTuple(n) => {
+                    let elems = self.pop_n(n);
+                    self.stack.push(Type::Tuple(elems));
+                }
+                Instruction::TupleGet(i) => {
+                    let v = self.pop();
+                    let elem = self.tuple_get(v, i);
+                    self.stack.push(elem);
+                }
+                Instruction::StoreEnum(name, variants) => {
+                    self.enum_defs.insert(name, variants);
+                }
+                Instruction::NewVariant(enum_name, name) => self.exec_new_variant(enum_name, name),
+                Instruction::MatchVariant(arms, default) => {
+                    let label = self.exec_match_variant(&arms, default);
+                    self.pointer = *self
+                        .labels
+                        .get(&label)
+                        .unwrap_or_else(|| self.runtime_error(&format!("unknown label `{label}`")));
+                    continue;
+                }
+                Instruction::MatchVariantRel(arms, default) => {
+                    let offset = self.exec_match_variant(&arms, default);
+                    self.jump_by(offset);
+                    continue;
+                }
                 Instruction::FieldLValue(field) => self.exec_field_lvalue(field),
                 Instruction::StoreThrough => self.exec_store_through(),
                 Instruction::StoreThroughReactive(expr) => self.exec_store_through_reactive(expr),
@@ -120,28 +366,164 @@ impl VM {
                     if !self.imported_modules.contains(&module_name) {
                         self.imported_modules.insert(module_name.clone());
                         self.import_module(path);
+                        if let Some(code) = self.archive_modules.get(&module_name).cloned() {
+                            crate::verbose!("import {module_name}: running archived module");
+                            self.run_module(module_name, code);
+                        }
                     }
                 }
                 Instruction::Cast(target) => {
                     let v = self.pop();
                     match target {
                         CastType::Int => {
-                            let n = self.as_int(v);
+                            let n = self.cast_int(v);
                             self.stack.push(Type::Integer(n));
                         }
                         CastType::Char => {
-                            let n = self.as_int(v);
-                            if n < 0 || n > 0x10FFFF {
+                            let n = self.cast_int(v);
+                            if !(0..=0x10FFFF).contains(&n) {
                                 self.runtime_error(&format!("invalid char code {}", n));
                             }
                             self.stack.push(Type::Char(n as u32));
                         }
                     }
                 }
+                Instruction::IsNull => {
+                    let v = self.pop();
+                    let v = self.force(v);
+                    self.stack
+                        .push(Type::Integer(matches!(v, Type::Null) as i64));
+                }
+                Instruction::Interp(n) => {
+                    let parts = self.pop_args(n);
+                    let mut text = String::new();
+                    for part in parts {
+                        text.push_str(&self.render_printable(part));
+                    }
+                    let s = self.string_to_array(&text);
+                    self.stack.push(s);
+                }
+                Instruction::TypeOf => {
+                    let v = self.pop();
+                    let name = self.type_name(v);
+                    let tag = self.string_to_array(&name);
+                    self.stack.push(tag);
+                }
             }
 
+            if self.stack.len() > MAX_OPERAND_STACK {
+                self.stack_error(&format!(
+                    "operand stack overflow: more than {MAX_OPERAND_STACK} values"
+                ));
+            }
+            self.pointer += 1;
+        }
+
+        self.run_depth -= 1;
+    }
+
+    /// Handles the end of the current function body. Returns `false` when
+    /// `run` should return: the frame it was entered for is done, and (at the
+    /// outermost level) no other task is waiting.
+    fn finish_frame(&mut self, base: &mut usize) -> bool {
+        if self.call_stack.len() > *base {
+            let ret = self.pop_frame();
+            self.stack.push(ret);
             self.pointer += 1;
+            return true;
         }
+        self.run_depth == 1 && self.finish_task(base)
+    }
+
+    fn exec_new_variant(&mut self, enum_name: String, name: String) {
+        let arity = self
+            .enum_defs
+            .get(&enum_name)
+            .unwrap_or_else(|| self.runtime_error(&format!("unknown enum `{enum_name}`")))
+            .iter()
+            .find(|(variant, _)| *variant == name)
+            .map(|(_, arity)| *arity)
+            .unwrap_or_else(|| {
+                self.runtime_error(&format!("enum `{enum_name}` has no variant `{name}`"))
+            });
+        let payload = self.pop_n(arity);
+        self.stack.push(Type::Variant(Box::new(Variant {
+            enum_name,
+            name,
+            payload,
+        })));
+    }
+
+    /// Moves to the instruction `offset` away from the current one, for the
+    /// label-free jumps of RXB2 code. The end of the body is a valid target.
+    fn jump_by(&mut self, offset: i64) {
+        self.pointer = isize::try_from(offset)
+            .ok()
+            .and_then(|offset| self.pointer.checked_add_signed(offset))
+            .filter(|&target| target <= self.code.len())
+            .unwrap_or_else(|| {
+                self.runtime_error(&format!(
+                    "jump offset {offset} at instruction {} leaves the code body",
+                    self.pointer
+                ))
+            });
+    }
+
+    /// Pops a variant and picks the target (label or offset) of the arm
+    /// naming it, pushing the payload for that arm; falls back to `default`
+    /// with nothing pushed.
+    fn exec_match_variant<T: Clone>(&mut self, arms: &[(String, T)], default: T) -> T {
+        let v = self.pop();
+        let variant = match self.force(v) {
+            Type::Variant(variant) => variant,
+            other => self.runtime_error(&format!(
+                "MatchVariant expects variant, found {}",
+                self.format_value(&other)
+            )),
+        };
+        match arms.iter().find(|(name, _)| *name == variant.name) {
+            Some((_, label)) => {
+                self.stack.extend(variant.payload);
+                label.clone()
+            }
+            None => default,
+        }
+    }
+
+    /// Advances the iterator on top of the stack in place. Array and vec
+    /// lengths are re-read each step, so elements pushed mid-loop are visited.
+    fn iter_next(&mut self) -> Option<Type> {
+        let Some(Type::Iter(iter)) = self.stack.last_mut() else {
+            self.runtime_error("IterNext expects an iterator on top of the stack");
+        };
+        let (source, index) = match iter.as_mut() {
+            Iter::Range { next, end } => {
+                if *next >= *end {
+                    return None;
+                }
+                let item = Type::Integer(*next);
+                *next += 1;
+                return Some(item);
+            }
+            Iter::Array { id, index } => (Type::ArrayRef(*id), index),
+            Iter::Vec { id, index } => (Type::VecRef(*id), index),
+        };
+        let i = *index;
+        *index += 1;
+        let item = match source {
+            Type::ArrayRef(id) => self.array_heap[id].get(i).cloned(),
+            Type::VecRef(id) => self.vec_heap[id].get(i).cloned(),
+            _ => unreachable!(),
+        }?;
+        Some(self.force(item))
+    }
+
+    fn exec_load(&mut self, name: String) {
+        self.check_visibility(&name, "function");
+        let v = self.resolve_var(&name);
+
+        let value = self.force(v);
+        self.stack.push(value);
     }
 
     // =========================================================
@@ -149,6 +531,7 @@ impl VM {
     // =========================================================
     fn exec_store(&mut self, name: String) {
         self.ensure_mutable_binding(&name);
+        self.check_shadowing(&name);
         let v = self.pop();
         match &mut self.local_env {
             Some(env) => {
@@ -174,8 +557,9 @@ impl VM {
 
     fn exec_store_reactive(&mut self, name: String, expr: ReactiveExpr) {
         self.ensure_mutable_binding(&name);
+        self.check_shadowing(&name);
         let captured = self.capture_immutables(&expr.captures);
-        let value = Type::LazyValue(expr, captured);
+        let value = self.lazy_value(expr, captured);
 
         match &mut self.local_env {
             Some(env) => {
@@ -192,40 +576,231 @@ impl VM {
     // =========================================================
 
     fn exec_add(&mut self) {
-        let a = self.pop_int();
-        let b = self.pop_int();
-        self.stack.push(Type::Integer(b + a));
+        let a = self.pop();
+        let b = self.pop();
+        let a = self.force(a);
+        let b = self.force(b);
+        if let Some(chars) = self.string_concat(&b, &a) {
+            let id = self.alloc_array(chars, std::collections::HashSet::new());
+            self.stack.push(Type::ArrayRef(id));
+            return;
+        }
+        if self.is_string(&b) && matches!(a, Type::Integer(_))
+            || matches!(b, Type::Integer(_)) && self.is_string(&a)
+        {
+            self.runtime_error("type error: cannot add a string and an int");
+        }
+        self.stack.push(b);
+        self.stack.push(a);
+        self.exec_arith("Add", i64::checked_add, i64::wrapping_add);
+    }
+
+    /// `Add` on strings: string + string, string + char and char + string
+    /// build a new string. Arrays that hold non-chars keep the integer
+    /// meaning (their length).
+    fn string_concat(&mut self, b: &Type, a: &Type) -> Option<Vec<Type>> {
+        if !matches!(b, Type::ArrayRef(_) | Type::VecRef(_))
+            && !matches!(a, Type::ArrayRef(_) | Type::VecRef(_))
+        {
+            return None;
+        }
+        let mut out = self.string_chars(b)?;
+        out.extend(self.string_chars(a)?);
+        Some(out)
+    }
+
+    /// Shared by `AssertEq`/`AssertNe` and the `assert_eq`/`assert_ne` natives.
+    pub(crate) fn assert_compare(&mut self, expected: Type, actual: Type, want_equal: bool) {
+        if self.deep_equal(expected.clone(), actual.clone()) == want_equal {
+            return;
+        }
+        let expected = self.force(expected);
+        let expected = self.format_value(&expected);
+        if want_equal {
+            let actual = self.force(actual);
+            let actual = self.format_value(&actual);
+            self.runtime_error(&format!(
+                "assertion failed: expected `{expected}`, got `{actual}`"
+            ));
+        }
+        self.runtime_error(&format!(
+            "assertion failed: expected a value other than `{expected}`"
+        ));
+    }
+
+    // a non-empty array or vec of chars
+    fn is_string(&mut self, v: &Type) -> bool {
+        matches!(v, Type::ArrayRef(_) | Type::VecRef(_))
+            && self.string_chars(v).is_some_and(|chars| !chars.is_empty())
+    }
+
+    fn string_chars(&mut self, v: &Type) -> Option<Vec<Type>> {
+        let elems = match v {
+            Type::Char(c) => return Some(vec![Type::Char(*c)]),
+            Type::ArrayRef(id) => self.array_heap[*id].clone(),
+            Type::VecRef(id) => self.vec_heap[*id].clone(),
+            _ => return None,
+        };
+        elems
+            .into_iter()
+            .map(|e| match self.force(e) {
+                c @ Type::Char(_) => Some(c),
+                _ => None,
+            })
+            .collect()
     }
 
     fn exec_sub(&mut self) {
-        let a = self.pop_int();
-        let b = self.pop_int();
-        self.stack.push(Type::Integer(b - a));
+        self.exec_arith("Sub", i64::checked_sub, i64::wrapping_sub);
     }
 
     fn exec_modulo(&mut self) {
-        let a = self.pop_int();
-        let b = self.pop_int();
-        self.stack.push(Type::Integer(b % a));
+        self.exec_division("Modulo", i64::checked_rem, i64::wrapping_rem);
     }
+
     fn exec_mul(&mut self) {
-        let a = self.pop_int();
-        let b = self.pop_int();
-        self.stack.push(Type::Integer(b * a));
+        self.exec_arith("Mul", i64::checked_mul, i64::wrapping_mul);
     }
 
     fn exec_div(&mut self) {
-        let a = self.pop_int();
-        if a == 0 {
-            self.runtime_error("division by zero");
+        self.exec_division("Div", i64::checked_div, i64::wrapping_div);
+    }
+
+    /// `Div` / `Modulo`: like `exec_arith`, but a zero divisor is a runtime
+    /// error (even with a null dividend) rather than a Rust panic.
+    fn exec_division(
+        &mut self,
+        op: &str,
+        checked: fn(i64, i64) -> Option<i64>,
+        wrapping: fn(i64, i64) -> i64,
+    ) {
+        let a = self.pop_arith_operand(op);
+        let b = self.pop_arith_operand(op);
+        if a == Some(0) {
+            let (what, symbol) = match op {
+                "Div" => ("division", '/'),
+                _ => ("modulo", '%'),
+            };
+            let dividend = b.map_or("null".to_string(), |b| b.to_string());
+            let mut message = format!("{what} by zero: {dividend} {symbol} 0");
+            if let Some(site) = self.source_site() {
+                message.push_str(&format!(" at {site}"));
+            }
+            self.runtime_error(&message);
+        }
+        let (Some(b), Some(a)) = (b, a) else {
+            self.stack.push(Type::Null);
+            return;
+        };
+        let n = self.apply_overflow_policy(op, b, a, checked, wrapping);
+        self.stack.push(Type::Integer(n));
+    }
+
+    fn exec_neg(&mut self) {
+        let Some(a) = self.pop_arith_operand("Neg") else {
+            self.stack.push(Type::Null);
+            return;
+        };
+        let n = match self.options.overflow {
+            OverflowPolicy::Wrapping => a.wrapping_neg(),
+            OverflowPolicy::Checked => a
+                .checked_neg()
+                .unwrap_or_else(|| self.runtime_error(&format!("integer overflow: Neg of {a}"))),
+        };
+        self.stack.push(Type::Integer(n));
+    }
+
+    fn exec_arith(
+        &mut self,
+        op: &str,
+        checked: fn(i64, i64) -> Option<i64>,
+        wrapping: fn(i64, i64) -> i64,
+    ) {
+        let a = self.pop_arith_operand(op);
+        let b = self.pop_arith_operand(op);
+        let (Some(b), Some(a)) = (b, a) else {
+            self.stack.push(Type::Null);
+            return;
+        };
+        let n = self.apply_overflow_policy(op, b, a, checked, wrapping);
+        self.stack.push(Type::Integer(n));
+    }
+
+    pub(crate) fn apply_overflow_policy(
+        &mut self,
+        op: &str,
+        b: i64,
+        a: i64,
+        checked: fn(i64, i64) -> Option<i64>,
+        wrapping: fn(i64, i64) -> i64,
+    ) -> i64 {
+        match self.options.overflow {
+            OverflowPolicy::Wrapping => wrapping(b, a),
+            OverflowPolicy::Checked => checked(b, a).unwrap_or_else(|| {
+                self.runtime_error(&format!("integer overflow: {op} of {b} and {a}"))
+            }),
+        }
+    }
+
+    fn exec_cmp<F: FnOnce(i64, i64) -> i64>(&mut self, op: &str, f: F) {
+        let (b, a) = self.pop_compared(op);
+        let a = self.as_int(a);
+        let b = self.as_int(b);
+        self.stack.push(Type::Integer(f(b, a)));
+    }
+
+    // null is only equal to null; everything else compares as ints
+    fn exec_eq(&mut self, equal: bool) {
+        let op = if equal { "Equal" } else { "NotEqual" };
+        let (b, a) = self.pop_compared(op);
+        let a = (!matches!(a, Type::Null)).then(|| self.as_int(a));
+        let b = (!matches!(b, Type::Null)).then(|| self.as_int(b));
+        let same = match (b, a) {
+            (Some(b), Some(a)) => b == a,
+            (None, None) => true,
+            _ => false,
+        };
+        self.stack.push(Type::Integer((same == equal) as i64));
+    }
+
+    /// Pops an arithmetic operand like `pop_int_or_null`; in strict mode a
+    /// char must be cast to int first.
+    fn pop_arith_operand(&mut self, op: &str) -> Option<i64> {
+        let v = self.pop();
+        match self.force(v) {
+            Type::Null => None,
+            Type::Char(c) if self.options.strict => self.runtime_error(&format!(
+                "strict: char {:?} used in {op}; write `(int) c` for its code",
+                char::from_u32(c).unwrap_or('?')
+            )),
+            v => Some(self.as_int(v)),
+        }
+    }
+
+    /// Pops and forces the two operands of a comparison (deeper first); in
+    /// strict mode a char may only be compared with a char.
+    fn pop_compared(&mut self, op: &str) -> (Type, Type) {
+        let a = self.pop();
+        let b = self.pop();
+        let a = self.force(a);
+        let b = self.force(b);
+        if self.options.strict
+            && matches!(
+                (&b, &a),
+                (Type::Char(_), Type::Integer(_)) | (Type::Integer(_), Type::Char(_))
+            )
+        {
+            self.runtime_error(&format!(
+                "strict: {op} compares a char with an int; cast the char with `(int)`"
+            ));
         }
-        let b = self.pop_int();
-        self.stack.push(Type::Integer(b / a));
+        (b, a)
     }
 
-    fn exec_cmp<F: FnOnce(i32, i32) -> i32>(&mut self, f: F) {
-        let a = self.pop_int();
-        let b = self.pop_int();
+    // null counts as false in And / Or
+    fn exec_logic<F: FnOnce(i64, i64) -> i64>(&mut self, f: F) {
+        let a = self.pop_truth();
+        let b = self.pop_truth();
         self.stack.push(Type::Integer(f(b, a)));
     }
 }
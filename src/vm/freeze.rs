@@ -0,0 +1,136 @@
+use super::VM;
+use crate::grammar::Type;
+
+/// A heap object that `internal_freeze` can lock against changes to its
+/// shape (push, insert, clear, free, ...); single elements and fields are
+/// locked through the existing immutables sets instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Frozen {
+    Struct(usize),
+    Array(usize),
+    Vec(usize),
+    Buffer(usize),
+    Bytes(usize),
+    Set(usize),
+    Pq(usize),
+}
+
+impl Frozen {
+    /// The heap object `v` refers to, if any.
+    pub(crate) fn of(v: &Type) -> Option<Frozen> {
+        match *v {
+            Type::StructRef(id) => Some(Frozen::Struct(id)),
+            Type::ArrayRef(id) => Some(Frozen::Array(id)),
+            Type::VecRef(id) => Some(Frozen::Vec(id)),
+            Type::BufferRef(id) => Some(Frozen::Buffer(id)),
+            Type::BytesRef(id) => Some(Frozen::Bytes(id)),
+            Type::SetRef(id) => Some(Frozen::Set(id)),
+            Type::PqRef(id) => Some(Frozen::Pq(id)),
+            _ => None,
+        }
+    }
+
+    fn kind(self) -> &'static str {
+        match self {
+            Frozen::Struct(_) => "struct",
+            Frozen::Array(_) => "array",
+            Frozen::Vec(_) => "vec",
+            Frozen::Buffer(_) => "buffer",
+            Frozen::Bytes(_) => "bytes",
+            Frozen::Set(_) => "set",
+            Frozen::Pq(_) => "priority queue",
+        }
+    }
+}
+
+impl VM {
+    /// Makes `v` and everything reachable from it immutable. Reactive fields
+    /// and elements stay reactive; they are frozen as relationships.
+    pub(crate) fn freeze(&mut self, v: Type) {
+        let mut pending = vec![v];
+        while let Some(v) = pending.pop() {
+            let object = match v {
+                Type::Tuple(elems) => {
+                    pending.extend(elems);
+                    continue;
+                }
+                Type::Variant(variant) => {
+                    pending.extend(variant.payload);
+                    continue;
+                }
+                Type::LazyValue(_, captured, _) => {
+                    pending.extend(captured.into_values());
+                    continue;
+                }
+                v => match Frozen::of(&v) {
+                    Some(object) => object,
+                    None => continue,
+                },
+            };
+            self.ensure_not_busy(object, "internal_freeze");
+            if !self.frozen.insert(object) {
+                continue;
+            }
+            match object {
+                Frozen::Struct(id) => {
+                    let inst = &mut self.heap[id];
+                    inst.immutables.extend(inst.fields.keys().cloned());
+                    pending.extend(inst.fields.values().cloned());
+                }
+                Frozen::Array(id) => {
+                    self.array_immutables[id].extend(0..self.array_heap[id].len());
+                    pending.extend(self.array_heap[id].iter().cloned());
+                }
+                Frozen::Vec(id) => {
+                    self.vec_immutables[id].extend(0..self.vec_heap[id].len());
+                    pending.extend(self.vec_heap[id].iter().cloned());
+                }
+                Frozen::Set(id) => pending.extend(self.set_heap[id].values().cloned()),
+                Frozen::Pq(id) => pending.extend(self.pq_heap[id].items.iter().cloned()),
+                Frozen::Buffer(_) | Frozen::Bytes(_) => {}
+            }
+        }
+    }
+
+    pub(crate) fn is_frozen(&self, object: Frozen) -> bool {
+        self.frozen.contains(&object)
+    }
+
+    /// Raises a runtime error if `object` was frozen or is busy; `what`
+    /// names the operation attempting to change it.
+    pub(crate) fn ensure_not_frozen(&self, object: Frozen, what: &str) {
+        if self.is_frozen(object) {
+            self.runtime_error(&format!("{what}: cannot modify a frozen {}", object.kind()));
+        }
+        self.ensure_not_busy(object, what);
+    }
+
+    /// Raises a runtime error if a native is working on `object` (see
+    /// `while_busy`).
+    pub(crate) fn ensure_not_busy(&self, object: Frozen, what: &str) {
+        if let Some(owner) = self.busy.get(&object) {
+            self.runtime_error(&format!(
+                "{what}: cannot modify a {} while {owner} is using it",
+                object.kind()
+            ));
+        }
+    }
+
+    /// Runs `f` with `object` marked busy for `owner`, so the callbacks `f`
+    /// makes cannot change it under the native (`ensure_not_frozen` and
+    /// element stores fail).
+    pub(crate) fn while_busy<T>(
+        &mut self,
+        object: Frozen,
+        owner: &'static str,
+        f: impl FnOnce(&mut VM) -> T,
+    ) -> T {
+        let outer = self.busy.insert(object, owner);
+        let result = f(self);
+        match outer {
+            Some(outer) => self.busy.insert(object, outer),
+            None => self.busy.remove(&object),
+        };
+        result
+    }
+}
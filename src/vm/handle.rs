@@ -0,0 +1,152 @@
+use super::VM;
+use super::worker::{Channel, WorkerResult};
+use std::fs::File;
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// An OS resource owned by the VM and referenced from programs through
+/// `Type::HandleRef`.
+pub(crate) enum Resource {
+    File(File),
+    Channel(Arc<Channel>),
+    // `None` once joined
+    Thread(Option<JoinHandle<WorkerResult>>),
+    UdpSocket(UdpSocket),
+    #[cfg(feature = "websocket")]
+    WebSocket(Box<super::native::WebSocket>),
+}
+
+impl Resource {
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Resource::File(_) => "file",
+            Resource::Channel(_) => "channel",
+            Resource::Thread(_) => "thread",
+            Resource::UdpSocket(_) => "udp socket",
+            #[cfg(feature = "websocket")]
+            Resource::WebSocket(_) => "websocket",
+        }
+    }
+}
+
+enum HandleSlot {
+    Open(Resource),
+    Closed(&'static str),
+}
+
+/// Unified lifecycle table for OS resources.
+///
+/// Handles are never reused, so a stale handle always reports a
+/// double-close or use-after-close instead of touching another resource.
+/// Everything still open is released when the VM is dropped.
+#[derive(Default)]
+pub(crate) struct HandleTable {
+    slots: Vec<HandleSlot>,
+}
+
+impl HandleTable {
+    pub(crate) fn insert(&mut self, resource: Resource) -> usize {
+        let id = self.slots.len();
+        self.slots.push(HandleSlot::Open(resource));
+        id
+    }
+
+    pub(crate) fn close_all(&mut self) {
+        for slot in &mut self.slots {
+            if let HandleSlot::Open(resource) = slot {
+                *slot = HandleSlot::Closed(resource.kind());
+            }
+        }
+    }
+}
+
+/// Per-VM scratch directory backing `internal_temp_file` / `internal_temp_dir`.
+///
+/// Created under the OS temp dir on first use; it is removed, with everything
+/// inside it, when the VM is dropped or stops on a runtime error.
+#[derive(Default)]
+pub(crate) struct TempRoot {
+    dir: Option<PathBuf>,
+    next: usize,
+}
+
+impl TempRoot {
+    pub(crate) fn remove(&self) {
+        if let Some(dir) = &self.dir {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+impl VM {
+    /// Reserves a fresh, not-yet-created path inside the VM's temp root.
+    pub(crate) fn temp_path(&mut self, prefix: &str) -> std::io::Result<PathBuf> {
+        let root = match &self.temp_root.dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let suffix = crate::hash::to_hex(&super::native::os_random_bytes(8));
+                let dir =
+                    std::env::temp_dir().join(format!("reactive-{}-{suffix}", std::process::id()));
+                std::fs::create_dir(&dir)?;
+                self.temp_root.dir = Some(dir.clone());
+                dir
+            }
+        };
+        let id = self.temp_root.next;
+        self.temp_root.next += 1;
+        Ok(root.join(format!("{prefix}{id}")))
+    }
+
+    pub(crate) fn handle_mut(&mut self, id: usize, what: &str) -> &mut Resource {
+        match self.handles.slots.get(id) {
+            Some(HandleSlot::Open(_)) => {}
+            Some(HandleSlot::Closed(kind)) => {
+                self.runtime_error(&format!("{what}: use of closed {kind} handle {id}"))
+            }
+            None => self.runtime_error(&format!("{what}: invalid handle {id}")),
+        }
+        match &mut self.handles.slots[id] {
+            HandleSlot::Open(resource) => resource,
+            HandleSlot::Closed(_) => unreachable!(),
+        }
+    }
+
+    /// Like `handle_mut`, but the resource must be of kind `kind`.
+    pub(crate) fn handle_of_kind(&mut self, id: usize, kind: &str, what: &str) -> &mut Resource {
+        let found = self.handle_mut(id, what).kind();
+        if found != kind {
+            self.runtime_error(&format!(
+                "{what} expects a {kind} handle, found a {found} handle"
+            ));
+        }
+        self.handle_mut(id, what)
+    }
+
+    pub(crate) fn open_resource(&self, id: usize) -> Option<&Resource> {
+        match self.handles.slots.get(id) {
+            Some(HandleSlot::Open(resource)) => Some(resource),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn close_handle(&mut self, id: usize) {
+        let kind = match self.handles.slots.get(id) {
+            Some(HandleSlot::Open(resource)) => resource.kind(),
+            Some(HandleSlot::Closed(kind)) => {
+                self.runtime_error(&format!("double close of {kind} handle {id}"))
+            }
+            None => self.runtime_error(&format!("cannot close invalid handle {id}")),
+        };
+        // Replacing the slot drops the resource, releasing the OS handle.
+        self.handles.slots[id] = HandleSlot::Closed(kind);
+    }
+}
+
+impl Drop for VM {
+    fn drop(&mut self) {
+        self.handles.close_all();
+        self.temp_root.remove();
+    }
+}
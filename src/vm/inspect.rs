@@ -0,0 +1,186 @@
+use super::VM;
+use super::trace::summarize;
+use crate::grammar::{Iter, LValue, Type};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum HeapId {
+    Struct(usize),
+    Array(usize),
+    Vec(usize),
+    Buffer(usize),
+    Bytes(usize),
+    Set(usize),
+    Pq(usize),
+}
+
+impl VM {
+    /// Human-readable listing of every heap object with its inbound reference
+    /// count and whether it is still reachable from the stack or an environment.
+    pub fn heap_dump(&self) -> String {
+        let mut refs: HashMap<HeapId, usize> = HashMap::new();
+        let mut roots = Vec::new();
+        for v in self.root_values() {
+            each_ref(v, &mut |id| roots.push(id));
+        }
+        for id in self.heap_ids() {
+            for v in self.heap_children(id) {
+                each_ref(v, &mut |child| *refs.entry(child).or_insert(0) += 1);
+            }
+        }
+        for id in &roots {
+            *refs.entry(*id).or_insert(0) += 1;
+        }
+
+        let mut reachable: HashSet<HeapId> = HashSet::new();
+        while let Some(id) = roots.pop() {
+            if !reachable.insert(id) {
+                continue;
+            }
+            for v in self.heap_children(id) {
+                each_ref(v, &mut |child| roots.push(child));
+            }
+        }
+
+        let mut out = format!(
+            "heap: {} struct(s), {} array(s), {} vec(s), {} buffer(s), {} bytes, {} set(s), {} pq(s)\n",
+            self.heap.len(),
+            self.array_heap.len(),
+            self.vec_heap.len(),
+            self.buffer_heap.len(),
+            self.bytes_heap.len(),
+            self.set_heap.len(),
+            self.pq_heap.len()
+        );
+        for id in self.heap_ids() {
+            let status = format!(
+                "refs={} {}",
+                refs.get(&id).copied().unwrap_or(0),
+                if reachable.contains(&id) {
+                    "reachable"
+                } else {
+                    "unreachable"
+                }
+            );
+            let line = match id {
+                HeapId::Array(i) if self.is_freed_array(i) => format!("array#{i} freed"),
+                HeapId::Vec(i) if self.is_freed_vec(i) => format!("vec#{i} freed"),
+                HeapId::Struct(i) => {
+                    let mut fields: Vec<_> = self.heap[i].fields.iter().collect();
+                    fields.sort_by(|a, b| a.0.cmp(b.0));
+                    let fields: Vec<String> = fields
+                        .into_iter()
+                        .map(|(name, v)| format!("{name} = {}", summarize(v)))
+                        .collect();
+                    format!("struct#{i} {status} {{ {} }}", fields.join(", "))
+                }
+                HeapId::Array(i) => {
+                    format!("array#{i} {status} len={}", self.array_heap[i].len())
+                }
+                HeapId::Vec(i) => {
+                    let elems: Vec<String> = self.vec_heap[i].iter().map(summarize).collect();
+                    format!(
+                        "vec#{i} {status} len={} [{}]",
+                        elems.len(),
+                        elems.join(", ")
+                    )
+                }
+                HeapId::Buffer(i) => {
+                    format!("buffer#{i} {status} len={}", self.buffer_heap[i].len())
+                }
+                HeapId::Bytes(i) => {
+                    format!("bytes#{i} {status} len={}", self.bytes_heap[i].len())
+                }
+                HeapId::Set(i) => {
+                    format!("set#{i} {status} len={}", self.set_heap[i].len())
+                }
+                HeapId::Pq(i) => {
+                    format!("pq#{i} {status} len={}", self.pq_heap[i].items.len())
+                }
+            };
+            out.push_str(&line);
+            out.push('\n');
+        }
+        out
+    }
+
+    fn root_values(&self) -> Vec<&Type> {
+        let mut roots: Vec<&Type> = self.stack.iter().collect();
+        roots.extend(self.global_env.values());
+        roots.extend(self.local_env.iter().flat_map(|env| env.values()));
+        roots.extend(self.immutable_stack.iter().flat_map(|env| env.values()));
+        for frame in &self.call_stack {
+            roots.extend(frame.local_env.iter().flat_map(|env| env.values()));
+            roots.extend(frame.immutable_stack.iter().flat_map(|env| env.values()));
+        }
+        roots.extend(self.task_roots());
+        roots
+    }
+
+    fn heap_ids(&self) -> impl Iterator<Item = HeapId> {
+        (0..self.heap.len())
+            .map(HeapId::Struct)
+            .chain((0..self.array_heap.len()).map(HeapId::Array))
+            .chain((0..self.vec_heap.len()).map(HeapId::Vec))
+            .chain((0..self.buffer_heap.len()).map(HeapId::Buffer))
+            .chain((0..self.bytes_heap.len()).map(HeapId::Bytes))
+            .chain((0..self.set_heap.len()).map(HeapId::Set))
+            .chain((0..self.pq_heap.len()).map(HeapId::Pq))
+    }
+
+    fn heap_children(&self, id: HeapId) -> Vec<&Type> {
+        match id {
+            HeapId::Struct(i) => self.heap[i].fields.values().collect(),
+            HeapId::Array(i) => self.array_heap[i].iter().collect(),
+            HeapId::Vec(i) => self.vec_heap[i].iter().collect(),
+            HeapId::Set(i) => self.set_heap[i].values().collect(),
+            HeapId::Pq(i) => {
+                let pq = &self.pq_heap[i];
+                std::iter::once(&pq.cmp).chain(&pq.items).collect()
+            }
+            HeapId::Buffer(_) | HeapId::Bytes(_) => Vec::new(),
+        }
+    }
+}
+
+fn each_ref(v: &Type, f: &mut impl FnMut(HeapId)) {
+    match v {
+        Type::StructRef(id) => f(HeapId::Struct(*id)),
+        Type::ArrayRef(id) => f(HeapId::Array(*id)),
+        Type::VecRef(id) => f(HeapId::Vec(*id)),
+        Type::BufferRef(id) => f(HeapId::Buffer(*id)),
+        Type::BytesRef(id) => f(HeapId::Bytes(*id)),
+        Type::SetRef(id) => f(HeapId::Set(*id)),
+        Type::PqRef(id) => f(HeapId::Pq(*id)),
+        Type::LValue(LValue::ArrayElem { array_id, .. }) => f(HeapId::Array(*array_id)),
+        Type::LValue(LValue::VecElem { vec_id, .. }) => f(HeapId::Vec(*vec_id)),
+        Type::LValue(LValue::StructField { struct_id, .. }) => f(HeapId::Struct(*struct_id)),
+        Type::Variant(variant) => {
+            for elem in &variant.payload {
+                each_ref(elem, f);
+            }
+        }
+        Type::Iter(iter) => match iter.as_ref() {
+            Iter::Array { id, .. } => f(HeapId::Array(*id)),
+            Iter::Vec { id, .. } => f(HeapId::Vec(*id)),
+            Iter::Range { .. } => {}
+        },
+        Type::Tuple(elems) => {
+            for elem in elems {
+                each_ref(elem, f);
+            }
+        }
+        Type::LazyValue(_, captures, _) => {
+            for captured in captures.values() {
+                each_ref(captured, f);
+            }
+        }
+        Type::Integer(_)
+        | Type::Char(_)
+        | Type::Null
+        | Type::HandleRef(_)
+        | Type::Function { .. }
+        | Type::NativeFunction(_)
+        | Type::Uninitialized => {}
+    }
+}
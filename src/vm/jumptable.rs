@@ -0,0 +1,135 @@
+use crate::grammar::{CompiledStructFieldInit, Instruction, JumpTable, ReactiveExpr};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Replaces every `Switch` and `SwitchRel` in `code`, and in the bodies
+/// nested in it, with a `JumpTable`, so the VM dispatches on a shared map
+/// instead of cloning and scanning the case list each time. A switch naming
+/// an undefined label is left alone to fail when it runs.
+pub(crate) fn resolve_switches(code: Vec<Instruction>) -> Vec<Instruction> {
+    let mut labels = HashMap::new();
+    for (i, instr) in code.iter().enumerate() {
+        if let Instruction::Label(name) = instr {
+            labels.insert(name.clone(), i as i64);
+        }
+    }
+
+    code.into_iter()
+        .enumerate()
+        .map(|(at, instr)| {
+            let offset = |label: &String| labels.get(label).map(|target| target - at as i64);
+            let resolved = match &instr {
+                Instruction::Switch(cases, default) => cases
+                    .iter()
+                    .rev()
+                    .map(|(value, label)| Some((*value, offset(label)?)))
+                    .collect::<Option<_>>()
+                    .zip(offset(default)),
+                Instruction::SwitchRel(cases, default) => {
+                    Some((cases.iter().rev().copied().collect(), *default))
+                }
+                _ => return resolve_nested(instr),
+            };
+            match resolved {
+                // the first case for a value wins, as in a linear scan
+                Some((cases, default)) => Instruction::JumpTable(Arc::new(JumpTable {
+                    cases,
+                    default,
+                    source: instr,
+                })),
+                None => instr,
+            }
+        })
+        .collect()
+}
+
+fn resolve_nested(instr: Instruction) -> Instruction {
+    let expr = |e: ReactiveExpr| ReactiveExpr {
+        code: resolve_switches(e.code),
+        captures: e.captures,
+    };
+
+    match instr {
+        Instruction::StoreFunction(name, params, body, visibility) => {
+            Instruction::StoreFunction(name, params, resolve_switches(body), visibility)
+        }
+        Instruction::StoreReactive(name, e) => Instruction::StoreReactive(name, expr(e)),
+        Instruction::StoreIndexReactive(name, e) => Instruction::StoreIndexReactive(name, expr(e)),
+        Instruction::FieldSetReactive(name, e) => Instruction::FieldSetReactive(name, expr(e)),
+        Instruction::StoreThroughReactive(e) => Instruction::StoreThroughReactive(expr(e)),
+        Instruction::StoreStruct(name, fields, visibility) => {
+            let fields = fields
+                .into_iter()
+                .map(|(field, init)| {
+                    let init = init.map(|init| match init {
+                        CompiledStructFieldInit::Mutable(code) => {
+                            CompiledStructFieldInit::Mutable(resolve_switches(code))
+                        }
+                        CompiledStructFieldInit::Immutable(code) => {
+                            CompiledStructFieldInit::Immutable(resolve_switches(code))
+                        }
+                        CompiledStructFieldInit::Reactive(e) => {
+                            CompiledStructFieldInit::Reactive(expr(e))
+                        }
+                    });
+                    (field, init)
+                })
+                .collect();
+            Instruction::StoreStruct(name, fields, visibility)
+        }
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::{Instruction::*, JumpTable, Visibility};
+
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    fn table(instr: &Instruction) -> &JumpTable {
+        match instr {
+            Instruction::JumpTable(table) => table,
+            other => panic!("expected a jump table, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn switches_become_offset_tables_and_the_first_case_wins() {
+        let switch = Switch(vec![(1, s("one")), (2, s("two")), (1, s("two"))], s("end"));
+        let code = resolve_switches(vec![
+            Push(1),
+            switch.clone(),
+            Label(s("one")),
+            Label(s("two")),
+            Label(s("end")),
+        ]);
+        let resolved = table(&code[1]);
+        assert_eq!(resolved.cases, HashMap::from([(1, 1), (2, 2)]));
+        assert_eq!((resolved.offset(7), resolved.default), (3, 3));
+        assert_eq!(resolved.source, switch);
+
+        let code = resolve_switches(vec![SwitchRel(vec![(0, 2), (0, 5)], -1)]);
+        assert_eq!(table(&code[0]).cases, HashMap::from([(0, 2)]));
+    }
+
+    #[test]
+    fn nested_bodies_are_resolved_and_undefined_labels_left_alone() {
+        let dangling = Switch(vec![(0, s("nowhere"))], s("nowhere"));
+        let body = vec![SwitchRel(Vec::new(), 1), dangling.clone()];
+        let code = resolve_switches(vec![StoreFunction(
+            s("f"),
+            Vec::new(),
+            body,
+            Visibility::Public,
+        )]);
+        let StoreFunction(_, _, body, _) = &code[0] else {
+            panic!("expected the function back");
+        };
+        assert_eq!(table(&body[0]).default, 1);
+        assert_eq!(body[1], dangling);
+    }
+}
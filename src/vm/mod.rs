@@ -1,14 +1,171 @@
+pub mod alloc;
 pub mod call;
+pub mod coverage;
+pub mod debug;
+pub mod display;
 pub mod env;
+pub mod eval;
 pub mod exec;
+pub mod freeze;
+pub mod handle;
+pub mod inspect;
+pub mod jumptable;
 pub mod native;
+pub mod profile;
 pub mod reactive;
+pub mod replay;
 pub mod runtime;
+pub mod superinstr;
+pub mod task;
+pub mod timing;
+pub mod trace;
+pub mod worker;
 
+use crate::debug_info::{BodyMatcher, DebugInfo};
 use crate::grammar::{CompiledStructFieldInit, Instruction, StructInstance, Type};
-use std::collections::{HashMap, HashSet};
+use coverage::Coverage;
+use debug::Debugger;
+use handle::{HandleTable, TempRoot};
+use profile::Profiler;
+use replay::Replay;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::Instant;
+use superinstr::NgramProfile;
+use task::Task;
+use timing::Timings;
+use trace::Tracer;
 
 type NativeFunction = fn(&mut VM, Vec<Type>) -> Type;
+
+/// A function supplied by the embedding application, called like a native;
+/// unlike a native it can carry state. See `VM::register_host_function`.
+pub type HostFunction = Rc<dyn Fn(&mut VM, Vec<Type>) -> Type>;
+
+/// What integer arithmetic does when a result does not fit in an i64.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Two's complement wrap-around (identical in debug and release builds).
+    #[default]
+    Wrapping,
+    /// Raise a runtime error naming the operation and operands.
+    Checked,
+}
+
+/// What a `Store` inside a function does when its name is immutable in an
+/// enclosing scope (a parameter, an outer `:=` or a global `:=`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShadowPolicy {
+    /// Create a local that hides the immutable for the rest of the call.
+    #[default]
+    Allow,
+    /// Do the same, but report each such store once on stderr.
+    Warn,
+    /// Raise a runtime error naming the store and the immutable's binding.
+    Error,
+}
+
+impl ShadowPolicy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "allow" => Some(Self::Allow),
+            "warn" => Some(Self::Warn),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+/// Severity for `internal_log`; messages below the VM's level are dropped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "debug" => Some(Self::Debug),
+            "info" => Some(Self::Info),
+            "warn" => Some(Self::Warn),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Debug => "DEBUG",
+            Self::Info => "INFO",
+            Self::Warn => "WARN",
+            Self::Error => "ERROR",
+        }
+    }
+}
+
+/// Default for `VmOptions::max_call_depth`.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 4096;
+
+/// Operand stack size at which a run stops with an error. Expression
+/// statements leave their value on the stack, but returning from a call
+/// drops whatever its body left there, so only top-level code can grow it
+/// across calls; this only catches bytecode that pushes without bound.
+pub const MAX_OPERAND_STACK: usize = 1 << 24;
+
+/// Native stack size for threads running a VM. Nested evaluation (reactive
+/// values, callbacks from natives) recurses in Rust, and reaching
+/// `DEFAULT_MAX_CALL_DEPTH` of it takes far more than the usual 8 MiB.
+pub const VM_STACK_SIZE: usize = 256 << 20;
+
+/// Behaviour switches fixed for the lifetime of a VM.
+#[derive(Debug, Clone)]
+pub struct VmOptions {
+    pub overflow: OverflowPolicy,
+    pub log_level: LogLevel,
+    pub shadowing: ShadowPolicy,
+    /// Reject implicit coercions: arrays and vecs used as ints (their
+    /// length), chars in arithmetic and chars compared with ints. Explicit
+    /// `(int)` / `(char)` casts still convert.
+    pub strict: bool,
+    /// Calls (bytecode and native) that may be active at once, and nested
+    /// evaluations (reactive values, callbacks from natives); going deeper is
+    /// a "stack overflow" runtime error.
+    pub max_call_depth: usize,
+    /// On a runtime error, unwind with a `RuntimeError` payload instead of
+    /// exiting the process, so a host can `catch_unwind` and carry on.
+    pub unwind_on_error: bool,
+    /// Std modules with natives that `import` may install (`std.file`, ...);
+    /// importing any other is a runtime error. `None` allows them all.
+    pub allowed_imports: Option<Vec<String>>,
+}
+
+impl Default for VmOptions {
+    fn default() -> Self {
+        Self {
+            overflow: OverflowPolicy::default(),
+            log_level: LogLevel::default(),
+            shadowing: ShadowPolicy::default(),
+            strict: false,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            unwind_on_error: false,
+            allowed_imports: None,
+        }
+    }
+}
+
+/// Panic payload raised by a runtime error when `unwind_on_error` is set.
+/// The error report has already been written to the VM's stdout sink.
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub message: String,
+}
+
 struct CallFrame {
     code: Vec<Instruction>,
     labels: HashMap<String, usize>,
@@ -19,6 +176,12 @@ struct CallFrame {
 
     stack_base: usize,
     function_name: String,
+    // module whose code runs in this frame, for visibility checks; `None`
+    // for top-level code, which is not checked
+    module: Option<String>,
+
+    // entry time, set for profiled calls made from bytecode
+    started: Option<Instant>,
 }
 pub struct VM {
     // Operand stack
@@ -40,27 +203,117 @@ pub struct VM {
 
     // Runtime heaps
     struct_defs: HashMap<String, Vec<(String, Option<CompiledStructFieldInit>)>>,
+    enum_defs: HashMap<String, Vec<(String, usize)>>,
+    // Functions and structs defined `private`, usable only by their module
+    private_symbols: HashSet<String>,
+    // Module that defined each struct, whose private names its field
+    // initializers may use (`None` for top-level definitions)
+    struct_modules: HashMap<String, Option<String>>,
+    // Call depth at which reactive or initializer code is running inline,
+    // and the module that wrote it, which need not be that frame's module
+    inline_module: Option<(usize, Option<String>)>,
     heap: Vec<StructInstance>,
     array_heap: Vec<Vec<Type>>,
     array_immutables: Vec<HashSet<usize>>,
     vec_heap: Vec<Vec<Type>>,
     vec_immutables: Vec<HashSet<usize>>,
     buffer_heap: Vec<Vec<u32>>,
+    bytes_heap: Vec<Vec<u8>>,
+    set_heap: Vec<HashMap<native::SetKey, Type>>,
+    pq_heap: Vec<native::PriorityQueue>,
+
+    // released array / vec slots awaiting reuse, and per slot whether it is
+    // on its free list
+    free_arrays: Vec<usize>,
+    free_vecs: Vec<usize>,
+    array_freed: Vec<bool>,
+    vec_freed: Vec<bool>,
+
+    // functions to call when a struct is destroyed, and structs already
+    // destroyed (`std.finalize`)
+    finalizers: HashMap<usize, Type>,
+    destroyed_structs: HashSet<usize>,
+
+    // objects locked by `internal_freeze`, with everything reachable from them
+    frozen: HashSet<freeze::Frozen>,
+    // objects a native is working on while it calls back into Reactive code
+    // (a sort's comparator), with that native's name
+    busy: HashMap<freeze::Frozen, &'static str>,
+
+    // OS resources (files, ...) referenced by HandleRef
+    handles: HandleTable,
+
+    // scratch directory for temp files / dirs, removed on exit
+    temp_root: TempRoot,
 
     // Module import memoization
     imported_modules: HashSet<String>,
 
+    // bytecode modules from an archive, run by the first `Import` of each
+    archive_modules: HashMap<String, Vec<Instruction>>,
+
     // call stack
     call_stack: Vec<CallFrame>,
 
+    // nesting of `run` (natives calling back into bytecode, reactive
+    // evaluation); tasks can only be switched at depth 1
+    run_depth: usize,
+
+    // suspended green threads, resumed round-robin by `Yield`
+    tasks: VecDeque<Task>,
+
     // native function registry
     native_functions: HashMap<String, NativeFunction>,
+
+    // functions registered by the embedding application
+    host_functions: HashMap<String, HostFunction>,
+
+    options: VmOptions,
+
+    // opcode pair/triple counts for superinstruction selection (opt-in)
+    ngram_profile: Option<NgramProfile>,
+
+    // per-function / per-opcode counters for `reactive profile` (opt-in)
+    profiler: Option<Profiler>,
+
+    // per-instruction execution log (opt-in)
+    tracer: Option<Tracer>,
+
+    // link / native time breakdown for `--time` (opt-in)
+    timings: Option<Timings>,
+
+    // per-instruction execution counts for `--coverage` (opt-in)
+    coverage: Option<Coverage>,
+
+    // interactive debugger (`reactive dap`), called before every instruction
+    debugger: Option<Box<dyn Debugger>>,
+
+    // source locations for error reports (opt-in)
+    debug_info: Option<(DebugInfo, BodyMatcher)>,
+
+    // shadowing stores already reported under `ShadowPolicy::Warn`
+    shadow_warnings: HashSet<String>,
+
+    // nondeterministic input log (--record / --replay)
+    replay: Option<Replay>,
+
+    // destination of Print / Println and runtime error reports (process
+    // stdout unless redirected); a RefCell so `runtime_error` can write to it
+    stdout: RefCell<Box<dyn Write>>,
+
+    // destination of EPrint / EPrintln (process stderr unless redirected)
+    stderr: Box<dyn Write>,
 }
 
 impl VM {
     pub fn new(code: Vec<Instruction>) -> Self {
+        Self::with_options(code, VmOptions::default())
+    }
+
+    pub fn with_options(code: Vec<Instruction>, options: VmOptions) -> Self {
+        let code = jumptable::resolve_switches(code);
         let labels = Self::build_labels(&code);
-        let vm = Self {
+        Self {
             stack: Vec::new(),
             global_env: HashMap::new(),
             local_env: None,
@@ -69,17 +322,58 @@ impl VM {
             code,
             labels,
             struct_defs: HashMap::new(),
+            enum_defs: HashMap::new(),
+            private_symbols: HashSet::new(),
+            struct_modules: HashMap::new(),
+            inline_module: None,
             heap: Vec::new(),
             array_heap: Vec::new(),
             array_immutables: Vec::new(),
             vec_heap: Vec::new(),
             vec_immutables: Vec::new(),
             buffer_heap: Vec::new(),
+            bytes_heap: Vec::new(),
+            set_heap: Vec::new(),
+            pq_heap: Vec::new(),
+            free_arrays: Vec::new(),
+            free_vecs: Vec::new(),
+            array_freed: Vec::new(),
+            vec_freed: Vec::new(),
+            finalizers: HashMap::new(),
+            destroyed_structs: HashSet::new(),
+            frozen: HashSet::new(),
+            busy: HashMap::new(),
+            handles: HandleTable::default(),
+            temp_root: TempRoot::default(),
             imported_modules: HashSet::new(),
+            archive_modules: HashMap::new(),
             call_stack: Vec::new(),
+            run_depth: 0,
+            tasks: VecDeque::new(),
             native_functions: HashMap::new(),
-        };
-        vm
+            host_functions: HashMap::new(),
+            options,
+            ngram_profile: None,
+            profiler: None,
+            tracer: None,
+            timings: None,
+            coverage: None,
+            debugger: None,
+            debug_info: None,
+            shadow_warnings: HashSet::new(),
+            replay: None,
+            stdout: RefCell::new(Box::new(io::stdout())),
+            stderr: Box::new(io::stderr()),
+        }
+    }
+
+    /// Starts counting executed opcode pairs and triples.
+    pub fn enable_ngram_profile(&mut self) {
+        self.ngram_profile = Some(NgramProfile::default());
+    }
+
+    pub fn ngram_profile(&self) -> Option<&NgramProfile> {
+        self.ngram_profile.as_ref()
     }
 
     fn build_labels(code: &[Instruction]) -> HashMap<String, usize> {
@@ -92,12 +386,50 @@ impl VM {
         labels
     }
 
+    /// Redirects program output (`Print`, `Println`) and runtime error reports.
+    pub fn set_stdout_sink(&mut self, sink: Box<dyn Write>) {
+        self.stdout = RefCell::new(sink);
+    }
+
+    /// Redirects program stderr output (`EPrint`, `EPrintln`).
+    pub fn set_stderr_sink(&mut self, sink: Box<dyn Write>) {
+        self.stderr = sink;
+    }
+
     pub(crate) fn runtime_error(&self, message: &str) -> ! {
-        println!("Runtime error: {message}");
-        println!("Stack trace (most recent call last):");
-        for frame in self.call_stack.iter().rev() {
-            println!("  at {}()", frame.function_name);
+        let mut report =
+            format!("Runtime error: {message}\nStack trace (most recent call last):\n");
+        // Runs of the same function (recursion) are shown once with a count.
+        let mut frames = self.call_stack.iter().rev().peekable();
+        while let Some(frame) = frames.next() {
+            report.push_str(&format!("  at {}()\n", frame.function_name));
+            let mut repeats = 0;
+            while frames
+                .next_if(|next| next.function_name == frame.function_name)
+                .is_some()
+            {
+                repeats += 1;
+            }
+            if repeats > 0 {
+                report.push_str(&format!(
+                    "  ... {repeats} more call(s) of {}()\n",
+                    frame.function_name
+                ));
+            }
+        }
+        if let Ok(mut out) = self.stdout.try_borrow_mut() {
+            let _ = out.write_all(report.as_bytes()).and_then(|_| out.flush());
+        }
+        if let Some(profiler) = &self.profiler {
+            eprint!("{}", profiler.report());
+        }
+        if self.options.unwind_on_error {
+            // Drop (handles, temp root) runs as the VM unwinds.
+            std::panic::resume_unwind(Box::new(RuntimeError {
+                message: message.to_string(),
+            }));
         }
+        self.temp_root.remove();
         std::process::exit(1);
     }
 }
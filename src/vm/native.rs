@@ -1,26 +1,45 @@
-use super::{NativeFunction, VM};
-use crate::grammar::Type;
-use std::collections::HashSet;
+use super::freeze::Frozen;
+use super::handle::Resource;
+use super::profile::TOP_LEVEL;
+use super::replay::Input;
+use super::worker::{Channel, run_map_shard, run_worker};
+use super::{LogLevel, NativeFunction, VM, VM_STACK_SIZE};
+use crate::grammar::{StructInstance, Type};
+use crate::manifest::Value as ConfigValue;
+use std::cmp::Ordering;
 #[cfg(unix)]
 use std::collections::VecDeque;
-use std::io::{self, Write};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::net::{ToSocketAddrs, UdpSocket};
 use std::path::Path;
-use std::sync::{Mutex, OnceLock};
+use std::sync::{Arc, Mutex, OnceLock};
 
 #[cfg(windows)]
 use windows_sys::Win32::Foundation::{BOOL, HANDLE, INVALID_HANDLE_VALUE};
 #[cfg(windows)]
 use windows_sys::Win32::System::Console::{
-    ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, ENABLE_PROCESSED_INPUT, GetConsoleMode, GetStdHandle,
-    STD_INPUT_HANDLE, SetConsoleCtrlHandler, SetConsoleMode,
+    CONSOLE_SCREEN_BUFFER_INFO, ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, ENABLE_PROCESSED_INPUT,
+    GetConsoleMode, GetConsoleScreenBufferInfo, GetStdHandle, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE,
+    SetConsoleCtrlHandler, SetConsoleMode,
 };
 
 impl VM {
     pub(crate) fn install_native_fs(&mut self) {
         self.register_native("internal_file_read", native_read);
         self.register_native("internal_file_write", native_write);
+        self.register_native("internal_file_append", native_append);
+        self.register_native("internal_file_write_atomic", native_write_atomic);
         self.register_native("internal_file_exists", native_exists);
         self.register_native("internal_file_remove", native_remove);
+        self.register_native("internal_file_open", native_file_open);
+        self.register_native("internal_file_handle_read", native_file_handle_read);
+        self.register_native("internal_file_handle_write", native_file_handle_write);
+        self.register_native("internal_handle_close", native_handle_close);
+        self.register_native("internal_file_read_bytes", native_file_read_bytes);
+        self.register_native("internal_file_write_bytes", native_file_write_bytes);
+        self.register_native("internal_temp_file", native_temp_file);
+        self.register_native("internal_temp_dir", native_temp_dir);
     }
 
     pub(crate) fn install_native_buf(&mut self) {
@@ -29,12 +48,85 @@ impl VM {
         self.register_native("internal_buf_push_str", native_buf_push_str);
         self.register_native("internal_buf_to_string", native_buf_to_string);
         self.register_native("internal_buf_write_file", native_buf_write_file);
+        self.register_native("internal_buf_len", native_buf_len);
+        self.register_native("internal_buf_clear", native_buf_clear);
+        self.register_native("internal_buf_insert", native_buf_insert);
+        self.register_native("internal_buf_slice", native_buf_slice);
+        self.register_native("internal_buf_from_string", native_buf_from_string);
+        self.register_native("internal_buf_from_file", native_buf_from_file);
+    }
+
+    pub(crate) fn install_native_bytes(&mut self) {
+        self.register_native("internal_bytes_new", native_bytes_new);
+        self.register_native("internal_bytes_len", native_bytes_len);
+        self.register_native("internal_bytes_get", native_bytes_get);
+        self.register_native("internal_bytes_set", native_bytes_set);
+        self.register_native("internal_bytes_push", native_bytes_push);
+        self.register_native("internal_bytes_from_string", native_bytes_from_string);
+        self.register_native("internal_bytes_to_string", native_bytes_to_string);
+    }
+
+    pub(crate) fn install_native_set(&mut self) {
+        self.register_native("internal_set_new", native_set_new);
+        self.register_native("internal_set_add", native_set_add);
+        self.register_native("internal_set_contains", native_set_contains);
+        self.register_native("internal_set_remove", native_set_remove);
+        self.register_native("internal_set_len", native_set_len);
+        self.register_native("internal_set_to_vec", native_set_to_vec);
+    }
+
+    pub(crate) fn install_native_pq(&mut self) {
+        self.register_native("internal_pq_new", native_pq_new);
+        self.register_native("internal_pq_push", native_pq_push);
+        self.register_native("internal_pq_pop_min", native_pq_pop_min);
+        self.register_native("internal_pq_len", native_pq_len);
     }
 
     pub(crate) fn install_native_vec(&mut self) {
         self.register_native("internal_vec_new", native_vec_new);
         self.register_native("internal_vec_push", native_vec_push);
         self.register_native("internal_vec_pop", native_vec_pop);
+        self.register_native("internal_vec_free", native_vec_free);
+        self.register_native("internal_vec_len", native_vec_len);
+        self.register_native("internal_vec_get", native_vec_get);
+        self.register_native("internal_vec_set", native_vec_set);
+        self.register_native("internal_vec_insert", native_vec_insert);
+        self.register_native("internal_vec_remove", native_vec_remove);
+        self.register_native("internal_vec_clear", native_vec_clear);
+        self.register_native("internal_vec_contains", native_vec_contains);
+        self.register_native("internal_vec_sort", native_vec_sort);
+        self.register_native("internal_vec_binary_search", native_vec_binary_search);
+        self.register_native("internal_vec_map", native_vec_map);
+        self.register_native("internal_vec_par_map", native_vec_par_map);
+        self.register_native("internal_vec_filter", native_vec_filter);
+        self.register_native("internal_vec_reduce", native_vec_reduce);
+    }
+
+    pub(crate) fn install_native_math(&mut self) {
+        self.register_native("internal_math_abs", native_math_abs);
+        self.register_native("internal_math_min", native_math_min);
+        self.register_native("internal_math_max", native_math_max);
+        self.register_native("internal_math_clamp", native_math_clamp);
+        self.register_native("internal_math_sqrt", native_math_sqrt);
+        self.register_native("internal_math_pow", native_math_pow);
+        self.register_native("internal_math_sin", native_math_sin);
+        self.register_native("internal_math_cos", native_math_cos);
+        self.register_native("internal_math_atan2", native_math_atan2);
+    }
+
+    pub(crate) fn install_native_array(&mut self) {
+        self.register_native("internal_array_free", native_array_free);
+        self.register_native("internal_clone", native_clone);
+        self.register_native("internal_freeze", native_freeze);
+        self.register_native("internal_is_frozen", native_is_frozen);
+        self.register_native("internal_array_slice", native_array_slice);
+        self.register_native("internal_array_concat", native_array_concat);
+    }
+
+    pub(crate) fn install_native_finalize(&mut self) {
+        self.register_native("internal_on_destroy", native_on_destroy);
+        self.register_native("internal_destroy", native_destroy);
+        self.register_native("internal_with", native_with);
     }
 
     pub(crate) fn install_native_input(&mut self) {
@@ -44,13 +136,112 @@ impl VM {
         self.register_native("internal_input_shutdown", native_input_shutdown);
     }
 
-    fn register_native(&mut self, name: &str, f: NativeFunction) {
+    pub(crate) fn install_native_datetime(&mut self) {
+        self.register_native("internal_datetime_now", native_datetime_now);
+        self.register_native("internal_datetime_from_timestamp", native_datetime_from_ts);
+        self.register_native("internal_datetime_format", native_datetime_format);
+        self.register_native("internal_datetime_parse", native_datetime_parse);
+    }
+
+    pub(crate) fn install_native_uuid(&mut self) {
+        self.register_native("internal_uuid_v4", native_uuid_v4);
+        self.register_native("internal_nonce", native_nonce);
+    }
+
+    pub(crate) fn install_native_hash(&mut self) {
+        self.register_native("internal_hash_crc32", native_hash_crc32);
+        self.register_native("internal_hash_fnv", native_hash_fnv);
+        self.register_native("internal_hash_sha256", native_hash_sha256);
+    }
+
+    pub(crate) fn install_native_gzip(&mut self) {
+        self.register_native("internal_gzip_compress", native_gzip_compress);
+        self.register_native("internal_gzip_decompress", native_gzip_decompress);
+        self.register_native("internal_zlib_compress", native_zlib_compress);
+        self.register_native("internal_zlib_decompress", native_zlib_decompress);
+    }
+
+    pub(crate) fn install_native_io(&mut self) {
+        self.register_native("internal_eprint", native_eprint);
+        self.register_native("internal_eprintln", native_eprintln);
+    }
+
+    pub(crate) fn install_native_csv(&mut self) {
+        self.register_native("internal_csv_parse", native_csv_parse);
+        self.register_native("internal_csv_write", native_csv_write);
+    }
+
+    pub(crate) fn install_native_config(&mut self) {
+        self.register_native("internal_toml_parse", native_toml_parse);
+        self.register_native("internal_ini_parse", native_ini_parse);
+        self.register_native("internal_config_get", native_config_get);
+        self.register_native("internal_config_keys", native_config_keys);
+    }
+
+    pub(crate) fn install_native_null(&mut self) {
+        self.register_native("internal_null", native_null);
+        self.register_native("internal_is_null", native_is_null);
+    }
+
+    pub(crate) fn install_native_tuple(&mut self) {
+        self.register_native("internal_tuple", native_tuple);
+        self.register_native("internal_tuple_get", native_tuple_get);
+        self.register_native("internal_tuple_len", native_tuple_len);
+    }
+
+    pub(crate) fn install_native_log(&mut self) {
+        self.register_native("internal_log", native_log);
+    }
+
+    pub(crate) fn install_native_term(&mut self) {
+        self.register_native("internal_term_size", native_term_size);
+        self.register_native("internal_term_raw_mode", native_term_raw_mode);
+        self.register_native("internal_term_clear", native_term_clear);
+        self.register_native("internal_term_move", native_term_move);
+        self.register_native("internal_term_poll_key", native_term_poll_key);
+    }
+
+    pub(crate) fn install_native_thread(&mut self) {
+        self.register_native("internal_thread_spawn", native_thread_spawn);
+        self.register_native("internal_thread_join", native_thread_join);
+        self.register_native("internal_channel_new", native_channel_new);
+        self.register_native("internal_channel_send", native_channel_send);
+        self.register_native("internal_channel_recv", native_channel_recv);
+    }
+
+    pub(crate) fn install_native_net(&mut self) {
+        self.register_native("internal_udp_bind", native_udp_bind);
+        self.register_native("internal_udp_send_to", native_udp_send_to);
+        self.register_native("internal_udp_recv_from", native_udp_recv_from);
+        self.register_native("internal_udp_local_addr", native_udp_local_addr);
+        self.register_native("internal_udp_set_timeout", native_udp_set_timeout);
+        self.register_native("internal_handle_close", native_handle_close);
+        self.register_native("internal_dns_resolve", native_dns_resolve);
+    }
+
+    pub(crate) fn install_native_ws(&mut self) {
+        self.register_native("internal_ws_connect", native_ws_connect);
+        self.register_native("internal_ws_send", native_ws_send);
+        self.register_native("internal_ws_recv", native_ws_recv);
+        self.register_native("internal_ws_close", native_ws_close);
+    }
+
+    pub(crate) fn install_native_debug(&mut self) {
+        self.register_native("internal_trace", native_trace);
+        self.register_native("internal_heap_dump", native_heap_dump);
+        self.register_native("internal_backtrace", native_backtrace);
+        self.register_native("internal_typeof", native_typeof);
+        self.register_native("internal_assert_eq", native_assert_eq);
+        self.register_native("internal_assert_ne", native_assert_ne);
+    }
+
+    pub(super) fn register_native(&mut self, name: &str, f: NativeFunction) {
         self.native_functions.insert(name.to_string(), f);
         self.global_env
             .insert(name.to_string(), Type::NativeFunction(name.to_string()));
     }
 
-    fn value_to_string(&mut self, v: Type, what: &str) -> String {
+    pub(crate) fn value_to_string(&mut self, v: Type, what: &str) -> String {
         match self.force(v) {
             Type::ArrayRef(id) => {
                 let elems = self.array_heap[id].clone();
@@ -63,8 +254,8 @@ impl VM {
                                 .runtime_error(&format!("{what} contains invalid char code {c}")),
                         },
                         other => self.runtime_error(&format!(
-                            "{what} must be a string (array of chars), found {:?}",
-                            other
+                            "{what} must be a string (array of chars), found {}",
+                            self.format_value(&other)
                         )),
                     }
                 }
@@ -81,26 +272,44 @@ impl VM {
                                 .runtime_error(&format!("{what} contains invalid char code {c}")),
                         },
                         other => self.runtime_error(&format!(
-                            "{what} must be a string (array of chars), found {:?}",
-                            other
+                            "{what} must be a string (array of chars), found {}",
+                            self.format_value(&other)
                         )),
                     }
                 }
                 out
             }
             other => self.runtime_error(&format!(
-                "{what} must be a string (array of chars), found {:?}",
-                other
+                "{what} must be a string (array of chars), found {}",
+                self.format_value(&other)
             )),
         }
     }
 
-    fn string_to_array(&mut self, s: &str) -> Type {
-        let id = self.array_heap.len();
+    pub(crate) fn string_to_array(&mut self, s: &str) -> Type {
         let elems: Vec<Type> = s.chars().map(|ch| Type::Char(ch as u32)).collect();
-        self.array_heap.push(elems);
-        self.array_immutables.push(HashSet::new());
-        Type::ArrayRef(id)
+        Type::ArrayRef(self.alloc_array(elems, HashSet::new()))
+    }
+
+    /// The text of `v` if it is a string (an array or vec of chars, which
+    /// includes an empty one); `None` for any other value.
+    pub fn string_value(&mut self, v: Type) -> Option<String> {
+        let elems = match self.force(v) {
+            Type::ArrayRef(id) => self.array_heap[id].clone(),
+            Type::VecRef(id) => self.vec_heap[id].clone(),
+            _ => return None,
+        };
+        elems
+            .into_iter()
+            .map(|elem| match self.force(elem) {
+                Type::Char(c) => char::from_u32(c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn values_to_vec(&mut self, elems: Vec<Type>) -> Type {
+        Type::VecRef(self.alloc_vec(elems, HashSet::new()))
     }
 }
 
@@ -113,8 +322,10 @@ fn native_read(vm: &mut VM, args: Vec<Type>) -> Type {
     }
 
     let path = vm.value_to_string(args[0].clone(), "internal_file_read path");
-    let contents = std::fs::read_to_string(&path).unwrap_or_else(|e| {
-        vm.runtime_error(&format!("internal_file_read failed for `{}`: {}", path, e))
+    let contents = vm.recorded_str("internal_file_read", |vm| {
+        std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            vm.runtime_error(&format!("internal_file_read failed for `{}`: {}", path, e))
+        })
     });
     vm.string_to_array(&contents)
 }
@@ -135,9 +346,83 @@ fn native_write(vm: &mut VM, args: Vec<Type>) -> Type {
     });
 
     let count = contents.chars().count();
-    let count_i32 = i32::try_from(count)
+    let count_i64 = i64::try_from(count)
+        .unwrap_or_else(|_| vm.runtime_error("write contents too large for int"));
+    Type::Integer(count_i64)
+}
+
+fn native_append(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_file_append expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let path = vm.value_to_string(args[0].clone(), "internal_file_append path");
+    let contents = vm.value_to_string(args[1].clone(), "internal_file_append contents");
+
+    std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .and_then(|mut f| f.write_all(contents.as_bytes()))
+        .unwrap_or_else(|e| {
+            vm.runtime_error(&format!(
+                "internal_file_append failed for `{}`: {}",
+                path, e
+            ))
+        });
+
+    let count = contents.chars().count();
+    let count_i64 = i64::try_from(count)
+        .unwrap_or_else(|_| vm.runtime_error("append contents too large for int"));
+    Type::Integer(count_i64)
+}
+
+fn native_write_atomic(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_file_write_atomic expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let path = vm.value_to_string(args[0].clone(), "internal_file_write_atomic path");
+    let contents = vm.value_to_string(args[1].clone(), "internal_file_write_atomic contents");
+
+    // Write a sibling temp file and rename it over the target: the rename is
+    // atomic on the same filesystem, so readers see the old or new file, never
+    // a partial one.
+    let target = Path::new(&path);
+    let name = target
+        .file_name()
+        .unwrap_or_else(|| {
+            vm.runtime_error(&format!(
+                "internal_file_write_atomic: `{path}` is not a file path"
+            ))
+        })
+        .to_string_lossy();
+    let tmp = target.with_file_name(format!(".{name}.{}.tmp", std::process::id()));
+
+    let result = std::fs::File::create(&tmp)
+        .and_then(|mut f| {
+            f.write_all(contents.as_bytes())?;
+            f.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&tmp, target));
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&tmp);
+        vm.runtime_error(&format!(
+            "internal_file_write_atomic failed for `{}`: {}",
+            path, e
+        ));
+    }
+
+    let count = contents.chars().count();
+    let count_i64 = i64::try_from(count)
         .unwrap_or_else(|_| vm.runtime_error("write contents too large for int"));
-    Type::Integer(count_i32)
+    Type::Integer(count_i64)
 }
 
 fn native_exists(vm: &mut VM, args: Vec<Type>) -> Type {
@@ -149,8 +434,10 @@ fn native_exists(vm: &mut VM, args: Vec<Type>) -> Type {
     }
 
     let path = vm.value_to_string(args[0].clone(), "internal_file_exists path");
-    let exists = Path::new(&path).exists();
-    Type::Integer(if exists { 1 } else { 0 })
+    let exists = vm.recorded_int("internal_file_exists", |_| {
+        if Path::new(&path).exists() { 1 } else { 0 }
+    });
+    Type::Integer(exists)
 }
 
 fn native_remove(vm: &mut VM, args: Vec<Type>) -> Type {
@@ -171,6 +458,179 @@ fn native_remove(vm: &mut VM, args: Vec<Type>) -> Type {
     Type::Integer(1)
 }
 
+fn native_file_open(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_file_open expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let path = vm.value_to_string(args[0].clone(), "internal_file_open path");
+    let mode = vm.value_to_string(args[1].clone(), "internal_file_open mode");
+
+    let mut options = std::fs::OpenOptions::new();
+    match mode.as_str() {
+        "r" => options.read(true),
+        "w" => options.write(true).create(true).truncate(true),
+        "a" => options.append(true).create(true),
+        other => vm.runtime_error(&format!(
+            "internal_file_open mode must be \"r\", \"w\" or \"a\", found `{}`",
+            other
+        )),
+    };
+
+    let file = options.open(&path).unwrap_or_else(|e| {
+        vm.runtime_error(&format!("internal_file_open failed for `{}`: {}", path, e))
+    });
+    Type::HandleRef(vm.handles.insert(Resource::File(file)))
+}
+
+fn native_file_handle_read(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_file_handle_read expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_handle(vm, args[0].clone(), "internal_file_handle_read");
+    let contents = vm.recorded_str("internal_file_handle_read", |vm| {
+        let Resource::File(file) = vm.handle_of_kind(id, "file", "internal_file_handle_read")
+        else {
+            unreachable!()
+        };
+        let mut contents = String::new();
+        if let Err(e) = file.read_to_string(&mut contents) {
+            vm.runtime_error(&format!("internal_file_handle_read failed: {}", e));
+        }
+        contents
+    });
+    vm.string_to_array(&contents)
+}
+
+fn native_file_handle_write(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_file_handle_write expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_handle(vm, args[0].clone(), "internal_file_handle_write");
+    let contents = vm.value_to_string(args[1].clone(), "internal_file_handle_write contents");
+    let Resource::File(file) = vm.handle_of_kind(id, "file", "internal_file_handle_write") else {
+        unreachable!()
+    };
+
+    if let Err(e) = file.write_all(contents.as_bytes()) {
+        vm.runtime_error(&format!("internal_file_handle_write failed: {}", e));
+    }
+
+    let count = contents.chars().count();
+    let count_i64 = i64::try_from(count)
+        .unwrap_or_else(|_| vm.runtime_error("write contents too large for int"));
+    Type::Integer(count_i64)
+}
+
+fn native_handle_close(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_handle_close expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_handle(vm, args[0].clone(), "internal_handle_close");
+    vm.close_handle(id);
+    Type::Integer(1)
+}
+
+fn native_file_read_bytes(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_file_read_bytes expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let path = vm.value_to_string(args[0].clone(), "internal_file_read_bytes path");
+    let bytes = vm.recorded_bytes("internal_file_read_bytes", |vm| {
+        std::fs::read(&path).unwrap_or_else(|e| {
+            vm.runtime_error(&format!(
+                "internal_file_read_bytes failed for `{}`: {}",
+                path, e
+            ))
+        })
+    });
+    new_bytes(vm, bytes)
+}
+
+fn native_file_write_bytes(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_file_write_bytes expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let path = vm.value_to_string(args[0].clone(), "internal_file_write_bytes path");
+    let id = expect_bytes(vm, args[1].clone(), "internal_file_write_bytes");
+    if let Err(e) = std::fs::write(&path, &vm.bytes_heap[id]) {
+        vm.runtime_error(&format!(
+            "internal_file_write_bytes failed for `{}`: {}",
+            path, e
+        ));
+    }
+    Type::Integer(vm.bytes_heap[id].len() as i64)
+}
+
+fn native_temp_file(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_temp_file expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let created = vm.temp_path("file").and_then(|path| {
+        std::fs::File::create_new(&path)?;
+        Ok(path)
+    });
+    match created {
+        Ok(path) => vm.string_to_array(&path.to_string_lossy()),
+        Err(e) => vm.runtime_error(&format!("internal_temp_file failed: {e}")),
+    }
+}
+
+fn native_temp_dir(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_temp_dir expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let created = vm.temp_path("dir").and_then(|path| {
+        std::fs::create_dir(&path)?;
+        Ok(path)
+    });
+    match created {
+        Ok(path) => vm.string_to_array(&path.to_string_lossy()),
+        Err(e) => vm.runtime_error(&format!("internal_temp_dir failed: {e}")),
+    }
+}
+
+fn expect_handle(vm: &mut VM, v: Type, what: &str) -> usize {
+    match vm.force(v) {
+        Type::HandleRef(id) => id,
+        other => vm.runtime_error(&format!(
+            "{what} expects handle, found {}",
+            vm.format_value(&other)
+        )),
+    }
+}
+
 fn native_buf_new(vm: &mut VM, args: Vec<Type>) -> Type {
     if args.len() != 1 {
         vm.runtime_error(&format!(
@@ -196,16 +656,17 @@ fn native_buf_push_char(vm: &mut VM, args: Vec<Type>) -> Type {
     let id = match vm.force(args[0].clone()) {
         Type::BufferRef(id) => id,
         other => vm.runtime_error(&format!(
-            "internal_buf_push_char expects buffer, found {:?}",
-            other
+            "internal_buf_push_char expects buffer, found {}",
+            vm.format_value(&other)
         )),
     };
+    vm.ensure_not_frozen(Frozen::Buffer(id), "internal_buf_push_char");
 
     let ch = match vm.force(args[1].clone()) {
         Type::Char(c) => c,
         other => vm.runtime_error(&format!(
-            "internal_buf_push_char expects char, found {:?}",
-            other
+            "internal_buf_push_char expects char, found {}",
+            vm.format_value(&other)
         )),
     };
 
@@ -224,16 +685,17 @@ fn native_buf_push_str(vm: &mut VM, args: Vec<Type>) -> Type {
     let id = match vm.force(args[0].clone()) {
         Type::BufferRef(id) => id,
         other => vm.runtime_error(&format!(
-            "internal_buf_push_str expects buffer, found {:?}",
-            other
+            "internal_buf_push_str expects buffer, found {}",
+            vm.format_value(&other)
         )),
     };
+    vm.ensure_not_frozen(Frozen::Buffer(id), "internal_buf_push_str");
 
     let str_id = match vm.force(args[1].clone()) {
         Type::ArrayRef(id) => id,
         other => vm.runtime_error(&format!(
-            "internal_buf_push_str expects string, found {:?}",
-            other
+            "internal_buf_push_str expects string, found {}",
+            vm.format_value(&other)
         )),
     };
 
@@ -242,8 +704,8 @@ fn native_buf_push_str(vm: &mut VM, args: Vec<Type>) -> Type {
         match vm.force(elem) {
             Type::Char(c) => vm.buffer_heap[id].push(c),
             other => vm.runtime_error(&format!(
-                "internal_buf_push_str expects string of chars, found {:?}",
-                other
+                "internal_buf_push_str expects string of chars, found {}",
+                vm.format_value(&other)
             )),
         }
     }
@@ -262,16 +724,13 @@ fn native_buf_to_string(vm: &mut VM, args: Vec<Type>) -> Type {
     let id = match vm.force(args[0].clone()) {
         Type::BufferRef(id) => id,
         other => vm.runtime_error(&format!(
-            "internal_buf_to_string expects buffer, found {:?}",
-            other
+            "internal_buf_to_string expects buffer, found {}",
+            vm.format_value(&other)
         )),
     };
 
     let elems: Vec<Type> = vm.buffer_heap[id].iter().map(|c| Type::Char(*c)).collect();
-    let arr_id = vm.array_heap.len();
-    vm.array_heap.push(elems);
-    vm.array_immutables.push(HashSet::new());
-    Type::ArrayRef(arr_id)
+    Type::ArrayRef(vm.alloc_array(elems, HashSet::new()))
 }
 
 fn native_buf_write_file(vm: &mut VM, args: Vec<Type>) -> Type {
@@ -285,8 +744,8 @@ fn native_buf_write_file(vm: &mut VM, args: Vec<Type>) -> Type {
     let id = match vm.force(args[0].clone()) {
         Type::BufferRef(id) => id,
         other => vm.runtime_error(&format!(
-            "internal_buf_write_file expects buffer, found {:?}",
-            other
+            "internal_buf_write_file expects buffer, found {}",
+            vm.format_value(&other)
         )),
     };
     let path = vm.value_to_string(args[1].clone(), "internal_buf_write_file path");
@@ -313,152 +772,2264 @@ fn native_buf_write_file(vm: &mut VM, args: Vec<Type>) -> Type {
         count += 1;
     }
 
-    let count_i32 =
-        i32::try_from(count).unwrap_or_else(|_| vm.runtime_error("buffer too large for int"));
-    Type::Integer(count_i32)
+    let count_i64 =
+        i64::try_from(count).unwrap_or_else(|_| vm.runtime_error("buffer too large for int"));
+    Type::Integer(count_i64)
 }
 
-fn native_vec_new(vm: &mut VM, args: Vec<Type>) -> Type {
+fn expect_buffer(vm: &mut VM, v: Type, what: &str) -> usize {
+    match vm.force(v) {
+        Type::BufferRef(id) => id,
+        other => vm.runtime_error(&format!(
+            "{what} expects buffer, found {}",
+            vm.format_value(&other)
+        )),
+    }
+}
+
+fn native_buf_len(vm: &mut VM, args: Vec<Type>) -> Type {
     if args.len() != 1 {
         vm.runtime_error(&format!(
-            "internal_vec_new expects 1 argument, got {}",
+            "internal_buf_len expects 1 argument, got {}",
             args.len()
         ));
     }
 
-    let cap = vm.as_usize_nonneg(args[0].clone(), "internal_vec_new capacity");
-    let id = vm.vec_heap.len();
-    vm.vec_heap.push(Vec::with_capacity(cap));
-    vm.vec_immutables.push(HashSet::new());
-    Type::VecRef(id)
+    let id = expect_buffer(vm, args[0].clone(), "internal_buf_len");
+    Type::Integer(vm.buffer_heap[id].len() as i64)
 }
 
-fn native_vec_push(vm: &mut VM, args: Vec<Type>) -> Type {
-    if args.len() != 2 {
+fn native_buf_clear(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
         vm.runtime_error(&format!(
-            "internal_vec_push expects 2 arguments, got {}",
+            "internal_buf_clear expects 1 argument, got {}",
             args.len()
         ));
     }
 
-    let id = match vm.force(args[0].clone()) {
-        Type::VecRef(id) => id,
-        other => vm.runtime_error(&format!("internal_vec_push expects vec, found {:?}", other)),
-    };
-
-    let val = args[1].clone();
-    vm.vec_heap[id].push(val);
-    Type::VecRef(id)
+    let id = expect_buffer(vm, args[0].clone(), "internal_buf_clear");
+    vm.ensure_not_frozen(Frozen::Buffer(id), "internal_buf_clear");
+    vm.buffer_heap[id].clear();
+    Type::BufferRef(id)
 }
 
-fn native_vec_pop(vm: &mut VM, args: Vec<Type>) -> Type {
-    if args.len() != 1 {
+fn native_buf_insert(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 3 {
         vm.runtime_error(&format!(
-            "internal_vec_pop expects 1 argument, got {}",
+            "internal_buf_insert expects 3 arguments, got {}",
             args.len()
         ));
     }
 
-    let id = match vm.force(args[0].clone()) {
-        Type::VecRef(id) => id,
-        other => vm.runtime_error(&format!("internal_vec_pop expects vec, found {:?}", other)),
-    };
+    let id = expect_buffer(vm, args[0].clone(), "internal_buf_insert");
+    vm.ensure_not_frozen(Frozen::Buffer(id), "internal_buf_insert");
+    let index = vm.as_usize_nonneg(args[1].clone(), "internal_buf_insert index");
+    let len = vm.buffer_heap[id].len();
+    if index > len {
+        vm.runtime_error(&format!(
+            "internal_buf_insert out of bounds: index {index}, length {len}"
+        ));
+    }
 
-    let value = vm.vec_heap[id]
-        .pop()
-        .unwrap_or_else(|| vm.runtime_error("internal_vec_pop on empty vec"));
-    let len = vm.vec_heap[id].len();
-    vm.vec_immutables[id].remove(&len);
-    value
+    // Accepts a single char or a whole string.
+    let chars: Vec<u32> = match vm.force(args[2].clone()) {
+        Type::Char(c) => vec![c],
+        s => vm
+            .value_to_string(s, "internal_buf_insert value")
+            .chars()
+            .map(|c| c as u32)
+            .collect(),
+    };
+    vm.buffer_heap[id].splice(index..index, chars);
+    Type::BufferRef(id)
 }
 
-const KEY_UP: i32 = 1000;
-const KEY_DOWN: i32 = 1001;
-const KEY_LEFT: i32 = 1002;
-const KEY_RIGHT: i32 = 1003;
-
-fn native_input_readline(vm: &mut VM, args: Vec<Type>) -> Type {
-    if !args.is_empty() {
+fn native_buf_slice(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 3 {
         vm.runtime_error(&format!(
-            "internal_input_readline expects 0 arguments, got {}",
+            "internal_buf_slice expects 3 arguments, got {}",
             args.len()
         ));
     }
 
-    #[cfg(unix)]
-    let restore = unix_suspend_raw_input();
+    let id = expect_buffer(vm, args[0].clone(), "internal_buf_slice");
+    let start = vm.as_usize_nonneg(args[1].clone(), "internal_buf_slice start");
+    let end = vm.as_usize_nonneg(args[2].clone(), "internal_buf_slice end");
+    let len = vm.buffer_heap[id].len();
+    if start > end || end > len {
+        vm.runtime_error(&format!(
+            "internal_buf_slice range {start}..{end} out of bounds for length {len}"
+        ));
+    }
 
-    #[cfg(windows)]
-    let restore = win_suspend_raw_input();
+    let chars = vm.buffer_heap[id][start..end].to_vec();
+    let new_id = vm.buffer_heap.len();
+    vm.buffer_heap.push(chars);
+    Type::BufferRef(new_id)
+}
 
-    let mut line = String::new();
-    io::stdin()
-        .read_line(&mut line)
-        .unwrap_or_else(|e| vm.runtime_error(&format!("internal_input_readline failed: {e}")));
+fn native_buf_from_string(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_buf_from_string expects 1 argument, got {}",
+            args.len()
+        ));
+    }
 
-    #[cfg(unix)]
-    unix_restore_raw_input(restore);
+    let s = vm.value_to_string(args[0].clone(), "internal_buf_from_string value");
+    let id = vm.buffer_heap.len();
+    vm.buffer_heap.push(s.chars().map(|c| c as u32).collect());
+    Type::BufferRef(id)
+}
 
-    #[cfg(windows)]
+fn native_buf_from_file(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_buf_from_file expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let path = vm.value_to_string(args[0].clone(), "internal_buf_from_file path");
+    let contents = vm.recorded_str("internal_buf_from_file", |vm| {
+        std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            vm.runtime_error(&format!(
+                "internal_buf_from_file failed for `{}`: {}",
+                path, e
+            ))
+        })
+    });
+    let id = vm.buffer_heap.len();
+    vm.buffer_heap
+        .push(contents.chars().map(|c| c as u32).collect());
+    Type::BufferRef(id)
+}
+
+fn expect_bytes(vm: &mut VM, v: Type, what: &str) -> usize {
+    match vm.force(v) {
+        Type::BytesRef(id) => id,
+        other => vm.runtime_error(&format!(
+            "{what} expects bytes, found {}",
+            vm.format_value(&other)
+        )),
+    }
+}
+
+fn expect_byte(vm: &mut VM, v: Type, what: &str) -> u8 {
+    let n = vm.as_int(v);
+    u8::try_from(n)
+        .unwrap_or_else(|_| vm.runtime_error(&format!("{what} must be a byte (0..255), got {n}")))
+}
+
+fn new_bytes(vm: &mut VM, bytes: Vec<u8>) -> Type {
+    let id = vm.bytes_heap.len();
+    vm.bytes_heap.push(bytes);
+    Type::BytesRef(id)
+}
+
+fn native_bytes_new(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_bytes_new expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let cap = vm.as_usize_nonneg(args[0].clone(), "internal_bytes_new capacity");
+    new_bytes(vm, Vec::with_capacity(cap))
+}
+
+fn native_bytes_len(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_bytes_len expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_bytes(vm, args[0].clone(), "internal_bytes_len");
+    Type::Integer(vm.bytes_heap[id].len() as i64)
+}
+
+fn native_bytes_get(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_bytes_get expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_bytes(vm, args[0].clone(), "internal_bytes_get");
+    let index = vm.as_usize_nonneg(args[1].clone(), "internal_bytes_get index");
+    let len = vm.bytes_heap[id].len();
+    if index >= len {
+        vm.runtime_error(&format!(
+            "internal_bytes_get out of bounds: index {index}, length {len}"
+        ));
+    }
+    Type::Integer(vm.bytes_heap[id][index].into())
+}
+
+fn native_bytes_set(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 3 {
+        vm.runtime_error(&format!(
+            "internal_bytes_set expects 3 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_bytes(vm, args[0].clone(), "internal_bytes_set");
+    vm.ensure_not_frozen(Frozen::Bytes(id), "internal_bytes_set");
+    let index = vm.as_usize_nonneg(args[1].clone(), "internal_bytes_set index");
+    let byte = expect_byte(vm, args[2].clone(), "internal_bytes_set value");
+    let len = vm.bytes_heap[id].len();
+    if index >= len {
+        vm.runtime_error(&format!(
+            "internal_bytes_set out of bounds: index {index}, length {len}"
+        ));
+    }
+    vm.bytes_heap[id][index] = byte;
+    Type::BytesRef(id)
+}
+
+fn native_bytes_push(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_bytes_push expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_bytes(vm, args[0].clone(), "internal_bytes_push");
+    vm.ensure_not_frozen(Frozen::Bytes(id), "internal_bytes_push");
+    let byte = expect_byte(vm, args[1].clone(), "internal_bytes_push value");
+    vm.bytes_heap[id].push(byte);
+    Type::BytesRef(id)
+}
+
+fn native_bytes_from_string(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_bytes_from_string expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let s = vm.value_to_string(args[0].clone(), "internal_bytes_from_string value");
+    new_bytes(vm, s.into_bytes())
+}
+
+fn native_bytes_to_string(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_bytes_to_string expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_bytes(vm, args[0].clone(), "internal_bytes_to_string");
+    let s = String::from_utf8(vm.bytes_heap[id].clone()).unwrap_or_else(|e| {
+        vm.runtime_error(&format!(
+            "internal_bytes_to_string: invalid UTF-8 at byte {}",
+            e.utf8_error().valid_up_to()
+        ))
+    });
+    vm.string_to_array(&s)
+}
+
+/// Hashable form of a value stored in a set. Ints and chars share a key
+/// (like `==`), arrays and vecs key on their contents, so strings dedupe by
+/// text. Contents are read when the value is added: mutating an array
+/// afterwards does not rehash it.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum SetKey {
+    Null,
+    Int(i64),
+    Seq(Vec<SetKey>),
+    Tuple(Vec<SetKey>),
+    Variant(String, String, Vec<SetKey>),
+}
+
+impl VM {
+    pub(crate) fn set_key(&mut self, v: Type, what: &str) -> SetKey {
+        let mut path = HashSet::new();
+        self.set_key_inner(v, what, &mut path)
+    }
+
+    fn set_key_inner(&mut self, v: Type, what: &str, path: &mut HashSet<(char, usize)>) -> SetKey {
+        match self.force(v) {
+            Type::Null => SetKey::Null,
+            Type::Integer(n) => SetKey::Int(n),
+            Type::Char(c) => SetKey::Int(c.into()),
+            Type::Tuple(elems) => SetKey::Tuple(self.set_keys(elems, what, path)),
+            Type::Variant(variant) => SetKey::Variant(
+                variant.enum_name,
+                variant.name,
+                self.set_keys(variant.payload, what, path),
+            ),
+            Type::ArrayRef(id) => {
+                let elems = self.array_heap[id].clone();
+                self.sequence_key(('a', id), elems, what, path)
+            }
+            Type::VecRef(id) => {
+                let elems = self.vec_heap[id].clone();
+                self.sequence_key(('v', id), elems, what, path)
+            }
+            other => {
+                let kind = self.type_name(other);
+                self.runtime_error(&format!("{what}: {kind} values are not hashable"))
+            }
+        }
+    }
+
+    fn sequence_key(
+        &mut self,
+        id: (char, usize),
+        elems: Vec<Type>,
+        what: &str,
+        path: &mut HashSet<(char, usize)>,
+    ) -> SetKey {
+        if !path.insert(id) {
+            self.runtime_error(&format!("{what}: cyclic values are not hashable"));
+        }
+        let key = SetKey::Seq(self.set_keys(elems, what, path));
+        path.remove(&id);
+        key
+    }
+
+    fn set_keys(
+        &mut self,
+        elems: Vec<Type>,
+        what: &str,
+        path: &mut HashSet<(char, usize)>,
+    ) -> Vec<SetKey> {
+        elems
+            .into_iter()
+            .map(|e| self.set_key_inner(e, what, path))
+            .collect()
+    }
+
+    pub(crate) fn new_set(&mut self, elems: HashMap<SetKey, Type>) -> Type {
+        let id = self.set_heap.len();
+        self.set_heap.push(elems);
+        Type::SetRef(id)
+    }
+}
+
+fn expect_set(vm: &mut VM, v: Type, what: &str) -> usize {
+    match vm.force(v) {
+        Type::SetRef(id) => id,
+        other => vm.runtime_error(&format!(
+            "{what} expects set, found {}",
+            vm.format_value(&other)
+        )),
+    }
+}
+
+fn native_set_new(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_set_new expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    vm.new_set(HashMap::new())
+}
+
+fn native_set_add(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_set_add expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_set(vm, args[0].clone(), "internal_set_add");
+    vm.ensure_not_frozen(Frozen::Set(id), "internal_set_add");
+    let value = vm.force(args[1].clone());
+    let key = vm.set_key(value.clone(), "internal_set_add");
+    if vm.set_heap[id].contains_key(&key) {
+        return Type::Integer(0);
+    }
+    vm.set_heap[id].insert(key, value);
+    Type::Integer(1)
+}
+
+fn native_set_contains(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_set_contains expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_set(vm, args[0].clone(), "internal_set_contains");
+    let key = vm.set_key(args[1].clone(), "internal_set_contains");
+    Type::Integer(vm.set_heap[id].contains_key(&key).into())
+}
+
+fn native_set_remove(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_set_remove expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_set(vm, args[0].clone(), "internal_set_remove");
+    vm.ensure_not_frozen(Frozen::Set(id), "internal_set_remove");
+    let key = vm.set_key(args[1].clone(), "internal_set_remove");
+    Type::Integer(vm.set_heap[id].remove(&key).is_some().into())
+}
+
+fn native_set_len(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_set_len expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_set(vm, args[0].clone(), "internal_set_len");
+    Type::Integer(vm.set_heap[id].len() as i64)
+}
+
+// Elements come out ordered by key (ints ascending, then sequences), so the
+// result does not depend on hash order.
+fn native_set_to_vec(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_set_to_vec expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_set(vm, args[0].clone(), "internal_set_to_vec");
+    let mut entries: Vec<(&SetKey, &Type)> = vm.set_heap[id].iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    let elems = entries.into_iter().map(|(_, v)| v.clone()).collect();
+    vm.values_to_vec(elems)
+}
+
+fn native_vec_new(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_vec_new expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let cap = vm.as_usize_nonneg(args[0].clone(), "internal_vec_new capacity");
+    Type::VecRef(vm.alloc_vec(Vec::with_capacity(cap), HashSet::new()))
+}
+
+fn native_vec_push(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_vec_push expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = match vm.force(args[0].clone()) {
+        Type::VecRef(id) => id,
+        other => vm.runtime_error(&format!(
+            "internal_vec_push expects vec, found {}",
+            vm.format_value(&other)
+        )),
+    };
+    vm.ensure_not_frozen(Frozen::Vec(id), "internal_vec_push");
+
+    let val = args[1].clone();
+    vm.vec_heap[id].push(val);
+    Type::VecRef(id)
+}
+
+fn native_vec_pop(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_vec_pop expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = match vm.force(args[0].clone()) {
+        Type::VecRef(id) => id,
+        other => vm.runtime_error(&format!(
+            "internal_vec_pop expects vec, found {}",
+            vm.format_value(&other)
+        )),
+    };
+    vm.ensure_not_frozen(Frozen::Vec(id), "internal_vec_pop");
+
+    let value = vm.vec_heap[id]
+        .pop()
+        .unwrap_or_else(|| vm.runtime_error("internal_vec_pop on empty vec"));
+    let len = vm.vec_heap[id].len();
+    vm.vec_immutables[id].remove(&len);
+    value
+}
+
+fn expect_vec(vm: &mut VM, v: Type, what: &str) -> usize {
+    match vm.force(v) {
+        Type::VecRef(id) => id,
+        other => vm.runtime_error(&format!(
+            "{what} expects vec, found {}",
+            vm.format_value(&other)
+        )),
+    }
+}
+
+fn expect_vec_index(vm: &mut VM, v: Type, len: usize, what: &str) -> usize {
+    let index = vm.as_usize_nonneg(v, &format!("{what} index"));
+    if index >= len {
+        vm.runtime_error(&format!(
+            "{what} out of bounds: index {index}, length {len}"
+        ));
+    }
+    index
+}
+
+fn native_vec_len(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_vec_len expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_vec(vm, args[0].clone(), "internal_vec_len");
+    let len = vm.vec_heap[id].len();
+    Type::Integer(len as i64)
+}
+
+fn native_vec_get(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_vec_get expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_vec(vm, args[0].clone(), "internal_vec_get");
+    let len = vm.vec_heap[id].len();
+    let index = expect_vec_index(vm, args[1].clone(), len, "internal_vec_get");
+    let v = vm.vec_heap[id][index].clone();
+    vm.force(v)
+}
+
+fn native_vec_set(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 3 {
+        vm.runtime_error(&format!(
+            "internal_vec_set expects 3 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_vec(vm, args[0].clone(), "internal_vec_set");
+    vm.ensure_not_frozen(Frozen::Vec(id), "internal_vec_set");
+    let len = vm.vec_heap[id].len();
+    let index = expect_vec_index(vm, args[1].clone(), len, "internal_vec_set");
+    if vm.vec_immutables[id].contains(&index) {
+        vm.runtime_error("cannot reassign immutable vec element");
+    }
+    vm.vec_heap[id][index] = args[2].clone();
+    Type::VecRef(id)
+}
+
+fn native_vec_insert(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 3 {
+        vm.runtime_error(&format!(
+            "internal_vec_insert expects 3 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_vec(vm, args[0].clone(), "internal_vec_insert");
+    vm.ensure_not_frozen(Frozen::Vec(id), "internal_vec_insert");
+    // Inserting at `len` appends.
+    let len = vm.vec_heap[id].len();
+    let index = expect_vec_index(vm, args[1].clone(), len + 1, "internal_vec_insert");
+    vm.vec_heap[id].insert(index, args[2].clone());
+
+    // Immutable elements move with their values.
+    let shifted = vm.vec_immutables[id]
+        .iter()
+        .map(|&i| if i >= index { i + 1 } else { i })
+        .collect();
+    vm.vec_immutables[id] = shifted;
+    Type::VecRef(id)
+}
+
+fn native_vec_remove(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_vec_remove expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_vec(vm, args[0].clone(), "internal_vec_remove");
+    vm.ensure_not_frozen(Frozen::Vec(id), "internal_vec_remove");
+    let len = vm.vec_heap[id].len();
+    let index = expect_vec_index(vm, args[1].clone(), len, "internal_vec_remove");
+    let value = vm.vec_heap[id].remove(index);
+
+    let shifted = vm.vec_immutables[id]
+        .iter()
+        .filter(|&&i| i != index)
+        .map(|&i| if i > index { i - 1 } else { i })
+        .collect();
+    vm.vec_immutables[id] = shifted;
+    vm.force(value)
+}
+
+fn native_vec_clear(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_vec_clear expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_vec(vm, args[0].clone(), "internal_vec_clear");
+    vm.ensure_not_frozen(Frozen::Vec(id), "internal_vec_clear");
+    vm.vec_heap[id].clear();
+    vm.vec_immutables[id].clear();
+    Type::VecRef(id)
+}
+
+fn native_vec_contains(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_vec_contains expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_vec(vm, args[0].clone(), "internal_vec_contains");
+    let elems = vm.vec_heap[id].clone();
+    for elem in elems {
+        if vm.deep_equal(elem, args[1].clone()) {
+            return Type::Integer(1);
+        }
+    }
+    Type::Integer(0)
+}
+
+// Calls a Reactive comparator; negative, zero and positive results mean
+// less, equal and greater.
+fn compare_with(vm: &mut VM, cmp: &Type, a: Type, b: Type, what: &str) -> Ordering {
+    let result = vm.call_value(cmp.clone(), vec![a, b], what);
+    match vm.force(result) {
+        Type::Integer(n) => n.cmp(&0),
+        other => vm.runtime_error(&format!(
+            "{what}: comparator must return an int, found {}",
+            vm.format_value(&other)
+        )),
+    }
+}
+
+fn native_vec_sort(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_vec_sort expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_vec(vm, args[0].clone(), "internal_vec_sort");
+    vm.ensure_not_frozen(Frozen::Vec(id), "internal_vec_sort");
+    let elems: Vec<Type> = vm.vec_heap[id].clone();
+    let elems: Vec<Type> = elems.into_iter().map(|e| vm.force(e)).collect();
+
+    // Merge sort over indices: stable, and tolerant of comparators that are
+    // not a consistent total order (the result is then unspecified, not a panic).
+    // The vec is busy meanwhile, so the comparator cannot change what is
+    // written back.
+    let mut order: Vec<usize> = (0..elems.len()).collect();
+    let mut scratch = order.clone();
+    vm.while_busy(Frozen::Vec(id), "internal_vec_sort", |vm| {
+        merge_sort(&mut order, &mut scratch, &mut |x, y| {
+            compare_with(
+                vm,
+                &args[1],
+                elems[x].clone(),
+                elems[y].clone(),
+                "internal_vec_sort",
+            )
+        })
+    });
+
+    // Immutable elements move with their values.
+    let immutables = &vm.vec_immutables[id];
+    let moved = order
+        .iter()
+        .enumerate()
+        .filter(|(_, old)| immutables.contains(old))
+        .map(|(new, _)| new)
+        .collect();
+    let sorted = order.iter().map(|&i| elems[i].clone()).collect();
+    vm.vec_heap[id] = sorted;
+    vm.vec_immutables[id] = moved;
+    Type::VecRef(id)
+}
+
+/// Binary min-heap ordered by a Reactive comparator; `items[0]` is the
+/// element the comparator ranks lowest.
+#[derive(Clone)]
+pub(crate) struct PriorityQueue {
+    pub(crate) cmp: Type,
+    pub(crate) items: Vec<Type>,
+}
+
+fn expect_pq(vm: &mut VM, v: Type, what: &str) -> usize {
+    match vm.force(v) {
+        Type::PqRef(id) => id,
+        other => vm.runtime_error(&format!(
+            "{what} expects priority queue, found {}",
+            vm.format_value(&other)
+        )),
+    }
+}
+
+fn native_pq_new(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_pq_new expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let cmp = vm.force(args[0].clone());
+    let id = vm.pq_heap.len();
+    vm.pq_heap.push(PriorityQueue {
+        cmp,
+        items: Vec::new(),
+    });
+    Type::PqRef(id)
+}
+
+// The comparator runs with the items in place and the queue busy, so a
+// comparator that looks at the same queue sees every item, and one that
+// pushes to or pops from it raises a runtime error.
+fn native_pq_push(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_pq_push expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_pq(vm, args[0].clone(), "internal_pq_push");
+    vm.ensure_not_frozen(Frozen::Pq(id), "internal_pq_push");
+    let cmp = vm.pq_heap[id].cmp.clone();
+    let item = vm.force(args[1].clone());
+    vm.pq_heap[id].items.push(item);
+
+    vm.while_busy(Frozen::Pq(id), "internal_pq_push", |vm| {
+        let mut child = vm.pq_heap[id].items.len() - 1;
+        while child > 0 {
+            let parent = (child - 1) / 2;
+            let items = &vm.pq_heap[id].items;
+            let (a, b) = (items[child].clone(), items[parent].clone());
+            if compare_with(vm, &cmp, a, b, "internal_pq_push") != Ordering::Less {
+                break;
+            }
+            vm.pq_heap[id].items.swap(child, parent);
+            child = parent;
+        }
+    });
+
+    Type::Integer(vm.pq_heap[id].items.len() as i64)
+}
+
+fn native_pq_pop_min(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_pq_pop_min expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_pq(vm, args[0].clone(), "internal_pq_pop_min");
+    vm.ensure_not_frozen(Frozen::Pq(id), "internal_pq_pop_min");
+    if vm.pq_heap[id].items.is_empty() {
+        vm.runtime_error("internal_pq_pop_min on empty priority queue");
+    }
+    let cmp = vm.pq_heap[id].cmp.clone();
+    let min = vm.pq_heap[id].items.swap_remove(0);
+
+    vm.while_busy(Frozen::Pq(id), "internal_pq_pop_min", |vm| {
+        let mut parent = 0;
+        loop {
+            let mut smallest = parent;
+            for child in [2 * parent + 1, 2 * parent + 2] {
+                let items = &vm.pq_heap[id].items;
+                if child >= items.len() {
+                    continue;
+                }
+                let (a, b) = (items[child].clone(), items[smallest].clone());
+                if compare_with(vm, &cmp, a, b, "internal_pq_pop_min") == Ordering::Less {
+                    smallest = child;
+                }
+            }
+            if smallest == parent {
+                break;
+            }
+            vm.pq_heap[id].items.swap(parent, smallest);
+            parent = smallest;
+        }
+    });
+
+    min
+}
+
+fn native_pq_len(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_pq_len expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_pq(vm, args[0].clone(), "internal_pq_len");
+    Type::Integer(vm.pq_heap[id].items.len() as i64)
+}
+
+fn merge_sort(
+    items: &mut [usize],
+    scratch: &mut [usize],
+    cmp: &mut impl FnMut(usize, usize) -> Ordering,
+) {
+    let len = items.len();
+    if len < 2 {
+        return;
+    }
+    let mid = len / 2;
+    merge_sort(&mut items[..mid], &mut scratch[..mid], cmp);
+    merge_sort(&mut items[mid..], &mut scratch[mid..], cmp);
+
+    let (mut i, mut j) = (0, mid);
+    for slot in scratch[..len].iter_mut() {
+        if j >= len || (i < mid && cmp(items[i], items[j]) != Ordering::Greater) {
+            *slot = items[i];
+            i += 1;
+        } else {
+            *slot = items[j];
+            j += 1;
+        }
+    }
+    items.copy_from_slice(&scratch[..len]);
+}
+
+fn native_vec_binary_search(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 3 {
+        vm.runtime_error(&format!(
+            "internal_vec_binary_search expects 3 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_vec(vm, args[0].clone(), "internal_vec_binary_search");
+    let elems: Vec<Type> = vm.vec_heap[id].clone();
+    let key = vm.force(args[1].clone());
+
+    let (mut lo, mut hi) = (0, elems.len());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let elem = vm.force(elems[mid].clone());
+        match compare_with(
+            vm,
+            &args[2],
+            elem,
+            key.clone(),
+            "internal_vec_binary_search",
+        ) {
+            Ordering::Less => lo = mid + 1,
+            Ordering::Greater => hi = mid,
+            Ordering::Equal => return Type::Integer(mid as i64),
+        }
+    }
+    // Not found: encode the insertion point so callers can keep the vec sorted.
+    Type::Integer(-(lo as i64) - 1)
+}
+
+fn native_vec_map(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_vec_map expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_vec(vm, args[0].clone(), "internal_vec_map");
+    let elems = vm.vec_heap[id].clone();
+    let mut out = Vec::with_capacity(elems.len());
+    for elem in elems {
+        let elem = vm.force(elem);
+        let mapped = vm.call_value(args[1].clone(), vec![elem], "internal_vec_map");
+        out.push(mapped);
+    }
+    vm.values_to_vec(out)
+}
+
+/// Like `internal_vec_map`, but the vec is split into one contiguous shard
+/// per available core and each shard is mapped by a worker VM (see
+/// `std.thread`) holding copies of the elements and the program's globals.
+fn native_vec_par_map(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_vec_par_map expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_vec(vm, args[0].clone(), "internal_vec_par_map");
+    let elems = vm.vec_heap[id].clone();
+    if elems.is_empty() {
+        return vm.values_to_vec(Vec::new());
+    }
+
+    let f = vm
+        .export_value(args[1].clone())
+        .unwrap_or_else(|e| vm.runtime_error(&format!("internal_vec_par_map: {e}")));
+    let mut messages = Vec::with_capacity(elems.len());
+    for elem in elems {
+        let message = vm
+            .export_value(elem)
+            .unwrap_or_else(|e| vm.runtime_error(&format!("internal_vec_par_map: {e}")));
+        messages.push(message);
+    }
+
+    let workers = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(messages.len());
+    let shard_len = messages.len().div_ceil(workers);
+    let mut shards = Vec::with_capacity(workers);
+    while !messages.is_empty() {
+        let rest = messages.split_off(shard_len.min(messages.len()));
+        shards.push(std::mem::replace(&mut messages, rest));
+    }
+
+    let seed = vm.worker_seed();
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = shards
+            .into_iter()
+            .map(|shard| {
+                let (seed, f) = (seed.clone(), f.clone());
+                std::thread::Builder::new()
+                    .stack_size(VM_STACK_SIZE)
+                    .spawn_scoped(scope, move || run_map_shard(seed, f, shard))
+            })
+            .collect();
+        handles.into_iter().map(|h| h.map(|h| h.join())).collect()
+    });
+
+    let mut out = Vec::new();
+    for result in results {
+        match result {
+            Ok(Ok(Ok(mapped))) => {
+                for message in mapped {
+                    out.push(vm.import_value(message));
+                }
+            }
+            Ok(Ok(Err(e))) => {
+                vm.runtime_error(&format!("internal_vec_par_map: worker failed: {e}"))
+            }
+            Ok(Err(_)) => vm.runtime_error("internal_vec_par_map: worker thread panicked"),
+            Err(e) => vm.runtime_error(&format!("internal_vec_par_map: spawn failed: {e}")),
+        }
+    }
+    vm.values_to_vec(out)
+}
+
+fn native_vec_filter(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_vec_filter expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_vec(vm, args[0].clone(), "internal_vec_filter");
+    let elems = vm.vec_heap[id].clone();
+    let mut out = Vec::new();
+    for elem in elems {
+        let elem = vm.force(elem);
+        let keep = vm.call_value(args[1].clone(), vec![elem.clone()], "internal_vec_filter");
+        if vm.as_int(keep) != 0 {
+            out.push(elem);
+        }
+    }
+    vm.values_to_vec(out)
+}
+
+fn native_vec_reduce(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 3 {
+        vm.runtime_error(&format!(
+            "internal_vec_reduce expects 3 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_vec(vm, args[0].clone(), "internal_vec_reduce");
+    let elems = vm.vec_heap[id].clone();
+    let mut acc = vm.force(args[1].clone());
+    for elem in elems {
+        let elem = vm.force(elem);
+        acc = vm.call_value(args[2].clone(), vec![acc, elem], "internal_vec_reduce");
+    }
+    acc
+}
+
+fn native_vec_free(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_vec_free expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    match vm.force(args[0].clone()) {
+        Type::VecRef(id) => {
+            vm.ensure_not_frozen(Frozen::Vec(id), "internal_vec_free");
+            vm.free_vec(id)
+        }
+        other => vm.runtime_error(&format!(
+            "internal_vec_free expects vec, found {}",
+            vm.format_value(&other)
+        )),
+    }
+    Type::Integer(0)
+}
+
+fn expect_struct(vm: &mut VM, v: Type, what: &str) -> usize {
+    match vm.force(v) {
+        Type::StructRef(id) => id,
+        other => vm.runtime_error(&format!(
+            "{what} expects struct, found {}",
+            vm.format_value(&other)
+        )),
+    }
+}
+
+fn native_on_destroy(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_on_destroy expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_struct(vm, args[0].clone(), "internal_on_destroy");
+    if vm.destroyed_structs.contains(&id) {
+        vm.runtime_error(&format!("internal_on_destroy on destroyed struct#{id}"));
+    }
+    let f = vm.force(args[1].clone());
+    vm.finalizers.insert(id, f);
+    Type::StructRef(id)
+}
+
+// The finalizer is unregistered before it runs, so destroying the struct
+// again from inside it is reported as a double destroy instead of recursing.
+fn destroy_struct(vm: &mut VM, id: usize, what: &str) -> Type {
+    if !vm.destroyed_structs.insert(id) {
+        vm.runtime_error(&format!("double destroy of struct#{id}"));
+    }
+    match vm.finalizers.remove(&id) {
+        Some(f) => vm.call_value(f, vec![Type::StructRef(id)], what),
+        None => Type::Integer(0),
+    }
+}
+
+fn native_destroy(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_destroy expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_struct(vm, args[0].clone(), "internal_destroy");
+    destroy_struct(vm, id, "internal_destroy")
+}
+
+// A runtime error inside `f` is caught only long enough to release the
+// resource, then continues unwinding to the host. Without
+// `unwind_on_error` (the CLI) the error exits the process on the spot: the
+// OS closes the handle, but a struct's finalizer does not run.
+fn native_with(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_with expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let resource = vm.force(args[0].clone());
+    if !matches!(resource, Type::HandleRef(_) | Type::StructRef(_)) {
+        vm.runtime_error(&format!(
+            "internal_with expects a handle or struct, found {}",
+            vm.format_value(&resource)
+        ));
+    }
+    let f = args[1].clone();
+    let result = vm.catch_callback_error(f, vec![resource.clone()], "internal_with");
+
+    // `f` may already have released the resource itself.
+    match resource {
+        Type::HandleRef(id) if vm.open_resource(id).is_some() => vm.close_handle(id),
+        Type::StructRef(id) if !vm.destroyed_structs.contains(&id) => {
+            destroy_struct(vm, id, "internal_with");
+        }
+        _ => {}
+    }
+    result.unwrap_or_else(|error| std::panic::resume_unwind(Box::new(error)))
+}
+
+fn native_array_free(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_array_free expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    match vm.force(args[0].clone()) {
+        Type::ArrayRef(id) => {
+            vm.ensure_not_frozen(Frozen::Array(id), "internal_array_free");
+            vm.free_array(id)
+        }
+        other => vm.runtime_error(&format!(
+            "internal_array_free expects array, found {}",
+            vm.format_value(&other)
+        )),
+    }
+    Type::Integer(0)
+}
+
+fn native_clone(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_clone expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let v = vm.force(args[0].clone());
+    vm.deep_copy(v)
+}
+
+fn native_freeze(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_freeze expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let v = vm.force(args[0].clone());
+    vm.freeze(v.clone());
+    v
+}
+
+fn native_is_frozen(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_is_frozen expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let v = vm.force(args[0].clone());
+    let frozen = Frozen::of(&v).is_some_and(|object| vm.is_frozen(object));
+    Type::Integer(frozen.into())
+}
+
+fn native_array_slice(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 3 {
+        vm.runtime_error(&format!(
+            "internal_array_slice expects 3 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    vm.array_slice(args[0].clone(), args[1].clone(), args[2].clone())
+}
+
+fn native_array_concat(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_array_concat expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    vm.array_concat(args[0].clone(), args[1].clone())
+}
+
+fn int_args<const N: usize>(vm: &mut VM, args: Vec<Type>, name: &str) -> [i64; N] {
+    if args.len() != N {
+        vm.runtime_error(&format!(
+            "{name} expects {N} argument(s), got {}",
+            args.len()
+        ));
+    }
+    let mut out = [0; N];
+    for (slot, arg) in out.iter_mut().zip(args) {
+        *slot = vm.as_int(arg);
+    }
+    out
+}
+
+fn native_math_abs(vm: &mut VM, args: Vec<Type>) -> Type {
+    let [x] = int_args(vm, args, "internal_math_abs");
+    let n = vm.apply_overflow_policy("Abs", x, 0, |x, _| x.checked_abs(), |x, _| x.wrapping_abs());
+    Type::Integer(n)
+}
+
+fn native_math_min(vm: &mut VM, args: Vec<Type>) -> Type {
+    let [x, y] = int_args(vm, args, "internal_math_min");
+    Type::Integer(x.min(y))
+}
+
+fn native_math_max(vm: &mut VM, args: Vec<Type>) -> Type {
+    let [x, y] = int_args(vm, args, "internal_math_max");
+    Type::Integer(x.max(y))
+}
+
+fn native_math_clamp(vm: &mut VM, args: Vec<Type>) -> Type {
+    let [x, lo, hi] = int_args(vm, args, "internal_math_clamp");
+    // The lower bound wins when the bounds are swapped, as the std.maths
+    // version always did (`i64::clamp` would panic instead).
+    let n = if x < lo {
+        lo
+    } else if x > hi {
+        hi
+    } else {
+        x
+    };
+    Type::Integer(n)
+}
+
+fn native_math_sqrt(vm: &mut VM, args: Vec<Type>) -> Type {
+    let [x] = int_args(vm, args, "internal_math_sqrt");
+    if x < 0 {
+        vm.runtime_error(&format!("internal_math_sqrt of negative number {x}"));
+    }
+    Type::Integer(x.isqrt())
+}
+
+fn native_math_pow(vm: &mut VM, args: Vec<Type>) -> Type {
+    let [base, exp] = int_args(vm, args, "internal_math_pow");
+    if exp < 0 {
+        vm.runtime_error(&format!("internal_math_pow negative exponent {exp}"));
+    }
+    let n = vm.apply_overflow_policy("Pow", base, exp, checked_pow, wrapping_pow);
+    Type::Integer(n)
+}
+
+// Square-and-multiply over the whole (non-negative) i64 exponent; the std
+// `pow`s only take a u32 exponent. Squaring only happens while exponent bits
+// remain, so an overflow there means the result overflows too.
+fn checked_pow(mut base: i64, mut exp: i64) -> Option<i64> {
+    let mut acc: i64 = 1;
+    loop {
+        if exp & 1 == 1 {
+            acc = acc.checked_mul(base)?;
+        }
+        exp >>= 1;
+        if exp == 0 {
+            return Some(acc);
+        }
+        base = base.checked_mul(base)?;
+    }
+}
+
+fn wrapping_pow(mut base: i64, mut exp: i64) -> i64 {
+    let mut acc: i64 = 1;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            acc = acc.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exp >>= 1;
+    }
+    acc
+}
+
+// Integers have no fractional part, so trig works in fixed point:
+// angles and results are scaled by `scale` (e.g. 1000 for milli-units).
+fn fixed_point_scale(vm: &mut VM, scale: i64, name: &str) -> f64 {
+    if scale <= 0 {
+        vm.runtime_error(&format!("{name} scale must be positive, got {scale}"));
+    }
+    scale as f64
+}
+
+fn native_math_sin(vm: &mut VM, args: Vec<Type>) -> Type {
+    let [x, scale] = int_args(vm, args, "internal_math_sin");
+    let s = fixed_point_scale(vm, scale, "internal_math_sin");
+    Type::Integer(((x as f64 / s).sin() * s).round() as i64)
+}
+
+fn native_math_cos(vm: &mut VM, args: Vec<Type>) -> Type {
+    let [x, scale] = int_args(vm, args, "internal_math_cos");
+    let s = fixed_point_scale(vm, scale, "internal_math_cos");
+    Type::Integer(((x as f64 / s).cos() * s).round() as i64)
+}
+
+fn native_math_atan2(vm: &mut VM, args: Vec<Type>) -> Type {
+    let [y, x, scale] = int_args(vm, args, "internal_math_atan2");
+    let s = fixed_point_scale(vm, scale, "internal_math_atan2");
+    Type::Integer(((y as f64).atan2(x as f64) * s).round() as i64)
+}
+
+fn native_trace(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_trace expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    // Most recent call first, skipping the native frame for internal_trace itself.
+    let names: Vec<String> = vm
+        .call_stack
+        .iter()
+        .rev()
+        .skip(1)
+        .map(|frame| frame.function_name.clone())
+        .collect();
+
+    let elems = names.iter().map(|name| vm.string_to_array(name)).collect();
+    vm.values_to_vec(elems)
+}
+
+fn native_typeof(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_typeof expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let name = vm.type_name(args[0].clone());
+    vm.string_to_array(&name)
+}
+
+fn native_assert_eq(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_assert_eq expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    vm.assert_compare(args[0].clone(), args[1].clone(), true);
+    Type::Integer(0)
+}
+
+fn native_assert_ne(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_assert_ne expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    vm.assert_compare(args[0].clone(), args[1].clone(), false);
+    Type::Integer(0)
+}
+
+fn native_backtrace(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_backtrace expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    // Each frame saves its caller's pointer, so a function's current position
+    // lives in the frame above it. The last frame is internal_backtrace itself.
+    let frames = &vm.call_stack[..vm.call_stack.len() - 1];
+    let mut lines = Vec::with_capacity(frames.len() + 1);
+    for (i, frame) in frames.iter().enumerate().rev() {
+        let at = match vm.call_stack.get(i + 1) {
+            Some(callee) if i + 1 < frames.len() => callee.pointer,
+            _ => vm.pointer,
+        };
+        lines.push(format!("{} @ {}", frame.function_name, at));
+    }
+    let top_level_at = match vm.call_stack.first() {
+        Some(first) if !frames.is_empty() => first.pointer,
+        _ => vm.pointer,
+    };
+    lines.push(format!("{} @ {}", TOP_LEVEL, top_level_at));
+
+    let elems = lines.iter().map(|line| vm.string_to_array(line)).collect();
+    vm.values_to_vec(elems)
+}
+
+fn native_heap_dump(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_heap_dump expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let dump = vm.heap_dump();
+    vm.string_to_array(&dump)
+}
+
+const KEY_UP: i32 = 1000;
+const KEY_DOWN: i32 = 1001;
+const KEY_LEFT: i32 = 1002;
+const KEY_RIGHT: i32 = 1003;
+
+fn native_input_readline(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_input_readline expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let line = vm.recorded_str("internal_input_readline", read_stdin_line);
+    vm.string_to_array(&line)
+}
+
+fn read_stdin_line(vm: &mut VM) -> String {
+    #[cfg(unix)]
+    let restore = unix_suspend_raw_input();
+
+    #[cfg(windows)]
+    let restore = win_suspend_raw_input();
+
+    let mut line = String::new();
+    io::stdin()
+        .read_line(&mut line)
+        .unwrap_or_else(|e| vm.runtime_error(&format!("internal_input_readline failed: {e}")));
+
+    #[cfg(unix)]
+    unix_restore_raw_input(restore);
+
+    #[cfg(windows)]
     win_restore_raw_input(restore);
 
-    while line.ends_with('\n') || line.ends_with('\r') {
-        line.pop();
+    while line.ends_with('\n') || line.ends_with('\r') {
+        line.pop();
+    }
+    line
+}
+
+fn native_input_init(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_input_init expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    #[cfg(unix)]
+    unix_input_init(vm);
+
+    #[cfg(windows)]
+    win_input_init(vm);
+
+    Type::Integer(0)
+}
+
+fn native_input_poll(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_input_poll expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    Type::Integer(vm.recorded_int("internal_input_poll", poll_key))
+}
+
+fn poll_key(vm: &mut VM) -> i64 {
+    #[cfg(unix)]
+    return unix_input_poll(vm).into();
+
+    #[cfg(windows)]
+    return win_input_poll(vm).into();
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        vm.runtime_error("internal_input_poll is not supported on this platform");
+    }
+}
+
+fn native_input_shutdown(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_input_shutdown expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    #[cfg(unix)]
+    unix_input_shutdown(vm);
+
+    #[cfg(windows)]
+    win_input_shutdown(vm);
+
+    Type::Integer(0)
+}
+
+fn datetime_struct(vm: &mut VM, ts: i64) -> Type {
+    let dt = crate::datetime::from_timestamp(ts);
+    let fields = [
+        ("timestamp", ts),
+        ("year", dt.year),
+        ("month", dt.month),
+        ("day", dt.day),
+        ("hour", dt.hour),
+        ("minute", dt.minute),
+        ("second", dt.second),
+        ("weekday", dt.weekday),
+    ];
+    let id = vm.heap.len();
+    vm.heap.push(StructInstance {
+        name: "DateTime".to_string(),
+        fields: fields
+            .into_iter()
+            .map(|(name, v)| (name.to_string(), Type::Integer(v)))
+            .collect(),
+        immutables: HashSet::new(),
+    });
+    Type::StructRef(id)
+}
+
+fn native_datetime_now(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_datetime_now expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let ts = vm.recorded_int("internal_datetime_now", |vm| {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_else(|_| vm.runtime_error("system clock is before the Unix epoch"));
+        now.as_secs() as i64
+    });
+    datetime_struct(vm, ts)
+}
+
+fn native_datetime_from_ts(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_datetime_from_timestamp expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let ts = vm.as_int(args[0].clone());
+    datetime_struct(vm, ts)
+}
+
+fn native_datetime_format(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_datetime_format expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let ts = vm.as_int(args[0].clone());
+    let fmt = vm.value_to_string(args[1].clone(), "internal_datetime_format format");
+    let text = crate::datetime::format(ts, &fmt)
+        .unwrap_or_else(|e| vm.runtime_error(&format!("internal_datetime_format: {e}")));
+    vm.string_to_array(&text)
+}
+
+fn native_datetime_parse(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_datetime_parse expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let text = vm.value_to_string(args[0].clone(), "internal_datetime_parse input");
+    let fmt = vm.value_to_string(args[1].clone(), "internal_datetime_parse format");
+    let ts = crate::datetime::parse(&text, &fmt)
+        .unwrap_or_else(|e| vm.runtime_error(&format!("internal_datetime_parse: {e}")));
+    Type::Integer(ts)
+}
+
+// OS randomness: /dev/urandom where available, otherwise the randomly keyed
+// SipHash behind std's HashMap (unpredictable, but not a CSPRNG).
+pub(super) fn os_random_bytes(n: usize) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        let mut buf = vec![0u8; n];
+        if let Ok(mut f) = std::fs::File::open("/dev/urandom")
+            && f.read_exact(&mut buf).is_ok()
+        {
+            return buf;
+        }
+    }
+
+    use std::hash::{BuildHasher, Hasher};
+    let state = std::collections::hash_map::RandomState::new();
+    let mut out = Vec::with_capacity(n + 8);
+    let mut counter = 0u64;
+    while out.len() < n {
+        let mut hasher = state.build_hasher();
+        hasher.write_u64(counter);
+        hasher.write_u128(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0),
+        );
+        out.extend_from_slice(&hasher.finish().to_le_bytes());
+        counter += 1;
+    }
+    out.truncate(n);
+    out
+}
+
+fn native_uuid_v4(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_uuid_v4 expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let uuid = vm.recorded_str("internal_uuid_v4", |_| {
+        let mut b = os_random_bytes(16);
+        b[6] = (b[6] & 0x0f) | 0x40; // version 4
+        b[8] = (b[8] & 0x3f) | 0x80; // RFC 4122 variant
+        let hex = crate::hash::to_hex(&b);
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    });
+    vm.string_to_array(&uuid)
+}
+
+fn native_nonce(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_nonce expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let n = vm.as_usize_nonneg(args[0].clone(), "internal_nonce length");
+    let nonce = vm.recorded_str("internal_nonce", |_| {
+        crate::hash::to_hex(&os_random_bytes(n))
+    });
+    vm.string_to_array(&nonce)
+}
+
+// Strings and buffers hash as UTF-8, byte buffers as their raw bytes.
+fn bytes_input(vm: &mut VM, args: Vec<Type>, name: &str) -> Vec<u8> {
+    if args.len() != 1 {
+        vm.runtime_error(&format!("{name} expects 1 argument, got {}", args.len()));
+    }
+
+    match vm.force(args[0].clone()) {
+        Type::BytesRef(id) => vm.bytes_heap[id].clone(),
+        Type::BufferRef(id) => {
+            let mut out = String::with_capacity(vm.buffer_heap[id].len());
+            for &c in &vm.buffer_heap[id] {
+                match char::from_u32(c) {
+                    Some(ch) => out.push(ch),
+                    None => vm.runtime_error(&format!("invalid char code {c} in buffer")),
+                }
+            }
+            out.into_bytes()
+        }
+        other => vm
+            .value_to_string(other, &format!("{name} input"))
+            .into_bytes(),
+    }
+}
+
+fn native_hash_crc32(vm: &mut VM, args: Vec<Type>) -> Type {
+    let data = bytes_input(vm, args, "internal_hash_crc32");
+    Type::Integer(crate::hash::crc32(&data).into())
+}
+
+fn native_hash_fnv(vm: &mut VM, args: Vec<Type>) -> Type {
+    let data = bytes_input(vm, args, "internal_hash_fnv");
+    // Reinterpret the 64 hash bits as an int; the value may be negative.
+    Type::Integer(crate::hash::fnv1a64(&data) as i64)
+}
+
+fn native_hash_sha256(vm: &mut VM, args: Vec<Type>) -> Type {
+    let data = bytes_input(vm, args, "internal_hash_sha256");
+    let hex = crate::hash::to_hex(&crate::hash::sha256(&data));
+    vm.string_to_array(&hex)
+}
+
+fn native_gzip_compress(vm: &mut VM, args: Vec<Type>) -> Type {
+    let data = bytes_input(vm, args, "internal_gzip_compress");
+    let out = compress(vm, Codec::Gzip, &data, "internal_gzip_compress");
+    new_bytes(vm, out)
+}
+
+fn native_gzip_decompress(vm: &mut VM, args: Vec<Type>) -> Type {
+    let data = bytes_input(vm, args, "internal_gzip_decompress");
+    let out = decompress(vm, Codec::Gzip, &data, "internal_gzip_decompress");
+    new_bytes(vm, out)
+}
+
+fn native_zlib_compress(vm: &mut VM, args: Vec<Type>) -> Type {
+    let data = bytes_input(vm, args, "internal_zlib_compress");
+    let out = compress(vm, Codec::Zlib, &data, "internal_zlib_compress");
+    new_bytes(vm, out)
+}
+
+fn native_zlib_decompress(vm: &mut VM, args: Vec<Type>) -> Type {
+    let data = bytes_input(vm, args, "internal_zlib_decompress");
+    let out = decompress(vm, Codec::Zlib, &data, "internal_zlib_decompress");
+    new_bytes(vm, out)
+}
+
+#[derive(Clone, Copy)]
+enum Codec {
+    Gzip,
+    Zlib,
+}
+
+#[cfg(feature = "gzip")]
+fn compress(vm: &mut VM, codec: Codec, data: &[u8], what: &str) -> Vec<u8> {
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+
+    let result = match codec {
+        Codec::Gzip => {
+            let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+            enc.write_all(data).and_then(|_| enc.finish())
+        }
+        Codec::Zlib => {
+            let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+            enc.write_all(data).and_then(|_| enc.finish())
+        }
+    };
+    result.unwrap_or_else(|e| vm.runtime_error(&format!("{what}: {e}")))
+}
+
+#[cfg(feature = "gzip")]
+fn decompress(vm: &mut VM, codec: Codec, data: &[u8], what: &str) -> Vec<u8> {
+    use flate2::read::{GzDecoder, ZlibDecoder};
+
+    let mut out = Vec::new();
+    let result = match codec {
+        Codec::Gzip => GzDecoder::new(data).read_to_end(&mut out),
+        Codec::Zlib => ZlibDecoder::new(data).read_to_end(&mut out),
+    };
+    if let Err(e) = result {
+        vm.runtime_error(&format!("{what}: invalid compressed data: {e}"));
+    }
+    out
+}
+
+#[cfg(not(feature = "gzip"))]
+fn compress(vm: &mut VM, _codec: Codec, _data: &[u8], what: &str) -> Vec<u8> {
+    vm.runtime_error(&format!(
+        "{what} is unavailable: reactive was built without the `gzip` feature"
+    ))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn decompress(vm: &mut VM, _codec: Codec, _data: &[u8], what: &str) -> Vec<u8> {
+    vm.runtime_error(&format!(
+        "{what} is unavailable: reactive was built without the `gzip` feature"
+    ))
+}
+
+fn native_null(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_null expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    Type::Null
+}
+
+fn native_is_null(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_is_null expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let v = vm.force(args[0].clone());
+    Type::Integer(matches!(v, Type::Null) as i64)
+}
+
+fn native_tuple(_vm: &mut VM, args: Vec<Type>) -> Type {
+    Type::Tuple(args)
+}
+
+fn native_tuple_get(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_tuple_get expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let index = vm.as_usize_nonneg(args[1].clone(), "tuple index");
+    vm.tuple_get(args[0].clone(), index)
+}
+
+fn native_tuple_len(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_tuple_len expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    match vm.force(args[0].clone()) {
+        Type::Tuple(elems) => Type::Integer(elems.len() as i64),
+        other => vm.runtime_error(&format!(
+            "internal_tuple_len expects tuple, found {}",
+            vm.format_value(&other)
+        )),
+    }
+}
+
+fn native_log(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_log expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let level_name = vm.value_to_string(args[0].clone(), "internal_log level");
+    let level = LogLevel::parse(&level_name).unwrap_or_else(|| {
+        vm.runtime_error(&format!(
+            "internal_log level must be debug, info, warn or error, found `{level_name}`"
+        ))
+    });
+    if level < vm.options.log_level {
+        return Type::Integer(0);
+    }
+
+    let message = vm.render_printable(args[1].clone());
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let stamp = crate::datetime::format(now, "%Y-%m-%dT%H:%M:%SZ").unwrap_or_default();
+
+    let line = format!("{stamp} {:<5} {message}\n", level.name());
+    if let Err(e) = vm
+        .stderr
+        .write_all(line.as_bytes())
+        .and_then(|_| vm.stderr.flush())
+    {
+        vm.runtime_error(&format!("failed to write log line: {e}"));
+    }
+    Type::Integer(1)
+}
+
+fn native_eprint(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_eprint expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    vm.eprint_value(args[0].clone(), false);
+    Type::Integer(0)
+}
+
+fn native_eprintln(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_eprintln expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    vm.eprint_value(args[0].clone(), true);
+    Type::Integer(0)
+}
+
+fn native_csv_parse(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_csv_parse expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let text = vm.value_to_string(args[0].clone(), "internal_csv_parse input");
+    let rows = crate::csv::parse(&text)
+        .unwrap_or_else(|e| vm.runtime_error(&format!("internal_csv_parse: {e}")));
+
+    let mut out = Vec::with_capacity(rows.len());
+    for row in rows {
+        let fields = row.iter().map(|f| vm.string_to_array(f)).collect();
+        out.push(vm.values_to_vec(fields));
+    }
+    vm.values_to_vec(out)
+}
+
+fn native_csv_write(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_csv_write expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let rows = csv_elements(vm, args[0].clone(), "internal_csv_write rows");
+    let mut table = Vec::with_capacity(rows.len());
+    for row in rows {
+        let fields = csv_elements(vm, row, "internal_csv_write row");
+        let row = fields
+            .into_iter()
+            .map(|field| vm.render_printable(field))
+            .collect();
+        table.push(row);
+    }
+    let text = crate::csv::write(&table);
+    vm.string_to_array(&text)
+}
+
+fn native_toml_parse(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_toml_parse expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let text = vm.value_to_string(args[0].clone(), "internal_toml_parse input");
+    let table = crate::config::parse_toml_tree(&text)
+        .unwrap_or_else(|e| vm.runtime_error(&format!("internal_toml_parse: {e}")));
+    config_table(vm, table)
+}
+
+fn native_ini_parse(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_ini_parse expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let text = vm.value_to_string(args[0].clone(), "internal_ini_parse input");
+    let table = crate::config::parse_ini(&text)
+        .unwrap_or_else(|e| vm.runtime_error(&format!("internal_ini_parse: {e}")));
+    config_table(vm, table)
+}
+
+/// A parsed table as a `Table` struct with one field per key; booleans
+/// become 1 / 0.
+fn config_table(vm: &mut VM, table: HashMap<String, ConfigValue>) -> Type {
+    let fields = table
+        .into_iter()
+        .map(|(key, value)| (key, config_value(vm, value)))
+        .collect();
+    vm.heap.push(StructInstance {
+        name: "Table".to_string(),
+        fields,
+        immutables: HashSet::new(),
+    });
+    Type::StructRef(vm.heap.len() - 1)
+}
+
+fn config_value(vm: &mut VM, value: ConfigValue) -> Type {
+    match value {
+        ConfigValue::Str(s) => vm.string_to_array(&s),
+        ConfigValue::Int(n) => Type::Integer(n),
+        ConfigValue::Bool(b) => Type::Integer(b as i64),
+        ConfigValue::Array(items) => {
+            let elems = items.into_iter().map(|v| config_value(vm, v)).collect();
+            Type::ArrayRef(vm.alloc_array(elems, HashSet::new()))
+        }
+        ConfigValue::Table(table) => config_table(vm, table),
+    }
+}
+
+fn native_config_get(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_config_get expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let path = vm.value_to_string(args[1].clone(), "internal_config_get key");
+    let mut value = args[0].clone();
+    for key in path.split('.') {
+        let Type::StructRef(id) = vm.force(value) else {
+            return Type::Null;
+        };
+        match vm.heap[id].fields.get(key).cloned() {
+            Some(field) => value = vm.force_struct_field(id, field),
+            None => return Type::Null,
+        }
+    }
+    value
+}
+
+fn native_config_keys(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_config_keys expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = match vm.force(args[0].clone()) {
+        Type::StructRef(id) => id,
+        other => vm.runtime_error(&format!(
+            "internal_config_keys expects a table, found {}",
+            vm.format_value(&other)
+        )),
+    };
+    let mut keys: Vec<String> = vm.heap[id].fields.keys().cloned().collect();
+    keys.sort();
+    let keys = keys.iter().map(|key| vm.string_to_array(key)).collect();
+    vm.values_to_vec(keys)
+}
+
+fn csv_elements(vm: &mut VM, v: Type, what: &str) -> Vec<Type> {
+    match vm.force(v) {
+        Type::ArrayRef(id) => vm.array_heap[id].clone(),
+        Type::VecRef(id) => vm.vec_heap[id].clone(),
+        other => vm.runtime_error(&format!(
+            "{what} must be an array or vec, found {}",
+            vm.format_value(&other)
+        )),
+    }
+}
+
+fn native_term_size(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_term_size expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let (cols, rows) = terminal_size().unwrap_or_else(|| {
+        // Not a terminal (piped output): honour COLUMNS / LINES, else 80x24.
+        let env = |key: &str, default: i64| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        (env("COLUMNS", 80), env("LINES", 24))
+    });
+    let cols = vm.recorded_int("internal_term_size", |_| cols);
+    let rows = vm.recorded_int("internal_term_size", |_| rows);
+
+    Type::ArrayRef(vm.alloc_array(
+        vec![Type::Integer(cols), Type::Integer(rows)],
+        HashSet::new(),
+    ))
+}
+
+#[cfg(unix)]
+fn terminal_size() -> Option<(i64, i64)> {
+    let mut size = unsafe { std::mem::zeroed::<libc::winsize>() };
+    if unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_col == 0 {
+        return None;
+    }
+    Some((size.ws_col.into(), size.ws_row.into()))
+}
+
+#[cfg(windows)]
+fn terminal_size() -> Option<(i64, i64)> {
+    let handle = unsafe { GetStdHandle(STD_OUTPUT_HANDLE) };
+    let mut info = unsafe { std::mem::zeroed::<CONSOLE_SCREEN_BUFFER_INFO>() };
+    if unsafe { GetConsoleScreenBufferInfo(handle, &mut info) } == 0 {
+        return None;
     }
-    vm.string_to_array(&line)
+    let window = info.srWindow;
+    Some((
+        i64::from(window.Right - window.Left) + 1,
+        i64::from(window.Bottom - window.Top) + 1,
+    ))
 }
 
-fn native_input_init(vm: &mut VM, args: Vec<Type>) -> Type {
-    if !args.is_empty() {
+#[cfg(not(any(unix, windows)))]
+fn terminal_size() -> Option<(i64, i64)> {
+    None
+}
+
+fn native_term_raw_mode(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
         vm.runtime_error(&format!(
-            "internal_input_init expects 0 arguments, got {}",
+            "internal_term_raw_mode expects 1 argument, got {}",
             args.len()
         ));
     }
 
-    #[cfg(unix)]
-    unix_input_init(vm);
-
-    #[cfg(windows)]
-    win_input_init(vm);
-
+    let on = vm.as_int(args[0].clone()) != 0;
+    term_set_raw_mode(vm, on);
     Type::Integer(0)
 }
 
-fn native_input_poll(vm: &mut VM, args: Vec<Type>) -> Type {
+fn native_term_poll_key(vm: &mut VM, args: Vec<Type>) -> Type {
     if !args.is_empty() {
         vm.runtime_error(&format!(
-            "internal_input_poll expects 0 arguments, got {}",
+            "internal_term_poll_key expects 0 arguments, got {}",
             args.len()
         ));
     }
 
-    #[cfg(unix)]
-    return Type::Integer(unix_input_poll(vm));
+    Type::Integer(vm.recorded_int("internal_term_poll_key", term_poll_key))
+}
 
-    #[cfg(windows)]
-    return Type::Integer(win_input_poll(vm));
+// With the `terminal` feature crossterm owns raw mode and decodes keys the
+// same way on every platform; without it the term natives share the
+// termios / console state of input_init and input_poll.
+#[cfg(feature = "terminal")]
+fn term_set_raw_mode(vm: &mut VM, on: bool) {
+    use crossterm::terminal;
+
+    let result = if on {
+        terminal::enable_raw_mode()
+    } else {
+        terminal::disable_raw_mode()
+    };
+    if let Err(e) = result {
+        vm.runtime_error(&format!("internal_term_raw_mode failed: {e}"));
+    }
+    if on {
+        term_register_atexit();
+    }
+}
 
-    #[cfg(not(any(unix, windows)))]
-    {
-        vm.runtime_error("internal_input_poll is not supported on this platform");
+#[cfg(feature = "terminal")]
+fn term_register_atexit() {
+    static REGISTER: OnceLock<()> = OnceLock::new();
+    if REGISTER.set(()).is_ok() {
+        unsafe {
+            libc::atexit(term_atexit);
+        }
     }
 }
 
-fn native_input_shutdown(vm: &mut VM, args: Vec<Type>) -> Type {
+#[cfg(feature = "terminal")]
+extern "C" fn term_atexit() {
+    let _ = crossterm::terminal::disable_raw_mode();
+}
+
+/// The next key press as a code (see `term_poll_key` in std.term), or -1
+/// once no events are waiting. Other events and keys without a code are
+/// skipped.
+#[cfg(feature = "terminal")]
+fn term_poll_key(vm: &mut VM) -> i64 {
+    use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+
+    loop {
+        match event::poll(std::time::Duration::ZERO) {
+            Ok(true) => {}
+            Ok(false) => return -1,
+            Err(e) => vm.runtime_error(&format!("internal_term_poll_key failed: {e}")),
+        }
+        let key = match event::read() {
+            Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => key,
+            Ok(_) => continue,
+            Err(e) => vm.runtime_error(&format!("internal_term_poll_key failed: {e}")),
+        };
+        let code = match key.code {
+            KeyCode::Up => KEY_UP,
+            KeyCode::Down => KEY_DOWN,
+            KeyCode::Left => KEY_LEFT,
+            KeyCode::Right => KEY_RIGHT,
+            KeyCode::Enter => '\n' as i32,
+            KeyCode::Tab => '\t' as i32,
+            KeyCode::Backspace => 127,
+            KeyCode::Esc => 27,
+            // Ctrl+letter as its ASCII control code (Ctrl+C is 3)
+            KeyCode::Char(c)
+                if key.modifiers.contains(KeyModifiers::CONTROL) && c.is_ascii_alphabetic() =>
+            {
+                c.to_ascii_lowercase() as i32 & 0x1f
+            }
+            KeyCode::Char(c) => c as i32,
+            _ => continue,
+        };
+        return code.into();
+    }
+}
+
+#[cfg(not(feature = "terminal"))]
+fn term_set_raw_mode(vm: &mut VM, on: bool) {
+    if on {
+        native_input_init(vm, Vec::new());
+    } else {
+        native_input_shutdown(vm, Vec::new());
+    }
+}
+
+#[cfg(not(feature = "terminal"))]
+fn term_poll_key(vm: &mut VM) -> i64 {
+    poll_key(vm)
+}
+
+fn write_terminal(vm: &mut VM, seq: &str, what: &str) {
+    let mut out = io::stdout();
+    if let Err(e) = out.write_all(seq.as_bytes()).and_then(|_| out.flush()) {
+        vm.runtime_error(&format!("{what} failed: {e}"));
+    }
+}
+
+fn native_term_clear(vm: &mut VM, args: Vec<Type>) -> Type {
     if !args.is_empty() {
         vm.runtime_error(&format!(
-            "internal_input_shutdown expects 0 arguments, got {}",
+            "internal_term_clear expects 0 arguments, got {}",
             args.len()
         ));
     }
 
-    #[cfg(unix)]
-    unix_input_shutdown(vm);
+    write_terminal(vm, "\x1b[2J\x1b[H", "internal_term_clear");
+    Type::Integer(0)
+}
 
-    #[cfg(windows)]
-    win_input_shutdown(vm);
+fn native_term_move(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_term_move expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
 
+    let x = vm.as_usize_nonneg(args[0].clone(), "internal_term_move x");
+    let y = vm.as_usize_nonneg(args[1].clone(), "internal_term_move y");
+    // ANSI cursor positions are 1-based (row;column).
+    write_terminal(
+        vm,
+        &format!("\x1b[{};{}H", y + 1, x + 1),
+        "internal_term_move",
+    );
     Type::Integer(0)
 }
 
@@ -789,3 +3360,434 @@ fn win_input_poll(vm: &mut VM) -> i32 {
 
     ch as i32
 }
+
+// =========================================================
+// Worker threads and channels
+// =========================================================
+
+fn native_thread_spawn(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_thread_spawn expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let (name, function) = match vm.force(args[0].clone()) {
+        f @ Type::Function { .. } => ("<thread>".to_string(), f),
+        s @ (Type::ArrayRef(_) | Type::VecRef(_)) => {
+            let name = vm.value_to_string(s, "internal_thread_spawn");
+            vm.check_callback_visibility(&name);
+            let f = vm.global_env.get(&name).cloned().unwrap_or_else(|| {
+                vm.unknown_name_error(
+                    &format!("internal_thread_spawn: function `{name}` is not defined"),
+                    &name,
+                    true,
+                )
+            });
+            (name, f)
+        }
+        other => vm.runtime_error(&format!(
+            "internal_thread_spawn expects a function, found {}",
+            vm.format_value(&other)
+        )),
+    };
+    match &function {
+        Type::Function { params, .. } if params.len() == 1 => {}
+        Type::Function { params, .. } => vm.runtime_error(&format!(
+            "internal_thread_spawn: `{name}` must take 1 parameter, takes {}",
+            params.len()
+        )),
+        _ => vm.runtime_error(&format!(
+            "internal_thread_spawn: `{name}` is not a bytecode function"
+        )),
+    }
+
+    let arg = vm
+        .export_value(args[1].clone())
+        .unwrap_or_else(|e| vm.runtime_error(&format!("internal_thread_spawn: {e}")));
+    let seed = vm.worker_seed();
+
+    let handle = std::thread::Builder::new()
+        .name(name.clone())
+        .stack_size(VM_STACK_SIZE)
+        .spawn(move || run_worker(seed, name, function, arg))
+        .unwrap_or_else(|e| vm.runtime_error(&format!("internal_thread_spawn failed: {e}")));
+    Type::HandleRef(vm.handles.insert(Resource::Thread(Some(handle))))
+}
+
+fn native_thread_join(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_thread_join expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_handle(vm, args[0].clone(), "internal_thread_join");
+    let Resource::Thread(handle) = vm.handle_of_kind(id, "thread", "internal_thread_join") else {
+        unreachable!()
+    };
+    let handle = handle.take().unwrap();
+    vm.close_handle(id);
+
+    match handle.join() {
+        Ok(Ok(message)) => vm.import_value(message),
+        Ok(Err(e)) => vm.runtime_error(&format!("internal_thread_join: worker failed: {e}")),
+        Err(_) => vm.runtime_error("internal_thread_join: worker thread panicked"),
+    }
+}
+
+fn native_channel_new(vm: &mut VM, args: Vec<Type>) -> Type {
+    if !args.is_empty() {
+        vm.runtime_error(&format!(
+            "internal_channel_new expects 0 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let channel = Arc::new(Channel::default());
+    Type::HandleRef(vm.handles.insert(Resource::Channel(channel)))
+}
+
+fn expect_channel(vm: &mut VM, v: Type, what: &str) -> Arc<Channel> {
+    let id = expect_handle(vm, v, what);
+    let Resource::Channel(channel) = vm.handle_of_kind(id, "channel", what) else {
+        unreachable!()
+    };
+    channel.clone()
+}
+
+fn native_channel_send(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_channel_send expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let channel = expect_channel(vm, args[0].clone(), "internal_channel_send");
+    let message = vm
+        .export_value(args[1].clone())
+        .unwrap_or_else(|e| vm.runtime_error(&format!("internal_channel_send: {e}")));
+    channel.send(message);
+    Type::Integer(1)
+}
+
+fn native_channel_recv(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_channel_recv expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let channel = expect_channel(vm, args[0].clone(), "internal_channel_recv");
+    let message = channel.recv();
+    vm.import_value(message)
+}
+
+// =========================================================
+// UDP sockets and DNS
+// =========================================================
+
+fn native_udp_bind(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_udp_bind expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let addr = vm.value_to_string(args[0].clone(), "internal_udp_bind address");
+    let socket = UdpSocket::bind(addr.as_str()).unwrap_or_else(|e| {
+        vm.runtime_error(&format!("internal_udp_bind failed for `{addr}`: {e}"))
+    });
+    Type::HandleRef(vm.handles.insert(Resource::UdpSocket(socket)))
+}
+
+fn expect_udp_socket(vm: &mut VM, v: Type, what: &str) -> UdpSocket {
+    let id = expect_handle(vm, v, what);
+    let Resource::UdpSocket(socket) = vm.handle_of_kind(id, "udp socket", what) else {
+        unreachable!()
+    };
+    socket
+        .try_clone()
+        .unwrap_or_else(|e| vm.runtime_error(&format!("{what} failed: {e}")))
+}
+
+fn native_udp_send_to(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 3 {
+        vm.runtime_error(&format!(
+            "internal_udp_send_to expects 3 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let socket = expect_udp_socket(vm, args[0].clone(), "internal_udp_send_to");
+    let addr = vm.value_to_string(args[1].clone(), "internal_udp_send_to address");
+    let data = match vm.force(args[2].clone()) {
+        Type::BytesRef(id) => vm.bytes_heap[id].clone(),
+        other => vm
+            .value_to_string(other, "internal_udp_send_to data")
+            .into_bytes(),
+    };
+    let sent = socket.send_to(&data, addr.as_str()).unwrap_or_else(|e| {
+        vm.runtime_error(&format!("internal_udp_send_to failed for `{addr}`: {e}"))
+    });
+    Type::Integer(sent as i64)
+}
+
+fn native_udp_recv_from(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_udp_recv_from expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let socket = expect_udp_socket(vm, args[0].clone(), "internal_udp_recv_from");
+    let max = vm.as_usize_nonneg(args[1].clone(), "internal_udp_recv_from size");
+    // the sender stays empty when the read timed out
+    let mut sender = String::new();
+    let data = vm.recorded_bytes("internal_udp_recv_from", |vm| {
+        let mut buf = vec![0; max];
+        match socket.recv_from(&mut buf) {
+            Ok((n, from)) => {
+                buf.truncate(n);
+                sender = from.to_string();
+                buf
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Vec::new()
+            }
+            Err(e) => vm.runtime_error(&format!("internal_udp_recv_from failed: {e}")),
+        }
+    });
+    let sender = vm.recorded_str("internal_udp_recv_from", |_| sender);
+    if sender.is_empty() {
+        return Type::Null;
+    }
+    let data = new_bytes(vm, data);
+    let sender = vm.string_to_array(&sender);
+    Type::Tuple(vec![data, sender])
+}
+
+fn native_udp_local_addr(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_udp_local_addr expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let socket = expect_udp_socket(vm, args[0].clone(), "internal_udp_local_addr");
+    let addr = socket
+        .local_addr()
+        .unwrap_or_else(|e| vm.runtime_error(&format!("internal_udp_local_addr failed: {e}")));
+    vm.string_to_array(&addr.to_string())
+}
+
+fn native_udp_set_timeout(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_udp_set_timeout expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let socket = expect_udp_socket(vm, args[0].clone(), "internal_udp_set_timeout");
+    let ms = vm.as_int(args[1].clone());
+    let timeout = (ms > 0).then(|| std::time::Duration::from_millis(ms as u64));
+    if let Err(e) = socket.set_read_timeout(timeout) {
+        vm.runtime_error(&format!("internal_udp_set_timeout failed: {e}"));
+    }
+    Type::Integer(1)
+}
+
+fn native_dns_resolve(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_dns_resolve expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let host = vm.value_to_string(args[0].clone(), "internal_dns_resolve host");
+    let addrs = vm.recorded_str("internal_dns_resolve", |vm| {
+        let resolved = (host.as_str(), 0).to_socket_addrs().unwrap_or_else(|e| {
+            vm.runtime_error(&format!("internal_dns_resolve failed for `{host}`: {e}"))
+        });
+        let mut ips: Vec<String> = Vec::new();
+        for addr in resolved {
+            let ip = addr.ip().to_string();
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+        ips.join("\n")
+    });
+    let elems = addrs
+        .lines()
+        .map(|ip| vm.string_to_array(ip))
+        .collect::<Vec<_>>();
+    Type::ArrayRef(vm.alloc_array(elems, HashSet::new()))
+}
+
+// =========================================================
+// WebSocket client
+// =========================================================
+
+#[cfg(feature = "websocket")]
+pub(crate) type WebSocket =
+    tungstenite::WebSocket<tungstenite::stream::MaybeTlsStream<std::net::TcpStream>>;
+
+fn native_ws_connect(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_ws_connect expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let url = vm.value_to_string(args[0].clone(), "internal_ws_connect url");
+    let socket = ws_connect(vm, &url);
+    Type::HandleRef(vm.handles.insert(socket))
+}
+
+fn native_ws_send(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 2 {
+        vm.runtime_error(&format!(
+            "internal_ws_send expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_handle(vm, args[0].clone(), "internal_ws_send");
+    // strings go as text messages, bytes as binary ones
+    let message = match vm.force(args[1].clone()) {
+        Type::BytesRef(bytes) => Input::Bytes(vm.bytes_heap[bytes].clone()),
+        other => Input::Str(vm.value_to_string(other, "internal_ws_send message")),
+    };
+    ws_send(vm, id, message);
+    Type::Integer(1)
+}
+
+fn native_ws_recv(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_ws_recv expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_handle(vm, args[0].clone(), "internal_ws_recv");
+    // an int stands for the connection having been closed
+    match vm.recorded("internal_ws_recv", |vm| ws_recv(vm, id)) {
+        Input::Str(text) => vm.string_to_array(&text),
+        Input::Bytes(bytes) => new_bytes(vm, bytes),
+        Input::Int(_) => Type::Null,
+    }
+}
+
+fn native_ws_close(vm: &mut VM, args: Vec<Type>) -> Type {
+    if args.len() != 1 {
+        vm.runtime_error(&format!(
+            "internal_ws_close expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+
+    let id = expect_handle(vm, args[0].clone(), "internal_ws_close");
+    ws_close(vm, id);
+    vm.close_handle(id);
+    Type::Integer(1)
+}
+
+#[cfg(feature = "websocket")]
+fn expect_ws<'a>(vm: &'a mut VM, id: usize, what: &str) -> &'a mut WebSocket {
+    let Resource::WebSocket(socket) = vm.handle_of_kind(id, "websocket", what) else {
+        unreachable!()
+    };
+    socket
+}
+
+#[cfg(feature = "websocket")]
+fn ws_connect(vm: &mut VM, url: &str) -> Resource {
+    match tungstenite::connect(url) {
+        Ok((socket, _)) => Resource::WebSocket(Box::new(socket)),
+        Err(e) => vm.runtime_error(&format!("internal_ws_connect failed for `{url}`: {e}")),
+    }
+}
+
+#[cfg(feature = "websocket")]
+fn ws_send(vm: &mut VM, id: usize, message: Input) {
+    use tungstenite::Message;
+
+    let message = match message {
+        Input::Str(text) => Message::Text(text),
+        Input::Bytes(bytes) => Message::Binary(bytes),
+        Input::Int(_) => unreachable!(),
+    };
+    if let Err(e) = expect_ws(vm, id, "internal_ws_send").send(message) {
+        vm.runtime_error(&format!("internal_ws_send failed: {e}"));
+    }
+}
+
+/// The next text or binary message; pings are answered while waiting.
+#[cfg(feature = "websocket")]
+fn ws_recv(vm: &mut VM, id: usize) -> Input {
+    use tungstenite::{Error, Message};
+
+    loop {
+        match expect_ws(vm, id, "internal_ws_recv").read() {
+            Ok(Message::Text(text)) => return Input::Str(text),
+            Ok(Message::Binary(bytes)) => return Input::Bytes(bytes),
+            Ok(Message::Close(_)) | Err(Error::ConnectionClosed | Error::AlreadyClosed) => {
+                return Input::Int(0);
+            }
+            Ok(_) => {}
+            Err(e) => vm.runtime_error(&format!("internal_ws_recv failed: {e}")),
+        }
+    }
+}
+
+#[cfg(feature = "websocket")]
+fn ws_close(vm: &mut VM, id: usize) {
+    let socket = expect_ws(vm, id, "internal_ws_close");
+    // the peer may already be gone; the handle is released either way
+    let _ = socket.close(None);
+    let _ = socket.flush();
+}
+
+#[cfg(not(feature = "websocket"))]
+fn ws_unavailable(vm: &mut VM, what: &str) -> ! {
+    vm.runtime_error(&format!(
+        "{what} is unavailable: reactive was built without the `websocket` feature"
+    ))
+}
+
+#[cfg(not(feature = "websocket"))]
+fn ws_connect(vm: &mut VM, _url: &str) -> Resource {
+    ws_unavailable(vm, "internal_ws_connect")
+}
+
+#[cfg(not(feature = "websocket"))]
+fn ws_send(vm: &mut VM, _id: usize, _message: Input) {
+    ws_unavailable(vm, "internal_ws_send")
+}
+
+#[cfg(not(feature = "websocket"))]
+fn ws_recv(vm: &mut VM, _id: usize) -> Input {
+    ws_unavailable(vm, "internal_ws_recv")
+}
+
+#[cfg(not(feature = "websocket"))]
+fn ws_close(vm: &mut VM, _id: usize) {
+    ws_unavailable(vm, "internal_ws_close")
+}
@@ -0,0 +1,257 @@
+use super::VM;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub(crate) const TOP_LEVEL: &str = "<top-level>";
+
+#[derive(Debug, Default, Clone)]
+pub struct FunctionStats {
+    pub calls: u64,
+    pub instructions: u64,
+    /// Wall time including callees.
+    pub total_time: Duration,
+}
+
+/// Per-function and per-opcode execution counters collected by `reactive profile`.
+#[derive(Debug)]
+pub struct Profiler {
+    started: Instant,
+    functions: HashMap<String, FunctionStats>,
+    opcodes: HashMap<&'static str, u64>,
+    sampler: Option<StackSampler>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            functions: HashMap::new(),
+            opcodes: HashMap::new(),
+            sampler: None,
+        }
+    }
+}
+
+/// Periodic snapshots of the call stack, for flamegraphs. The clock is only
+/// read every `CHECK_EVERY` instructions; a snapshot taken late counts once
+/// per interval that has passed, so a long native call is not undercounted.
+#[derive(Debug)]
+struct StackSampler {
+    interval: Duration,
+    next_sample: Instant,
+    countdown: u32,
+    // call stack (outermost first) -> number of samples
+    stacks: HashMap<Vec<String>, u64>,
+}
+
+const CHECK_EVERY: u32 = 64;
+
+impl Profiler {
+    pub(crate) fn record_instruction(&mut self, function: &str, op: &'static str) {
+        *self.opcodes.entry(op).or_insert(0) += 1;
+        match self.functions.get_mut(function) {
+            Some(stats) => stats.instructions += 1,
+            None => {
+                self.functions.insert(
+                    function.to_string(),
+                    FunctionStats {
+                        instructions: 1,
+                        ..FunctionStats::default()
+                    },
+                );
+            }
+        }
+    }
+
+    pub(crate) fn record_call(&mut self, function: &str, elapsed: Duration) {
+        let stats = self.functions.entry(function.to_string()).or_default();
+        stats.calls += 1;
+        stats.total_time += elapsed;
+    }
+
+    /// Sampled call stacks (outermost frame first) with their sample counts.
+    pub fn stacks(&self) -> Option<&HashMap<Vec<String>, u64>> {
+        self.sampler.as_ref().map(|sampler| &sampler.stacks)
+    }
+
+    /// Sampled stacks in the collapsed format read by `flamegraph.pl` and
+    /// `inferno`: `outer;inner count` per line.
+    pub fn collapsed_stacks(&self) -> String {
+        let mut lines: Vec<String> = self
+            .stacks()
+            .into_iter()
+            .flatten()
+            .map(|(stack, count)| format!("{} {count}", stack.join(";")))
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+
+    /// Sampled stacks as a speedscope "sampled" profile, weighted in
+    /// milliseconds.
+    pub fn speedscope_json(&self, name: &str) -> String {
+        let interval_ms = self
+            .sampler
+            .as_ref()
+            .map_or(0.0, |sampler| sampler.interval.as_secs_f64() * 1000.0);
+
+        let mut stacks: Vec<(&Vec<String>, &u64)> = self.stacks().into_iter().flatten().collect();
+        stacks.sort();
+
+        let mut frames: Vec<&str> = Vec::new();
+        let mut frame_ids: HashMap<&str, usize> = HashMap::new();
+        let mut samples = Vec::new();
+        let mut weights = Vec::new();
+        let mut total = 0.0;
+        for (stack, count) in stacks {
+            let ids: Vec<String> = stack
+                .iter()
+                .map(|frame| {
+                    let id = *frame_ids.entry(frame).or_insert_with(|| {
+                        frames.push(frame);
+                        frames.len() - 1
+                    });
+                    id.to_string()
+                })
+                .collect();
+            samples.push(format!("[{}]", ids.join(",")));
+            let weight = *count as f64 * interval_ms;
+            weights.push(format!("{weight}"));
+            total += weight;
+        }
+
+        let frames: Vec<String> = frames
+            .iter()
+            .map(|frame| format!("{{\"name\":{}}}", json_string(frame)))
+            .collect();
+        format!(
+            "{{\"$schema\":\"https://www.speedscope.app/file-format-schema.json\",\
+             \"exporter\":\"reactive\",\"name\":{name},\
+             \"shared\":{{\"frames\":[{frames}]}},\
+             \"profiles\":[{{\"type\":\"sampled\",\"name\":{name},\"unit\":\"milliseconds\",\
+             \"startValue\":0,\"endValue\":{total},\"samples\":[{samples}],\"weights\":[{weights}]}}]}}\n",
+            name = json_string(name),
+            frames = frames.join(","),
+            samples = samples.join(","),
+            weights = weights.join(","),
+        )
+    }
+
+    pub fn functions(&self) -> &HashMap<String, FunctionStats> {
+        &self.functions
+    }
+
+    pub fn opcodes(&self) -> &HashMap<&'static str, u64> {
+        &self.opcodes
+    }
+
+    /// Human-readable report: functions by inclusive time, then opcode histogram.
+    pub fn report(&self) -> String {
+        let total_instructions: u64 = self.opcodes.values().sum();
+        let mut out = String::new();
+        out.push_str("Profile report\n");
+        out.push_str(&format!(
+            "{} instruction(s) in {:.3} ms\n\n",
+            total_instructions,
+            self.started.elapsed().as_secs_f64() * 1000.0
+        ));
+
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|a, b| {
+            b.1.total_time
+                .cmp(&a.1.total_time)
+                .then(b.1.instructions.cmp(&a.1.instructions))
+                .then(a.0.cmp(b.0))
+        });
+        out.push_str("Functions (by inclusive time):\n");
+        out.push_str(&format!(
+            "  {:>10}  {:>14}  {:>12}  name\n",
+            "calls", "instructions", "total ms"
+        ));
+        for (name, stats) in functions {
+            out.push_str(&format!(
+                "  {:>10}  {:>14}  {:>12.3}  {}\n",
+                stats.calls,
+                stats.instructions,
+                stats.total_time.as_secs_f64() * 1000.0,
+                name
+            ));
+        }
+
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        out.push_str("\nOpcodes:\n");
+        for (op, count) in opcodes {
+            let share = *count as f64 * 100.0 / total_instructions.max(1) as f64;
+            out.push_str(&format!("  {:>14}  {:>6.2}%  {}\n", count, share, op));
+        }
+        out
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 32 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl VM {
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::default());
+    }
+
+    /// Also snapshot the call stack every `interval` while profiling.
+    pub fn enable_stack_sampling(&mut self, interval: Duration) {
+        let profiler = self.profiler.get_or_insert_with(Profiler::default);
+        profiler.sampler = Some(StackSampler {
+            interval,
+            next_sample: Instant::now() + interval,
+            countdown: CHECK_EVERY,
+            stacks: HashMap::new(),
+        });
+    }
+
+    pub(crate) fn sample_stack(&mut self) {
+        let Some(sampler) = self.profiler.as_mut().and_then(|p| p.sampler.as_mut()) else {
+            return;
+        };
+        sampler.countdown -= 1;
+        if sampler.countdown > 0 {
+            return;
+        }
+        sampler.countdown = CHECK_EVERY;
+
+        let now = Instant::now();
+        if now < sampler.next_sample {
+            return;
+        }
+        let late = now - sampler.next_sample;
+        let count = 1 + (late.as_nanos() / sampler.interval.as_nanos().max(1)) as u64;
+        sampler.next_sample = now + sampler.interval;
+
+        let stack: Vec<String> = std::iter::once(TOP_LEVEL.to_string())
+            .chain(self.call_stack.iter().map(|f| f.function_name.clone()))
+            .collect();
+        *sampler.stacks.entry(stack).or_insert(0) += count;
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub(crate) fn current_function_name(&self) -> &str {
+        self.call_stack
+            .last()
+            .map(|frame| frame.function_name.as_str())
+            .unwrap_or(TOP_LEVEL)
+    }
+}
@@ -13,9 +13,9 @@ impl VM {
     /// - Everything else is returned as-is
     pub(crate) fn force(&mut self, v: Type) -> Type {
         match v {
-            Type::LazyValue(expr, captured) => {
+            Type::LazyValue(expr, captured, module) => {
                 self.immutable_stack.push(captured);
-                let out = self.evaluate_reactive_expr(&expr);
+                let out = self.evaluate_reactive_expr(&expr, module);
                 self.immutable_stack.pop();
                 self.force(out)
             }
@@ -49,9 +49,9 @@ impl VM {
     /// with a struct-local immutable frame binding all fields as LValues.
     pub(crate) fn force_struct_field(&mut self, struct_id: usize, v: Type) -> Type {
         match v {
-            Type::LazyValue(expr, captured) => {
+            Type::LazyValue(expr, captured, module) => {
                 self.immutable_stack.push(captured);
-                let out = self.eval_reactive_field_in_struct(struct_id, &expr);
+                let out = self.eval_reactive_field_in_struct(struct_id, &expr, module);
                 self.immutable_stack.pop();
                 self.force(out)
             }
@@ -63,8 +63,18 @@ impl VM {
     // Reactive evaluation helpers
     // =========================================================
 
-    pub(crate) fn evaluate_reactive_expr(&mut self, expr: &ReactiveExpr) -> Type {
-        self.run_reactive_code(expr.code.clone())
+    pub(crate) fn evaluate_reactive_expr(
+        &mut self,
+        expr: &ReactiveExpr,
+        module: Option<String>,
+    ) -> Type {
+        self.run_reactive_code(expr.code.clone(), module)
+    }
+
+    /// `expr` as a reactive value, owned by the module of the running code.
+    pub(crate) fn lazy_value(&self, expr: ReactiveExpr, captured: HashMap<String, Type>) -> Type {
+        let module = self.current_module().map(str::to_string);
+        Type::LazyValue(expr, captured, module)
     }
 
     pub(crate) fn capture_immutables(&self, names: &[String]) -> HashMap<String, Type> {
@@ -77,11 +87,18 @@ impl VM {
         captured
     }
 
-    pub(crate) fn run_reactive_code(&mut self, code: Vec<Instruction>) -> Type {
+    /// Runs `code` inline in the current frame, as code written by `module`.
+    pub(crate) fn run_reactive_code(
+        &mut self,
+        code: Vec<Instruction>,
+        module: Option<String>,
+    ) -> Type {
+        let labels = self.link_labels(&code);
         let saved_code = std::mem::replace(&mut self.code, code);
-        let saved_labels = std::mem::replace(&mut self.labels, Self::build_labels(&self.code));
+        let saved_labels = std::mem::replace(&mut self.labels, labels);
         let saved_ptr = self.pointer;
         let saved_stack_len = self.stack.len();
+        let saved_module = self.inline_module.replace((self.call_stack.len(), module));
 
         self.pointer = 0;
         self.run();
@@ -95,6 +112,7 @@ impl VM {
         self.code = saved_code;
         self.labels = saved_labels;
         self.pointer = saved_ptr;
+        self.inline_module = saved_module;
 
         result
     }
@@ -0,0 +1,149 @@
+use super::VM;
+use crate::bytecode::{quote, tokenize_line};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::Write;
+
+const REPLAY_MAGIC: &str = "RXREPLAY1";
+
+/// A value a nondeterministic native handed back to the program.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Input {
+    Int(i64),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+/// Record-and-replay of nondeterministic native results (`--record`,
+/// `--replay`). Every native whose result depends on the outside world
+/// (file contents, stdin, key polling) routes through `VM::recorded`.
+pub(crate) enum Replay {
+    // Entries are flushed as they happen so a crashing run still leaves
+    // a complete log behind.
+    Record(File),
+    Replay(VecDeque<(String, Input)>),
+}
+
+impl VM {
+    pub fn enable_record(&mut self, path: &str) -> Result<(), String> {
+        let mut file = File::create(path)
+            .map_err(|e| format!("failed to create replay log `{}`: {}", path, e))?;
+        writeln!(file, "{REPLAY_MAGIC}")
+            .map_err(|e| format!("failed to write replay log `{}`: {}", path, e))?;
+        self.replay = Some(Replay::Record(file));
+        Ok(())
+    }
+
+    pub fn enable_replay(&mut self, path: &str) -> Result<(), String> {
+        let input = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read replay log `{}`: {}", path, e))?;
+        let entries = parse_log(&input).map_err(|e| format!("replay log `{}`: {}", path, e))?;
+        self.replay = Some(Replay::Replay(entries));
+        Ok(())
+    }
+
+    /// Returns the next logged result for `native` when replaying, otherwise
+    /// runs `live` and logs its result when recording.
+    pub(crate) fn recorded(&mut self, native: &str, live: impl FnOnce(&mut VM) -> Input) -> Input {
+        if let Some(Replay::Replay(entries)) = &mut self.replay {
+            return match entries.pop_front() {
+                Some((name, value)) if name == native => value,
+                Some((name, _)) => self.runtime_error(&format!(
+                    "replay diverged: program called {native} but the log recorded {name}"
+                )),
+                None => self.runtime_error(&format!(
+                    "replay log exhausted: program called {native} after the recorded run ended"
+                )),
+            };
+        }
+
+        let value = live(self);
+        if let Some(Replay::Record(file)) = &mut self.replay {
+            let line = match &value {
+                Input::Int(n) => format!("{native} int {n}"),
+                Input::Str(s) => format!("{native} str {}", quote(s)),
+                Input::Bytes(b) => {
+                    let hex: String = b.iter().map(|byte| format!("{byte:02x}")).collect();
+                    format!("{native} bytes \"{hex}\"")
+                }
+            };
+            if let Err(e) = writeln!(file, "{line}").and_then(|_| file.flush()) {
+                self.runtime_error(&format!("failed to write replay log: {e}"));
+            }
+        }
+        value
+    }
+
+    pub(crate) fn recorded_int(&mut self, native: &str, live: impl FnOnce(&mut VM) -> i64) -> i64 {
+        match self.recorded(native, |vm| Input::Int(live(vm))) {
+            Input::Int(n) => n,
+            _ => self.runtime_error(&format!(
+                "replay diverged: log entry for {native} is not an int"
+            )),
+        }
+    }
+
+    pub(crate) fn recorded_str(
+        &mut self,
+        native: &str,
+        live: impl FnOnce(&mut VM) -> String,
+    ) -> String {
+        match self.recorded(native, |vm| Input::Str(live(vm))) {
+            Input::Str(s) => s,
+            _ => self.runtime_error(&format!(
+                "replay diverged: log entry for {native} is not a string"
+            )),
+        }
+    }
+
+    pub(crate) fn recorded_bytes(
+        &mut self,
+        native: &str,
+        live: impl FnOnce(&mut VM) -> Vec<u8>,
+    ) -> Vec<u8> {
+        match self.recorded(native, |vm| Input::Bytes(live(vm))) {
+            Input::Bytes(b) => b,
+            _ => self.runtime_error(&format!(
+                "replay diverged: log entry for {native} is not bytes"
+            )),
+        }
+    }
+}
+
+fn parse_log(input: &str) -> Result<VecDeque<(String, Input)>, String> {
+    let mut lines = input.lines();
+    if lines.next().map(str::trim) != Some(REPLAY_MAGIC) {
+        return Err(format!("invalid header: expected {REPLAY_MAGIC}"));
+    }
+
+    let mut entries = VecDeque::new();
+    for (i, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let tokens = tokenize_line(line).map_err(|e| format!("line {}: {}", i + 2, e))?;
+        let value = match tokens.as_slice() {
+            [_, kind, n] if kind == "int" => Input::Int(
+                n.parse()
+                    .map_err(|_| format!("line {}: invalid int `{}`", i + 2, n))?,
+            ),
+            [_, kind, s] if kind == "str" => Input::Str(s.clone()),
+            [_, kind, hex] if kind == "bytes" => Input::Bytes(
+                parse_hex(hex).ok_or_else(|| format!("line {}: invalid bytes", i + 2))?,
+            ),
+            _ => return Err(format!("line {}: malformed entry", i + 2)),
+        };
+        entries.push_back((tokens[0].clone(), value));
+    }
+    Ok(entries)
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -1,8 +1,11 @@
 use super::VM;
+use super::freeze::Frozen;
+use super::profile::TOP_LEVEL;
 use crate::grammar::{
     CompiledStructFieldInit, Instruction, LValue, ReactiveExpr, StructInstance, Type,
 };
 use std::collections::{HashMap, HashSet};
+use std::io::Write;
 
 impl VM {
     // =========================================================
@@ -12,35 +15,93 @@ impl VM {
     pub(crate) fn pop(&mut self) -> Type {
         match self.stack.pop() {
             Some(value) => value,
-            None => self.runtime_error("stack underflow"),
+            None => self.stack_error("stack underflow: no operand to pop"),
         }
     }
 
-    pub(crate) fn pop_int(&mut self) -> i32 {
+    /// Pops the top `n` operands, deepest first.
+    pub(crate) fn pop_n(&mut self, n: usize) -> Vec<Type> {
+        if n > self.stack.len() {
+            self.stack_error(&format!(
+                "stack underflow: needs {n} operand(s), {} available",
+                self.stack.len()
+            ));
+        }
+        self.stack.split_off(self.stack.len() - n)
+    }
+
+    /// Operand stack error naming where it happened; these come from
+    /// malformed bytecode rather than the program's logic.
+    pub(crate) fn stack_error(&self, message: &str) -> ! {
+        let function = match self.current_function_name() {
+            TOP_LEVEL => "top-level code".to_string(),
+            name => format!("{name}()"),
+        };
+        let opcode = self
+            .code
+            .get(self.pointer)
+            .map_or("end of body", Instruction::opcode);
+        self.runtime_error(&format!(
+            "{message} in {function} at instruction {} (`{opcode}`)",
+            self.pointer
+        ))
+    }
+
+    pub(crate) fn pop_int(&mut self) -> i64 {
         let v = self.pop();
         self.as_int(v)
     }
 
-    pub(crate) fn pop_args(&mut self, argc: usize) -> Vec<Type> {
-        let mut args = Vec::with_capacity(argc);
-        for _ in 0..argc {
-            args.push(self.pop());
+    /// Pops an int operand for arithmetic, where null propagates: `None`
+    /// means the operand was null and the result should be null too.
+    pub(crate) fn pop_int_or_null(&mut self) -> Option<i64> {
+        match self.pop() {
+            Type::Null => None,
+            v => match self.force(v) {
+                Type::Null => None,
+                v => Some(self.as_int(v)),
+            },
         }
-        args.reverse();
-        args
+    }
+
+    /// Pops a condition: null counts as false, everything else coerces to int.
+    pub(crate) fn pop_truth(&mut self) -> i64 {
+        self.pop_int_or_null().unwrap_or(0)
+    }
+
+    pub(crate) fn pop_args(&mut self, argc: usize) -> Vec<Type> {
+        self.pop_n(argc)
     }
 
     // =========================================================
     // Coercions / bounds
     // =========================================================
 
-    pub(crate) fn as_int(&mut self, v: Type) -> i32 {
+    pub(crate) fn as_int(&mut self, v: Type) -> i64 {
+        match self.force(v) {
+            v @ (Type::ArrayRef(_) | Type::VecRef(_)) if self.options.strict => {
+                let kind = self.type_name(v);
+                self.runtime_error(&format!(
+                    "strict: {kind} used where an int is required; write `(int) x` for its length"
+                ))
+            }
+            v => self.cast_int(v),
+        }
+    }
+
+    /// `as_int` for an explicit `(int)` cast, which converts arrays and vecs
+    /// to their length even in strict mode.
+    pub(crate) fn cast_int(&mut self, v: Type) -> i64 {
         match self.force(v) {
             Type::Integer(n) => n,
-            Type::Char(c) => c as i32,
-            Type::ArrayRef(id) => self.array_heap[id].len() as i32,
-            Type::VecRef(id) => self.vec_heap[id].len() as i32,
-            other => self.runtime_error(&format!("type error: cannot coerce {:?} to int", other)),
+            Type::Char(c) => c as i64,
+            Type::ArrayRef(id) => self.array_heap[id].len() as i64,
+            Type::VecRef(id) => self.vec_heap[id].len() as i64,
+            Type::Null => self.runtime_error("type error: null used where an int is required"),
+            other => self.runtime_error(&format!(
+                "type error: cannot coerce {} to int",
+                self.format_value(&other)
+            )),
         }
     }
 
@@ -52,17 +113,307 @@ impl VM {
         i as usize
     }
 
+    pub(crate) fn tuple_get(&mut self, v: Type, index: usize) -> Type {
+        match self.force(v) {
+            Type::Tuple(mut elems) => {
+                if index >= elems.len() {
+                    self.runtime_error(&format!(
+                        "tuple index out of bounds: index {index}, length {}",
+                        elems.len()
+                    ));
+                }
+                elems.swap_remove(index)
+            }
+            other => self.runtime_error(&format!(
+                "expected tuple, found {}",
+                self.format_value(&other)
+            )),
+        }
+    }
+
+    // =========================================================
+    // Deep equality
+    // =========================================================
+
+    /// Structural equality used by `DeepEqual`:
+    /// - ints and chars compare by value (chars coerce like `Equal`)
+    /// - arrays and vecs compare element-wise (so strings compare by text)
+    /// - structs compare field-by-field
+    /// - buffers compare their contents
+    /// - sets compare their members
+    ///
+    /// A pair of containers met again while still being compared is assumed
+    /// equal, so self-containing arrays, vecs and structs terminate.
+    pub(crate) fn deep_equal(&mut self, a: Type, b: Type) -> bool {
+        let mut visited = HashSet::new();
+        self.deep_equal_inner(a, b, &mut visited)
+    }
+
+    fn deep_equal_inner(
+        &mut self,
+        a: Type,
+        b: Type,
+        visited: &mut HashSet<(Container, Container)>,
+    ) -> bool {
+        let a = self.force(a);
+        let b = self.force(b);
+
+        match (a, b) {
+            (Type::Integer(x), Type::Integer(y)) => x == y,
+            (Type::Char(x), Type::Char(y)) => x == y,
+            (Type::Integer(n), Type::Char(c)) | (Type::Char(c), Type::Integer(n)) => n == c as i64,
+
+            (
+                a @ (Type::ArrayRef(_) | Type::VecRef(_)),
+                b @ (Type::ArrayRef(_) | Type::VecRef(_)),
+            ) => {
+                // Already comparing this pair further up: assume equal to stop cycles.
+                let pair = (Container::of(&a), Container::of(&b));
+                if pair.0 == pair.1 || !visited.insert(pair) {
+                    return true;
+                }
+
+                let xs = self.sequence_elems(&a);
+                let ys = self.sequence_elems(&b);
+                if xs.len() != ys.len() {
+                    return false;
+                }
+                for (x, y) in xs.into_iter().zip(ys) {
+                    if !self.deep_equal_inner(x, y, visited) {
+                        return false;
+                    }
+                }
+                true
+            }
+
+            (Type::StructRef(x), Type::StructRef(y)) => {
+                // Already comparing this pair further up: assume equal to stop cycles.
+                let pair = (Container::Struct(x), Container::Struct(y));
+                if x == y || !visited.insert(pair) {
+                    return true;
+                }
+
+                let mut keys: Vec<String> = self.heap[x].fields.keys().cloned().collect();
+                if keys.len() != self.heap[y].fields.len()
+                    || keys.iter().any(|k| !self.heap[y].fields.contains_key(k))
+                {
+                    return false;
+                }
+                keys.sort();
+
+                for key in keys {
+                    let fx = self.heap[x].fields[&key].clone();
+                    let fy = self.heap[y].fields[&key].clone();
+                    let vx = self.force_struct_field(x, fx);
+                    let vy = self.force_struct_field(y, fy);
+                    if !self.deep_equal_inner(vx, vy, visited) {
+                        return false;
+                    }
+                }
+                true
+            }
+
+            (Type::BufferRef(x), Type::BufferRef(y)) => self.buffer_heap[x] == self.buffer_heap[y],
+            (Type::BytesRef(x), Type::BytesRef(y)) => self.bytes_heap[x] == self.bytes_heap[y],
+            (Type::SetRef(x), Type::SetRef(y)) => {
+                let (xs, ys) = (&self.set_heap[x], &self.set_heap[y]);
+                xs.len() == ys.len() && xs.keys().all(|k| ys.contains_key(k))
+            }
+            (Type::HandleRef(x), Type::HandleRef(y)) => x == y,
+            (Type::PqRef(x), Type::PqRef(y)) => x == y,
+            (Type::NativeFunction(x), Type::NativeFunction(y)) => x == y,
+            (Type::Uninitialized, Type::Uninitialized) => true,
+            (Type::Null, Type::Null) => true,
+            (Type::Variant(x), Type::Variant(y)) => {
+                x.enum_name == y.enum_name
+                    && x.name == y.name
+                    && x.payload.len() == y.payload.len()
+                    && x.payload
+                        .into_iter()
+                        .zip(y.payload)
+                        .all(|(a, b)| self.deep_equal_inner(a, b, visited))
+            }
+            (Type::Tuple(xs), Type::Tuple(ys)) => {
+                xs.len() == ys.len()
+                    && xs
+                        .into_iter()
+                        .zip(ys)
+                        .all(|(x, y)| self.deep_equal_inner(x, y, visited))
+            }
+            _ => false,
+        }
+    }
+
+    fn sequence_elems(&self, v: &Type) -> Vec<Type> {
+        match v {
+            Type::ArrayRef(id) => self.array_heap[*id].clone(),
+            Type::VecRef(id) => self.vec_heap[*id].clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    // =========================================================
+    // Deep copy
+    // =========================================================
+
+    /// Copy used by `DeepCopy` and `internal_clone`: arrays, vecs and structs
+    /// are duplicated recursively. Sharing and cycles inside the value are
+    /// reproduced in the copy. Reactive relationships, lvalues and buffers are
+    /// kept as they are; struct reactive fields re-evaluate against the copy.
+    pub(crate) fn deep_copy(&mut self, v: Type) -> Type {
+        let mut copies = CopyMemo::default();
+        self.deep_copy_inner(v, &mut copies)
+    }
+
+    fn deep_copy_inner(&mut self, v: Type, copies: &mut CopyMemo) -> Type {
+        match v {
+            Type::ArrayRef(id) => {
+                if let Some(new_id) = copies.arrays.get(&id) {
+                    return Type::ArrayRef(*new_id);
+                }
+                let new_id = self.alloc_array(Vec::new(), self.array_immutables[id].clone());
+                copies.arrays.insert(id, new_id);
+                let elems = self.array_heap[id].clone();
+                let elems = elems
+                    .into_iter()
+                    .map(|e| self.deep_copy_inner(e, copies))
+                    .collect();
+                self.array_heap[new_id] = elems;
+                Type::ArrayRef(new_id)
+            }
+            Type::VecRef(id) => {
+                if let Some(new_id) = copies.vecs.get(&id) {
+                    return Type::VecRef(*new_id);
+                }
+                let new_id = self.alloc_vec(Vec::new(), self.vec_immutables[id].clone());
+                copies.vecs.insert(id, new_id);
+                let elems = self.vec_heap[id].clone();
+                let elems = elems
+                    .into_iter()
+                    .map(|e| self.deep_copy_inner(e, copies))
+                    .collect();
+                self.vec_heap[new_id] = elems;
+                Type::VecRef(new_id)
+            }
+            Type::StructRef(id) => {
+                if let Some(new_id) = copies.structs.get(&id) {
+                    return Type::StructRef(*new_id);
+                }
+                let inst = self.heap[id].clone();
+                let new_id = self.heap.len();
+                self.heap.push(StructInstance {
+                    name: inst.name,
+                    fields: HashMap::new(),
+                    immutables: inst.immutables,
+                });
+                copies.structs.insert(id, new_id);
+                let fields = inst
+                    .fields
+                    .into_iter()
+                    .map(|(name, v)| (name, self.deep_copy_inner(v, copies)))
+                    .collect();
+                self.heap[new_id].fields = fields;
+                Type::StructRef(new_id)
+            }
+            Type::Variant(mut variant) => {
+                variant.payload = std::mem::take(&mut variant.payload)
+                    .into_iter()
+                    .map(|e| self.deep_copy_inner(e, copies))
+                    .collect();
+                Type::Variant(variant)
+            }
+            Type::Tuple(elems) => Type::Tuple(
+                elems
+                    .into_iter()
+                    .map(|e| self.deep_copy_inner(e, copies))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    /// Tag pushed by `TypeOf`: "int", "char", "null", "tuple", "array",
+    /// "vec", "buffer", "bytes", "set", "pq", "struct:<Name>", "enum:<Name>",
+    /// "handle", "iterator", "function" or "uninitialized".
+    pub(crate) fn type_name(&mut self, v: Type) -> String {
+        match self.force(v) {
+            Type::Integer(_) => "int".to_string(),
+            Type::Char(_) => "char".to_string(),
+            Type::Null => "null".to_string(),
+            Type::Tuple(_) => "tuple".to_string(),
+            Type::Variant(variant) => format!("enum:{}", variant.enum_name),
+            Type::Iter(_) => "iterator".to_string(),
+            Type::ArrayRef(_) => "array".to_string(),
+            Type::VecRef(_) => "vec".to_string(),
+            Type::BufferRef(_) => "buffer".to_string(),
+            Type::BytesRef(_) => "bytes".to_string(),
+            Type::SetRef(_) => "set".to_string(),
+            Type::PqRef(_) => "pq".to_string(),
+            Type::StructRef(id) => format!("struct:{}", self.heap[id].name),
+            Type::HandleRef(_) => "handle".to_string(),
+            Type::Function { .. } | Type::NativeFunction(_) => "function".to_string(),
+            Type::Uninitialized | Type::LazyValue(..) | Type::LValue(_) => {
+                "uninitialized".to_string()
+            }
+        }
+    }
+
     // =========================================================
     // Printing
     // =========================================================
 
     pub(crate) fn print_value(&mut self, v: Type, newline: bool) {
+        let mut text = self.render_printable(v);
+        if newline {
+            text.push('\n');
+        }
+        if let Err(e) = self.stdout.get_mut().write_all(text.as_bytes()) {
+            self.runtime_error(&format!("failed to write to stdout: {e}"));
+        }
+    }
+
+    /// Like `print_value`, but to the VM's stderr sink.
+    pub(crate) fn eprint_value(&mut self, v: Type, newline: bool) {
+        let mut text = self.render_printable(v);
+        if newline {
+            text.push('\n');
+        }
+        if let Err(e) = self
+            .stderr
+            .write_all(text.as_bytes())
+            .and_then(|_| self.stderr.flush())
+        {
+            self.runtime_error(&format!("failed to write to stderr: {e}"));
+        }
+    }
+
+    pub(crate) fn render_printable(&mut self, v: Type) -> String {
+        let mut out = String::new();
         match self.force(v) {
             Type::Char(c) => {
-                print!("{}", char::from_u32(c).unwrap());
+                out.push(char::from_u32(c).unwrap());
             }
             Type::Integer(n) => {
-                print!("{n}");
+                out.push_str(&n.to_string());
+            }
+            Type::Null => out.push_str("null"),
+            Type::Variant(variant) => {
+                out.push_str(&format!("{}.{}", variant.enum_name, variant.name));
+                if !variant.payload.is_empty() {
+                    let parts: Vec<String> = variant
+                        .payload
+                        .into_iter()
+                        .map(|elem| self.render_printable(elem))
+                        .collect();
+                    out.push_str(&format!("({})", parts.join(", ")));
+                }
+            }
+            Type::Tuple(elems) => {
+                let parts: Vec<String> = elems
+                    .into_iter()
+                    .map(|elem| self.render_printable(elem))
+                    .collect();
+                out.push_str(&format!("({})", parts.join(", ")));
             }
             Type::ArrayRef(id) => {
                 // Attempt to treat as string (array of chars). If not, print length
@@ -82,10 +433,10 @@ impl VM {
 
                 if all_chars {
                     for c in chars {
-                        print!("{}", char::from_u32(c).unwrap());
+                        out.push(char::from_u32(c).unwrap());
                     }
                 } else {
-                    print!("{}", self.array_heap[id].len());
+                    out.push_str(&self.array_heap[id].len().to_string());
                 }
             }
             Type::VecRef(id) => {
@@ -105,31 +456,66 @@ impl VM {
 
                 if all_chars {
                     for c in chars {
-                        print!("{}", char::from_u32(c).unwrap());
+                        out.push(char::from_u32(c).unwrap());
                     }
                 } else {
-                    print!("{}", self.vec_heap[id].len());
+                    out.push_str(&self.vec_heap[id].len().to_string());
                 }
             }
-            other => self.runtime_error(&format!("cannot print value {:?}", other)),
-        }
-
-        if newline {
-            println!();
+            other => {
+                self.runtime_error(&format!("cannot print value {}", self.format_value(&other)))
+            }
         }
+        out
     }
 
     // =========================================================
     // Arrays
     // =========================================================
 
+    /// Elements `start..end` of an array or vec as a new array. Elements are
+    /// read by value, so reactive elements are snapshotted.
+    pub(crate) fn array_slice(&mut self, v: Type, start: Type, end: Type) -> Type {
+        let elems = self.array_elems(v, "ArraySlice");
+        let start = self.as_usize_nonneg(start, "slice start");
+        let end = self.as_usize_nonneg(end, "slice end");
+        if start > end || end > elems.len() {
+            self.runtime_error(&format!(
+                "slice {start}..{end} out of bounds for length {}",
+                elems.len()
+            ));
+        }
+        let out = elems[start..end]
+            .iter()
+            .map(|e| self.force(e.clone()))
+            .collect();
+        Type::ArrayRef(self.alloc_array(out, HashSet::new()))
+    }
+
+    /// `a` followed by `b` as a new array; either may be an array or vec.
+    pub(crate) fn array_concat(&mut self, a: Type, b: Type) -> Type {
+        let mut out = self.array_elems(a, "ArrayConcat");
+        out.extend(self.array_elems(b, "ArrayConcat"));
+        let out = out.into_iter().map(|e| self.force(e)).collect();
+        Type::ArrayRef(self.alloc_array(out, HashSet::new()))
+    }
+
+    fn array_elems(&mut self, v: Type, what: &str) -> Vec<Type> {
+        match self.force(v) {
+            Type::ArrayRef(id) => self.array_heap[id].clone(),
+            Type::VecRef(id) => self.vec_heap[id].clone(),
+            other => self.runtime_error(&format!(
+                "{what} expects array or vec, found {}",
+                self.format_value(&other)
+            )),
+        }
+    }
+
     pub(crate) fn exec_array_new(&mut self) {
         let size_val = self.pop();
         let n = self.as_usize_nonneg(size_val, "array size");
 
-        let id = self.array_heap.len();
-        self.array_heap.push(vec![Type::Integer(0); n]);
-        self.array_immutables.push(HashSet::new());
+        let id = self.alloc_array(vec![Type::Integer(0); n], HashSet::new());
         self.stack.push(Type::ArrayRef(id));
     }
 
@@ -164,8 +550,8 @@ impl VM {
                 self.stack.push(f);
             }
             other => self.runtime_error(&format!(
-                "type error: attempted to index non-array value {:?}",
-                other
+                "type error: attempted to index non-array value {}",
+                self.format_value(&other)
             )),
         }
     }
@@ -178,36 +564,9 @@ impl VM {
         let idx_val = self.pop();
         let idx = self.as_usize_nonneg(idx_val, "array index");
 
-        let target = self
-            .lookup_var(&name)
-            .cloned()
-            .unwrap_or_else(|| self.runtime_error(&format!("undefined variable: {name}")));
-
-        let arr = self.force(target);
-
-        match arr {
-            Type::ArrayRef(id) => {
-                let len = self.array_heap[id].len();
-                if idx >= len {
-                    self.runtime_error(&format!(
-                        "array assignment out of bounds: index {idx}, length {len}"
-                    ));
-                }
-                self.array_heap[id][idx] = val;
-            }
-            Type::VecRef(id) => {
-                let len = self.vec_heap[id].len();
-                if idx >= len {
-                    self.runtime_error(&format!(
-                        "vec assignment out of bounds: index {idx}, length {len}"
-                    ));
-                }
-                self.vec_heap[id][idx] = val;
-            }
-            other => {
-                self.runtime_error(&format!("type error: StoreIndex on non-array {:?}", other))
-            }
-        }
+        let target = self.resolve_var(&name);
+        let lv = self.element_lvalue(target, idx, "StoreIndex");
+        self.store_lvalue(lv, val, "assignment");
     }
 
     pub(crate) fn exec_store_index_reactive(&mut self, name: String, expr: ReactiveExpr) {
@@ -217,44 +576,26 @@ impl VM {
         let idx = self.as_usize_nonneg(idx_val, "array index");
 
         let captured = self.capture_immutables(&expr.captures);
-        let value = Type::LazyValue(expr, captured);
-
-        let target = self
-            .lookup_var(&name)
-            .cloned()
-            .unwrap_or_else(|| self.runtime_error(&format!("undefined variable: {name}")));
+        let value = self.lazy_value(expr, captured);
 
-        let arr = self.force(target);
-
-        match arr {
-            Type::ArrayRef(id) => {
-                let len = self.array_heap[id].len();
-                if idx >= len {
-                    self.runtime_error(&format!(
-                        "reactive array assignment out of bounds: index {idx}, length {len}"
-                    ));
-                }
-                self.array_heap[id][idx] = value;
-            }
-            Type::VecRef(id) => {
-                let len = self.vec_heap[id].len();
-                if idx >= len {
-                    self.runtime_error(&format!(
-                        "reactive vec assignment out of bounds: index {idx}, length {len}"
-                    ));
-                }
-                self.vec_heap[id][idx] = value;
-            }
-            other => self.runtime_error(&format!(
-                "type error: StoreIndexReactive on non-array {:?}",
-                other
-            )),
-        }
+        let target = self.resolve_var(&name);
+        let lv = self.element_lvalue(target, idx, "StoreIndexReactive");
+        self.store_lvalue(lv, value, "reactive assignment");
     }
 
     // =========================================================
     // LValues
     // =========================================================
+    //
+    // Every location an lvalue chain can end at (array element, vec element,
+    // struct field) follows one policy:
+    // - `=` and `::=` write it unless it is immutable
+    // - `:=` binds it once, if it is not immutable yet, and makes it immutable
+    // - immutability belongs to the location, not to the value stored there:
+    //   after `c.mat[0] := [2]` slot 0 cannot be rebound, but the new array's
+    //   own elements stay mutable (`freeze` makes a value immutable deeply)
+    // Each step of a chain is bounds-checked, so a bad index anywhere in
+    // `a[i].f[j] := v` is a runtime error rather than a VM panic.
 
     pub(crate) fn read_lvalue(&mut self, lv: LValue) -> Type {
         match lv {
@@ -291,301 +632,177 @@ impl VM {
                 self.force_to_storable(l)
             }
 
-            Type::LazyValue(_, _) => v, // keep relationships attached to locations
+            Type::LazyValue(..) => v, // keep relationships attached to locations
             other => other,
         }
     }
 
+    /// The value a base of a longer chain refers to: an lvalue base is read
+    /// (bounds-checked) so the next step indexes or selects into its value.
+    fn lvalue_base(&mut self, base: Type) -> Type {
+        match base {
+            Type::LValue(lv) => {
+                let value = self.read_lvalue(lv);
+                self.force(value)
+            }
+            other => self.force(other),
+        }
+    }
+
+    fn element_lvalue(&mut self, base: Type, index: usize, what: &str) -> LValue {
+        match self.lvalue_base(base) {
+            Type::ArrayRef(array_id) => LValue::ArrayElem { array_id, index },
+            Type::VecRef(vec_id) => LValue::VecElem { vec_id, index },
+            other => self.runtime_error(&format!(
+                "{what}: indexing non-array {}",
+                self.format_value(&other)
+            )),
+        }
+    }
+
     pub(crate) fn exec_array_lvalue(&mut self) {
         let idx_val = self.pop();
         let idx = self.as_usize_nonneg(idx_val, "array index");
 
         let base = self.pop();
-        let base_val = self.force(base);
-
-        match base_val {
-            Type::ArrayRef(id) => {
-                self.stack.push(Type::LValue(LValue::ArrayElem {
-                    array_id: id,
-                    index: idx,
-                }));
-            }
-            Type::VecRef(id) => {
-                self.stack.push(Type::LValue(LValue::VecElem {
-                    vec_id: id,
-                    index: idx,
-                }));
-            }
-
-            Type::LValue(LValue::ArrayElem { array_id, index }) => {
-                let nested_val = self.array_heap[array_id][index].clone();
-                let nested = self.force(nested_val);
-                match nested {
-                    Type::ArrayRef(nested_id) => {
-                        self.stack.push(Type::LValue(LValue::ArrayElem {
-                            array_id: nested_id,
-                            index: idx,
-                        }));
-                    }
-                    Type::VecRef(nested_id) => {
-                        self.stack.push(Type::LValue(LValue::VecElem {
-                            vec_id: nested_id,
-                            index: idx,
-                        }));
-                    }
-                    other => self.runtime_error(&format!("indexing non-array (found {:?})", other)),
-                }
-            }
-            Type::LValue(LValue::VecElem { vec_id, index }) => {
-                let nested_val = self.vec_heap[vec_id][index].clone();
-                let nested = self.force(nested_val);
-                match nested {
-                    Type::ArrayRef(array_id) => {
-                        self.stack.push(Type::LValue(LValue::ArrayElem {
-                            array_id,
-                            index: idx,
-                        }));
-                    }
-                    Type::VecRef(nested_id) => {
-                        self.stack.push(Type::LValue(LValue::VecElem {
-                            vec_id: nested_id,
-                            index: idx,
-                        }));
-                    }
-                    other => self.runtime_error(&format!("indexing non-array (found {:?})", other)),
-                }
-            }
-
-            Type::LValue(LValue::StructField { struct_id, field }) => {
-                let field_val = self.heap[struct_id]
-                    .fields
-                    .get(&field)
-                    .cloned()
-                    .unwrap_or_else(|| {
-                        self.runtime_error(&format!("missing struct field `{field}`"))
-                    });
-
-                let arr_val = self.force(field_val);
-                match arr_val {
-                    Type::ArrayRef(array_id) => {
-                        self.stack.push(Type::LValue(LValue::ArrayElem {
-                            array_id,
-                            index: idx,
-                        }));
-                    }
-                    Type::VecRef(vec_id) => {
-                        self.stack.push(Type::LValue(LValue::VecElem {
-                            vec_id,
-                            index: idx,
-                        }));
-                    }
-                    other => self.runtime_error(&format!(
-                        "indexing non-array struct field (found {:?})",
-                        other
-                    )),
-                }
-            }
-
-            other => self.runtime_error(&format!("invalid ArrayLValue base {:?}", other)),
-        }
+        let lv = self.element_lvalue(base, idx, "ArrayLValue");
+        self.stack.push(Type::LValue(lv));
     }
 
     pub(crate) fn exec_field_lvalue(&mut self, field: String) {
         let base = self.pop();
-        match self.force(base) {
+        match self.lvalue_base(base) {
             Type::StructRef(id) => {
                 self.stack.push(Type::LValue(LValue::StructField {
                     struct_id: id,
                     field,
                 }));
             }
+            other => self.runtime_error(&format!(
+                "FieldLValue `{field}` on non-struct {}",
+                self.format_value(&other)
+            )),
+        }
+    }
 
-            Type::LValue(LValue::ArrayElem { array_id, index }) => {
-                let elem = self.force(self.array_heap[array_id][index].clone());
-                match elem {
-                    Type::StructRef(id) => {
-                        self.stack.push(Type::LValue(LValue::StructField {
-                            struct_id: id,
-                            field,
-                        }));
-                    }
-                    other => self.runtime_error(&format!(
-                        "FieldLValue on non-struct array element {:?}",
-                        other
-                    )),
+    /// Raises a runtime error unless `lv` names an in-bounds element or a
+    /// declared field. `what` is the kind of store, for the message.
+    fn check_lvalue(&self, lv: &LValue, what: &str) {
+        match lv {
+            LValue::ArrayElem { array_id, index } => {
+                let len = self.array_heap[*array_id].len();
+                if *index >= len {
+                    self.runtime_error(&format!(
+                        "array {what} out of bounds: index {index}, length {len}"
+                    ));
                 }
             }
-            Type::LValue(LValue::VecElem { vec_id, index }) => {
-                let elem = self.force(self.vec_heap[vec_id][index].clone());
-                match elem {
-                    Type::StructRef(id) => {
-                        self.stack.push(Type::LValue(LValue::StructField {
-                            struct_id: id,
-                            field,
-                        }));
-                    }
-                    other => self.runtime_error(&format!(
-                        "FieldLValue on non-struct vec element {:?}",
-                        other
-                    )),
+            LValue::VecElem { vec_id, index } => {
+                let len = self.vec_heap[*vec_id].len();
+                if *index >= len {
+                    self.runtime_error(&format!(
+                        "vec {what} out of bounds: index {index}, length {len}"
+                    ));
+                }
+            }
+            LValue::StructField { struct_id, field } => {
+                if !self.heap[*struct_id].fields.contains_key(field) {
+                    self.runtime_error(&format!("unknown struct field `{field}`"));
                 }
             }
-
-            other => self.runtime_error(&format!("invalid FieldLValue base {:?}", other)),
         }
     }
 
-    pub(crate) fn exec_store_through(&mut self) {
-        let value = self.pop();
-        let target = self.pop();
-
-        let stored = self.force_to_storable(value);
-
-        match target {
-            Type::LValue(LValue::ArrayElem { array_id, index }) => {
-                if self.array_immutables[array_id].contains(&index) {
-                    self.runtime_error("cannot reassign immutable array element");
-                }
-
-                let len = self.array_heap[array_id].len();
-                if index >= len {
-                    self.runtime_error("array assignment out of bounds");
-                }
-
-                self.array_heap[array_id][index] = stored;
+    fn lvalue_is_immutable(&self, lv: &LValue) -> bool {
+        match lv {
+            LValue::ArrayElem { array_id, index } => {
+                self.array_immutables[*array_id].contains(index)
             }
-            Type::LValue(LValue::VecElem { vec_id, index }) => {
-                if self.vec_immutables[vec_id].contains(&index) {
-                    self.runtime_error("cannot reassign immutable vec element");
-                }
-
-                let len = self.vec_heap[vec_id].len();
-                if index >= len {
-                    self.runtime_error("vec assignment out of bounds");
-                }
-
-                self.vec_heap[vec_id][index] = stored;
+            LValue::VecElem { vec_id, index } => self.vec_immutables[*vec_id].contains(index),
+            LValue::StructField { struct_id, field } => {
+                self.heap[*struct_id].immutables.contains(field)
             }
+        }
+    }
 
-            Type::LValue(LValue::StructField { struct_id, field }) => {
-                let inst = &mut self.heap[struct_id];
-
-                if !inst.fields.contains_key(&field) {
-                    self.runtime_error(&format!("unknown struct field `{}`", field));
+    fn ensure_lvalue_mutable(&self, lv: &LValue) {
+        if self.lvalue_is_immutable(lv) {
+            match lv {
+                LValue::ArrayElem { .. } => {
+                    self.runtime_error("cannot reassign immutable array element")
                 }
-
-                if inst.immutables.contains(&field) {
-                    self.runtime_error(&format!("cannot assign to immutable field `{}`", field));
+                LValue::VecElem { .. } => {
+                    self.runtime_error("cannot reassign immutable vec element")
+                }
+                LValue::StructField { field, .. } => {
+                    self.runtime_error(&format!("cannot reassign immutable field `{field}`"))
                 }
-
-                inst.fields.insert(field, stored);
             }
-
-            other => self.runtime_error(&format!(
-                "internal error: StoreThrough target is not an lvalue (got {:?})",
-                other
-            )),
         }
     }
 
-    pub(crate) fn exec_store_through_reactive(&mut self, expr: ReactiveExpr) {
-        let target = self.pop();
-
-        let captured = self.capture_immutables(&expr.captures);
-        let value = Type::LazyValue(expr, captured);
-
-        match target {
-            Type::LValue(LValue::ArrayElem { array_id, index }) => {
-                if self.array_immutables[array_id].contains(&index) {
-                    self.runtime_error("cannot reassign immutable array element");
-                }
-
-                let len = self.array_heap[array_id].len();
-                if index >= len {
-                    self.runtime_error("reactive array assignment out of bounds");
-                }
-
-                self.array_heap[array_id][index] = value;
+    /// Writes `value` to the location `lv` under the lvalue policy above;
+    /// `what` is the kind of store, for error messages.
+    fn store_lvalue(&mut self, lv: LValue, value: Type, what: &str) {
+        self.check_lvalue(&lv, what);
+        if let LValue::VecElem { vec_id, .. } = lv {
+            self.ensure_not_busy(Frozen::Vec(vec_id), &format!("vec {what}"));
+        }
+        self.ensure_lvalue_mutable(&lv);
+        match lv {
+            LValue::ArrayElem { array_id, index } => self.array_heap[array_id][index] = value,
+            LValue::VecElem { vec_id, index } => self.vec_heap[vec_id][index] = value,
+            LValue::StructField { struct_id, field } => {
+                self.heap[struct_id].fields.insert(field, value);
             }
-            Type::LValue(LValue::VecElem { vec_id, index }) => {
-                if self.vec_immutables[vec_id].contains(&index) {
-                    self.runtime_error("cannot reassign immutable vec element");
-                }
-
-                let len = self.vec_heap[vec_id].len();
-                if index >= len {
-                    self.runtime_error("reactive vec assignment out of bounds");
-                }
+        }
+    }
 
-                self.vec_heap[vec_id][index] = value;
+    fn mark_lvalue_immutable(&mut self, lv: LValue) {
+        match lv {
+            LValue::ArrayElem { array_id, index } => {
+                self.array_immutables[array_id].insert(index);
             }
-
-            Type::LValue(LValue::StructField { struct_id, field }) => {
-                let inst = &mut self.heap[struct_id];
-
-                if !inst.fields.contains_key(&field) {
-                    self.runtime_error(&format!("unknown struct field `{}`", field));
-                }
-
-                if inst.immutables.contains(&field) {
-                    self.runtime_error(&format!("cannot reassign immutable field `{}`", field));
-                }
-
-                inst.immutables.insert(field.clone());
-                inst.fields.insert(field, value);
+            LValue::VecElem { vec_id, index } => {
+                self.vec_immutables[vec_id].insert(index);
+            }
+            LValue::StructField { struct_id, field } => {
+                self.heap[struct_id].immutables.insert(field);
             }
+        }
+    }
 
+    fn pop_lvalue(&mut self, what: &str) -> LValue {
+        match self.pop() {
+            Type::LValue(lv) => lv,
             other => self.runtime_error(&format!(
-                "StoreThroughReactive target is not an lvalue (got {:?})",
-                other
+                "internal error: {what} target is not an lvalue (got {})",
+                self.format_value(&other)
             )),
         }
     }
 
-    pub(crate) fn store_through_immutable(&mut self) {
+    pub(crate) fn exec_store_through(&mut self) {
         let value = self.pop();
-        let target = self.pop();
+        let target = self.pop_lvalue("StoreThrough");
         let stored = self.force_to_storable(value);
+        self.store_lvalue(target, stored, "assignment");
+    }
 
-        match target {
-            Type::LValue(LValue::StructField { struct_id, field }) => {
-                let inst = &mut self.heap[struct_id];
-
-                match inst.fields.get(&field) {
-                    Some(Type::Uninitialized) => {}
-                    Some(_) => {
-                        self.runtime_error(&format!("cannot reassign immutable field `{}`", field))
-                    }
-                    None => self.runtime_error(&format!("unknown struct field `{}`", field)),
-                }
-
-                inst.fields.insert(field.clone(), stored);
-                inst.immutables.insert(field);
-            }
-
-            Type::LValue(LValue::ArrayElem { array_id, index }) => {
-                let imm = &mut self.array_immutables[array_id];
-
-                if imm.contains(&index) {
-                    self.runtime_error("cannot reassign immutable array element");
-                }
-
-                self.array_heap[array_id][index] = stored;
-                imm.insert(index);
-            }
-            Type::LValue(LValue::VecElem { vec_id, index }) => {
-                let imm = &mut self.vec_immutables[vec_id];
-
-                if imm.contains(&index) {
-                    self.runtime_error("cannot reassign immutable vec element");
-                }
+    pub(crate) fn exec_store_through_reactive(&mut self, expr: ReactiveExpr) {
+        let target = self.pop_lvalue("StoreThroughReactive");
 
-                self.vec_heap[vec_id][index] = stored;
-                imm.insert(index);
-            }
+        let captured = self.capture_immutables(&expr.captures);
+        let value = self.lazy_value(expr, captured);
+        self.store_lvalue(target, value, "reactive assignment");
+    }
 
-            _ => self.runtime_error("immutable assignment only allowed on lvalues"),
-        }
+    pub(crate) fn store_through_immutable(&mut self) {
+        let value = self.pop();
+        let target = self.pop_lvalue("StoreThroughImmutable");
+        let stored = self.force_to_storable(value);
+        self.store_lvalue(target.clone(), stored, "immutable assignment");
+        self.mark_lvalue_immutable(target);
     }
 
     // =========================================================
@@ -614,7 +831,10 @@ impl VM {
                 let out = self.force_struct_field(id, v);
                 self.stack.push(out);
             }
-            other => self.runtime_error(&format!("type error: FieldGet on non-struct {:?}", other)),
+            other => self.runtime_error(&format!(
+                "type error: FieldGet on non-struct {}",
+                self.format_value(&other)
+            )),
         }
     }
 
@@ -624,7 +844,10 @@ impl VM {
 
         let struct_id = match self.force(obj) {
             Type::StructRef(id) => id,
-            other => self.runtime_error(&format!("type error: FieldSet on non-struct {:?}", other)),
+            other => self.runtime_error(&format!(
+                "type error: FieldSet on non-struct {}",
+                self.format_value(&other)
+            )),
         };
 
         {
@@ -655,19 +878,45 @@ impl VM {
                     ));
                 }
                 let captured = self.capture_immutables(&expr.captures);
-                self.heap[id]
-                    .fields
-                    .insert(field, Type::LazyValue(expr, captured));
+                let value = self.lazy_value(expr, captured);
+                self.heap[id].fields.insert(field, value);
             }
             other => self.runtime_error(&format!(
-                "type error: FieldSetReactive on non-struct {:?}",
-                other
+                "type error: FieldSetReactive on non-struct {}",
+                self.format_value(&other)
             )),
         }
     }
 
+    /// `ExtendStruct child parent`: the child's definition becomes the
+    /// parent's fields followed by its own. A child field with an initializer
+    /// replaces the parent's field of that name in place; a bare one keeps the
+    /// inherited initializer.
+    pub(crate) fn exec_extend_struct(&mut self, name: String, parent: String) {
+        self.check_visibility(&parent, "struct");
+        let mut merged = self.struct_defs.get(&parent).cloned().unwrap_or_else(|| {
+            self.runtime_error(&format!(
+                "struct `{name}` extends unknown struct `{parent}`"
+            ))
+        });
+        let own = self.struct_defs.remove(&name).unwrap_or_else(|| {
+            self.runtime_error(&format!(
+                "internal error: ExtendStruct of undefined struct `{name}`"
+            ))
+        });
+        for (field, init) in own {
+            match merged.iter_mut().find(|(f, _)| *f == field) {
+                Some(slot) if init.is_some() => slot.1 = init,
+                Some(_) => {}
+                None => merged.push((field, init)),
+            }
+        }
+        self.struct_defs.insert(name, merged);
+    }
+
     pub(crate) fn instantiate_struct(
         &mut self,
+        type_name: String,
         fields: Vec<(String, Option<CompiledStructFieldInit>)>,
     ) -> Type {
         let mut map = HashMap::new();
@@ -697,8 +946,12 @@ impl VM {
             }
         }
 
+        // initializers run as code of the module that defined the struct
+        let module = self.struct_modules.get(&type_name).cloned().flatten();
+
         let id = self.heap.len();
         self.heap.push(StructInstance {
+            name: type_name,
             fields: map,
             immutables: imm.clone(),
         });
@@ -708,9 +961,11 @@ impl VM {
             if let Some(init) = init {
                 let value = match init {
                     CompiledStructFieldInit::Mutable(code)
-                    | CompiledStructFieldInit::Immutable(code) => self.eval_struct_code(id, code),
+                    | CompiledStructFieldInit::Immutable(code) => {
+                        self.eval_struct_code(id, code, module.clone())
+                    }
                     CompiledStructFieldInit::Reactive(expr) => {
-                        Type::LazyValue(expr, HashMap::new())
+                        Type::LazyValue(expr, HashMap::new(), module.clone())
                     }
                 };
 
@@ -723,7 +978,12 @@ impl VM {
         Type::StructRef(id)
     }
 
-    pub(crate) fn eval_struct_code(&mut self, struct_id: usize, code: Vec<Instruction>) -> Type {
+    pub(crate) fn eval_struct_code(
+        &mut self,
+        struct_id: usize,
+        code: Vec<Instruction>,
+        module: Option<String>,
+    ) -> Type {
         // Each evaluation creates a fresh immutable frame and binds all fields as LValues.
         self.immutable_stack.push(HashMap::new());
 
@@ -744,7 +1004,7 @@ impl VM {
             }
         }
 
-        let result = self.run_reactive_code(code);
+        let result = self.run_reactive_code(code, module);
         self.immutable_stack.pop();
         result
     }
@@ -753,6 +1013,7 @@ impl VM {
         &mut self,
         struct_id: usize,
         expr: &ReactiveExpr,
+        module: Option<String>,
     ) -> Type {
         self.immutable_stack.push(HashMap::new());
 
@@ -773,7 +1034,7 @@ impl VM {
             }
         }
 
-        let result = self.run_reactive_code(expr.code.clone());
+        let result = self.run_reactive_code(expr.code.clone(), module);
         self.immutable_stack.pop();
         result
     }
@@ -781,16 +1042,15 @@ impl VM {
     pub(crate) fn clone_value(&mut self, v: Type) -> Type {
         match v {
             Type::ArrayRef(id) => {
-                let new_id = self.array_heap.len();
-                self.array_heap.push(self.array_heap[id].clone());
-                self.array_immutables
-                    .push(self.array_immutables[id].clone());
+                let new_id = self.alloc_array(
+                    self.array_heap[id].clone(),
+                    self.array_immutables[id].clone(),
+                );
                 Type::ArrayRef(new_id)
             }
             Type::VecRef(id) => {
-                let new_id = self.vec_heap.len();
-                self.vec_heap.push(self.vec_heap[id].clone());
-                self.vec_immutables.push(self.vec_immutables[id].clone());
+                let new_id =
+                    self.alloc_vec(self.vec_heap[id].clone(), self.vec_immutables[id].clone());
                 Type::VecRef(new_id)
             }
 
@@ -801,14 +1061,58 @@ impl VM {
                 Type::StructRef(new_id)
             }
 
-            Type::LazyValue(expr, captured) => Type::LazyValue(expr, captured),
+            Type::LazyValue(expr, captured, module) => Type::LazyValue(expr, captured, module),
             Type::Integer(n) => Type::Integer(n),
-            Type::Function { params, code } => Type::Function { params, code },
+            Type::Function {
+                params,
+                code,
+                module,
+            } => Type::Function {
+                params,
+                code,
+                module,
+            },
             Type::NativeFunction(name) => Type::NativeFunction(name),
             Type::LValue(_) => self.runtime_error("cannot clone lvalue"),
             Type::Char(c) => Type::Char(c),
+            Type::Null => Type::Null,
+            Type::Tuple(elems) => Type::Tuple(elems),
+            Type::Variant(variant) => Type::Variant(variant),
+            Type::Iter(iter) => Type::Iter(iter),
             Type::BufferRef(id) => Type::BufferRef(id),
+            Type::BytesRef(id) => Type::BytesRef(id),
+            Type::SetRef(id) => Type::SetRef(id),
+            Type::PqRef(id) => Type::PqRef(id),
+            Type::HandleRef(id) => Type::HandleRef(id),
             Type::Uninitialized => Type::Uninitialized,
         }
     }
 }
+
+/// An array, vec or struct on the current path of a recursive walk
+/// (`deep_equal`, value rendering), so cycles through them are cut.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Container {
+    Array(usize),
+    Vec(usize),
+    Struct(usize),
+}
+
+impl Container {
+    /// The container `v` refers to; `v` must be an array, vec or struct.
+    pub(crate) fn of(v: &Type) -> Container {
+        match *v {
+            Type::ArrayRef(id) => Container::Array(id),
+            Type::VecRef(id) => Container::Vec(id),
+            Type::StructRef(id) => Container::Struct(id),
+            _ => unreachable!("not a container: {v}"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct CopyMemo {
+    arrays: HashMap<usize, usize>,
+    vecs: HashMap<usize, usize>,
+    structs: HashMap<usize, usize>,
+}
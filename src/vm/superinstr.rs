@@ -0,0 +1,201 @@
+use crate::grammar::{CompiledStructFieldInit, Instruction, ReactiveExpr};
+use crate::rxb2::{jump_targets, relocate_jumps};
+use std::collections::HashMap;
+use std::fs;
+
+const PROFILE_MAGIC: &str = "RXPROF1";
+
+// Only the most frequent pairs of a workload are worth fusing.
+const TOP_PAIRS: usize = 16;
+
+// Fused opcodes the load-time rewriter knows how to build.
+const CATALOG: &[(&str, &str)] = &[("Load", "Load"), ("Load", "Push"), ("Load", "FieldGet")];
+
+/// Counts of consecutively executed opcode pairs. Only pairs can be fused
+/// (see `CATALOG`), so longer sequences are not counted.
+#[derive(Debug, Default)]
+pub struct NgramProfile {
+    pairs: HashMap<(&'static str, &'static str), u64>,
+    prev: Option<&'static str>,
+}
+
+impl NgramProfile {
+    pub(crate) fn record(&mut self, op: &'static str) {
+        if let Some(prev) = self.prev {
+            *self.pairs.entry((prev, op)).or_insert(0) += 1;
+        }
+        self.prev = Some(op);
+    }
+
+    /// Serializes the profile, most frequent pairs first.
+    pub fn to_text(&self) -> String {
+        let mut out = String::from(PROFILE_MAGIC);
+        out.push('\n');
+        for ((a, b), n) in self.ranked() {
+            out.push_str(&format!("pair {a} {b} {n}\n"));
+        }
+        out
+    }
+
+    fn ranked(&self) -> Vec<(&(&'static str, &'static str), &u64)> {
+        let mut pairs: Vec<_> = self.pairs.iter().collect();
+        pairs.sort_by(|x, y| y.1.cmp(x.1).then(x.0.cmp(y.0)));
+        pairs
+    }
+
+    pub fn write_to_file(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_text())
+            .map_err(|e| format!("failed to write profile `{}`: {}", path, e))
+    }
+
+    pub fn read_from_file(path: &str) -> Result<Self, String> {
+        let input = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read profile `{}`: {}", path, e))?;
+        Self::from_text(&input).map_err(|e| format!("profile `{}`: {}", path, e))
+    }
+
+    pub fn from_text(input: &str) -> Result<Self, String> {
+        let mut lines = input.lines();
+        if lines.next().map(str::trim) != Some(PROFILE_MAGIC) {
+            return Err(format!("invalid profile header: expected {PROFILE_MAGIC}"));
+        }
+
+        let mut profile = Self::default();
+        for (i, line) in lines.enumerate() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let count = |s: &str| {
+                s.parse::<u64>()
+                    .map_err(|_| format!("line {}: invalid count `{}`", i + 2, s))
+            };
+            let opcode = |s: &str| {
+                Instruction::OPCODES
+                    .iter()
+                    .find(|&&op| op == s)
+                    .copied()
+                    .ok_or_else(|| format!("line {}: unknown opcode `{}`", i + 2, s))
+            };
+            match parts.as_slice() {
+                [] => {}
+                ["pair", a, b, n] => {
+                    profile.pairs.insert((opcode(a)?, opcode(b)?), count(n)?);
+                }
+                _ => return Err(format!("line {}: malformed entry", i + 2)),
+            }
+        }
+        Ok(profile)
+    }
+
+    /// Catalog fusions whose pattern is among the workload's hottest pairs,
+    /// with their observed counts.
+    pub fn select_fusions(&self) -> Vec<(&'static str, &'static str, u64)> {
+        self.ranked()
+            .into_iter()
+            .take(TOP_PAIRS)
+            .filter(|(pair, _)| CATALOG.contains(pair))
+            .map(|(&(a, b), &n)| (a, b, n))
+            .collect()
+    }
+}
+
+/// Load-time rewriter: replaces adjacent instruction pairs with the fused
+/// opcodes selected from `profile`. Labels are never fused, so jump targets
+/// stay valid once labels are rebuilt from the rewritten code; relative
+/// jumps are re-aimed at the rewritten positions.
+pub fn fuse(code: Vec<Instruction>, profile: &NgramProfile) -> Vec<Instruction> {
+    let selected = profile.select_fusions();
+    if selected.is_empty() {
+        return code;
+    }
+    fuse_block(code, &selected)
+}
+
+fn fuse_block(code: Vec<Instruction>, selected: &[(&str, &str, u64)]) -> Vec<Instruction> {
+    let weight = |a: &Instruction, b: &Instruction| {
+        selected
+            .iter()
+            .find(|(x, y, _)| *x == a.opcode() && *y == b.opcode())
+            .map(|(_, _, n)| *n)
+    };
+
+    let code: Vec<Instruction> = code.into_iter().map(|i| fuse_nested(i, selected)).collect();
+    // Relative jumps (RXB2 code) may land on any instruction, so one that is
+    // a target stays the start of its own instruction.
+    let targets = jump_targets(&code);
+    let mut out = Vec::with_capacity(code.len());
+    let mut origin = Vec::with_capacity(code.len());
+    let mut moved = Vec::with_capacity(code.len() + 1);
+    let mut i = 0;
+    while i < code.len() {
+        moved.push(out.len());
+        origin.push(i);
+        if i + 1 < code.len()
+            && !targets.contains(&(i + 1))
+            && let Some(here) = weight(&code[i], &code[i + 1])
+        {
+            // Leave this instruction alone if the next pair is hotter.
+            let next = (i + 2 < code.len() && !targets.contains(&(i + 2)))
+                .then(|| weight(&code[i + 1], &code[i + 2]))
+                .flatten();
+            if next.is_none_or(|n| n <= here) {
+                moved.push(out.len());
+                out.push(fuse_pair(&code[i], &code[i + 1]));
+                i += 2;
+                continue;
+            }
+        }
+        out.push(code[i].clone());
+        i += 1;
+    }
+    moved.push(out.len());
+    relocate_jumps(&mut out, &origin, &moved);
+    out
+}
+
+fn fuse_pair(a: &Instruction, b: &Instruction) -> Instruction {
+    match (a, b) {
+        (Instruction::Load(x), Instruction::Load(y)) => Instruction::LoadLoad(x.clone(), y.clone()),
+        (Instruction::Load(x), Instruction::Push(n)) => Instruction::LoadPush(x.clone(), *n),
+        (Instruction::Load(x), Instruction::FieldGet(f)) => {
+            Instruction::LoadField(x.clone(), f.clone())
+        }
+        _ => unreachable!("pair is not in the superinstruction catalog"),
+    }
+}
+
+fn fuse_nested(instr: Instruction, selected: &[(&str, &str, u64)]) -> Instruction {
+    let expr = |e: ReactiveExpr| ReactiveExpr {
+        code: fuse_block(e.code, selected),
+        captures: e.captures,
+    };
+
+    match instr {
+        Instruction::StoreFunction(name, params, body, visibility) => {
+            Instruction::StoreFunction(name, params, fuse_block(body, selected), visibility)
+        }
+        Instruction::StoreReactive(name, e) => Instruction::StoreReactive(name, expr(e)),
+        Instruction::StoreIndexReactive(name, e) => Instruction::StoreIndexReactive(name, expr(e)),
+        Instruction::FieldSetReactive(name, e) => Instruction::FieldSetReactive(name, expr(e)),
+        Instruction::StoreThroughReactive(e) => Instruction::StoreThroughReactive(expr(e)),
+        Instruction::StoreStruct(name, fields, visibility) => {
+            let fields = fields
+                .into_iter()
+                .map(|(field, init)| {
+                    let init = init.map(|init| match init {
+                        CompiledStructFieldInit::Mutable(code) => {
+                            CompiledStructFieldInit::Mutable(fuse_block(code, selected))
+                        }
+                        CompiledStructFieldInit::Immutable(code) => {
+                            CompiledStructFieldInit::Immutable(fuse_block(code, selected))
+                        }
+                        CompiledStructFieldInit::Reactive(e) => {
+                            CompiledStructFieldInit::Reactive(expr(e))
+                        }
+                    });
+                    (field, init)
+                })
+                .collect();
+            Instruction::StoreStruct(name, fields, visibility)
+        }
+        other => other,
+    }
+}
@@ -0,0 +1,153 @@
+use super::{CallFrame, VM};
+use crate::grammar::{Instruction, Type};
+use std::collections::HashMap;
+
+/// A suspended green thread: the execution state the VM holds in its own
+/// fields while that task is running. Heaps and globals are shared.
+pub(crate) struct Task {
+    stack: Vec<Type>,
+    pointer: usize,
+    code: Vec<Instruction>,
+    labels: HashMap<String, usize>,
+    local_env: Option<HashMap<String, Type>>,
+    immutable_stack: Vec<HashMap<String, Type>>,
+    call_stack: Vec<CallFrame>,
+
+    // call depth at which the task's entry frame returns
+    base: usize,
+}
+
+impl Task {
+    /// Values the task keeps alive (for heap inspection).
+    pub(crate) fn roots(&self) -> impl Iterator<Item = &Type> {
+        let frames = self.call_stack.iter().flat_map(|frame| {
+            frame
+                .local_env
+                .iter()
+                .chain(frame.immutable_stack.iter())
+                .flat_map(|env| env.values())
+        });
+        self.stack
+            .iter()
+            .chain(self.local_env.iter().flat_map(|env| env.values()))
+            .chain(self.immutable_stack.iter().flat_map(|env| env.values()))
+            .chain(frames)
+    }
+}
+
+impl VM {
+    // =========================================================
+    // Green threads (cooperative, one OS thread)
+    // =========================================================
+
+    /// `Spawn name argc`: queues a task that calls `name` with the popped
+    /// arguments. It first runs when the current task yields or finishes.
+    pub(crate) fn exec_spawn(&mut self, name: String, argc: usize) {
+        self.check_visibility(&name, "function");
+        let args = self.pop_args(argc);
+        let (params, code, module) = match self.global_env.get(&name).cloned() {
+            Some(Type::Function {
+                params,
+                code,
+                module,
+            }) => (params, code, module),
+            Some(_) => {
+                self.runtime_error(&format!("spawn error: `{name}` is not a bytecode function"))
+            }
+            None => self.unknown_name_error(
+                &format!("spawn error: `{name}` is not defined"),
+                &name,
+                true,
+            ),
+        };
+        if params.len() != argc {
+            self.runtime_error(&format!(
+                "spawn error: `{name}` takes {} parameter(s), spawned with {argc}",
+                params.len()
+            ));
+        }
+
+        let global_immutables = self.immutable_stack[0].clone();
+        let scope = params.into_iter().zip(args).collect();
+
+        // The entry frame has no code of its own: when the function returns
+        // into it, the task has run off its end and is finished.
+        let entry = CallFrame {
+            code: Vec::new(),
+            labels: HashMap::new(),
+            pointer: 0,
+            local_env: None,
+            immutable_stack: Vec::new(),
+            stack_base: 0,
+            function_name: name,
+            module: Some(module),
+            started: None,
+        };
+
+        self.tasks.push_back(Task {
+            stack: Vec::new(),
+            pointer: 0,
+            labels: Self::build_labels(&code),
+            code,
+            local_env: Some(HashMap::new()),
+            immutable_stack: vec![global_immutables, scope],
+            call_stack: vec![entry],
+            base: 0,
+        });
+    }
+
+    /// `Yield`: moves the running task to the back of the queue and resumes
+    /// the task at the front. A no-op when no other task is waiting.
+    pub(crate) fn exec_yield(&mut self, base: &mut usize) {
+        if self.tasks.is_empty() {
+            return;
+        }
+        if self.run_depth != 1 {
+            self.runtime_error("cannot yield inside a native callback or reactive expression");
+        }
+        let current = self.suspend(*base);
+        self.tasks.push_back(current);
+        let next = self.tasks.pop_front().unwrap();
+        *base = self.resume(next);
+    }
+
+    /// Called when the running task has returned from its entry frame.
+    /// Resumes the next queued task, or returns `false` if there is none.
+    pub(crate) fn finish_task(&mut self, base: &mut usize) -> bool {
+        match self.tasks.pop_front() {
+            Some(next) => {
+                *base = self.resume(next);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn task_roots(&self) -> impl Iterator<Item = &Type> {
+        self.tasks.iter().flat_map(Task::roots)
+    }
+
+    fn suspend(&mut self, base: usize) -> Task {
+        Task {
+            stack: std::mem::take(&mut self.stack),
+            pointer: self.pointer,
+            code: std::mem::take(&mut self.code),
+            labels: std::mem::take(&mut self.labels),
+            local_env: self.local_env.take(),
+            immutable_stack: std::mem::take(&mut self.immutable_stack),
+            call_stack: std::mem::take(&mut self.call_stack),
+            base,
+        }
+    }
+
+    fn resume(&mut self, task: Task) -> usize {
+        self.stack = task.stack;
+        self.pointer = task.pointer;
+        self.code = task.code;
+        self.labels = task.labels;
+        self.local_env = task.local_env;
+        self.immutable_stack = task.immutable_stack;
+        self.call_stack = task.call_stack;
+        task.base
+    }
+}
@@ -0,0 +1,96 @@
+use super::VM;
+use crate::grammar::Instruction;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Where a run spent its time, collected for `--time`.
+#[derive(Debug, Default, Clone)]
+pub struct Timings {
+    /// Building label tables for called functions and reactive expressions.
+    pub link: Duration,
+    /// Inside file natives (std.fs).
+    pub natives_fs: Duration,
+    /// Inside every other native, including callbacks they run.
+    pub natives_compute: Duration,
+    native_depth: usize,
+}
+
+impl Timings {
+    /// Report for `--time`. `load` is reading and decoding the bytecode,
+    /// `setup` building the VM (including the entry label table) and
+    /// `execute` the whole of `VM::run`.
+    pub fn report(&self, load: Duration, setup: Duration, execute: Duration) -> String {
+        let natives = self.natives_fs + self.natives_compute;
+        let dispatch = execute.saturating_sub(self.link + natives);
+        let rows = [
+            ("load bytecode", load),
+            ("link labels", setup + self.link),
+            ("execute", execute),
+            ("  dispatch", dispatch),
+            ("  natives (fs)", self.natives_fs),
+            ("  natives (compute)", self.natives_compute),
+        ];
+        time_table(&rows)
+    }
+}
+
+/// `name  12.345 ms` rows, one per line.
+pub fn time_table(rows: &[(&str, Duration)]) -> String {
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    rows.iter()
+        .map(|(name, time)| {
+            format!(
+                "  {name:width$}  {:>10.3} ms\n",
+                time.as_secs_f64() * 1000.0
+            )
+        })
+        .collect()
+}
+
+fn is_fs_native(name: &str) -> bool {
+    name.starts_with("internal_file_")
+        || name.starts_with("internal_temp_")
+        || name == "internal_handle_close"
+}
+
+impl VM {
+    pub fn enable_timing(&mut self) {
+        self.timings = Some(Timings::default());
+    }
+
+    pub fn timings(&self) -> Option<&Timings> {
+        self.timings.as_ref()
+    }
+
+    pub(crate) fn link_labels(&mut self, code: &[Instruction]) -> HashMap<String, usize> {
+        let Some(timings) = &mut self.timings else {
+            return Self::build_labels(code);
+        };
+        let start = Instant::now();
+        let labels = Self::build_labels(code);
+        timings.link += start.elapsed();
+        labels
+    }
+
+    /// Starts timing a native call; `None` unless timing is on and this is
+    /// the outermost native (callbacks into bytecode can call more natives).
+    pub(crate) fn native_timer(&mut self) -> Option<Instant> {
+        let timings = self.timings.as_mut()?;
+        timings.native_depth += 1;
+        (timings.native_depth == 1).then(Instant::now)
+    }
+
+    pub(crate) fn record_native_time(&mut self, name: &str, start: Option<Instant>) {
+        let Some(timings) = &mut self.timings else {
+            return;
+        };
+        timings.native_depth -= 1;
+        if let Some(start) = start {
+            if is_fs_native(name) {
+                timings.natives_fs += start.elapsed();
+            } else {
+                timings.natives_compute += start.elapsed();
+            }
+        }
+    }
+}
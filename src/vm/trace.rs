@@ -0,0 +1,80 @@
+use super::VM;
+use crate::bytecode::instruction_line;
+use crate::grammar::{Instruction, Type};
+use std::io::Write;
+
+/// Writes one line per executed instruction (`--trace`).
+pub(crate) struct Tracer {
+    sink: Box<dyn Write>,
+    function_filter: Option<String>,
+}
+
+impl VM {
+    /// Logs every executed instruction to `sink`, optionally only inside
+    /// the function named `function_filter`.
+    pub fn enable_trace(&mut self, sink: Box<dyn Write>, function_filter: Option<String>) {
+        self.tracer = Some(Tracer {
+            sink,
+            function_filter,
+        });
+    }
+
+    pub(crate) fn trace_instruction(&mut self, instr: &Instruction) {
+        let function = self.current_function_name();
+        let Some(tracer) = &self.tracer else {
+            return;
+        };
+        if tracer
+            .function_filter
+            .as_deref()
+            .is_some_and(|f| f != function)
+        {
+            return;
+        }
+
+        let line = format!(
+            "[{}] {:05} {:<40} | depth {} top {}\n",
+            function,
+            self.pointer,
+            instruction_line(instr),
+            self.stack.len(),
+            self.stack
+                .last()
+                .map(summarize)
+                .unwrap_or_else(|| "-".into())
+        );
+
+        if let Some(tracer) = &mut self.tracer {
+            // A broken trace sink must not change program behaviour.
+            let _ = tracer.sink.write_all(line.as_bytes());
+        }
+    }
+}
+
+/// One-token rendering of a value for trace lines and heap dumps.
+pub(super) fn summarize(v: &Type) -> String {
+    match v {
+        Type::Integer(n) => n.to_string(),
+        Type::Char(c) => match char::from_u32(*c) {
+            Some(ch) => format!("{:?}", ch),
+            None => format!("char({c})"),
+        },
+        Type::Null => "null".to_string(),
+        Type::Tuple(elems) => format!("tuple/{}", elems.len()),
+        Type::Variant(variant) => format!("{}.{}", variant.enum_name, variant.name),
+        Type::Iter(_) => "iterator".to_string(),
+        Type::ArrayRef(id) => format!("array#{id}"),
+        Type::VecRef(id) => format!("vec#{id}"),
+        Type::BufferRef(id) => format!("buffer#{id}"),
+        Type::BytesRef(id) => format!("bytes#{id}"),
+        Type::SetRef(id) => format!("set#{id}"),
+        Type::PqRef(id) => format!("pq#{id}"),
+        Type::StructRef(id) => format!("struct#{id}"),
+        Type::HandleRef(id) => format!("handle#{id}"),
+        Type::Function { params, .. } => format!("function/{}", params.len()),
+        Type::NativeFunction(name) => format!("native {name}"),
+        Type::LazyValue(..) => "lazy".to_string(),
+        Type::LValue(_) => "lvalue".to_string(),
+        Type::Uninitialized => "uninitialized".to_string(),
+    }
+}
@@ -0,0 +1,395 @@
+use super::handle::Resource;
+use super::{RuntimeError, VM, VmOptions};
+use crate::grammar::{CompiledStructFieldInit, Instruction, StructInstance, Type, Variant};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// A value detached from any VM's heaps, so it can cross to another thread.
+/// Arrays, vecs and structs are copied recursively; sharing inside the value
+/// is not preserved and cycles are rejected.
+#[derive(Clone)]
+pub(crate) enum Message {
+    Integer(i64),
+    Char(u32),
+    Null,
+    Tuple(Vec<Message>),
+    Variant {
+        enum_name: String,
+        name: String,
+        payload: Vec<Message>,
+    },
+    Array(Vec<Message>, HashSet<usize>),
+    Vec(Vec<Message>, HashSet<usize>),
+    Buffer(Vec<u32>),
+    Bytes(Vec<u8>),
+    Set(Vec<Message>),
+    Pq(Box<Message>, Vec<Message>),
+    Struct {
+        name: String,
+        fields: Vec<(String, Message)>,
+        immutables: HashSet<String>,
+    },
+    Function {
+        params: Vec<String>,
+        code: Vec<Instruction>,
+        module: String,
+    },
+    NativeFunction(String),
+    Channel(Arc<Channel>),
+}
+
+/// Unbounded FIFO shared by every VM holding a handle to it.
+#[derive(Default)]
+pub(crate) struct Channel {
+    queue: Mutex<VecDeque<Message>>,
+    ready: Condvar,
+}
+
+impl Channel {
+    pub(crate) fn send(&self, message: Message) {
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        queue.push_back(message);
+        self.ready.notify_one();
+    }
+
+    /// Blocks until a message is available.
+    pub(crate) fn recv(&self) -> Message {
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(message) = queue.pop_front() {
+                return message;
+            }
+            queue = self.ready.wait(queue).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+/// Everything a worker VM needs to run functions of the spawning program:
+/// its definitions, imported natives and (copies of) its globals.
+#[derive(Clone)]
+pub(crate) struct WorkerSeed {
+    options: VmOptions,
+    imports: Vec<Vec<String>>,
+    struct_defs: HashMap<String, Vec<(String, Option<CompiledStructFieldInit>)>>,
+    enum_defs: HashMap<String, Vec<(String, usize)>>,
+    private_symbols: HashSet<String>,
+    globals: Vec<(String, Message)>,
+    immutables: Vec<(String, Message)>,
+}
+
+/// What a worker thread hands back to `internal_thread_join`: the function's
+/// return value, or the message of the runtime error that stopped it.
+pub(crate) type WorkerResult = Result<Message, String>;
+
+impl VM {
+    // =========================================================
+    // Crossing VM boundaries
+    // =========================================================
+
+    pub(crate) fn export_value(&mut self, v: Type) -> Result<Message, String> {
+        let mut path = HashSet::new();
+        self.export_inner(v, &mut path)
+    }
+
+    fn export_inner(
+        &mut self,
+        v: Type,
+        path: &mut HashSet<(char, usize)>,
+    ) -> Result<Message, String> {
+        let v = self.force(v);
+        let key = match v {
+            Type::ArrayRef(id) => Some(('a', id)),
+            Type::VecRef(id) => Some(('v', id)),
+            Type::StructRef(id) => Some(('s', id)),
+            _ => None,
+        };
+        if let Some(key) = key
+            && !path.insert(key)
+        {
+            return Err("cannot send a cyclic value".to_string());
+        }
+
+        let message = match v {
+            Type::Integer(n) => Message::Integer(n),
+            Type::Char(c) => Message::Char(c),
+            Type::Null => Message::Null,
+            Type::Tuple(elems) => Message::Tuple(self.export_all(elems, path)?),
+            Type::Variant(variant) => Message::Variant {
+                enum_name: variant.enum_name,
+                name: variant.name,
+                payload: self.export_all(variant.payload, path)?,
+            },
+            Type::ArrayRef(id) => {
+                let elems = self.array_heap[id].clone();
+                let immutables = self.array_immutables[id].clone();
+                Message::Array(self.export_all(elems, path)?, immutables)
+            }
+            Type::VecRef(id) => {
+                let elems = self.vec_heap[id].clone();
+                let immutables = self.vec_immutables[id].clone();
+                Message::Vec(self.export_all(elems, path)?, immutables)
+            }
+            Type::BufferRef(id) => Message::Buffer(self.buffer_heap[id].clone()),
+            Type::BytesRef(id) => Message::Bytes(self.bytes_heap[id].clone()),
+            Type::SetRef(id) => {
+                let elems = self.set_heap[id].values().cloned().collect();
+                Message::Set(self.export_all(elems, path)?)
+            }
+            Type::PqRef(id) => {
+                let pq = self.pq_heap[id].clone();
+                let cmp = self.export_inner(pq.cmp, path)?;
+                Message::Pq(Box::new(cmp), self.export_all(pq.items, path)?)
+            }
+            Type::StructRef(id) => {
+                let inst = self.heap[id].clone();
+                let mut fields = Vec::with_capacity(inst.fields.len());
+                for (field, value) in inst.fields {
+                    fields.push((field, self.export_inner(value, path)?));
+                }
+                Message::Struct {
+                    name: inst.name,
+                    fields,
+                    immutables: inst.immutables,
+                }
+            }
+            Type::Function {
+                params,
+                code,
+                module,
+            } => Message::Function {
+                params,
+                code,
+                module,
+            },
+            Type::NativeFunction(name) => Message::NativeFunction(name),
+            Type::HandleRef(id) => match self.open_resource(id) {
+                Some(Resource::Channel(channel)) => Message::Channel(channel.clone()),
+                Some(other) => return Err(format!("cannot send a {} handle", other.kind())),
+                None => return Err("cannot send a closed handle".to_string()),
+            },
+            Type::Iter(_) => return Err("cannot send an iterator".to_string()),
+            Type::LValue(_) | Type::LazyValue(..) | Type::Uninitialized => {
+                return Err("cannot send an uninitialized value".to_string());
+            }
+        };
+
+        if let Some(key) = key {
+            path.remove(&key);
+        }
+        Ok(message)
+    }
+
+    fn export_all(
+        &mut self,
+        elems: Vec<Type>,
+        path: &mut HashSet<(char, usize)>,
+    ) -> Result<Vec<Message>, String> {
+        elems
+            .into_iter()
+            .map(|e| self.export_inner(e, path))
+            .collect()
+    }
+
+    pub(crate) fn import_value(&mut self, message: Message) -> Type {
+        match message {
+            Message::Integer(n) => Type::Integer(n),
+            Message::Char(c) => Type::Char(c),
+            Message::Null => Type::Null,
+            Message::Tuple(elems) => Type::Tuple(self.import_all(elems)),
+            Message::Variant {
+                enum_name,
+                name,
+                payload,
+            } => Type::Variant(Box::new(Variant {
+                enum_name,
+                name,
+                payload: self.import_all(payload),
+            })),
+            Message::Array(elems, immutables) => {
+                let elems = self.import_all(elems);
+                Type::ArrayRef(self.alloc_array(elems, immutables))
+            }
+            Message::Vec(elems, immutables) => {
+                let elems = self.import_all(elems);
+                Type::VecRef(self.alloc_vec(elems, immutables))
+            }
+            Message::Buffer(chars) => {
+                self.buffer_heap.push(chars);
+                Type::BufferRef(self.buffer_heap.len() - 1)
+            }
+            Message::Bytes(bytes) => {
+                self.bytes_heap.push(bytes);
+                Type::BytesRef(self.bytes_heap.len() - 1)
+            }
+            Message::Set(elems) => {
+                let mut set = HashMap::new();
+                for elem in self.import_all(elems) {
+                    let key = self.set_key(elem.clone(), "set received from another thread");
+                    set.insert(key, elem);
+                }
+                self.new_set(set)
+            }
+            // items arrive in heap order, so no re-sifting is needed
+            Message::Pq(cmp, items) => {
+                let cmp = self.import_value(*cmp);
+                let items = self.import_all(items);
+                self.pq_heap
+                    .push(super::native::PriorityQueue { cmp, items });
+                Type::PqRef(self.pq_heap.len() - 1)
+            }
+            Message::Struct {
+                name,
+                fields,
+                immutables,
+            } => {
+                let fields = fields
+                    .into_iter()
+                    .map(|(field, value)| (field, self.import_value(value)))
+                    .collect();
+                self.heap.push(StructInstance {
+                    name,
+                    fields,
+                    immutables,
+                });
+                Type::StructRef(self.heap.len() - 1)
+            }
+            Message::Function {
+                params,
+                code,
+                module,
+            } => Type::Function {
+                params,
+                code,
+                module,
+            },
+            Message::NativeFunction(name) => Type::NativeFunction(name),
+            Message::Channel(channel) => {
+                Type::HandleRef(self.handles.insert(Resource::Channel(channel)))
+            }
+        }
+    }
+
+    fn import_all(&mut self, elems: Vec<Message>) -> Vec<Type> {
+        elems.into_iter().map(|e| self.import_value(e)).collect()
+    }
+
+    // =========================================================
+    // Worker VMs
+    // =========================================================
+
+    /// Snapshot of this program for a worker. Globals that cannot be sent
+    /// (file handles, iterators) are left out.
+    pub(crate) fn worker_seed(&mut self) -> WorkerSeed {
+        let globals: Vec<(String, Type)> = self
+            .global_env
+            .iter()
+            .map(|(name, v)| (name.clone(), v.clone()))
+            .collect();
+        let immutables: Vec<(String, Type)> = self.immutable_stack[0]
+            .iter()
+            .map(|(name, v)| (name.clone(), v.clone()))
+            .collect();
+
+        WorkerSeed {
+            options: self.options.clone(),
+            imports: self
+                .imported_modules
+                .iter()
+                .map(|module| module.split('.').map(str::to_string).collect())
+                .collect(),
+            struct_defs: self.struct_defs.clone(),
+            enum_defs: self.enum_defs.clone(),
+            private_symbols: self.private_symbols.clone(),
+            globals: self.export_bindings(globals),
+            immutables: self.export_bindings(immutables),
+        }
+    }
+
+    fn export_bindings(&mut self, bindings: Vec<(String, Type)>) -> Vec<(String, Message)> {
+        bindings
+            .into_iter()
+            .filter_map(|(name, v)| Some((name, self.export_value(v).ok()?)))
+            .collect()
+    }
+}
+
+impl WorkerSeed {
+    /// A fresh VM with the seeded program loaded. Runtime errors unwind
+    /// instead of exiting, so the worker can report them to its spawner.
+    fn into_vm(self) -> VM {
+        let options = VmOptions {
+            unwind_on_error: true,
+            ..self.options
+        };
+        let mut vm = VM::with_options(Vec::new(), options);
+        for path in self.imports {
+            vm.imported_modules.insert(path.join("."));
+            vm.import_module(path);
+        }
+        vm.struct_defs = self.struct_defs;
+        vm.enum_defs = self.enum_defs;
+        vm.private_symbols = self.private_symbols;
+        for (name, message) in self.globals {
+            let v = vm.import_value(message);
+            vm.global_env.insert(name, v);
+        }
+        for (name, message) in self.immutables {
+            let v = vm.import_value(message);
+            vm.immutable_stack[0].insert(name, v);
+        }
+        vm
+    }
+}
+
+/// Body of a worker thread: calls `name(arg)` in a VM built from `seed`.
+/// Runtime errors are reported and returned, not fatal.
+pub(crate) fn run_worker(
+    seed: WorkerSeed,
+    name: String,
+    function: Type,
+    arg: Message,
+) -> WorkerResult {
+    let mut vm = seed.into_vm();
+    catch_runtime_error(|| {
+        let arg = vm.import_value(arg);
+        let ret = vm.call_function(name, function, vec![arg]);
+        let ret = vm.force(ret);
+        vm.export_value(ret)
+            .map_err(|e| format!("cannot return value from worker: {e}"))
+    })
+}
+
+/// One shard of `internal_vec_par_map`: applies `f` to each element in a VM
+/// built from `seed`, returning the results in order.
+pub(crate) fn run_map_shard(
+    seed: WorkerSeed,
+    f: Message,
+    elems: Vec<Message>,
+) -> Result<Vec<Message>, String> {
+    let mut vm = seed.into_vm();
+    catch_runtime_error(|| {
+        let f = vm.import_value(f);
+        let mut out = Vec::with_capacity(elems.len());
+        for elem in elems {
+            let elem = vm.import_value(elem);
+            let mapped = vm.call_value(f.clone(), vec![elem], "internal_vec_par_map");
+            let mapped = vm.force(mapped);
+            out.push(
+                vm.export_value(mapped)
+                    .map_err(|e| format!("cannot return value from worker: {e}"))?,
+            );
+        }
+        Ok(out)
+    })
+}
+
+fn catch_runtime_error<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => match payload.downcast::<RuntimeError>() {
+            Ok(error) => Err(error.message),
+            Err(payload) => std::panic::resume_unwind(payload),
+        },
+    }
+}